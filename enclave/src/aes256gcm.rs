@@ -1,17 +1,29 @@
-use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::Aead};
+use aes_gcm::{
+    Aes256Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
 use shared::error::Aes256GcmError;
 
+use crate::secret::SecretVec;
+
 pub fn encrypt_private_key_aes256gcm(
-    private_key: &[u8; 64],
-    encryption_key: &Vec<u8>,
+    private_key: &[u8],
+    encryption_key: &[u8],
     nonce: &[u8; 12],
+    aad: &[u8],
 ) -> Result<Vec<u8>, Aes256GcmError> {
     let cipher =
         Aes256Gcm::new_from_slice(encryption_key).map_err(|_| Aes256GcmError::InvalidLength)?;
     let nonce = Nonce::from_slice(nonce);
 
     let ciphertext = cipher
-        .encrypt(nonce, private_key.as_ref())
+        .encrypt(
+            nonce,
+            Payload {
+                msg: private_key,
+                aad,
+            },
+        )
         .map_err(|_| Aes256GcmError::EncryptionFailed)?;
 
     return Ok(ciphertext);
@@ -19,19 +31,26 @@ pub fn encrypt_private_key_aes256gcm(
 
 pub fn decrypt_private_key_aes256gcm(
     ciphertext: &[u8],
-    encryption_key: &Vec<u8>,
+    encryption_key: &[u8],
     nonce: &[u8; 12],
-) -> Result<Vec<u8>, Aes256GcmError> {
+    aad: &[u8],
+) -> Result<SecretVec, Aes256GcmError> {
     let cipher =
         Aes256Gcm::new_from_slice(encryption_key).map_err(|_| Aes256GcmError::InvalidLength)?;
 
     let nonce = Nonce::from_slice(nonce);
 
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
         .map_err(|_| Aes256GcmError::DecryptionFailed)?;
 
-    Ok(plaintext)
+    Ok(SecretVec::from_vec(plaintext))
 }
 
 #[cfg(test)]
@@ -44,16 +63,16 @@ mod tests {
         let encryption_key = vec![1u8; 32];
         let nonce = [0u8; 12];
 
-        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce)
+        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce, &[])
             .expect("encryption should succeed");
 
         assert_ne!(ciphertext.as_slice(), &private_key[..]);
 
-        let decrypted = decrypt_private_key_aes256gcm(&ciphertext, &encryption_key, &nonce)
+        let decrypted = decrypt_private_key_aes256gcm(&ciphertext, &encryption_key, &nonce, &[])
             .expect("decryption should succeed");
 
-        assert_eq!(decrypted.len(), 64);
-        assert_eq!(decrypted, private_key);
+        assert_eq!(decrypted.expose().len(), 64);
+        assert_eq!(decrypted.expose(), private_key);
     }
 
     #[test]
@@ -63,10 +82,10 @@ mod tests {
         let wrong_key = vec![2u8; 32];
         let nonce = [0u8; 12];
 
-        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce)
+        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce, &[])
             .expect("encryption unexpectedly failed");
 
-        let result = decrypt_private_key_aes256gcm(&ciphertext, &wrong_key, &nonce);
+        let result = decrypt_private_key_aes256gcm(&ciphertext, &wrong_key, &nonce, &[]);
 
         assert!(result.is_err());
         match result {
@@ -82,12 +101,27 @@ mod tests {
         let nonce = [0u8; 12];
         let wrong_nonce = [99u8; 12];
 
-        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce)
+        let ciphertext = encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce, &[])
             .expect("encryption unexpectedly failed");
 
-        let result = decrypt_private_key_aes256gcm(&ciphertext, &encryption_key, &wrong_nonce);
+        let result = decrypt_private_key_aes256gcm(&ciphertext, &encryption_key, &wrong_nonce, &[]);
 
         assert!(result.is_err());
         assert!(matches!(result, Err(Aes256GcmError::DecryptionFailed)));
     }
+
+    #[test]
+    fn test_decrypt_with_wrong_aad_fails() {
+        let private_key = [42u8; 64];
+        let encryption_key = vec![1u8; 32];
+        let nonce = [0u8; 12];
+
+        let ciphertext =
+            encrypt_private_key_aes256gcm(&private_key, &encryption_key, &nonce, b"policy")
+                .expect("encryption unexpectedly failed");
+
+        let result = decrypt_private_key_aes256gcm(&ciphertext, &encryption_key, &nonce, &[]);
+
+        assert!(matches!(result, Err(Aes256GcmError::DecryptionFailed)));
+    }
 }
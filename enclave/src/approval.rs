@@ -0,0 +1,481 @@
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use pallas_crypto::key::ed25519::{PublicKey, Signature};
+use shared::approval::{Approval, Quorum};
+use shared::error::{ApprovalError, ApprovalRejection, RejectedApproval};
+use shared::transport::{KmsCredentials, VsockHostRequest};
+
+/// The hash approvers sign. Credentials are per-session and approvals can't
+/// cover themselves, so both are reset before hashing; everything else,
+/// including the wallet envelope, is covered. FROST signature shares only cover
+/// what every signer has in common.
+pub fn request_hash(request: &VsockHostRequest) -> [u8; 32] {
+    let mut request = request.clone();
+    match &mut request {
+        VsockHostRequest::CreateWallet { credentials, .. }
+        | VsockHostRequest::ImportKey { credentials, .. } => {
+            *credentials = KmsCredentials::default();
+        }
+        VsockHostRequest::RewrapWallet {
+            credentials,
+            new_credentials,
+            approvals,
+            ..
+        } => {
+            *credentials = KmsCredentials::default();
+            *new_credentials = KmsCredentials::default();
+            approvals.clear();
+        }
+        VsockHostRequest::SetWalletPolicy {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::ExportBackup {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::ImportBackup {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::Sign {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignBatch {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignCardanoTx {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignCardanoMessage {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignEthereumTx {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignEthereumTypedData {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignEthereumMessage {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignPsbt {
+            credentials,
+            approvals,
+            ..
+        }
+        | VsockHostRequest::SignSolanaTx {
+            credentials,
+            approvals,
+            ..
+        } => {
+            *credentials = KmsCredentials::default();
+            approvals.clear();
+        }
+        VsockHostRequest::FrostDkgPart3 { credentials, .. }
+        | VsockHostRequest::FrostCommit { credentials, .. } => {
+            *credentials = KmsCredentials::default();
+        }
+        VsockHostRequest::FrostSign {
+            credentials,
+            key_share,
+            commitments,
+            approvals,
+            ..
+        } => {
+            // one set of approvals serves every signer, before the
+            // commitments exist, so only the group's fields are covered
+            *credentials = KmsCredentials::default();
+            key_share.identifier = 0;
+            key_share.encrypted_signing_share.clear();
+            key_share.aes_gcm_nonce = [0u8; 12];
+            key_share.kms_ciphertext.clear();
+            key_share.kms_key_id.clear();
+            commitments.clear();
+            approvals.clear();
+        }
+        VsockHostRequest::Handshake { .. }
+        | VsockHostRequest::FrostDkgPart1 { .. }
+        | VsockHostRequest::FrostDkgPart2 { .. }
+        | VsockHostRequest::GetSealingKey { .. }
+        | VsockHostRequest::GetAuditKey { .. }
+        | VsockHostRequest::Ping
+        | VsockHostRequest::HealthCheck { .. }
+        | VsockHostRequest::Shutdown
+        | VsockHostRequest::GetAttestation { .. }
+        | VsockHostRequest::Sealed { .. }
+        | VsockHostRequest::EvictCache { .. } => {}
+    }
+
+    // serializing a request that was just deserialized cannot fail
+    let request_cbor = serde_cbor::to_vec(&request).expect("request is serializable");
+    let mut hasher = Sha256::new();
+    hasher.input(&request_cbor);
+    let mut hash = [0u8; 32];
+    hasher.result(&mut hash);
+    return hash;
+}
+
+/// Appended to the envelope's associated data after the policy hash. Empty
+/// without a quorum so existing wallets keep decrypting, and domain separated
+/// so a quorum hash can never stand in for a policy hash.
+pub fn envelope_aad(quorum: Option<&Quorum>) -> Vec<u8> {
+    let Some(quorum) = quorum else {
+        return Vec::new();
+    };
+    let quorum_cbor = serde_cbor::to_vec(quorum).expect("quorum is serializable");
+    let mut hasher = Sha256::new();
+    hasher.input(b"quorum");
+    hasher.input(&quorum_cbor);
+    let mut aad = vec![0u8; 32];
+    hasher.result(&mut aad);
+    return aad;
+}
+
+pub fn validate_quorum(quorum: &Quorum) -> Result<(), ApprovalError> {
+    if quorum.threshold == 0 {
+        return Err(ApprovalError::InvalidQuorum(
+            "threshold must be at least 1".to_string(),
+        ));
+    }
+    if usize::from(quorum.threshold) > quorum.approvers.len() {
+        return Err(ApprovalError::InvalidQuorum(format!(
+            "threshold {} exceeds {} approvers",
+            quorum.threshold,
+            quorum.approvers.len()
+        )));
+    }
+    for (i, approver) in quorum.approvers.iter().enumerate() {
+        if quorum.approvers[..i].contains(approver) {
+            return Err(ApprovalError::InvalidQuorum(
+                "approvers must be distinct".to_string(),
+            ));
+        }
+    }
+    return Ok(());
+}
+
+/// Checks `approvals` over `request_hash` against the wallet's quorum. Wallets
+/// without one need no approvals. Like policies, this must only be called once
+/// the wallet secret has been decrypted, which proves the quorum is genuine.
+pub fn verify(
+    quorum: Option<&Quorum>,
+    request_hash: &[u8; 32],
+    approvals: &[Approval],
+) -> Result<(), ApprovalError> {
+    let Some(quorum) = quorum else {
+        return Ok(());
+    };
+
+    let mut approved: Vec<[u8; 32]> = Vec::new();
+    let mut rejected = Vec::new();
+    for approval in approvals {
+        let reason = if !quorum.approvers.contains(&approval.approver) {
+            Some(ApprovalRejection::UnknownApprover)
+        } else if approved.contains(&approval.approver) {
+            Some(ApprovalRejection::DuplicateApprover)
+        } else if !verify_signature(approval, request_hash) {
+            Some(ApprovalRejection::InvalidSignature)
+        } else {
+            None
+        };
+
+        match reason {
+            Some(reason) => rejected.push(RejectedApproval {
+                approver: approval.approver,
+                reason,
+            }),
+            None => approved.push(approval.approver),
+        }
+    }
+
+    if approved.len() < usize::from(quorum.threshold) {
+        return Err(ApprovalError::QuorumNotMet {
+            threshold: quorum.threshold,
+            approved: approved.len() as u8,
+            rejected,
+        });
+    }
+    return Ok(());
+}
+
+fn verify_signature(approval: &Approval, request_hash: &[u8; 32]) -> bool {
+    let Ok(signature) = <[u8; 64]>::try_from(approval.signature.as_slice()) else {
+        return false;
+    };
+    return PublicKey::from(approval.approver).verify(request_hash, &Signature::from(signature));
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use pallas_crypto::key::ed25519::SecretKey;
+    use shared::frost::{FrostKeyShare, FrostSigningCommitment};
+    use shared::policy::SignedPolicy;
+    use shared::transport::{SignatureScheme, VsockEnclaveCreateWalletData, WalletOrigin};
+
+    fn approver(i: u8) -> SecretKey {
+        return SecretKey::from([i; 32]);
+    }
+
+    pub(crate) fn quorum(threshold: u8) -> Quorum {
+        return Quorum {
+            threshold,
+            approvers: (1..=3).map(|i| approver(i).public_key().into()).collect(),
+        };
+    }
+
+    fn approve(i: u8, request_hash: &[u8; 32]) -> Approval {
+        let key = approver(i);
+        return Approval {
+            approver: key.public_key().into(),
+            signature: key.sign(request_hash).as_ref().to_vec(),
+        };
+    }
+
+    fn quorum_wallet() -> VsockEnclaveCreateWalletData {
+        return VsockEnclaveCreateWalletData {
+            encrypted_secret_key: vec![1],
+            aes_gcm_nonce: [0u8; 12],
+            kms_ciphertext: vec![2],
+            kms_key_id: "key".to_string(),
+            policy: None,
+            quorum: Some(quorum(2)),
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+    }
+
+    fn sign_request(
+        credentials: KmsCredentials,
+        payload: Vec<u8>,
+        approvals: Vec<Approval>,
+    ) -> VsockHostRequest {
+        return VsockHostRequest::Sign {
+            credentials,
+            wallet: quorum_wallet(),
+            signature_scheme: SignatureScheme::Secp256k1,
+            derivation_path: "m/0".to_string(),
+            payload,
+            approvals,
+        };
+    }
+
+    #[test]
+    fn test_request_hash_ignores_credentials_and_approvals() {
+        let hash = request_hash(&sign_request(
+            KmsCredentials::default(),
+            vec![3u8; 32],
+            vec![],
+        ));
+
+        let credentials = KmsCredentials {
+            aws_session_token: "token".to_string(),
+            ..KmsCredentials::default()
+        };
+        let approved = sign_request(credentials, vec![3u8; 32], vec![approve(1, &hash)]);
+        assert_eq!(request_hash(&approved), hash);
+
+        let other = sign_request(KmsCredentials::default(), vec![4u8; 32], vec![]);
+        assert_ne!(request_hash(&other), hash);
+    }
+
+    #[test]
+    fn test_validate_quorum() {
+        assert!(validate_quorum(&quorum(1)).is_ok());
+        assert!(validate_quorum(&quorum(3)).is_ok());
+        assert!(validate_quorum(&quorum(0)).is_err());
+        assert!(validate_quorum(&quorum(4)).is_err());
+
+        let mut duplicated = quorum(2);
+        duplicated.approvers[1] = duplicated.approvers[0];
+        assert!(validate_quorum(&duplicated).is_err());
+    }
+
+    #[test]
+    fn test_verify_counts_distinct_valid_approvals() {
+        let hash = [7u8; 32];
+        assert!(verify(None, &hash, &[]).is_ok());
+        assert!(
+            verify(
+                Some(&quorum(2)),
+                &hash,
+                &[approve(1, &hash), approve(3, &hash)]
+            )
+            .is_ok()
+        );
+
+        let mut forged = approve(2, &hash);
+        forged.signature = approve(2, &[8u8; 32]).signature;
+        let err = verify(
+            Some(&quorum(2)),
+            &hash,
+            &[
+                approve(1, &hash),
+                approve(1, &hash),
+                approve(9, &hash),
+                forged,
+            ],
+        )
+        .unwrap_err();
+
+        let approver_key = |i: u8| -> [u8; 32] { approver(i).public_key().into() };
+        assert_eq!(
+            err,
+            ApprovalError::QuorumNotMet {
+                threshold: 2,
+                approved: 1,
+                rejected: vec![
+                    RejectedApproval {
+                        approver: approver_key(1),
+                        reason: ApprovalRejection::DuplicateApprover,
+                    },
+                    RejectedApproval {
+                        approver: approver_key(9),
+                        reason: ApprovalRejection::UnknownApprover,
+                    },
+                    RejectedApproval {
+                        approver: approver_key(2),
+                        reason: ApprovalRejection::InvalidSignature,
+                    },
+                ],
+            }
+        );
+    }
+
+    fn rewrap_request(new_kms_key_id: &str, approvals: Vec<Approval>) -> VsockHostRequest {
+        return VsockHostRequest::RewrapWallet {
+            credentials: KmsCredentials::default(),
+            wallet: quorum_wallet(),
+            new_credentials: KmsCredentials::default(),
+            new_kms_key_id: new_kms_key_id.to_string(),
+            approvals,
+        };
+    }
+
+    #[test]
+    fn test_rewrap_of_quorum_wallet_needs_approvals_for_the_new_key() {
+        let verify_rewrap = |request: &VsockHostRequest| {
+            let VsockHostRequest::RewrapWallet {
+                wallet, approvals, ..
+            } = request
+            else {
+                unreachable!();
+            };
+            return verify(wallet.quorum.as_ref(), &request_hash(request), approvals);
+        };
+        let hash = request_hash(&rewrap_request("new-key", vec![]));
+
+        assert!(verify_rewrap(&rewrap_request("new-key", vec![])).is_err());
+        assert!(verify_rewrap(&rewrap_request("new-key", vec![approve(1, &hash)])).is_err());
+        assert!(
+            verify_rewrap(&rewrap_request(
+                "new-key",
+                vec![approve(1, &hash), approve(2, &hash)]
+            ))
+            .is_ok()
+        );
+        // approvals to move the wallet to one key do not cover another
+        assert!(
+            verify_rewrap(&rewrap_request(
+                "attacker-key",
+                vec![approve(1, &hash), approve(2, &hash)]
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_policy_change_of_quorum_wallet_needs_approvals_for_that_policy() {
+        let set_policy = |policy: &[u8], approvals: Vec<Approval>| {
+            return VsockHostRequest::SetWalletPolicy {
+                credentials: KmsCredentials::default(),
+                wallet: quorum_wallet(),
+                policy: SignedPolicy {
+                    policy: policy.to_vec(),
+                    signature: vec![],
+                },
+                approvals,
+            };
+        };
+        let verify_set_policy = |request: &VsockHostRequest| {
+            let VsockHostRequest::SetWalletPolicy {
+                wallet, approvals, ..
+            } = request
+            else {
+                unreachable!();
+            };
+            return verify(wallet.quorum.as_ref(), &request_hash(request), approvals);
+        };
+        let hash = request_hash(&set_policy(b"strict", vec![]));
+
+        assert!(verify_set_policy(&set_policy(b"strict", vec![approve(1, &hash)])).is_err());
+        assert!(
+            verify_set_policy(&set_policy(
+                b"strict",
+                vec![approve(1, &hash), approve(2, &hash)]
+            ))
+            .is_ok()
+        );
+        assert!(
+            verify_set_policy(&set_policy(
+                b"lenient",
+                vec![approve(1, &hash), approve(2, &hash)]
+            ))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_frost_sign_approvals_cover_every_signer() {
+        let frost_sign = |identifier: u16, message: &[u8]| {
+            return VsockHostRequest::FrostSign {
+                credentials: KmsCredentials::default(),
+                key_share: FrostKeyShare {
+                    identifier,
+                    threshold: 2,
+                    group_public_key: [7u8; 32],
+                    verifying_shares: vec![],
+                    encrypted_signing_share: vec![identifier as u8; 48],
+                    aes_gcm_nonce: [identifier as u8; 12],
+                    kms_ciphertext: vec![identifier as u8; 16],
+                    kms_key_id: format!("key-{}", identifier),
+                    policy: None,
+                    quorum: Some(quorum(2)),
+                },
+                message: message.to_vec(),
+                commitments: vec![FrostSigningCommitment {
+                    identifier,
+                    hiding: [identifier as u8; 32],
+                    binding: [identifier as u8; 32],
+                }],
+                approvals: vec![],
+            };
+        };
+
+        assert_eq!(
+            request_hash(&frost_sign(1, b"message")),
+            request_hash(&frost_sign(2, b"message"))
+        );
+        assert_ne!(
+            request_hash(&frost_sign(1, b"message")),
+            request_hash(&frost_sign(1, b"another"))
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use cryptoxide::{digest::Digest, sha2::Sha256};
+
+use pallas_crypto::key::ed25519::SecretKey;
+use shared::audit::{AuditDecision, AuditEvent, SignedAuditEvent};
+use shared::error::{NsmError, VsockEnclaveCreateWalletError, VsockEnclaveSignError};
+use shared::transport::{VsockEnclaveAuditKeyData, VsockHostRequest};
+use zeroize::Zeroizing;
+
+use crate::nsm;
+
+pub type SharedAuditLog = Arc<Mutex<AuditLog>>;
+
+/// Signs and chains the audit events of every request on a wallet. The
+/// signing key is generated at boot and dies with the enclave, so a reboot
+/// starts a new chain under a new key; the host keeps the events.
+pub struct AuditLog {
+    signing_key: SecretKey,
+    public_key: [u8; 32],
+    sequence: u64,
+    previous_hash: [u8; 32],
+}
+
+/// What is known about a request before it runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditContext {
+    pub operation: &'static str,
+    pub wallet_id: Option<[u8; 32]>,
+}
+
+/// Errors that tell a policy or quorum refusal apart from other failures.
+pub trait AuditedError: std::fmt::Display {
+    fn decision(&self) -> AuditDecision;
+}
+
+impl AuditedError for VsockEnclaveSignError {
+    fn decision(&self) -> AuditDecision {
+        return match self {
+            VsockEnclaveSignError::PolicyError(_)
+            | VsockEnclaveSignError::ApprovalError(_)
+            | VsockEnclaveSignError::Unauthenticated => AuditDecision::Denied,
+            _ => AuditDecision::Failed,
+        };
+    }
+}
+
+impl AuditedError for VsockEnclaveCreateWalletError {
+    fn decision(&self) -> AuditDecision {
+        return match self {
+            VsockEnclaveCreateWalletError::PolicyError(_)
+            | VsockEnclaveCreateWalletError::ApprovalError(_)
+            | VsockEnclaveCreateWalletError::Unauthenticated => AuditDecision::Denied,
+            _ => AuditDecision::Failed,
+        };
+    }
+}
+
+impl AuditLog {
+    pub fn generate() -> Result<Self, getrandom::Error> {
+        let mut seed = Zeroizing::new([0u8; 32]);
+        getrandom::getrandom(seed.as_mut())?;
+        return Ok(Self::from_seed(*seed));
+    }
+
+    fn from_seed(seed: [u8; 32]) -> Self {
+        let signing_key = SecretKey::from(seed);
+        let public_key = signing_key.public_key().into();
+        return Self {
+            signing_key,
+            public_key,
+            sequence: 0,
+            previous_hash: [0u8; 32],
+        };
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        return self.public_key;
+    }
+
+    pub fn attest(&self, nonce: Option<&[u8]>) -> Result<VsockEnclaveAuditKeyData, NsmError> {
+        return Ok(VsockEnclaveAuditKeyData {
+            public_key: self.public_key,
+            attestation: nsm::attestation(None, nonce, Some(&self.public_key))?,
+        });
+    }
+
+    /// Appends the outcome of a request to the chain and returns the signed
+    /// event, or `None` for requests that are not audited. `created` is the
+    /// `kms_ciphertext` of the wallet or key share the request returned.
+    pub fn record<T, E: AuditedError>(
+        &mut self,
+        context: Option<&AuditContext>,
+        request_hash: &[u8; 32],
+        result: &Result<T, E>,
+        created: Option<&[u8]>,
+    ) -> Option<SignedAuditEvent> {
+        let context = context?;
+        let (decision, error) = match result {
+            Ok(_) => (AuditDecision::Allowed, None),
+            Err(e) => (e.decision(), Some(e.to_string())),
+        };
+        let event = AuditEvent {
+            sequence: self.sequence,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or_default(),
+            operation: context.operation.to_string(),
+            wallet_id: context.wallet_id,
+            created_wallet_id: created.map(envelope_id),
+            request_hash: *request_hash,
+            decision,
+            error,
+            previous_hash: self.previous_hash,
+            enclave_key: self.public_key,
+        };
+
+        let hash = event.hash();
+        let signature = self.signing_key.sign(hash).as_ref().to_vec();
+        self.sequence += 1;
+        self.previous_hash = hash;
+        return Some(SignedAuditEvent { event, signature });
+    }
+}
+
+/// The audit context of requests that use or create a wallet or a FROST key
+/// share.
+pub fn context(request: &VsockHostRequest) -> Option<AuditContext> {
+    let kms_ciphertext = match request {
+        VsockHostRequest::CreateWallet { .. }
+        | VsockHostRequest::ImportBackup { .. }
+        | VsockHostRequest::ImportKey { .. }
+        | VsockHostRequest::FrostDkgPart3 { .. } => None,
+        VsockHostRequest::FrostCommit { key_share, .. }
+        | VsockHostRequest::FrostSign { key_share, .. } => Some(&key_share.kms_ciphertext),
+        VsockHostRequest::SetWalletPolicy { wallet, .. }
+        | VsockHostRequest::RewrapWallet { wallet, .. }
+        | VsockHostRequest::ExportBackup { wallet, .. }
+        | VsockHostRequest::Sign { wallet, .. }
+        | VsockHostRequest::SignBatch { wallet, .. }
+        | VsockHostRequest::SignCardanoTx { wallet, .. }
+        | VsockHostRequest::SignCardanoMessage { wallet, .. }
+        | VsockHostRequest::SignEthereumTx { wallet, .. }
+        | VsockHostRequest::SignEthereumTypedData { wallet, .. }
+        | VsockHostRequest::SignEthereumMessage { wallet, .. }
+        | VsockHostRequest::SignPsbt { wallet, .. }
+        | VsockHostRequest::SignSolanaTx { wallet, .. } => Some(&wallet.kms_ciphertext),
+        VsockHostRequest::Handshake { .. }
+        | VsockHostRequest::GetSealingKey { .. }
+        | VsockHostRequest::GetAuditKey { .. }
+        | VsockHostRequest::Ping
+        | VsockHostRequest::HealthCheck { .. }
+        | VsockHostRequest::Shutdown
+        | VsockHostRequest::GetAttestation { .. }
+        | VsockHostRequest::Sealed { .. }
+        | VsockHostRequest::FrostDkgPart1 { .. }
+        | VsockHostRequest::FrostDkgPart2 { .. }
+        | VsockHostRequest::EvictCache { .. } => return None,
+    };
+    return Some(AuditContext {
+        operation: request.operation(),
+        wallet_id: kms_ciphertext.map(|kms_ciphertext| envelope_id(kms_ciphertext)),
+    });
+}
+
+/// SHA-256 of a wallet's or key share's `kms_ciphertext`, which is unique per
+/// envelope, so changes when a wallet is rewrapped or restored.
+fn envelope_id(kms_ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(kms_ciphertext);
+    let mut id = [0u8; 32];
+    hasher.result(&mut id);
+    return id;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::audit::verify_chain;
+    use shared::error::PolicyError;
+    use shared::transport::{KmsCredentials, VsockEnclaveCreateWalletData, WalletOrigin};
+
+    fn wallet() -> VsockEnclaveCreateWalletData {
+        return VsockEnclaveCreateWalletData {
+            encrypted_secret_key: vec![1; 80],
+            aes_gcm_nonce: [0u8; 12],
+            kms_ciphertext: vec![2; 32],
+            kms_key_id: "key".to_string(),
+            policy: None,
+            quorum: None,
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+    }
+
+    #[test]
+    fn test_recorded_events_form_a_verifiable_chain() {
+        let mut audit_log = AuditLog::from_seed([3u8; 32]);
+        let create = context(&VsockHostRequest::CreateWallet {
+            credentials: KmsCredentials::default(),
+            kms_key_id: "key".to_string(),
+            aes_gcm_nonce: [0u8; 12],
+            policy: None,
+            quorum: None,
+        });
+        let sign = context(&VsockHostRequest::Sign {
+            credentials: KmsCredentials::default(),
+            wallet: wallet(),
+            signature_scheme: shared::transport::SignatureScheme::Ed25519,
+            derivation_path: "m".to_string(),
+            payload: vec![],
+            approvals: vec![],
+        });
+        assert_eq!(
+            sign.as_ref().unwrap().wallet_id,
+            Some(envelope_id(&wallet().kms_ciphertext))
+        );
+
+        let created: Result<_, VsockEnclaveCreateWalletError> = Ok(wallet());
+        let denied: Result<(), VsockEnclaveSignError> = Err(PolicyError::NoAdminKey.into());
+        let events = vec![
+            audit_log
+                .record(
+                    create.as_ref(),
+                    &[1u8; 32],
+                    &created,
+                    created
+                        .as_ref()
+                        .ok()
+                        .map(|wallet| wallet.kms_ciphertext.as_slice()),
+                )
+                .unwrap(),
+            audit_log
+                .record(sign.as_ref(), &[2u8; 32], &denied, None)
+                .unwrap(),
+        ];
+        assert_eq!(
+            events[0].event.created_wallet_id,
+            Some(envelope_id(&wallet().kms_ciphertext))
+        );
+        assert_eq!(events[1].event.decision, AuditDecision::Denied);
+        assert!(events[1].event.error.is_some());
+        assert_eq!(verify_chain(&events, &[audit_log.public_key()]), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_requests_without_a_wallet_are_not_recorded() {
+        let mut audit_log = AuditLog::from_seed([3u8; 32]);
+        let request = VsockHostRequest::EvictCache {
+            kms_ciphertext: None,
+        };
+        let result: Result<(), VsockEnclaveSignError> = Ok(());
+        assert!(
+            audit_log
+                .record(context(&request).as_ref(), &[0u8; 32], &result, None)
+                .is_none()
+        );
+        assert_eq!(audit_log.sequence, 0);
+    }
+}
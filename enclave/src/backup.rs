@@ -0,0 +1,214 @@
+use shared::backup::{BACKUP_SHARE_INFO, BackupPart, BackupSecret, BackupShare, WalletBackup};
+use shared::error::BackupError;
+use shared::sealed;
+use shared::transport::VsockEnclaveCreateWalletData;
+use zeroize::Zeroizing;
+
+use crate::secret::Secret;
+use crate::slip39;
+
+/// Seals the wallet secret to every key in `recovery_keys`: whole, or with
+/// `threshold` as one SLIP-39 share per key. Only wallets with a quorum can be
+/// exported, since the quorum is what authorizes the export.
+pub fn export(
+    wallet: &VsockEnclaveCreateWalletData,
+    secret_key: &Secret<[u8; 64]>,
+    recovery_keys: &[[u8; 32]],
+    threshold: Option<u8>,
+) -> Result<WalletBackup, BackupError> {
+    if wallet.quorum.is_none() {
+        return Err(BackupError::QuorumRequired);
+    }
+    if recovery_keys.is_empty() {
+        return Err(BackupError::NoRecipients);
+    }
+
+    let parts: Vec<BackupPart> = match threshold {
+        Some(threshold) => slip39::split(secret_key.expose(), threshold, recovery_keys.len())?
+            .into_iter()
+            .map(BackupPart::Slip39)
+            .collect(),
+        None => recovery_keys
+            .iter()
+            .map(|_| BackupPart::Secret(secret_key.expose().to_vec()))
+            .collect(),
+    };
+
+    let mut backup_id = [0u8; 16];
+    getrandom::getrandom(&mut backup_id).map_err(|e| BackupError::Randomness(e.to_string()))?;
+
+    let mut shares = Vec::with_capacity(recovery_keys.len());
+    for (recipient, part) in recovery_keys.iter().zip(parts) {
+        let backup_secret = BackupSecret {
+            backup_id,
+            part,
+            policy: wallet.policy.clone(),
+            quorum: wallet.quorum.clone(),
+            origin: wallet.origin,
+        };
+        let plaintext = Zeroizing::new(
+            serde_cbor::to_vec(&backup_secret).expect("backup secret is serializable"),
+        );
+        shares.push(BackupShare {
+            recipient: *recipient,
+            sealed_secret: sealed::seal(recipient, BACKUP_SHARE_INFO, &plaintext)?,
+        });
+    }
+
+    return Ok(WalletBackup {
+        backup_id,
+        threshold,
+        shares,
+    });
+}
+
+/// Recovers the wallet secret from opened shares. On success every share
+/// carries the same policy, quorum and origin, which the restored wallet keeps.
+pub fn restore(shares: &[BackupSecret]) -> Result<Secret<[u8; 64]>, BackupError> {
+    let Some(first) = shares.first() else {
+        return Err(BackupError::NotEnoughShares {
+            threshold: 1,
+            shares: 0,
+        });
+    };
+    let consistent = shares.iter().all(|share| {
+        share.backup_id == first.backup_id
+            && share.policy == first.policy
+            && share.quorum == first.quorum
+            && share.origin == first.origin
+    });
+    if !consistent {
+        return Err(BackupError::MismatchedShares);
+    }
+    if first.quorum.is_none() {
+        return Err(BackupError::QuorumRequired);
+    }
+
+    if let BackupPart::Secret(secret) = &first.part {
+        return Secret::<[u8; 64]>::from_slice(secret).ok_or(BackupError::InvalidShare(
+            "secret is not 64 bytes".to_string(),
+        ));
+    }
+    let mut slip39_shares = Vec::with_capacity(shares.len());
+    for share in shares {
+        let BackupPart::Slip39(slip39_share) = &share.part else {
+            return Err(BackupError::MismatchedShares);
+        };
+        slip39_shares.push(slip39_share.clone());
+    }
+
+    let secret = slip39::combine(&slip39_shares)?;
+    return Secret::<[u8; 64]>::from_slice(secret.expose()).ok_or(BackupError::InvalidShare(
+        "secret is not 64 bytes".to_string(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approval;
+    use shared::backup::open_share;
+    use shared::transport::WalletOrigin;
+    use x25519_dalek::{PublicKey, StaticSecret};
+
+    fn recovery_keys(count: u8) -> Vec<StaticSecret> {
+        return (1..=count).map(|i| StaticSecret::from([i; 32])).collect();
+    }
+
+    fn public_keys(keys: &[StaticSecret]) -> Vec<[u8; 32]> {
+        return keys
+            .iter()
+            .map(|key| PublicKey::from(key).to_bytes())
+            .collect();
+    }
+
+    fn wallet() -> VsockEnclaveCreateWalletData {
+        return VsockEnclaveCreateWalletData {
+            aes_gcm_nonce: [4u8; 12],
+            encrypted_secret_key: vec![],
+            kms_ciphertext: vec![2u8; 16],
+            kms_key_id: "key".to_string(),
+            policy: None,
+            quorum: Some(approval::tests::quorum(2)),
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+    }
+
+    #[test]
+    fn test_whole_secret_backup_round_trip() {
+        let keys = recovery_keys(2);
+        let secret_key = Secret::<[u8; 64]>::from_slice(&[9u8; 64]).unwrap();
+        let backup = export(&wallet(), &secret_key, &public_keys(&keys), None).unwrap();
+        assert_eq!(backup.shares.len(), 2);
+
+        // each recovery key opens only its own share, which restores alone
+        for (key, share) in keys.iter().zip(&backup.shares) {
+            let opened = open_share(key, share).unwrap();
+            assert_eq!(opened.quorum, wallet().quorum);
+            assert_eq!(restore(&[opened]).unwrap().expose(), &[9u8; 64]);
+        }
+        assert!(open_share(&keys[0], &backup.shares[1]).is_err());
+    }
+
+    #[test]
+    fn test_slip39_backup_needs_threshold_shares() {
+        let keys = recovery_keys(3);
+        let secret_key = Secret::<[u8; 64]>::from_slice(&[9u8; 64]).unwrap();
+        let backup = export(&wallet(), &secret_key, &public_keys(&keys), Some(2)).unwrap();
+        let opened: Vec<BackupSecret> = keys
+            .iter()
+            .zip(&backup.shares)
+            .map(|(key, share)| open_share(key, share).unwrap())
+            .collect();
+
+        assert!(matches!(
+            restore(&opened[..1]),
+            Err(BackupError::NotEnoughShares {
+                threshold: 2,
+                shares: 1
+            })
+        ));
+        assert_eq!(restore(&opened[1..]).unwrap().expose(), &[9u8; 64]);
+
+        // shares of another export of the same wallet do not mix
+        let other = export(&wallet(), &secret_key, &public_keys(&keys), Some(2)).unwrap();
+        let mixed = [
+            opened[0].clone(),
+            open_share(&keys[1], &other.shares[1]).unwrap(),
+        ];
+        assert!(matches!(
+            restore(&mixed),
+            Err(BackupError::MismatchedShares)
+        ));
+
+        // nor can a share drop the quorum
+        let mut stripped = opened[1].clone();
+        stripped.quorum = None;
+        let stripped = [opened[0].clone(), stripped];
+        assert!(matches!(
+            restore(&stripped),
+            Err(BackupError::MismatchedShares)
+        ));
+    }
+
+    #[test]
+    fn test_export_requires_quorum_and_recipients() {
+        let secret_key = Secret::<[u8; 64]>::from_slice(&[9u8; 64]).unwrap();
+        let mut no_quorum = wallet();
+        no_quorum.quorum = None;
+        let keys = public_keys(&recovery_keys(2));
+        assert!(matches!(
+            export(&no_quorum, &secret_key, &keys, None),
+            Err(BackupError::QuorumRequired)
+        ));
+        assert!(matches!(
+            export(&wallet(), &secret_key, &[], None),
+            Err(BackupError::NoRecipients)
+        ));
+        assert!(matches!(
+            export(&wallet(), &secret_key, &keys, Some(3)),
+            Err(BackupError::InvalidThreshold { .. })
+        ));
+    }
+}
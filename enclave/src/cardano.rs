@@ -0,0 +1,716 @@
+use bip32::DerivationPath;
+use cryptoxide::{hmac::Hmac, mac::Mac, pbkdf2::pbkdf2, sha2::Sha512};
+use minicbor::{Decoder, Encoder, data::Type};
+use pallas_crypto::hash::Hasher;
+use pallas_crypto::key::ed25519::{PublicKey, SecretKeyExtended, Signature};
+use shared::error::CardanoError;
+use shared::policy::{Chain, SignatureSchemeKind};
+use shared::transport::{VsockEnclaveSignCardanoMessageData, VsockEnclaveSignCardanoTxData};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::policy::{SigningIntent, Transfer};
+
+const HARDENED_OFFSET: u32 = 1 << 31;
+const ICARUS_PBKDF2_ITERATIONS: u32 = 4096;
+
+/// BIP32-Ed25519 extended private key (the "V2" derivation scheme used by CIP-1852 wallets).
+pub struct CardanoXPrv {
+    extended: [u8; 64],
+    chain_code: [u8; 32],
+}
+
+impl CardanoXPrv {
+    /// Icarus master key generation (CIP-3) with an empty passphrase, using the
+    /// wallet secret as the entropy.
+    pub fn from_entropy(entropy: &[u8]) -> Self {
+        let mut output = Zeroizing::new([0u8; 96]);
+        let mut mac = Hmac::new(Sha512::new(), &[]);
+        pbkdf2(&mut mac, entropy, ICARUS_PBKDF2_ITERATIONS, &mut *output);
+
+        output[0] &= 0b1111_1000;
+        output[31] &= 0b0001_1111;
+        output[31] |= 0b0100_0000;
+
+        // filled in place, so the key only exists where it is wiped on drop
+        let mut key = Self {
+            extended: [0u8; 64],
+            chain_code: [0u8; 32],
+        };
+        key.extended.copy_from_slice(&output[..64]);
+        key.chain_code.copy_from_slice(&output[64..]);
+        return key;
+    }
+
+    pub fn derive_path(&self, path: &str) -> Result<Self, CardanoError> {
+        let path: DerivationPath = path
+            .parse()
+            .map_err(|_| CardanoError::InvalidDerivationPath(path.to_string()))?;
+
+        let mut key = Self {
+            extended: self.extended,
+            chain_code: self.chain_code,
+        };
+        for child_number in path.iter() {
+            key = key.derive(child_number.into())?;
+        }
+        return Ok(key);
+    }
+
+    pub fn derive(&self, index: u32) -> Result<Self, CardanoError> {
+        let index_bytes = index.to_le_bytes();
+        let mut z_mac = Hmac::new(Sha512::new(), &self.chain_code);
+        let mut i_mac = Hmac::new(Sha512::new(), &self.chain_code);
+
+        if index >= HARDENED_OFFSET {
+            z_mac.input(&[0x00]);
+            z_mac.input(&self.extended);
+            i_mac.input(&[0x01]);
+            i_mac.input(&self.extended);
+        } else {
+            let public_key = self.public_key()?;
+            z_mac.input(&[0x02]);
+            z_mac.input(public_key.as_ref());
+            i_mac.input(&[0x03]);
+            i_mac.input(public_key.as_ref());
+        }
+        z_mac.input(&index_bytes);
+        i_mac.input(&index_bytes);
+
+        let mut z = Zeroizing::new([0u8; 64]);
+        let mut i = Zeroizing::new([0u8; 64]);
+        z_mac.raw_result(&mut *z);
+        i_mac.raw_result(&mut *i);
+
+        let mut key = Self {
+            extended: [0u8; 64],
+            chain_code: [0u8; 32],
+        };
+        add_28_mul8(&self.extended[..32], &z[..28], &mut key.extended[..32]);
+        add_256bits(&self.extended[32..], &z[32..], &mut key.extended[32..]);
+        key.chain_code.copy_from_slice(&i[32..]);
+        return Ok(key);
+    }
+
+    pub fn public_key(&self) -> Result<PublicKey, CardanoError> {
+        return Ok(self.secret_key()?.public_key());
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Result<Signature, CardanoError> {
+        return Ok(self.secret_key()?.sign(message));
+    }
+
+    fn secret_key(&self) -> Result<SecretKeyExtended, CardanoError> {
+        return SecretKeyExtended::from_bytes(self.extended)
+            .map_err(|_| CardanoError::InvalidExtendedKey);
+    }
+}
+
+impl Drop for CardanoXPrv {
+    fn drop(&mut self) {
+        self.extended.zeroize();
+        self.chain_code.zeroize();
+    }
+}
+
+/// kL' = kL + 8 * zL (zL being the first 28 bytes of Z), little endian.
+fn add_28_mul8(x: &[u8], y: &[u8], out: &mut [u8]) {
+    let mut carry: u16 = 0;
+    for i in 0..28 {
+        let r = x[i] as u16 + ((y[i] as u16) << 3) + carry;
+        out[i] = r as u8;
+        carry = r >> 8;
+    }
+    for i in 28..32 {
+        let r = x[i] as u16 + carry;
+        out[i] = r as u8;
+        carry = r >> 8;
+    }
+}
+
+/// kR' = kR + zR mod 2^256, little endian.
+fn add_256bits(x: &[u8], y: &[u8], out: &mut [u8]) {
+    let mut carry: u16 = 0;
+    for i in 0..32 {
+        let r = x[i] as u16 + y[i] as u16 + carry;
+        out[i] = r as u8;
+        carry = r >> 8;
+    }
+}
+
+/// Hashes the transaction body exactly as it was encoded in `tx_cbor`, so that
+/// re-encoding can never change what is being signed.
+pub fn tx_body_hash(tx_cbor: &[u8]) -> Result<[u8; 32], CardanoError> {
+    let invalid = |e: minicbor::decode::Error| CardanoError::InvalidTransaction(e.to_string());
+    let mut decoder = Decoder::new(tx_cbor);
+
+    decoder.array().map_err(invalid)?;
+    let start = decoder.position();
+    match decoder.datatype().map_err(invalid)? {
+        Type::Map | Type::MapIndef => {}
+        other => {
+            return Err(CardanoError::InvalidTransaction(format!(
+                "expected transaction body map, found {}",
+                other
+            )));
+        }
+    }
+    decoder.skip().map_err(invalid)?;
+    let end = decoder.position();
+
+    return Ok(*Hasher::<256>::hash(&tx_cbor[start..end]));
+}
+
+/// Transaction body fields whose whole effect is the lovelace in the outputs
+/// and the fee: inputs, outputs, fee, ttl, auxiliary data hash, validity start
+/// and network id. Anything else, e.g. certificates, withdrawals, minting,
+/// collateral or governance actions, makes a transaction opaque.
+const PLAIN_BODY_KEYS: [u64; 7] = [0, 1, 2, 3, 7, 8, 15];
+
+/// The fee and the lovelace paid to each output of the transaction body, for
+/// policy checks. Outputs back to an address whose credentials are all keys at
+/// `derivation_paths` are change and left out. Transactions that move more
+/// than lovelace are opaque.
+pub fn signing_intent(
+    secret_key: &[u8; 64],
+    tx_cbor: &[u8],
+    derivation_paths: &[String],
+) -> Result<SigningIntent, CardanoError> {
+    let schemes = vec![SignatureSchemeKind::Ed25519];
+    let Some((outputs, fee)) = decode_plain_body(tx_cbor)? else {
+        return Ok(SigningIntent::opaque(schemes));
+    };
+
+    let root = CardanoXPrv::from_entropy(secret_key);
+    let key_hashes = derivation_paths
+        .iter()
+        .map(|path| {
+            let public_key = root.derive_path(path)?.public_key()?;
+            return Ok(*Hasher::<224>::hash(public_key.as_ref()));
+        })
+        .collect::<Result<Vec<_>, CardanoError>>()?;
+
+    let mut transfers = vec![Transfer {
+        destination: None,
+        value: fee as u128,
+    }];
+    for output in outputs {
+        let is_change = output
+            .destination
+            .as_deref()
+            .is_some_and(|address| is_own_address(address, &key_hashes));
+        if !is_change {
+            transfers.push(output);
+        }
+    }
+    return Ok(SigningIntent::transaction(
+        schemes,
+        Chain::Cardano,
+        transfers,
+    ));
+}
+
+/// The outputs and fee of the transaction body, `None` if it has fields outside
+/// [`PLAIN_BODY_KEYS`] or outputs carrying native assets.
+fn decode_plain_body(tx_cbor: &[u8]) -> Result<Option<(Vec<Transfer>, u64)>, CardanoError> {
+    let invalid = |e: minicbor::decode::Error| CardanoError::InvalidTransaction(e.to_string());
+    let mut decoder = Decoder::new(tx_cbor);
+    let mut transfers = Vec::new();
+    let mut fee = 0;
+    let mut plain = true;
+
+    decoder.array().map_err(invalid)?;
+    let body_len = decoder.map().map_err(invalid)?;
+    decode_items(&mut decoder, body_len, |decoder| {
+        let key = decoder.u64()?;
+        if !PLAIN_BODY_KEYS.contains(&key) {
+            plain = false;
+        }
+        match key {
+            1 => {
+                let outputs_len = decoder.array()?;
+                return decode_items(decoder, outputs_len, |decoder| {
+                    match decode_output(decoder)? {
+                        Some(transfer) => transfers.push(transfer),
+                        None => plain = false,
+                    }
+                    return Ok(());
+                });
+            }
+            2 => fee = decoder.u64()?,
+            _ => decoder.skip()?,
+        }
+        return Ok(());
+    })
+    .map_err(invalid)?;
+
+    return Ok(plain.then_some((transfers, fee)));
+}
+
+/// Base addresses with two key credentials (header type 0) and enterprise
+/// addresses with a key credential (type 6), every credential one of ours.
+fn is_own_address(address: &[u8], key_hashes: &[[u8; 28]]) -> bool {
+    let credentials = match (address.first().map(|header| header >> 4), address.len()) {
+        (Some(0), 57) => vec![&address[1..29], &address[29..57]],
+        (Some(6), 29) => vec![&address[1..29]],
+        _ => return false,
+    };
+    return credentials
+        .into_iter()
+        .all(|credential| key_hashes.iter().any(|key_hash| key_hash == credential));
+}
+
+/// Legacy `[address, amount, ? datum_hash]` or post-Alonzo `{0: address, 1: amount, ...}`.
+/// `None` when the amount carries native assets.
+fn decode_output(decoder: &mut Decoder) -> Result<Option<Transfer>, minicbor::decode::Error> {
+    let mut destination = Vec::new();
+    let mut value = None;
+    match decoder.datatype()? {
+        Type::Map | Type::MapIndef => {
+            let len = decoder.map()?;
+            decode_items(decoder, len, |decoder| {
+                match decoder.u64()? {
+                    0 => destination = decoder.bytes()?.to_vec(),
+                    1 => value = decode_coin(decoder)?,
+                    _ => decoder.skip()?,
+                }
+                return Ok(());
+            })?;
+        }
+        _ => {
+            let len = decoder.array()?;
+            let mut index = 0;
+            decode_items(decoder, len, |decoder| {
+                match index {
+                    0 => destination = decoder.bytes()?.to_vec(),
+                    1 => value = decode_coin(decoder)?,
+                    _ => decoder.skip()?,
+                }
+                index += 1;
+                return Ok(());
+            })?;
+        }
+    }
+    return Ok(value.map(|value| Transfer {
+        destination: Some(destination),
+        value: value as u128,
+    }));
+}
+
+/// value = coin / [coin, multiasset<uint>], `None` for the latter.
+fn decode_coin(decoder: &mut Decoder) -> Result<Option<u64>, minicbor::decode::Error> {
+    match decoder.datatype()? {
+        Type::Array | Type::ArrayIndef => {
+            decoder.skip()?;
+            return Ok(None);
+        }
+        _ => return Ok(Some(decoder.u64()?)),
+    }
+}
+
+/// Calls `f` for each element of a definite or indefinite length array or map.
+fn decode_items(
+    decoder: &mut Decoder,
+    len: Option<u64>,
+    mut f: impl FnMut(&mut Decoder) -> Result<(), minicbor::decode::Error>,
+) -> Result<(), minicbor::decode::Error> {
+    match len {
+        Some(len) => {
+            for _ in 0..len {
+                f(decoder)?;
+            }
+        }
+        None => {
+            while decoder.datatype()? != Type::Break {
+                f(decoder)?;
+            }
+            decoder.set_position(decoder.position() + 1);
+        }
+    }
+    return Ok(());
+}
+
+pub fn sign_transaction(
+    secret_key: &[u8; 64],
+    tx_cbor: &[u8],
+    derivation_paths: &[String],
+) -> Result<VsockEnclaveSignCardanoTxData, CardanoError> {
+    let tx_body_hash = tx_body_hash(tx_cbor)?;
+    let root = CardanoXPrv::from_entropy(secret_key);
+
+    let mut witnesses: Vec<(PublicKey, Signature)> = Vec::with_capacity(derivation_paths.len());
+    for path in derivation_paths {
+        let key = root.derive_path(path)?;
+        let public_key = key.public_key()?;
+        if witnesses.iter().any(|(vkey, _)| vkey == &public_key) {
+            continue;
+        }
+        witnesses.push((public_key, key.sign(&tx_body_hash)?));
+    }
+
+    return Ok(VsockEnclaveSignCardanoTxData {
+        tx_body_hash,
+        witness_set: encode_vkey_witness_set(&witnesses),
+    });
+}
+
+/// transaction_witness_set = { ? 0: [* vkeywitness] }, vkeywitness = [vkey, signature]
+fn encode_vkey_witness_set(witnesses: &[(PublicKey, Signature)]) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new());
+    // writing into a Vec<u8> is infallible
+    encoder.map(1).unwrap().u8(0).unwrap();
+    encoder.array(witnesses.len() as u64).unwrap();
+    for (vkey, signature) in witnesses {
+        encoder
+            .array(2)
+            .unwrap()
+            .bytes(vkey.as_ref())
+            .unwrap()
+            .bytes(signature.as_ref())
+            .unwrap();
+    }
+    return encoder.into_writer();
+}
+
+/// Shelley address header types 0..=7 carry the payment credential in bytes
+/// 1..29, base addresses (0..=3) also carry a stake credential in bytes 29..57
+/// and reward addresses (14, 15) carry only a stake credential.
+fn address_has_key_hash(address: &[u8], key_hash: &[u8; 28]) -> bool {
+    let header_type = match address.first() {
+        Some(header) => header >> 4,
+        None => return false,
+    };
+    let payment_or_reward = match header_type {
+        0..=7 | 14 | 15 => address.get(1..29),
+        _ => None,
+    };
+    let stake = match header_type {
+        0..=3 => address.get(29..57),
+        _ => None,
+    };
+    return [payment_or_reward, stake]
+        .into_iter()
+        .flatten()
+        .any(|credential| credential == key_hash);
+}
+
+/// CIP-8 `signData`: builds the COSE_Sign1 protected headers inside the enclave
+/// and signs `payload` with the key at `derivation_path`, which must be one of
+/// the credentials of `address`.
+pub fn sign_data(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+    address: &[u8],
+    payload: &[u8],
+) -> Result<VsockEnclaveSignCardanoMessageData, CardanoError> {
+    let key = CardanoXPrv::from_entropy(secret_key).derive_path(derivation_path)?;
+    let public_key = key.public_key()?;
+    let key_hash = *Hasher::<224>::hash(public_key.as_ref());
+    if !address_has_key_hash(address, &key_hash) {
+        return Err(CardanoError::AddressMismatch);
+    }
+
+    // protected = { 1: -8 (EdDSA), "address": address }
+    let mut protected = Encoder::new(Vec::new());
+    protected.map(2).unwrap();
+    protected.u8(1).unwrap().i8(-8).unwrap();
+    protected.str("address").unwrap().bytes(address).unwrap();
+    let protected = protected.into_writer();
+
+    // Sig_structure = ["Signature1", protected, external_aad, payload]
+    let mut sig_structure = Encoder::new(Vec::new());
+    sig_structure.array(4).unwrap();
+    sig_structure.str("Signature1").unwrap();
+    sig_structure.bytes(&protected).unwrap();
+    sig_structure.bytes(&[]).unwrap();
+    sig_structure.bytes(payload).unwrap();
+    let signature = key.sign(&sig_structure.into_writer())?;
+
+    // COSE_Sign1 = [protected, { "hashed": false }, payload, signature]
+    let mut cose_sign1 = Encoder::new(Vec::new());
+    cose_sign1.array(4).unwrap();
+    cose_sign1.bytes(&protected).unwrap();
+    cose_sign1
+        .map(1)
+        .unwrap()
+        .str("hashed")
+        .unwrap()
+        .bool(false)
+        .unwrap();
+    cose_sign1.bytes(payload).unwrap();
+    cose_sign1.bytes(signature.as_ref()).unwrap();
+
+    // COSE_Key = { 1: 1 (OKP), 3: -8 (EdDSA), -1: 6 (Ed25519), -2: public key }
+    let mut cose_key = Encoder::new(Vec::new());
+    cose_key.map(4).unwrap();
+    cose_key.u8(1).unwrap().u8(1).unwrap();
+    cose_key.u8(3).unwrap().i8(-8).unwrap();
+    cose_key.i8(-1).unwrap().u8(6).unwrap();
+    cose_key.i8(-2).unwrap().bytes(public_key.as_ref()).unwrap();
+
+    return Ok(VsockEnclaveSignCardanoMessageData {
+        cose_sign1: cose_sign1.into_writer(),
+        cose_key: cose_key.into_writer(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // [ {0: [], 2: 0}, {}, true, null ] with the body map deliberately using a
+    // non-canonical (indefinite length) encoding.
+    const TX_CBOR: [u8; 10] = [0x84, 0xbf, 0x00, 0x80, 0x02, 0x00, 0xff, 0xa0, 0xf5, 0xf6];
+
+    #[test]
+    fn test_tx_body_hash_uses_original_body_bytes() {
+        let hash = tx_body_hash(&TX_CBOR).unwrap();
+        assert_eq!(hash, *Hasher::<256>::hash(&TX_CBOR[1..7]));
+    }
+
+    const PAYMENT_PATH: &str = "m/1852'/1815'/0'/0/0";
+    const STAKE_PATH: &str = "m/1852'/1815'/0'/2/0";
+
+    fn key_hash(secret_key: &[u8; 64], path: &str) -> [u8; 28] {
+        let root = CardanoXPrv::from_entropy(secret_key);
+        let public_key = root.derive_path(path).unwrap().public_key().unwrap();
+        return *Hasher::<224>::hash(public_key.as_ref());
+    }
+
+    /// `[{0: [], 1: outputs, 2: 170000, ? extra_key: []}, {}, true, null]`,
+    /// alternating legacy and map outputs, the last one carrying an empty
+    /// multi-asset map when `multiasset`.
+    fn build_tx(outputs: &[(Vec<u8>, u64)], multiasset: bool, extra_key: Option<u64>) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.array(4).unwrap();
+        encoder.map(3 + extra_key.is_some() as u64).unwrap();
+        encoder.u8(0).unwrap().array(0).unwrap();
+        encoder.u8(1).unwrap().array(outputs.len() as u64).unwrap();
+        for (index, (address, coin)) in outputs.iter().enumerate() {
+            match index % 2 {
+                0 => encoder.array(2).unwrap().bytes(address).unwrap(),
+                _ => encoder
+                    .map(2)
+                    .unwrap()
+                    .u8(0)
+                    .unwrap()
+                    .bytes(address)
+                    .unwrap()
+                    .u8(1)
+                    .unwrap(),
+            };
+            if multiasset && index == outputs.len() - 1 {
+                encoder
+                    .array(2)
+                    .unwrap()
+                    .u64(*coin)
+                    .unwrap()
+                    .map(0)
+                    .unwrap();
+            } else {
+                encoder.u64(*coin).unwrap();
+            }
+        }
+        encoder.u8(2).unwrap().u64(170_000).unwrap();
+        if let Some(key) = extra_key {
+            encoder.u64(key).unwrap().array(0).unwrap();
+        }
+        encoder.map(0).unwrap().bool(true).unwrap().null().unwrap();
+        return encoder.into_writer();
+    }
+
+    #[test]
+    fn test_signing_intent_counts_fee_and_skips_change() {
+        let secret_key = [9u8; 64];
+        let payment = key_hash(&secret_key, PAYMENT_PATH);
+        let stake = key_hash(&secret_key, STAKE_PATH);
+        let foreign = vec![0x61; 29];
+        let own_enterprise = [&[0x61][..], &payment].concat();
+        let own_base = [&[0x01][..], &payment, &stake].concat();
+        // our payment key, but someone else's stake key
+        let foreign_stake = [&[0x01][..], &payment, &[7u8; 28]].concat();
+        let tx_cbor = build_tx(
+            &[
+                (foreign.clone(), 5),
+                (own_enterprise, 7),
+                (own_base, 11),
+                (foreign_stake.clone(), 13),
+            ],
+            false,
+            None,
+        );
+        let paths = vec![PAYMENT_PATH.to_string(), STAKE_PATH.to_string()];
+
+        let intent = signing_intent(&secret_key, &tx_cbor, &paths).unwrap();
+        let (chain, transfers) = intent.transaction.unwrap();
+        assert_eq!(chain, Chain::Cardano);
+        assert_eq!(
+            transfers,
+            vec![
+                Transfer {
+                    destination: None,
+                    value: 170_000,
+                },
+                Transfer {
+                    destination: Some(foreign),
+                    value: 5,
+                },
+                Transfer {
+                    destination: Some(foreign_stake),
+                    value: 13,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_signing_intent_is_opaque_beyond_lovelace() {
+        let secret_key = [9u8; 64];
+        let paths = vec![PAYMENT_PATH.to_string()];
+        let outputs = [(vec![0x61; 29], 5), (vec![0x61; 29], 7)];
+
+        let plain = build_tx(&outputs, false, None);
+        assert!(
+            signing_intent(&secret_key, &plain, &paths)
+                .unwrap()
+                .transaction
+                .is_some()
+        );
+
+        let multiasset = build_tx(&outputs, true, None);
+        assert!(
+            signing_intent(&secret_key, &multiasset, &paths)
+                .unwrap()
+                .transaction
+                .is_none()
+        );
+        // certificates, withdrawals, mint and collateral
+        for key in [4, 5, 9, 13] {
+            let tx_cbor = build_tx(&outputs, false, Some(key));
+            assert!(
+                signing_intent(&secret_key, &tx_cbor, &paths)
+                    .unwrap()
+                    .transaction
+                    .is_none()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tx_body_hash_rejects_non_map_body() {
+        let result = tx_body_hash(&[0x84, 0x01, 0xa0, 0xf5, 0xf6]);
+        assert!(matches!(result, Err(CardanoError::InvalidTransaction(_))));
+    }
+
+    #[test]
+    fn test_derive_path_hardened_and_soft() {
+        let root = CardanoXPrv::from_entropy(&[7u8; 64]);
+        let account = root.derive_path("m/1852'/1815'/0'").unwrap();
+        let payment = root.derive_path("m/1852'/1815'/0'/0/0").unwrap();
+        let payment_from_account = account.derive(0).unwrap().derive(0).unwrap();
+
+        assert_eq!(
+            payment.public_key().unwrap(),
+            payment_from_account.public_key().unwrap()
+        );
+        assert_ne!(
+            payment.public_key().unwrap(),
+            root.derive_path("m/1852'/1815'/0'/2/0")
+                .unwrap()
+                .public_key()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_derive_path_rejects_garbage() {
+        let root = CardanoXPrv::from_entropy(&[7u8; 64]);
+        assert!(matches!(
+            root.derive_path("m/not/a/path"),
+            Err(CardanoError::InvalidDerivationPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_sign_transaction_produces_valid_witnesses() {
+        let secret_key = [9u8; 64];
+        let paths = vec![
+            "m/1852'/1815'/0'/0/0".to_string(),
+            "m/1852'/1815'/0'/2/0".to_string(),
+            "m/1852'/1815'/0'/0/0".to_string(),
+        ];
+        let result = sign_transaction(&secret_key, &TX_CBOR, &paths).unwrap();
+
+        let mut decoder = Decoder::new(&result.witness_set);
+        assert_eq!(decoder.map().unwrap(), Some(1));
+        assert_eq!(decoder.u8().unwrap(), 0);
+        assert_eq!(decoder.array().unwrap(), Some(2));
+
+        let root = CardanoXPrv::from_entropy(&secret_key);
+        for path in &paths[..2] {
+            assert_eq!(decoder.array().unwrap(), Some(2));
+            let vkey = PublicKey::try_from(decoder.bytes().unwrap()).unwrap();
+            let signature = Signature::try_from(decoder.bytes().unwrap()).unwrap();
+            assert_eq!(vkey, root.derive_path(path).unwrap().public_key().unwrap());
+            assert!(vkey.verify(result.tx_body_hash, &signature));
+        }
+    }
+
+    fn enterprise_address(secret_key: &[u8; 64], path: &str) -> Vec<u8> {
+        let root = CardanoXPrv::from_entropy(secret_key);
+        let public_key = root.derive_path(path).unwrap().public_key().unwrap();
+        let mut address = vec![0x61];
+        address.extend_from_slice(&*Hasher::<224>::hash(public_key.as_ref()));
+        return address;
+    }
+
+    #[test]
+    fn test_sign_data_produces_verifiable_cose_sign1() {
+        let secret_key = [3u8; 64];
+        let path = "m/1852'/1815'/0'/0/0";
+        let address = enterprise_address(&secret_key, path);
+        let payload = b"hello trustvault";
+
+        let result = sign_data(&secret_key, path, &address, payload).unwrap();
+
+        let mut decoder = Decoder::new(&result.cose_key);
+        assert_eq!(decoder.map().unwrap(), Some(4));
+        for _ in 0..3 {
+            decoder.skip().unwrap();
+            decoder.skip().unwrap();
+        }
+        assert_eq!(decoder.i8().unwrap(), -2);
+        let public_key = PublicKey::try_from(decoder.bytes().unwrap()).unwrap();
+
+        let mut decoder = Decoder::new(&result.cose_sign1);
+        assert_eq!(decoder.array().unwrap(), Some(4));
+        let protected = decoder.bytes().unwrap().to_vec();
+        decoder.skip().unwrap();
+        assert_eq!(decoder.bytes().unwrap(), payload);
+        let signature = Signature::try_from(decoder.bytes().unwrap()).unwrap();
+
+        let mut protected_decoder = Decoder::new(&protected);
+        assert_eq!(protected_decoder.map().unwrap(), Some(2));
+        assert_eq!(protected_decoder.u8().unwrap(), 1);
+        assert_eq!(protected_decoder.i8().unwrap(), -8);
+        assert_eq!(protected_decoder.str().unwrap(), "address");
+        assert_eq!(protected_decoder.bytes().unwrap(), address.as_slice());
+
+        let mut sig_structure = Encoder::new(Vec::new());
+        sig_structure.array(4).unwrap();
+        sig_structure.str("Signature1").unwrap();
+        sig_structure.bytes(&protected).unwrap();
+        sig_structure.bytes(&[]).unwrap();
+        sig_structure.bytes(payload).unwrap();
+        assert!(public_key.verify(sig_structure.into_writer(), &signature));
+    }
+
+    #[test]
+    fn test_sign_data_rejects_foreign_address() {
+        let secret_key = [3u8; 64];
+        let address = enterprise_address(&[4u8; 64], "m/1852'/1815'/0'/0/0");
+
+        let result = sign_data(&secret_key, "m/1852'/1815'/0'/0/0", &address, b"payload");
+
+        assert!(matches!(result, Err(CardanoError::AddressMismatch)));
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use shared::error::{
+    VsockChannelError, VsockEnclaveCreateWalletError, VsockEnclaveSignError, VsockSendError,
+};
+use shared::transport::{
+    NOISE_PARAMS, VsockEnclaveCreateWalletResponse, VsockHostRequest, VsockTransport,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::warn;
+
+use crate::nsm;
+use crate::secret::SecretVec;
+
+pub type SharedOperatorChannel = Arc<OperatorChannel>;
+
+/// The enclave's side of the Noise channel operators use to reach it.
+///
+/// The static key is generated at boot and never leaves the enclave. Every
+/// handshake carries an attestation document with it as the public key, so
+/// operators can tell they are talking to the attested image and not the host.
+pub struct OperatorChannel {
+    private_key: SecretVec,
+    attestation: Vec<u8>,
+    operator_keys: Vec<[u8; 32]>,
+}
+
+impl OperatorChannel {
+    /// With no `operator_keys`, requests are accepted with or without a handshake.
+    pub fn new(operator_keys: Vec<[u8; 32]>) -> Result<Self, snow::Error> {
+        let keypair = snow::Builder::new(NOISE_PARAMS.parse()?).generate_keypair()?;
+        let attestation = match nsm::attestation(None, None, Some(&keypair.public)) {
+            Ok(document) => document,
+            Err(e) => {
+                // outside of a Nitro enclave, e.g. during development
+                warn!(error = %e, "failed to attest channel key");
+                Vec::new()
+            }
+        };
+
+        return Ok(Self {
+            private_key: SecretVec::from_vec(keypair.private),
+            attestation,
+            operator_keys,
+        });
+    }
+
+    pub fn requires_authentication(&self) -> bool {
+        return !self.operator_keys.is_empty();
+    }
+
+    /// Completes a handshake and reports whether the caller holds an operator key.
+    pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        transport: &mut VsockTransport<S>,
+        noise_message: &[u8],
+    ) -> Result<bool, VsockChannelError> {
+        let caller_key = transport
+            .accept_handshake(self.private_key.expose(), noise_message, &self.attestation)
+            .await?;
+        return Ok(self.operator_keys.contains(&caller_key));
+    }
+}
+
+/// Answers `request` with an `Unauthenticated` error of its response type.
+pub async fn reject_unauthenticated<S: AsyncRead + AsyncWrite + Unpin>(
+    transport: &mut VsockTransport<S>,
+    request: &VsockHostRequest,
+) -> Result<(), VsockSendError> {
+    match request {
+        VsockHostRequest::CreateWallet { .. } => {
+            let response: VsockEnclaveCreateWalletResponse =
+                Err(VsockEnclaveCreateWalletError::Unauthenticated);
+            return transport.send(&response).await;
+        }
+        _ => {
+            let response: Result<(), VsockEnclaveSignError> =
+                Err(VsockEnclaveSignError::Unauthenticated);
+            return transport.send(&response).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::transport::{VsockEnclaveEvictCacheResponse, VsockEnclaveSignResponse};
+
+    fn operator_keypair() -> snow::Keypair {
+        return snow::Builder::new(NOISE_PARAMS.parse().unwrap())
+            .generate_keypair()
+            .unwrap();
+    }
+
+    async fn connect(channel: &OperatorChannel, operator_private_key: &[u8]) -> bool {
+        let (host_stream, enclave_stream) = tokio::io::duplex(1024);
+        let mut host = VsockTransport::new(host_stream);
+        let mut enclave = VsockTransport::new(enclave_stream);
+
+        let operator_private_key = operator_private_key.to_vec();
+        let host_task = tokio::spawn(async move {
+            host.initiate_handshake(&operator_private_key)
+                .await
+                .unwrap();
+            host
+        });
+        let VsockHostRequest::Handshake { noise_message } =
+            enclave.receive::<VsockHostRequest>().await.unwrap()
+        else {
+            panic!("expected a handshake");
+        };
+        let is_operator = channel.accept(&mut enclave, &noise_message).await.unwrap();
+        let mut host = host_task.await.unwrap();
+
+        // the channel carries frames larger than a single noise message
+        let request = VsockHostRequest::EvictCache {
+            kms_ciphertext: Some(vec![7u8; 200_000]),
+        };
+        let host_task = tokio::spawn(async move {
+            host.send(&request).await.unwrap();
+            host
+        });
+        let received = enclave.receive::<VsockHostRequest>().await.unwrap();
+        assert!(matches!(
+            &received,
+            VsockHostRequest::EvictCache { kms_ciphertext: Some(bytes) } if bytes.len() == 200_000
+        ));
+        reject_unauthenticated(&mut enclave, &received)
+            .await
+            .unwrap();
+        let mut host = host_task.await.unwrap();
+        let response = host
+            .receive::<VsockEnclaveEvictCacheResponse>()
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            Err(VsockEnclaveSignError::Unauthenticated)
+        ));
+
+        return is_operator;
+    }
+
+    #[tokio::test]
+    async fn test_handshake_identifies_operators() {
+        let operator = operator_keypair();
+        let stranger = operator_keypair();
+        let channel =
+            OperatorChannel::new(vec![operator.public.as_slice().try_into().unwrap()]).unwrap();
+        assert!(channel.requires_authentication());
+
+        assert!(connect(&channel, &operator.private).await);
+        assert!(!connect(&channel, &stranger.private).await);
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_rejection_is_typed() {
+        let (host_stream, enclave_stream) = tokio::io::duplex(1024);
+        let mut host = VsockTransport::new(host_stream);
+        let mut enclave = VsockTransport::new(enclave_stream);
+
+        reject_unauthenticated(
+            &mut enclave,
+            &VsockHostRequest::EvictCache {
+                kms_ciphertext: None,
+            },
+        )
+        .await
+        .unwrap();
+        let response = host.receive::<VsockEnclaveSignResponse>().await.unwrap();
+        assert!(matches!(
+            response,
+            Err(VsockEnclaveSignError::Unauthenticated)
+        ));
+    }
+}
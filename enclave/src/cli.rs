@@ -1,7 +1,74 @@
 use clap::Parser;
+use std::net::SocketAddr;
+use std::str::FromStr;
 
 #[derive(Parser)]
 pub struct Args {
+    #[arg(long, required_unless_present = "tcp_listen")]
+    pub vsock_port: Option<u32>,
+    /// Listen on TCP instead of vsock, to run several enclaves on one machine
+    /// outside Nitro, e.g. to test threshold signing. Never use in production.
+    #[arg(long, conflicts_with = "vsock_port")]
+    pub tcp_listen: Option<SocketAddr>,
+    /// Maximum number of unwrapped data keys kept in memory; 0 disables the cache.
+    #[arg(long, default_value_t = 0)]
+    pub data_key_cache_size: usize,
+    #[arg(long, default_value_t = 300)]
+    pub data_key_cache_ttl_secs: u64,
+    /// Hex encoded Ed25519 key that wallet policies must be signed with. Without
+    /// it, wallets with a policy cannot be created or used.
     #[arg(long)]
-    pub vsock_port: u32,
+    pub policy_admin_public_key: Option<PublicKeyArg>,
+    /// Hex encoded X25519 Noise static key of an operator allowed to send
+    /// requests; may be repeated. Without any, requests need no handshake.
+    #[arg(long)]
+    pub operator_public_key: Vec<PublicKeyArg>,
+    /// `INDEX=HEX` PCR value that peer enclaves' attestation documents must
+    /// contain during a threshold key generation; may be repeated. Without any,
+    /// threshold key generation is refused.
+    #[arg(long)]
+    pub peer_pcr: Vec<PcrArg>,
+    /// Trust peers' sealing keys as the host relays them during a threshold key
+    /// generation, for tests outside Nitro. Never use in production.
+    #[arg(long, requires = "tcp_listen", conflicts_with = "peer_pcr")]
+    pub insecure_skip_peer_attestation: bool,
+    /// Parent instance vsock port to forward logs to, read by the host's
+    /// `--enclave-log-port`. Logs always go to stderr as well.
+    #[arg(long)]
+    pub log_vsock_port: Option<u32>,
+    /// How long requests in progress get to finish once a shutdown is
+    /// requested, by SIGTERM, SIGINT or a `Shutdown` request.
+    #[arg(long, default_value_t = 30)]
+    pub shutdown_grace_secs: u64,
+}
+
+#[derive(Clone)]
+pub struct PublicKeyArg(pub [u8; 32]);
+
+impl FromStr for PublicKeyArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+        let key = bytes
+            .try_into()
+            .map_err(|_| "expected a 32 byte key".to_string())?;
+        return Ok(PublicKeyArg(key));
+    }
+}
+
+#[derive(Clone)]
+pub struct PcrArg(pub usize, pub Vec<u8>);
+
+impl FromStr for PcrArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, value) = s.split_once('=').ok_or("expected INDEX=HEX")?;
+        let index = index
+            .parse()
+            .map_err(|e| format!("invalid pcr index: {}", e))?;
+        let value = hex::decode(value).map_err(|e| format!("invalid pcr value: {}", e))?;
+        return Ok(PcrArg(index, value));
+    }
 }
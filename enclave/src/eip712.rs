@@ -0,0 +1,484 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use cryptoxide::hashing::keccak256;
+use serde::Deserialize;
+use serde_json::Value;
+use shared::error::EthereumError;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TypedData {
+    types: BTreeMap<String, Vec<TypedField>>,
+    primary_type: String,
+    domain: serde_json::Map<String, Value>,
+    message: Value,
+}
+
+#[derive(Deserialize, Clone)]
+struct TypedField {
+    name: String,
+    #[serde(rename = "type")]
+    field_type: String,
+}
+
+/// The EIP712Domain fields in their canonical order, used when the typed data
+/// does not declare the domain type explicitly.
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+fn invalid(message: impl Into<String>) -> EthereumError {
+    return EthereumError::InvalidTypedData(message.into());
+}
+
+/// `eth_signTypedData_v4` digest: keccak256(0x19 0x01 || domainSeparator || hashStruct(message)).
+pub fn hash_typed_data(typed_data_json: &str) -> Result<[u8; 32], EthereumError> {
+    let mut typed_data: TypedData =
+        serde_json::from_str(typed_data_json).map_err(|e| invalid(e.to_string()))?;
+
+    if !typed_data.types.contains_key("EIP712Domain") {
+        let domain_type = DOMAIN_FIELDS
+            .iter()
+            .filter(|(name, _)| typed_data.domain.contains_key(*name))
+            .map(|(name, field_type)| TypedField {
+                name: name.to_string(),
+                field_type: field_type.to_string(),
+            })
+            .collect();
+        typed_data
+            .types
+            .insert("EIP712Domain".to_string(), domain_type);
+    }
+
+    let domain = Value::Object(typed_data.domain.clone());
+    let domain_separator = hash_struct(&typed_data.types, "EIP712Domain", &domain)?;
+    let message_hash = hash_struct(
+        &typed_data.types,
+        &typed_data.primary_type,
+        &typed_data.message,
+    )?;
+
+    let mut digest_input = Vec::with_capacity(66);
+    digest_input.extend_from_slice(&[0x19, 0x01]);
+    digest_input.extend_from_slice(&domain_separator);
+    digest_input.extend_from_slice(&message_hash);
+    return Ok(keccak256(&digest_input));
+}
+
+fn hash_struct(
+    types: &BTreeMap<String, Vec<TypedField>>,
+    type_name: &str,
+    value: &Value,
+) -> Result<[u8; 32], EthereumError> {
+    return Ok(keccak256(&encode_data(types, type_name, value)?));
+}
+
+fn encode_type(
+    types: &BTreeMap<String, Vec<TypedField>>,
+    type_name: &str,
+) -> Result<String, EthereumError> {
+    let mut dependencies = BTreeSet::new();
+    collect_dependencies(types, type_name, &mut dependencies);
+    dependencies.remove(type_name);
+
+    let mut encoded = String::new();
+    for name in std::iter::once(type_name).chain(dependencies.iter().map(String::as_str)) {
+        let fields = types
+            .get(name)
+            .ok_or_else(|| invalid(format!("unknown type {}", name)))?;
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| format!("{} {}", field.field_type, field.name))
+            .collect();
+        encoded.push_str(&format!("{}({})", name, fields.join(",")));
+    }
+    return Ok(encoded);
+}
+
+fn collect_dependencies(
+    types: &BTreeMap<String, Vec<TypedField>>,
+    type_name: &str,
+    found: &mut BTreeSet<String>,
+) {
+    let base_type = base_type(type_name);
+    if found.contains(base_type) {
+        return;
+    }
+    let Some(fields) = types.get(base_type) else {
+        return;
+    };
+    found.insert(base_type.to_string());
+    for field in fields {
+        collect_dependencies(types, &field.field_type, found);
+    }
+}
+
+/// Strips any array suffixes, e.g. `Person[][2]` -> `Person`.
+fn base_type(type_name: &str) -> &str {
+    return type_name.split('[').next().unwrap_or(type_name);
+}
+
+fn encode_data(
+    types: &BTreeMap<String, Vec<TypedField>>,
+    type_name: &str,
+    value: &Value,
+) -> Result<Vec<u8>, EthereumError> {
+    let fields = types
+        .get(type_name)
+        .ok_or_else(|| invalid(format!("unknown type {}", type_name)))?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| invalid(format!("expected object for {}", type_name)))?;
+
+    let mut encoded = keccak256(encode_type(types, type_name)?.as_bytes()).to_vec();
+    for field in fields {
+        let field_value = object.get(&field.name).unwrap_or(&Value::Null);
+        encoded.extend_from_slice(&encode_field(types, &field.field_type, field_value)?);
+    }
+    return Ok(encoded);
+}
+
+fn encode_field(
+    types: &BTreeMap<String, Vec<TypedField>>,
+    field_type: &str,
+    value: &Value,
+) -> Result<[u8; 32], EthereumError> {
+    if let Some(element_type) = field_type
+        .strip_suffix(']')
+        .and_then(|t| t.rfind('[').map(|i| &t[..i]))
+    {
+        let elements = value
+            .as_array()
+            .ok_or_else(|| invalid(format!("expected array for {}", field_type)))?;
+        let mut encoded = Vec::with_capacity(elements.len() * 32);
+        for element in elements {
+            encoded.extend_from_slice(&encode_field(types, element_type, element)?);
+        }
+        return Ok(keccak256(&encoded));
+    }
+
+    if types.contains_key(field_type) {
+        if value.is_null() {
+            return Ok([0u8; 32]);
+        }
+        return hash_struct(types, field_type, value);
+    }
+
+    return match field_type {
+        "string" => {
+            let string = value.as_str().ok_or_else(|| invalid("expected string"))?;
+            Ok(keccak256(string.as_bytes()))
+        }
+        "bytes" => Ok(keccak256(&parse_hex(value)?)),
+        "bool" => {
+            let mut encoded = [0u8; 32];
+            encoded[31] = value.as_bool().ok_or_else(|| invalid("expected bool"))? as u8;
+            Ok(encoded)
+        }
+        "address" => {
+            let address = parse_hex(value)?;
+            if address.len() != 20 {
+                return Err(invalid("address must be 20 bytes"));
+            }
+            let mut encoded = [0u8; 32];
+            encoded[12..].copy_from_slice(&address);
+            Ok(encoded)
+        }
+        _ if field_type.starts_with("bytes") => {
+            let size = match field_type["bytes".len()..].parse::<usize>() {
+                Ok(size) if (1..=32).contains(&size) => size,
+                _ => return Err(invalid(format!("unsupported type {}", field_type))),
+            };
+            let bytes = parse_hex(value)?;
+            if bytes.len() != size {
+                return Err(invalid(format!(
+                    "{} value must be {} bytes, got {}",
+                    field_type,
+                    size,
+                    bytes.len()
+                )));
+            }
+            let mut encoded = [0u8; 32];
+            encoded[..bytes.len()].copy_from_slice(&bytes);
+            Ok(encoded)
+        }
+        _ if field_type.starts_with("uint") => {
+            parse_integer(value, integer_bits(field_type, "uint")?, false)
+        }
+        _ if field_type.starts_with("int") => {
+            parse_integer(value, integer_bits(field_type, "int")?, true)
+        }
+        _ => Err(invalid(format!("unsupported type {}", field_type))),
+    };
+}
+
+/// Width of a `uintN` or `intN` type; a bare `uint` or `int` is 256 bits.
+fn integer_bits(field_type: &str, prefix: &str) -> Result<usize, EthereumError> {
+    let suffix = &field_type[prefix.len()..];
+    if suffix.is_empty() {
+        return Ok(256);
+    }
+    return match suffix.parse::<usize>() {
+        Ok(bits) if bits % 8 == 0 && (8..=256).contains(&bits) => Ok(bits),
+        _ => Err(invalid(format!("unsupported type {}", field_type))),
+    };
+}
+
+fn parse_hex(value: &Value) -> Result<Vec<u8>, EthereumError> {
+    let string = value
+        .as_str()
+        .ok_or_else(|| invalid("expected hex string"))?;
+    let digits = string
+        .strip_prefix("0x")
+        .ok_or_else(|| invalid(format!("expected 0x prefix: {}", string)))?;
+    if digits.len() % 2 != 0 {
+        return Err(invalid(format!("odd length hex: {}", string)));
+    }
+    return (0..digits.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map_err(|_| invalid(format!("invalid hex: {}", string)))
+        })
+        .collect();
+}
+
+/// Parses a JSON number, decimal string or 0x hex string into a big endian
+/// 256 bit word, two's complement for negative signed integers. Values that do
+/// not fit in `bits` are rejected rather than truncated.
+fn parse_integer(value: &Value, bits: usize, signed: bool) -> Result<[u8; 32], EthereumError> {
+    let text = match value {
+        Value::Number(number) => number.to_string(),
+        Value::String(string) => string.clone(),
+        _ => return Err(invalid("expected integer")),
+    };
+    let (negative, magnitude) = match text.strip_prefix('-') {
+        Some(magnitude) if signed => (true, magnitude),
+        Some(_) => return Err(invalid(format!("negative unsigned integer: {}", text))),
+        None => (false, text.as_str()),
+    };
+
+    let mut word = [0u8; 32];
+    if let Some(hex) = magnitude.strip_prefix("0x") {
+        let bytes = parse_hex(&Value::String(format!(
+            "0x{}{}",
+            "0".repeat(hex.len() % 2),
+            hex
+        )))?;
+        if bytes.len() > 32 {
+            return Err(invalid(format!("integer overflow: {}", text)));
+        }
+        word[32 - bytes.len()..].copy_from_slice(&bytes);
+    } else {
+        if magnitude.is_empty() {
+            return Err(invalid("empty integer"));
+        }
+        for digit in magnitude.chars() {
+            let digit = digit
+                .to_digit(10)
+                .ok_or_else(|| invalid(format!("invalid integer: {}", text)))?;
+            let mut carry = digit;
+            for byte in word.iter_mut().rev() {
+                let product = (*byte as u32) * 10 + carry;
+                *byte = product as u8;
+                carry = product >> 8;
+            }
+            if carry != 0 {
+                return Err(invalid(format!("integer overflow: {}", text)));
+            }
+        }
+    }
+
+    // a signed type holds magnitudes up to 2^(bits - 1), reached only when
+    // negative, and an unsigned one up to 2^bits - 1
+    let magnitude_bits = bit_length(&word);
+    let fits = match (signed, negative) {
+        (false, _) => magnitude_bits <= bits,
+        (true, false) => magnitude_bits < bits,
+        (true, true) => {
+            magnitude_bits < bits || (magnitude_bits == bits && trailing_zeros(&word) == bits - 1)
+        }
+    };
+    if !fits {
+        return Err(invalid(format!(
+            "integer out of range for {} bits: {}",
+            bits, text
+        )));
+    }
+
+    if negative {
+        let mut carry = 1u16;
+        for byte in word.iter_mut().rev() {
+            let sum = (!*byte) as u16 + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+    }
+    return Ok(word);
+}
+
+fn bit_length(word: &[u8; 32]) -> usize {
+    return match word.iter().position(|byte| *byte != 0) {
+        Some(i) => (32 - i) * 8 - word[i].leading_zeros() as usize,
+        None => 0,
+    };
+}
+
+fn trailing_zeros(word: &[u8; 32]) -> usize {
+    return match word.iter().rposition(|byte| *byte != 0) {
+        Some(i) => (31 - i) * 8 + word[i].trailing_zeros() as usize,
+        None => 256,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // https://eips.ethereum.org/EIPS/eip-712 example
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "version", "type": "string" },
+                { "name": "chainId", "type": "uint256" },
+                { "name": "verifyingContract", "type": "address" }
+            ],
+            "Person": [
+                { "name": "name", "type": "string" },
+                { "name": "wallet", "type": "address" }
+            ],
+            "Mail": [
+                { "name": "from", "type": "Person" },
+                { "name": "to", "type": "Person" },
+                { "name": "contents", "type": "string" }
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+            "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+
+    fn hex(s: &str) -> Vec<u8> {
+        return parse_hex(&Value::String(format!("0x{}", s))).unwrap();
+    }
+
+    #[test]
+    fn test_mail_example() {
+        let typed_data: TypedData = serde_json::from_str(MAIL).unwrap();
+        assert_eq!(
+            encode_type(&typed_data.types, "Mail").unwrap(),
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)"
+        );
+        assert_eq!(
+            hash_struct(
+                &typed_data.types,
+                "EIP712Domain",
+                &Value::Object(typed_data.domain.clone())
+            )
+            .unwrap()
+            .to_vec(),
+            hex("f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f")
+        );
+        assert_eq!(
+            hash_struct(&typed_data.types, "Mail", &typed_data.message)
+                .unwrap()
+                .to_vec(),
+            hex("c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e")
+        );
+        assert_eq!(
+            hash_typed_data(MAIL).unwrap().to_vec(),
+            hex("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+        );
+    }
+
+    #[test]
+    fn test_domain_type_is_inferred() {
+        let without_domain_type = MAIL.replacen(
+            r#""EIP712Domain": [
+                { "name": "name", "type": "string" },
+                { "name": "version", "type": "string" },
+                { "name": "chainId", "type": "uint256" },
+                { "name": "verifyingContract", "type": "address" }
+            ],"#,
+            "",
+            1,
+        );
+        assert_ne!(without_domain_type, MAIL);
+        assert_eq!(
+            hash_typed_data(&without_domain_type).unwrap(),
+            hash_typed_data(MAIL).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_integer() {
+        let mut expected = [0u8; 32];
+        expected[30..].copy_from_slice(&[0x01, 0x00]);
+        assert_eq!(
+            parse_integer(&Value::String("256".into()), 256, false).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_integer(&Value::String("0x100".into()), 256, false).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_integer(&serde_json::json!(-1), 256, true).unwrap(),
+            [0xff; 32]
+        );
+        assert!(parse_integer(&serde_json::json!(-1), 256, false).is_err());
+        assert!(parse_integer(&Value::String("1".repeat(80)), 256, false).is_err());
+    }
+
+    #[test]
+    fn test_integer_range() {
+        let types = BTreeMap::new();
+        let encode = |field_type: &str, value: Value| encode_field(&types, field_type, &value);
+        assert!(encode("uint8", serde_json::json!(255)).is_ok());
+        assert!(encode("uint8", serde_json::json!(256)).is_err());
+        assert!(encode("uint8", Value::String("0x100".into())).is_err());
+        assert!(encode("int8", serde_json::json!(127)).is_ok());
+        assert!(encode("int8", serde_json::json!(128)).is_err());
+        assert_eq!(
+            encode("int8", serde_json::json!(-128)).unwrap(),
+            parse_integer(&serde_json::json!(-128), 256, true).unwrap()
+        );
+        assert!(encode("int8", serde_json::json!(-129)).is_err());
+        assert!(encode("int256", Value::String(format!("0x8{}", "0".repeat(63)))).is_err());
+        assert!(encode("int256", Value::String(format!("-0x8{}", "0".repeat(63)))).is_ok());
+        assert!(encode("uint256", Value::String(format!("0x{}", "f".repeat(64)))).is_ok());
+        assert!(encode("uint", Value::String(format!("0x1{}", "0".repeat(64)))).is_err());
+        assert!(encode("uint7", serde_json::json!(1)).is_err());
+        assert!(encode("int264", serde_json::json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_fixed_bytes_length() {
+        let types = BTreeMap::new();
+        let encode = |field_type: &str, value: &str| {
+            encode_field(&types, field_type, &Value::String(value.into()))
+        };
+        let mut expected = [0u8; 32];
+        expected[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(encode("bytes4", "0xdeadbeef").unwrap(), expected);
+        assert!(encode("bytes4", "0xdead").is_err());
+        assert!(encode("bytes4", "0xdeadbeef00").is_err());
+        assert!(encode("bytes32", &format!("0x{}", "00".repeat(32))).is_ok());
+        assert!(encode("bytes0", "0x").is_err());
+        assert!(encode("bytes33", &format!("0x{}", "00".repeat(33))).is_err());
+    }
+}
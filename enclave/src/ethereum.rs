@@ -0,0 +1,497 @@
+use bip32::{DerivationPath, XPrv};
+use cryptoxide::hashing::keccak256;
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use shared::error::EthereumError;
+use shared::policy::{Chain, SignatureSchemeKind};
+use shared::transport::{
+    EthereumAccessListItem, EthereumTransaction, VsockEnclaveSignEthereumMessageData,
+    VsockEnclaveSignEthereumTxData,
+};
+
+use crate::policy::{SigningIntent, Transfer};
+
+/// Derives the secp256k1 key at `derivation_path` (e.g. `m/44'/60'/0'/0/0`)
+/// using the wallet secret as the BIP32 seed. The returned key is zeroized
+/// when dropped.
+pub fn derive_signing_key(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+) -> Result<SigningKey, EthereumError> {
+    let path: DerivationPath = derivation_path
+        .parse()
+        .map_err(|_| EthereumError::InvalidDerivationPath(derivation_path.to_string()))?;
+    let xprv = XPrv::derive_from_path(secret_key, &path)
+        .map_err(|_| EthereumError::InvalidDerivationPath(derivation_path.to_string()))?;
+    // k256 keys are `ZeroizeOnDrop`, so this copy and the one in `xprv` are
+    // both cleared
+    return Ok(xprv.private_key().clone());
+}
+
+/// keccak256 of the uncompressed public key, last 20 bytes.
+pub fn address(signing_key: &SigningKey) -> [u8; 20] {
+    let public_key = signing_key.verifying_key().to_encoded_point(false);
+    let hash = keccak256(&public_key.as_bytes()[1..]);
+    let mut address = [0u8; 20];
+    address.copy_from_slice(&hash[12..]);
+    return address;
+}
+
+/// Signs a 32 byte digest, returning `r || s` (low-s normalized) and the recovery id.
+pub fn sign_hash(
+    signing_key: &SigningKey,
+    hash: &[u8; 32],
+) -> Result<([u8; 64], RecoveryId), EthereumError> {
+    let (signature, recovery_id) = signing_key
+        .sign_prehash_recoverable(hash)
+        .map_err(|_| EthereumError::SigningFailed)?;
+    let (signature, recovery_id) = match signature.normalize_s() {
+        Some(normalized) => (
+            normalized,
+            RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced()),
+        ),
+        None => (signature, recovery_id),
+    };
+    return Ok((signature.to_bytes().into(), recovery_id));
+}
+
+/// EIP-191 version 0x45 (`personal_sign`) hash of `message`.
+pub fn hash_personal_message(message: &[u8]) -> [u8; 32] {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    return keccak256(&prefixed);
+}
+
+/// Signs an already domain separated `hash` (EIP-191 or EIP-712) and returns the
+/// address recovered from the produced signature.
+pub fn sign_message_hash(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+    hash: [u8; 32],
+) -> Result<VsockEnclaveSignEthereumMessageData, EthereumError> {
+    let signing_key = derive_signing_key(secret_key, derivation_path)?;
+    let (signature, recovery_id) = sign_hash(&signing_key, &hash)?;
+
+    let recovered = VerifyingKey::recover_from_prehash(
+        &hash,
+        &Signature::from_slice(&signature).map_err(|_| EthereumError::SigningFailed)?,
+        recovery_id,
+    )
+    .map_err(|_| EthereumError::SigningFailed)?;
+    let public_key = recovered.to_encoded_point(false);
+    let mut recovered_address = [0u8; 20];
+    recovered_address.copy_from_slice(&keccak256(&public_key.as_bytes()[1..])[12..]);
+
+    let mut rsv = signature.to_vec();
+    rsv.push(27 + recovery_id.is_y_odd() as u8);
+    return Ok(VsockEnclaveSignEthereumMessageData {
+        hash,
+        signature: rsv,
+        recovered_address,
+    });
+}
+
+pub fn sign_transaction(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+    transaction: &EthereumTransaction,
+) -> Result<VsockEnclaveSignEthereumTxData, EthereumError> {
+    let signing_key = derive_signing_key(secret_key, derivation_path)?;
+    return sign_transaction_with_key(&signing_key, transaction);
+}
+
+pub fn sign_transaction_with_key(
+    signing_key: &SigningKey,
+    transaction: &EthereumTransaction,
+) -> Result<VsockEnclaveSignEthereumTxData, EthereumError> {
+    let signing_hash = keccak256(&encode_transaction(transaction, None)?);
+    let (signature, recovery_id) = sign_hash(signing_key, &signing_hash)?;
+    let raw_transaction = encode_transaction(transaction, Some((&signature, recovery_id)))?;
+
+    return Ok(VsockEnclaveSignEthereumTxData {
+        tx_hash: keccak256(&raw_transaction),
+        raw_transaction,
+    });
+}
+
+/// The transaction's chain, native value transfer and the most it can pay in
+/// gas, for policy checks. Contract calls and creations are opaque: their
+/// `data` can move tokens to any recipient while `to` and `value` only show the
+/// contract. Values above `u128::MAX` saturate.
+pub fn signing_intent(transaction: &EthereumTransaction) -> SigningIntent {
+    let (chain_id, to, value, data, gas_limit, gas_price) = match transaction {
+        EthereumTransaction::Legacy {
+            chain_id,
+            to,
+            value,
+            data,
+            gas_limit,
+            gas_price,
+            ..
+        }
+        | EthereumTransaction::Eip2930 {
+            chain_id,
+            to,
+            value,
+            data,
+            gas_limit,
+            gas_price,
+            ..
+        } => (*chain_id, to, value, data, *gas_limit, *gas_price),
+        EthereumTransaction::Eip1559 {
+            chain_id,
+            to,
+            value,
+            data,
+            gas_limit,
+            max_fee_per_gas,
+            ..
+        } => (*chain_id, to, value, data, *gas_limit, *max_fee_per_gas),
+    };
+    let Some(to) = to.filter(|_| data.is_empty()) else {
+        return SigningIntent::opaque(vec![SignatureSchemeKind::Secp256k1]);
+    };
+    let value = match value[..16].iter().all(|byte| *byte == 0) {
+        true => u128::from_be_bytes(value[16..].try_into().unwrap()),
+        false => u128::MAX,
+    };
+
+    return SigningIntent::transaction(
+        vec![SignatureSchemeKind::Secp256k1],
+        Chain::Ethereum { chain_id },
+        vec![
+            Transfer {
+                destination: Some(to.to_vec()),
+                value,
+            },
+            Transfer {
+                destination: None,
+                value: gas_price.saturating_mul(u128::from(gas_limit)),
+            },
+        ],
+    );
+}
+
+/// Encodes the signing payload when `signature` is `None`, otherwise the signed
+/// raw transaction. Typed transactions are prefixed with their EIP-2718 type.
+fn encode_transaction(
+    transaction: &EthereumTransaction,
+    signature: Option<(&[u8; 64], RecoveryId)>,
+) -> Result<Vec<u8>, EthereumError> {
+    let mut fields: Vec<Vec<u8>> = Vec::new();
+    let tx_type = match transaction {
+        EthereumTransaction::Legacy {
+            chain_id,
+            nonce,
+            gas_price,
+            gas_limit,
+            to,
+            value,
+            data,
+        } => {
+            if *chain_id == 0 {
+                return Err(EthereumError::InvalidChainId);
+            }
+            fields.push(rlp_uint(&nonce.to_be_bytes()));
+            fields.push(rlp_uint(&gas_price.to_be_bytes()));
+            fields.push(rlp_uint(&gas_limit.to_be_bytes()));
+            fields.push(rlp_to(to));
+            fields.push(rlp_uint(value));
+            fields.push(rlp_bytes(data));
+            match signature {
+                // EIP-155: v = recovery_id + chain_id * 2 + 35
+                Some((signature, recovery_id)) => {
+                    let v = (*chain_id as u128) * 2 + 35 + recovery_id.is_y_odd() as u128;
+                    fields.push(rlp_uint(&v.to_be_bytes()));
+                    fields.push(rlp_uint(&signature[..32]));
+                    fields.push(rlp_uint(&signature[32..]));
+                }
+                None => {
+                    fields.push(rlp_uint(&chain_id.to_be_bytes()));
+                    fields.push(rlp_uint(&[]));
+                    fields.push(rlp_uint(&[]));
+                }
+            }
+            None
+        }
+        EthereumTransaction::Eip2930 {
+            chain_id,
+            nonce,
+            gas_price,
+            gas_limit,
+            to,
+            value,
+            data,
+            access_list,
+        } => {
+            fields.push(rlp_uint(&chain_id.to_be_bytes()));
+            fields.push(rlp_uint(&nonce.to_be_bytes()));
+            fields.push(rlp_uint(&gas_price.to_be_bytes()));
+            fields.push(rlp_uint(&gas_limit.to_be_bytes()));
+            fields.push(rlp_to(to));
+            fields.push(rlp_uint(value));
+            fields.push(rlp_bytes(data));
+            fields.push(rlp_access_list(access_list));
+            Some(0x01)
+        }
+        EthereumTransaction::Eip1559 {
+            chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit,
+            to,
+            value,
+            data,
+            access_list,
+        } => {
+            fields.push(rlp_uint(&chain_id.to_be_bytes()));
+            fields.push(rlp_uint(&nonce.to_be_bytes()));
+            fields.push(rlp_uint(&max_priority_fee_per_gas.to_be_bytes()));
+            fields.push(rlp_uint(&max_fee_per_gas.to_be_bytes()));
+            fields.push(rlp_uint(&gas_limit.to_be_bytes()));
+            fields.push(rlp_to(to));
+            fields.push(rlp_uint(value));
+            fields.push(rlp_bytes(data));
+            fields.push(rlp_access_list(access_list));
+            Some(0x02)
+        }
+    };
+
+    if let (Some(_), Some((signature, recovery_id))) = (tx_type, signature) {
+        fields.push(rlp_uint(&[recovery_id.is_y_odd() as u8]));
+        fields.push(rlp_uint(&signature[..32]));
+        fields.push(rlp_uint(&signature[32..]));
+    }
+
+    let mut encoded = Vec::new();
+    if let Some(tx_type) = tx_type {
+        encoded.push(tx_type);
+    }
+    encoded.extend(rlp_list(&fields));
+    return Ok(encoded);
+}
+
+fn rlp_to(to: &Option<[u8; 20]>) -> Vec<u8> {
+    return match to {
+        Some(address) => rlp_bytes(address),
+        // contract creation
+        None => rlp_bytes(&[]),
+    };
+}
+
+fn rlp_access_list(access_list: &[EthereumAccessListItem]) -> Vec<u8> {
+    let items: Vec<Vec<u8>> = access_list
+        .iter()
+        .map(|item| {
+            let storage_keys: Vec<Vec<u8>> =
+                item.storage_keys.iter().map(|key| rlp_bytes(key)).collect();
+            rlp_list(&[rlp_bytes(&item.address), rlp_list(&storage_keys)])
+        })
+        .collect();
+    return rlp_list(&items);
+}
+
+/// Big endian integer with leading zeros stripped; zero encodes as the empty string.
+fn rlp_uint(big_endian: &[u8]) -> Vec<u8> {
+    let first_non_zero = big_endian
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(big_endian.len());
+    return rlp_bytes(&big_endian[first_non_zero..]);
+}
+
+fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut encoded = rlp_length_prefix(bytes.len(), 0x80);
+    encoded.extend_from_slice(bytes);
+    return encoded;
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload: Vec<u8> = items.concat();
+    let mut encoded = rlp_length_prefix(payload.len(), 0xc0);
+    encoded.extend(payload);
+    return encoded;
+}
+
+fn rlp_length_prefix(length: usize, offset: u8) -> Vec<u8> {
+    if length < 56 {
+        return vec![offset + length as u8];
+    }
+    let length_bytes = length.to_be_bytes();
+    let first_non_zero = length_bytes.iter().position(|byte| *byte != 0).unwrap();
+    let mut prefix = vec![offset + 55 + (length_bytes.len() - first_non_zero) as u8];
+    prefix.extend_from_slice(&length_bytes[first_non_zero..]);
+    return prefix;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_keys_zeroize_on_drop() {
+        fn zeroize_on_drop<T: zeroize::ZeroizeOnDrop>() {}
+        zeroize_on_drop::<SigningKey>();
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        return (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+    }
+
+    fn one_ether() -> [u8; 32] {
+        let mut value = [0u8; 32];
+        value[24..].copy_from_slice(&1_000_000_000_000_000_000u64.to_be_bytes());
+        return value;
+    }
+
+    fn recover(hash: &[u8; 32], signature: &[u8], y_parity: u8) -> VerifyingKey {
+        let signature = Signature::from_slice(signature).unwrap();
+        let recovery_id = RecoveryId::from_byte(y_parity).unwrap();
+        return VerifyingKey::recover_from_prehash(hash, &signature, recovery_id).unwrap();
+    }
+
+    #[test]
+    fn test_rlp_encoding() {
+        assert_eq!(rlp_bytes(b"dog"), hex("83646f67"));
+        assert_eq!(rlp_uint(&0u64.to_be_bytes()), hex("80"));
+        assert_eq!(rlp_uint(&15u64.to_be_bytes()), hex("0f"));
+        assert_eq!(rlp_uint(&1024u64.to_be_bytes()), hex("820400"));
+        assert_eq!(
+            rlp_list(&[rlp_bytes(b"cat"), rlp_bytes(b"dog")]),
+            hex("c88363617483646f67")
+        );
+        let long = [b'a'; 56];
+        assert_eq!(rlp_bytes(&long)[..2], [0xb8, 56]);
+    }
+
+    // https://eips.ethereum.org/EIPS/eip-155 example
+    #[test]
+    fn test_eip155_legacy_transaction() {
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let transaction = EthereumTransaction::Legacy {
+            chain_id: 1,
+            nonce: 9,
+            gas_price: 20_000_000_000,
+            gas_limit: 21_000,
+            to: Some([0x35; 20]),
+            value: one_ether(),
+            data: vec![],
+        };
+
+        let signing_payload = encode_transaction(&transaction, None).unwrap();
+        assert_eq!(
+            signing_payload,
+            hex(
+                "ec098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a764000080018080"
+            )
+        );
+        assert_eq!(
+            keccak256(&signing_payload).to_vec(),
+            hex("daf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53")
+        );
+
+        let result = sign_transaction_with_key(&signing_key, &transaction).unwrap();
+        assert_eq!(
+            result.raw_transaction,
+            hex(
+                "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+            )
+        );
+        assert_eq!(result.tx_hash, keccak256(&result.raw_transaction));
+    }
+
+    #[test]
+    fn test_eip1559_transaction_recovers_signer() {
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let transaction = EthereumTransaction::Eip1559 {
+            chain_id: 11155111,
+            nonce: 0,
+            max_priority_fee_per_gas: 1_000_000_000,
+            max_fee_per_gas: 30_000_000_000,
+            gas_limit: 21_000,
+            to: Some([0x35; 20]),
+            value: one_ether(),
+            data: vec![],
+            access_list: vec![EthereumAccessListItem {
+                address: [0x11; 20],
+                storage_keys: vec![[0x22; 32]],
+            }],
+        };
+
+        let result = sign_transaction_with_key(&signing_key, &transaction).unwrap();
+        assert_eq!(result.raw_transaction[0], 0x02);
+
+        let signing_hash = keccak256(&encode_transaction(&transaction, None).unwrap());
+        let (signature, recovery_id) = sign_hash(&signing_key, &signing_hash).unwrap();
+        assert_eq!(
+            result.raw_transaction,
+            encode_transaction(&transaction, Some((&signature, recovery_id))).unwrap()
+        );
+        let recovered = recover(&signing_hash, &signature, recovery_id.to_byte());
+        assert_eq!(&recovered, signing_key.verifying_key());
+    }
+
+    #[test]
+    fn test_legacy_transaction_requires_chain_id() {
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        let transaction = EthereumTransaction::Legacy {
+            chain_id: 0,
+            nonce: 0,
+            gas_price: 1,
+            gas_limit: 21_000,
+            to: None,
+            value: [0u8; 32],
+            data: vec![0x60, 0x00],
+        };
+
+        let result = sign_transaction_with_key(&signing_key, &transaction);
+
+        assert!(matches!(result, Err(EthereumError::InvalidChainId)));
+    }
+
+    #[test]
+    fn test_address_and_derivation() {
+        let signing_key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        assert_eq!(
+            address(&signing_key).to_vec(),
+            hex("9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f")
+        );
+
+        let secret_key = [5u8; 64];
+        let first = derive_signing_key(&secret_key, "m/44'/60'/0'/0/0").unwrap();
+        let second = derive_signing_key(&secret_key, "m/44'/60'/0'/0/1").unwrap();
+        assert_ne!(address(&first), address(&second));
+        assert!(matches!(
+            derive_signing_key(&secret_key, "44/60"),
+            Err(EthereumError::InvalidDerivationPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_personal_message_hash() {
+        assert_eq!(
+            hash_personal_message(b"hello world").to_vec(),
+            hex("d9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68")
+        );
+    }
+
+    #[test]
+    fn test_sign_message_hash_recovers_derived_address() {
+        let secret_key = [5u8; 64];
+        let path = "m/44'/60'/0'/0/0";
+        let hash = hash_personal_message(b"trustvault");
+
+        let result = sign_message_hash(&secret_key, path, hash).unwrap();
+
+        let signing_key = derive_signing_key(&secret_key, path).unwrap();
+        assert_eq!(result.recovered_address, address(&signing_key));
+        assert_eq!(result.signature.len(), 65);
+        assert!(result.signature[64] == 27 || result.signature[64] == 28);
+    }
+}
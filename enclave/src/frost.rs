@@ -0,0 +1,896 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use curve25519_dalek::edwards::EdwardsPoint;
+use curve25519_dalek::scalar::Scalar;
+use lru::LruCache;
+use shared::approval::Quorum;
+use shared::attestation::AttestationVerifier;
+use shared::error::{FrostError, VsockEnclaveSignError};
+use shared::frost::{
+    self, FROST_SHARE_INFO, FrostKeyShare, FrostRound1Package, FrostRound2Package,
+    FrostSignatureShare, FrostSigningCommitment, FrostVerifyingShare,
+};
+use shared::policy::SignedPolicy;
+use shared::sealed;
+use tracing::warn;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::aes256gcm::{decrypt_private_key_aes256gcm, encrypt_private_key_aes256gcm};
+use crate::approval;
+use crate::policy;
+use crate::sealing::SealingKey;
+use crate::secret::SecretVec;
+
+const MAX_DKG_SESSIONS: usize = 64;
+const MAX_SIGNING_NONCES: usize = 1024;
+/// session id, sender, recipient and the share itself
+const SHARE_PLAINTEXT_LENGTH: usize = 16 + 2 + 2 + 32;
+
+pub type SharedFrostSessions = Arc<Mutex<FrostSessions>>;
+
+/// How DKG round two checks the sealing keys of the other participants.
+pub enum PeerAttestation {
+    /// Each must come with an attestation document meeting these PCRs.
+    Verify(AttestationVerifier),
+    /// Trusted as the host relays them. Only for tests outside Nitro, where
+    /// there is no NSM to attest with.
+    Insecure,
+}
+
+struct DkgSession {
+    identifier: u16,
+    threshold: u8,
+    participants: u8,
+    /// Secret polynomial, constant term first.
+    coefficients: Vec<Scalar>,
+    /// Everyone's round one packages, once round two ran.
+    round1_packages: Option<Vec<FrostRound1Package>>,
+}
+
+impl Drop for DkgSession {
+    fn drop(&mut self) {
+        self.coefficients.zeroize();
+    }
+}
+
+struct SigningNonces {
+    hiding: Scalar,
+    binding: Scalar,
+    identifier: u16,
+    group_public_key: [u8; 32],
+}
+
+impl Drop for SigningNonces {
+    fn drop(&mut self) {
+        self.hiding.zeroize();
+        self.binding.zeroize();
+    }
+}
+
+/// A participant's view of a finished DKG.
+pub struct DkgOutput {
+    pub identifier: u16,
+    pub threshold: u8,
+    pub signing_share: Zeroizing<Scalar>,
+    pub group_public_key: [u8; 32],
+    pub verifying_shares: Vec<FrostVerifyingShare>,
+}
+
+/// Secret state the enclave keeps between FROST rounds: DKG polynomials by
+/// session id and signing nonces by their commitment. Both only live in memory,
+/// so a restart aborts runs in progress rather than leaking anything, and both
+/// are bounded so abandoned runs cannot exhaust it. Entries are zeroized when
+/// used or evicted; a nonce is never used twice.
+pub struct FrostSessions {
+    dkg: LruCache<[u8; 16], DkgSession>,
+    nonces: LruCache<([u8; 32], [u8; 32]), SigningNonces>,
+}
+
+impl Default for FrostSessions {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+impl FrostSessions {
+    pub fn new() -> Self {
+        return Self {
+            dkg: LruCache::new(NonZeroUsize::new(MAX_DKG_SESSIONS).unwrap()),
+            nonces: LruCache::new(NonZeroUsize::new(MAX_SIGNING_NONCES).unwrap()),
+        };
+    }
+
+    /// Drops every DKG session and signing nonce, zeroizing them, e.g. on
+    /// shutdown.
+    pub fn clear(&mut self) {
+        self.dkg.clear();
+        self.nonces.clear();
+    }
+
+    /// DKG round one: picks this participant's secret polynomial and returns
+    /// the commitment to it with a proof of knowledge of its constant term.
+    pub fn dkg_part1(
+        &mut self,
+        sealing_key: &SealingKey,
+        session_id: [u8; 16],
+        identifier: u16,
+        threshold: u8,
+        participants: u8,
+    ) -> Result<FrostRound1Package, FrostError> {
+        if threshold < 2 || threshold > participants {
+            return Err(FrostError::InvalidParameters(format!(
+                "threshold {} of {} participants",
+                threshold, participants
+            )));
+        }
+        if identifier == 0 || identifier > u16::from(participants) {
+            return Err(FrostError::InvalidParameters(format!(
+                "identifier {} of {} participants",
+                identifier, participants
+            )));
+        }
+        if self.dkg.contains(&session_id) {
+            return Err(FrostError::SessionExists);
+        }
+
+        let coefficients = (0..threshold)
+            .map(|_| random_scalar())
+            .collect::<Result<Vec<_>, _>>()?;
+        let commitment: Vec<[u8; 32]> = coefficients
+            .iter()
+            .map(|coefficient| EdwardsPoint::mul_base(coefficient).compress().to_bytes())
+            .collect();
+
+        let k = Zeroizing::new(random_scalar()?);
+        let proof_r = EdwardsPoint::mul_base(&k).compress().to_bytes();
+        let c = frost::dkg_challenge(identifier, &commitment[0], &proof_r);
+        let proof_z = *k + coefficients[0] * c;
+
+        self.dkg.put(
+            session_id,
+            DkgSession {
+                identifier,
+                threshold,
+                participants,
+                coefficients,
+                round1_packages: None,
+            },
+        );
+
+        let attestation = match sealing_key.attest(None) {
+            Ok(data) => data.attestation,
+            Err(e) => {
+                // outside an enclave there is no NSM, only insecure peers accept this
+                warn!(error = %e, "failed to attest sealing key");
+                Vec::new()
+            }
+        };
+
+        return Ok(FrostRound1Package {
+            identifier,
+            commitment,
+            proof_r,
+            proof_z: proof_z.to_bytes(),
+            sealing_key: sealing_key.public_key(),
+            attestation,
+        });
+    }
+
+    /// DKG round two: checks every participant's round one package and their
+    /// attestation, then seals this participant's share for each of the others
+    /// to their sealing key. Refused without `peer_attestation`.
+    pub fn dkg_part2(
+        &mut self,
+        session_id: [u8; 16],
+        round1_packages: Vec<FrostRound1Package>,
+        peer_attestation: Option<&PeerAttestation>,
+    ) -> Result<Vec<FrostRound2Package>, FrostError> {
+        let peer_attestation = peer_attestation.ok_or(FrostError::PeerAttestationRequired)?;
+        let session = self
+            .dkg
+            .get_mut(&session_id)
+            .ok_or(FrostError::UnknownSession)?;
+        if session.round1_packages.is_some() {
+            return Err(FrostError::InvalidParameters(
+                "round two already ran".to_string(),
+            ));
+        }
+
+        let mut round1_packages = round1_packages;
+        round1_packages.sort_by_key(|package| package.identifier);
+        let identifiers: Vec<u16> = round1_packages
+            .iter()
+            .map(|package| package.identifier)
+            .collect();
+        if identifiers != (1..=u16::from(session.participants)).collect::<Vec<u16>>() {
+            return Err(FrostError::InvalidParameters(
+                "expected one round one package per participant".to_string(),
+            ));
+        }
+
+        for package in &round1_packages {
+            let identifier = package.identifier;
+            if package.commitment.len() != usize::from(session.threshold) {
+                return Err(FrostError::InvalidPackage(
+                    identifier,
+                    "commitment has the wrong degree".to_string(),
+                ));
+            }
+            let commitment = decompress_commitment(package)?;
+
+            if identifier == session.identifier {
+                let own = session
+                    .coefficients
+                    .iter()
+                    .map(EdwardsPoint::mul_base)
+                    .collect::<Vec<_>>();
+                if own != commitment {
+                    return Err(FrostError::InvalidPackage(
+                        identifier,
+                        "not this participant's commitment".to_string(),
+                    ));
+                }
+                continue;
+            }
+
+            let r = frost::decompress(&package.proof_r)
+                .map_err(|e| FrostError::InvalidPackage(identifier, e.to_string()))?;
+            let z = frost::scalar(&package.proof_z)
+                .map_err(|e| FrostError::InvalidPackage(identifier, e.to_string()))?;
+            let c = frost::dkg_challenge(identifier, &package.commitment[0], &package.proof_r);
+            if EdwardsPoint::mul_base(&z) != r + commitment[0] * c {
+                return Err(FrostError::InvalidPackage(
+                    identifier,
+                    "invalid proof of knowledge".to_string(),
+                ));
+            }
+
+            if let PeerAttestation::Verify(verifier) = peer_attestation {
+                let document = verifier
+                    .verify(&package.attestation)
+                    .map_err(|e| FrostError::InvalidPackage(identifier, e.to_string()))?;
+                if document.public_key.as_deref().map(|key| key.as_slice())
+                    != Some(package.sealing_key.as_slice())
+                {
+                    return Err(FrostError::InvalidPackage(
+                        identifier,
+                        "attestation is not for the sealing key".to_string(),
+                    ));
+                }
+            }
+        }
+
+        let mut round2_packages = Vec::with_capacity(round1_packages.len() - 1);
+        for package in round1_packages
+            .iter()
+            .filter(|package| package.identifier != session.identifier)
+        {
+            let share = Zeroizing::new(evaluate(&session.coefficients, package.identifier)?);
+            let mut plaintext = Zeroizing::new(Vec::with_capacity(SHARE_PLAINTEXT_LENGTH));
+            plaintext.extend_from_slice(&session_id);
+            plaintext.extend_from_slice(&session.identifier.to_be_bytes());
+            plaintext.extend_from_slice(&package.identifier.to_be_bytes());
+            plaintext.extend_from_slice(share.as_bytes());
+
+            round2_packages.push(FrostRound2Package {
+                sender: session.identifier,
+                recipient: package.identifier,
+                sealed_share: sealed::seal(&package.sealing_key, FROST_SHARE_INFO, &plaintext)?,
+            });
+        }
+
+        session.round1_packages = Some(round1_packages);
+        return Ok(round2_packages);
+    }
+
+    /// DKG round three: opens and checks the shares sent to this participant
+    /// and derives its signing share and everyone's verifying shares. The
+    /// session ends here whether or not it succeeds.
+    pub fn dkg_part3(
+        &mut self,
+        sealing_key: &SealingKey,
+        session_id: [u8; 16],
+        round2_packages: &[FrostRound2Package],
+    ) -> Result<DkgOutput, FrostError> {
+        let session = self
+            .dkg
+            .pop(&session_id)
+            .ok_or(FrostError::UnknownSession)?;
+        let Some(round1_packages) = &session.round1_packages else {
+            return Err(FrostError::InvalidParameters(
+                "round two has not run".to_string(),
+            ));
+        };
+
+        let mut signing_share =
+            Zeroizing::new(evaluate(&session.coefficients, session.identifier)?);
+        let mut commitments = Vec::with_capacity(round1_packages.len());
+        for package in round1_packages {
+            let commitment = decompress_commitment(package)?;
+            if package.identifier != session.identifier {
+                let share =
+                    open_share(sealing_key, &session, session_id, package, round2_packages)?;
+                if EdwardsPoint::mul_base(&share)
+                    != frost::evaluate_commitment(&commitment, session.identifier)?
+                {
+                    return Err(FrostError::InvalidPackage(
+                        package.identifier,
+                        "share does not match its commitment".to_string(),
+                    ));
+                }
+                *signing_share += *share;
+            }
+            commitments.push(commitment);
+        }
+
+        let group_public_key: EdwardsPoint =
+            commitments.iter().map(|commitment| commitment[0]).sum();
+        let mut verifying_shares = Vec::with_capacity(commitments.len());
+        for identifier in 1..=u16::from(session.participants) {
+            let mut verifying_share = EdwardsPoint::default();
+            for commitment in &commitments {
+                verifying_share += frost::evaluate_commitment(commitment, identifier)?;
+            }
+            verifying_shares.push(FrostVerifyingShare {
+                identifier,
+                public_key: verifying_share.compress().to_bytes(),
+            });
+        }
+
+        return Ok(DkgOutput {
+            identifier: session.identifier,
+            threshold: session.threshold,
+            signing_share,
+            group_public_key: group_public_key.compress().to_bytes(),
+            verifying_shares,
+        });
+    }
+
+    /// FROST round one: commits to fresh nonces, remembered until they sign.
+    pub fn commit(
+        &mut self,
+        key_share: &FrostKeyShare,
+        signing_share: &Scalar,
+    ) -> Result<FrostSigningCommitment, FrostError> {
+        let mut random_bytes = Zeroizing::new([0u8; 64]);
+        getrandom::getrandom(random_bytes.as_mut())
+            .map_err(|e| FrostError::Randomness(e.to_string()))?;
+        let nonces = SigningNonces {
+            hiding: frost::nonce_generate(random_bytes[..32].try_into().unwrap(), signing_share),
+            binding: frost::nonce_generate(random_bytes[32..].try_into().unwrap(), signing_share),
+            identifier: key_share.identifier,
+            group_public_key: key_share.group_public_key,
+        };
+
+        let commitment = FrostSigningCommitment {
+            identifier: key_share.identifier,
+            hiding: EdwardsPoint::mul_base(&nonces.hiding).compress().to_bytes(),
+            binding: EdwardsPoint::mul_base(&nonces.binding)
+                .compress()
+                .to_bytes(),
+        };
+        self.nonces
+            .put((commitment.hiding, commitment.binding), nonces);
+        return Ok(commitment);
+    }
+
+    /// FROST round two: signs `message` with the nonces this participant
+    /// committed to in `commitments`. The nonces are consumed even if signing
+    /// fails, so they can never sign two different messages.
+    pub fn sign(
+        &mut self,
+        key_share: &FrostKeyShare,
+        signing_share: &Scalar,
+        message: &[u8],
+        commitments: &[FrostSigningCommitment],
+    ) -> Result<FrostSignatureShare, FrostError> {
+        let Some(position) = commitments
+            .iter()
+            .position(|commitment| commitment.identifier == key_share.identifier)
+        else {
+            return Err(FrostError::InvalidCommitment(
+                "no commitment from this participant".to_string(),
+            ));
+        };
+        let own = &commitments[position];
+        let nonces = self
+            .nonces
+            .pop(&(own.hiding, own.binding))
+            .ok_or(FrostError::UnknownCommitment)?;
+        if nonces.identifier != key_share.identifier
+            || nonces.group_public_key != key_share.group_public_key
+        {
+            return Err(FrostError::UnknownCommitment);
+        }
+
+        if commitments.len() < usize::from(key_share.threshold) {
+            return Err(FrostError::InvalidParameters(format!(
+                "{} signers are fewer than the threshold of {}",
+                commitments.len(),
+                key_share.threshold
+            )));
+        }
+        for commitment in commitments {
+            if !key_share
+                .verifying_shares
+                .iter()
+                .any(|share| share.identifier == commitment.identifier)
+            {
+                return Err(FrostError::InvalidCommitment(format!(
+                    "{} is not a participant",
+                    commitment.identifier
+                )));
+            }
+        }
+
+        let binding_factors =
+            frost::binding_factors(&key_share.group_public_key, commitments, message)?;
+        let group_commitment = frost::group_commitment(commitments, &binding_factors)?;
+        let challenge = frost::challenge(&group_commitment, &key_share.group_public_key, message);
+        let signers: Vec<u16> = commitments
+            .iter()
+            .map(|commitment| commitment.identifier)
+            .collect();
+        let lambda = frost::lagrange_coefficient(key_share.identifier, &signers)?;
+
+        let share = Zeroizing::new(
+            nonces.hiding
+                + nonces.binding * binding_factors[position]
+                + lambda * signing_share * challenge,
+        );
+        return Ok(FrostSignatureShare {
+            identifier: key_share.identifier,
+            share: share.to_bytes(),
+        });
+    }
+}
+
+/// Encrypts a DKG result under the plaintext data key, with its public fields,
+/// `policy` and `quorum` as associated data. `data_key` comes straight from
+/// kmstool and is wiped before returning.
+pub fn seal_key_share(
+    output: DkgOutput,
+    data_key: Vec<u8>,
+    kms_ciphertext: Vec<u8>,
+    kms_key_id: String,
+    policy: Option<SignedPolicy>,
+    quorum: Option<Quorum>,
+) -> Result<FrostKeyShare, VsockEnclaveSignError> {
+    let data_key = SecretVec::from_vec(data_key);
+    let mut aes_gcm_nonce = [0u8; 12];
+    getrandom::getrandom(&mut aes_gcm_nonce).map_err(|e| FrostError::Randomness(e.to_string()))?;
+
+    let mut key_share = FrostKeyShare {
+        identifier: output.identifier,
+        threshold: output.threshold,
+        group_public_key: output.group_public_key,
+        verifying_shares: output.verifying_shares.clone(),
+        encrypted_signing_share: Vec::new(),
+        aes_gcm_nonce,
+        kms_ciphertext,
+        kms_key_id,
+        policy,
+        quorum,
+    };
+    key_share.encrypted_signing_share = encrypt_private_key_aes256gcm(
+        output.signing_share.as_bytes(),
+        data_key.expose(),
+        &aes_gcm_nonce,
+        &key_share_aad(&key_share),
+    )?;
+    return Ok(key_share);
+}
+
+/// Decrypts a key share's signing share with an already unwrapped data key and
+/// checks it against the share's own verifying share.
+pub fn open_key_share(
+    key_share: &FrostKeyShare,
+    data_key: &SecretVec,
+) -> Result<Zeroizing<Scalar>, VsockEnclaveSignError> {
+    let plaintext = decrypt_private_key_aes256gcm(
+        &key_share.encrypted_signing_share,
+        data_key.expose(),
+        &key_share.aes_gcm_nonce,
+        &key_share_aad(key_share),
+    )?;
+    let bytes: &[u8; 32] = plaintext
+        .expose()
+        .try_into()
+        .map_err(|_| FrostError::InvalidKeyShare)?;
+    let signing_share =
+        Zeroizing::new(frost::scalar(bytes).map_err(|_| FrostError::InvalidKeyShare)?);
+
+    let verifying_share = key_share
+        .verifying_shares
+        .iter()
+        .find(|share| share.identifier == key_share.identifier)
+        .ok_or(FrostError::InvalidKeyShare)?;
+    if EdwardsPoint::mul_base(&signing_share).compress().to_bytes() != verifying_share.public_key {
+        return Err(FrostError::InvalidKeyShare.into());
+    }
+    return Ok(signing_share);
+}
+
+/// SHA-256 over the key share's public fields.
+fn key_share_aad(key_share: &FrostKeyShare) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.input(b"frost key share");
+    hasher.input(&key_share.identifier.to_be_bytes());
+    hasher.input(&[key_share.threshold]);
+    hasher.input(&key_share.group_public_key);
+    for verifying_share in &key_share.verifying_shares {
+        hasher.input(&verifying_share.identifier.to_be_bytes());
+        hasher.input(&verifying_share.public_key);
+    }
+    // both empty without a policy or quorum
+    hasher.input(&policy::envelope_aad(key_share.policy.as_ref()));
+    hasher.input(&approval::envelope_aad(key_share.quorum.as_ref()));
+    let mut aad = vec![0u8; 32];
+    hasher.result(&mut aad);
+    return aad;
+}
+
+/// Stable identity of the group key a share belongs to, which policies are
+/// bound to like a wallet's.
+pub fn key_id(key_share: &FrostKeyShare) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(b"trustvault frost key id");
+    hasher.input(&key_share.group_public_key);
+    let mut id = [0u8; 32];
+    hasher.result(&mut id);
+    return id;
+}
+
+fn random_scalar() -> Result<Scalar, FrostError> {
+    let mut bytes = Zeroizing::new([0u8; 64]);
+    getrandom::getrandom(bytes.as_mut()).map_err(|e| FrostError::Randomness(e.to_string()))?;
+    return Ok(Scalar::from_bytes_mod_order_wide(&bytes));
+}
+
+/// Evaluates a secret polynomial at `identifier`.
+fn evaluate(coefficients: &[Scalar], identifier: u16) -> Result<Scalar, FrostError> {
+    let x = frost::identifier_scalar(identifier)?;
+    let mut result = Scalar::ZERO;
+    for coefficient in coefficients.iter().rev() {
+        result = result * x + coefficient;
+    }
+    return Ok(result);
+}
+
+fn decompress_commitment(package: &FrostRound1Package) -> Result<Vec<EdwardsPoint>, FrostError> {
+    return package
+        .commitment
+        .iter()
+        .map(|point| {
+            frost::decompress(point)
+                .map_err(|e| FrostError::InvalidPackage(package.identifier, e.to_string()))
+        })
+        .collect();
+}
+
+/// Opens the share `package`'s sender sealed to this participant.
+fn open_share(
+    sealing_key: &SealingKey,
+    session: &DkgSession,
+    session_id: [u8; 16],
+    package: &FrostRound1Package,
+    round2_packages: &[FrostRound2Package],
+) -> Result<Zeroizing<Scalar>, FrostError> {
+    let sender = package.identifier;
+    let round2_package = round2_packages
+        .iter()
+        .find(|round2_package| {
+            round2_package.sender == sender && round2_package.recipient == session.identifier
+        })
+        .ok_or_else(|| FrostError::InvalidPackage(sender, "missing share".to_string()))?;
+    let plaintext = sealing_key
+        .open_box(FROST_SHARE_INFO, &round2_package.sealed_share)
+        .map_err(|e| FrostError::InvalidPackage(sender, e.to_string()))?;
+
+    let mut expected = Vec::with_capacity(SHARE_PLAINTEXT_LENGTH - 32);
+    expected.extend_from_slice(&session_id);
+    expected.extend_from_slice(&sender.to_be_bytes());
+    expected.extend_from_slice(&session.identifier.to_be_bytes());
+    if plaintext.len() != SHARE_PLAINTEXT_LENGTH || plaintext[..expected.len()] != expected[..] {
+        return Err(FrostError::InvalidPackage(
+            sender,
+            "share is for another session or participant".to_string(),
+        ));
+    }
+    let share: &[u8; 32] = plaintext[expected.len()..].try_into().unwrap();
+    return Ok(Zeroizing::new(
+        frost::scalar(share).map_err(|e| FrostError::InvalidPackage(sender, e.to_string()))?,
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    struct Participant {
+        sealing_key: SealingKey,
+        sessions: FrostSessions,
+    }
+
+    fn participants(count: usize) -> Vec<Participant> {
+        return (0..count)
+            .map(|_| Participant {
+                sealing_key: SealingKey::generate().unwrap(),
+                sessions: FrostSessions::new(),
+            })
+            .collect();
+    }
+
+    fn run_dkg(participants: &mut [Participant], threshold: u8) -> Vec<DkgOutput> {
+        let session_id = [7u8; 16];
+        let count = participants.len() as u8;
+        let round1: Vec<FrostRound1Package> = participants
+            .iter_mut()
+            .enumerate()
+            .map(|(i, participant)| {
+                participant
+                    .sessions
+                    .dkg_part1(
+                        &participant.sealing_key,
+                        session_id,
+                        i as u16 + 1,
+                        threshold,
+                        count,
+                    )
+                    .unwrap()
+            })
+            .collect();
+        let round2: Vec<FrostRound2Package> = participants
+            .iter_mut()
+            .flat_map(|participant| {
+                participant
+                    .sessions
+                    .dkg_part2(session_id, round1.clone(), Some(&PeerAttestation::Insecure))
+                    .unwrap()
+            })
+            .collect();
+        return participants
+            .iter_mut()
+            .map(|participant| {
+                participant
+                    .sessions
+                    .dkg_part3(&participant.sealing_key, session_id, &round2)
+                    .unwrap()
+            })
+            .collect();
+    }
+
+    fn key_share(output: &DkgOutput) -> FrostKeyShare {
+        return seal_key_share(
+            DkgOutput {
+                identifier: output.identifier,
+                threshold: output.threshold,
+                signing_share: output.signing_share.clone(),
+                group_public_key: output.group_public_key,
+                verifying_shares: output.verifying_shares.clone(),
+            },
+            vec![1u8; 32],
+            vec![2u8; 16],
+            "key".to_string(),
+            None,
+            None,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_any_threshold_of_signers_produce_an_ed25519_signature() {
+        let mut participants = participants(3);
+        let outputs = run_dkg(&mut participants, 2);
+        assert!(
+            outputs
+                .windows(2)
+                .all(|pair| pair[0].group_public_key == pair[1].group_public_key)
+        );
+        let key_shares: Vec<FrostKeyShare> = outputs.iter().map(key_share).collect();
+        let data_key = SecretVec::from_vec(vec![1u8; 32]);
+
+        let message = b"threshold signed payload";
+        for signers in [[0usize, 1], [0, 2], [1, 2]] {
+            let mut commitments = Vec::new();
+            for i in signers {
+                let signing_share = open_key_share(&key_shares[i], &data_key).unwrap();
+                commitments.push(
+                    participants[i]
+                        .sessions
+                        .commit(&key_shares[i], &signing_share)
+                        .unwrap(),
+                );
+            }
+            let mut shares = Vec::new();
+            for i in signers {
+                let signing_share = open_key_share(&key_shares[i], &data_key).unwrap();
+                shares.push(
+                    participants[i]
+                        .sessions
+                        .sign(&key_shares[i], &signing_share, message, &commitments)
+                        .unwrap(),
+                );
+            }
+
+            let signature =
+                frost::aggregate(&key_shares[0], message, &commitments, &shares).unwrap();
+            let public_key =
+                pallas_crypto::key::ed25519::PublicKey::from(key_shares[0].group_public_key);
+            let signature: [u8; 64] = signature.try_into().unwrap();
+            assert!(public_key.verify(
+                message,
+                &pallas_crypto::key::ed25519::Signature::from(signature)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_nonces_are_single_use() {
+        let mut participants = participants(2);
+        let outputs = run_dkg(&mut participants, 2);
+        let key_shares: Vec<FrostKeyShare> = outputs.iter().map(key_share).collect();
+
+        let commitments: Vec<FrostSigningCommitment> = (0..2)
+            .map(|i| {
+                participants[i]
+                    .sessions
+                    .commit(&key_shares[i], &outputs[i].signing_share)
+                    .unwrap()
+            })
+            .collect();
+        participants[0]
+            .sessions
+            .sign(
+                &key_shares[0],
+                &outputs[0].signing_share,
+                b"one",
+                &commitments,
+            )
+            .unwrap();
+        assert!(matches!(
+            participants[0].sessions.sign(
+                &key_shares[0],
+                &outputs[0].signing_share,
+                b"two",
+                &commitments
+            ),
+            Err(FrostError::UnknownCommitment)
+        ));
+        // fewer commitments than the threshold
+        assert!(
+            participants[1]
+                .sessions
+                .sign(
+                    &key_shares[1],
+                    &outputs[1].signing_share,
+                    b"one",
+                    &commitments[1..]
+                )
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tampered_dkg_packages_are_rejected() {
+        let mut participants = participants(3);
+        let session_id = [9u8; 16];
+        let round1: Vec<FrostRound1Package> = participants
+            .iter_mut()
+            .enumerate()
+            .map(|(i, participant)| {
+                participant
+                    .sessions
+                    .dkg_part1(&participant.sealing_key, session_id, i as u16 + 1, 2, 3)
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(matches!(
+            participants[0]
+                .sessions
+                .dkg_part2(session_id, round1.clone(), None),
+            Err(FrostError::PeerAttestationRequired)
+        ));
+        // outside Nitro the packages carry no attestation
+        let verify = PeerAttestation::Verify(AttestationVerifier::new(BTreeMap::new()));
+        assert!(matches!(
+            participants[0]
+                .sessions
+                .dkg_part2(session_id, round1.clone(), Some(&verify)),
+            Err(FrostError::InvalidPackage(2, _))
+        ));
+
+        let mut forged = round1.clone();
+        forged[1].proof_z = forged[2].proof_z;
+        assert!(matches!(
+            participants[0].sessions.dkg_part2(
+                session_id,
+                forged,
+                Some(&PeerAttestation::Insecure)
+            ),
+            Err(FrostError::InvalidPackage(2, _))
+        ));
+        assert!(
+            participants[0]
+                .sessions
+                .dkg_part2(
+                    session_id,
+                    round1[..2].to_vec(),
+                    Some(&PeerAttestation::Insecure)
+                )
+                .is_err()
+        );
+
+        // a share swapped for another recipient's does not open
+        let mut round2 = participants[1]
+            .sessions
+            .dkg_part2(session_id, round1.clone(), Some(&PeerAttestation::Insecure))
+            .unwrap();
+        round2.extend(
+            participants[2]
+                .sessions
+                .dkg_part2(session_id, round1.clone(), Some(&PeerAttestation::Insecure))
+                .unwrap(),
+        );
+        participants[0]
+            .sessions
+            .dkg_part2(session_id, round1, Some(&PeerAttestation::Insecure))
+            .unwrap();
+        round2.retain(|package| package.recipient != 1);
+        for package in round2.iter_mut() {
+            package.recipient = 1;
+        }
+        let participant = &mut participants[0];
+        assert!(
+            participant
+                .sessions
+                .dkg_part3(&participant.sealing_key, session_id, &round2)
+                .is_err()
+        );
+        assert!(matches!(
+            participant
+                .sessions
+                .dkg_part3(&participant.sealing_key, session_id, &round2),
+            Err(FrostError::UnknownSession)
+        ));
+    }
+
+    #[test]
+    fn test_key_share_is_bound_to_its_public_fields() {
+        let mut participants = participants(2);
+        let outputs = run_dkg(&mut participants, 2);
+        let mut key_share = key_share(&outputs[0]);
+        let data_key = SecretVec::from_vec(vec![1u8; 32]);
+        assert!(open_key_share(&key_share, &data_key).is_ok());
+
+        key_share.verifying_shares.swap(0, 1);
+        assert!(open_key_share(&key_share, &data_key).is_err());
+        key_share.verifying_shares.swap(0, 1);
+
+        // a quorum cannot be stripped, nor added
+        let mut quorum_share = seal_key_share(
+            DkgOutput {
+                identifier: outputs[0].identifier,
+                threshold: outputs[0].threshold,
+                signing_share: outputs[0].signing_share.clone(),
+                group_public_key: outputs[0].group_public_key,
+                verifying_shares: outputs[0].verifying_shares.clone(),
+            },
+            vec![1u8; 32],
+            vec![2u8; 16],
+            "key".to_string(),
+            None,
+            Some(approval::tests::quorum(2)),
+        )
+        .unwrap();
+        assert!(open_key_share(&quorum_share, &data_key).is_ok());
+        quorum_share.quorum = None;
+        assert!(open_key_share(&quorum_share, &data_key).is_err());
+        key_share.quorum = Some(approval::tests::quorum(1));
+        assert!(open_key_share(&key_share, &data_key).is_err());
+    }
+}
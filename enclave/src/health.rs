@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use shared::transport::{HealthIssue, KmsCredentials, VsockEnclaveHealthData};
+use zeroize::Zeroize;
+
+use crate::kmstool;
+use crate::nsm;
+
+/// Bytes asked of KMS to check that it is reachable; they are thrown away.
+const KMS_CHECK_BYTES: &str = "4";
+
+/// Reports the enclave's state for [`shared::transport::VsockHostRequest::HealthCheck`].
+pub async fn check(
+    credentials: &KmsCredentials,
+    uptime: Duration,
+    vsock_port: Option<u32>,
+) -> VsockEnclaveHealthData {
+    let mut degraded = Vec::new();
+
+    match kmstool::genrandom(
+        credentials.aws_region.as_str(),
+        credentials.aws_access_key_id.as_str(),
+        credentials.aws_secret_access_key.as_str(),
+        credentials.aws_session_token.as_str(),
+        credentials.kms_proxy_port.as_str(),
+        KMS_CHECK_BYTES,
+    )
+    .await
+    {
+        Ok([mut random_bytes]) => random_bytes.zeroize(),
+        Err(e) => degraded.push(HealthIssue::KmsUnreachable {
+            error: e.to_string(),
+        }),
+    }
+
+    let pcr0 = match nsm::describe_pcr(0) {
+        Ok(pcr0) => Some(pcr0),
+        Err(e) => {
+            degraded.push(HealthIssue::NsmUnavailable {
+                error: e.to_string(),
+            });
+            None
+        }
+    };
+
+    return VsockEnclaveHealthData {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        uptime_secs: uptime.as_secs(),
+        pcr0,
+        vsock_port,
+        degraded,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unreachable_dependencies_are_reported_as_degraded() {
+        // neither kmstool nor the NSM device exist outside an enclave
+        let health = check(
+            &KmsCredentials::default(),
+            Duration::from_secs(90),
+            Some(5000),
+        )
+        .await;
+
+        assert!(!health.is_healthy());
+        assert!(matches!(
+            health.degraded.as_slice(),
+            [
+                HealthIssue::KmsUnreachable { .. },
+                HealthIssue::NsmUnavailable { .. }
+            ]
+        ));
+        assert_eq!(health.pcr0, None);
+        assert_eq!(health.uptime_secs, 90);
+        assert_eq!(health.vsock_port, Some(5000));
+    }
+}
@@ -0,0 +1,167 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use pallas_crypto::key::ed25519;
+use shared::error::{ImportKeyError, VsockEnclaveSignError};
+use shared::policy::SignatureSchemeKind;
+use shared::transport::{ImportedKey, KmsCredentials};
+use zeroize::Zeroizing;
+
+use crate::kmstool;
+use crate::secret::{Erased, Secret, SecretVec};
+
+/// Gets the raw key out of an `ImportKey` request, decrypting it with KMS if
+/// needed. A plaintext key is only accepted if the request arrived sealed.
+pub async fn unwrap_key(
+    credentials: &KmsCredentials,
+    key: &ImportedKey,
+    arrived_sealed: bool,
+) -> Result<SecretVec, VsockEnclaveSignError> {
+    match key {
+        ImportedKey::Plaintext(key) => {
+            if !arrived_sealed {
+                return Err(ImportKeyError::NotSealed.into());
+            }
+            return Ok(SecretVec::from_slice(key));
+        }
+        ImportedKey::KmsRsaOaep { key_id, ciphertext } => {
+            let [key] = kmstool::decrypt_rsa_oaep(
+                credentials.aws_region.as_str(),
+                credentials.aws_access_key_id.as_str(),
+                credentials.aws_secret_access_key.as_str(),
+                credentials.aws_session_token.as_str(),
+                credentials.kms_proxy_port.as_str(),
+                key_id.as_str(),
+                BASE64_STANDARD.encode(ciphertext).as_str(),
+            )
+            .await?;
+            return Ok(SecretVec::from_vec(key));
+        }
+    }
+}
+
+/// Checks that `key` is a private key for `signature_scheme` and lays it out
+/// as a wallet secret: the 32 byte key, then zeros. Returns it with the public
+/// key `Sign` reports for it.
+pub fn validate(
+    signature_scheme: SignatureSchemeKind,
+    key: &[u8],
+) -> Result<(Secret<[u8; 64]>, Vec<u8>), ImportKeyError> {
+    let invalid = |reason: &str| ImportKeyError::InvalidKey {
+        scheme: signature_scheme,
+        reason: reason.to_string(),
+    };
+
+    let public_key = match signature_scheme {
+        SignatureSchemeKind::Secp256k1 | SignatureSchemeKind::Secp256k1Schnorr => {
+            if key.len() != 32 {
+                return Err(invalid("expected 32 bytes"));
+            }
+            let secret_key =
+                Erased(SecretKey::from_slice(key).map_err(|_| invalid("not a valid secret key"))?);
+            let public_key = secret_key.public_key(&Secp256k1::new());
+            match signature_scheme {
+                SignatureSchemeKind::Secp256k1Schnorr => {
+                    public_key.x_only_public_key().0.serialize().to_vec()
+                }
+                _ => public_key.serialize().to_vec(),
+            }
+        }
+        SignatureSchemeKind::Ed25519 => {
+            // Solana keypair files append the public key to the seed
+            if key.len() != 32 && key.len() != 64 {
+                return Err(invalid("expected a 32 byte seed"));
+            }
+            let seed: Zeroizing<[u8; 32]> = Zeroizing::new(key[..32].try_into().unwrap());
+            let public_key: [u8; 32] = ed25519::SecretKey::from(*seed).public_key().into();
+            if key.len() == 64 && key[32..] != public_key {
+                return Err(invalid("public key does not match the seed"));
+            }
+            public_key.to_vec()
+        }
+    };
+
+    let mut secret_key = Secret::<[u8; 64]>::from_slice(&[0u8; 64]).unwrap();
+    secret_key.expose_mut()[..32].copy_from_slice(&key[..32]);
+    return Ok((secret_key, public_key));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer;
+    use shared::transport::{SignatureScheme, WalletOrigin};
+
+    #[test]
+    fn test_imported_keys_sign_as_is() {
+        let key = [7u8; 32];
+        let (secret_key, public_key) = validate(SignatureSchemeKind::Secp256k1, &key).unwrap();
+        assert_eq!(&secret_key.expose()[..32], &key);
+        assert_eq!(&secret_key.expose()[32..], &[0u8; 32]);
+
+        let origin = WalletOrigin::Imported {
+            signature_scheme: SignatureSchemeKind::Secp256k1,
+        };
+        let signed = signer::sign(
+            secret_key.expose(),
+            origin,
+            "m",
+            &SignatureScheme::Secp256k1,
+            &[1u8; 32],
+        )
+        .unwrap();
+        assert_eq!(signed.public_key, public_key);
+
+        // no derivation, and no other scheme
+        assert!(
+            signer::sign(
+                secret_key.expose(),
+                origin,
+                "m/0",
+                &SignatureScheme::Secp256k1,
+                &[1u8; 32]
+            )
+            .is_err()
+        );
+        assert!(
+            signer::sign(
+                secret_key.expose(),
+                origin,
+                "m",
+                &SignatureScheme::Ed25519,
+                b"payload"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_ed25519_keypair_must_match_its_seed() {
+        let seed = [5u8; 32];
+        let (secret_key, public_key) = validate(SignatureSchemeKind::Ed25519, &seed).unwrap();
+        let keypair = [seed.as_slice(), &public_key].concat();
+        assert!(validate(SignatureSchemeKind::Ed25519, &keypair).is_ok());
+
+        let mismatched = [seed.as_slice(), &[1u8; 32]].concat();
+        assert!(validate(SignatureSchemeKind::Ed25519, &mismatched).is_err());
+
+        let origin = WalletOrigin::Imported {
+            signature_scheme: SignatureSchemeKind::Ed25519,
+        };
+        let signed = signer::sign(
+            secret_key.expose(),
+            origin,
+            "m",
+            &SignatureScheme::Ed25519,
+            b"payload",
+        )
+        .unwrap();
+        assert_eq!(signed.public_key, public_key);
+    }
+
+    #[test]
+    fn test_invalid_secp256k1_keys_are_rejected() {
+        assert!(validate(SignatureSchemeKind::Secp256k1, &[0u8; 32]).is_err());
+        assert!(validate(SignatureSchemeKind::Secp256k1Schnorr, &[0xffu8; 32]).is_err());
+        assert!(validate(SignatureSchemeKind::Secp256k1, &[7u8; 31]).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use lru::LruCache;
+
+use crate::secret::SecretVec;
+
+pub type SharedDataKeyCache = Arc<Mutex<DataKeyCache>>;
+
+struct CachedDataKey {
+    data_key: SecretVec,
+    inserted_at: Instant,
+}
+
+/// LRU cache of KMS-unwrapped AES data keys, keyed by the SHA-256 of the
+/// wallet's `kms_ciphertext`. Wallet secrets themselves are never cached.
+///
+/// A hit skips the KMS decrypt call, and with it KMS authorization of the
+/// request's credentials, so the cache is disabled unless a size is configured.
+/// Keys are zeroized when evicted, expired or replaced.
+pub struct DataKeyCache {
+    entries: Option<LruCache<[u8; 32], CachedDataKey>>,
+    ttl: Duration,
+}
+
+impl DataKeyCache {
+    /// A `max_size` of zero disables caching.
+    pub fn new(max_size: usize, ttl: Duration) -> Self {
+        return Self {
+            entries: NonZeroUsize::new(max_size).map(LruCache::new),
+            ttl,
+        };
+    }
+
+    pub fn get(&mut self, kms_ciphertext: &[u8]) -> Option<SecretVec> {
+        let entries = self.entries.as_mut()?;
+        let key = cache_key(kms_ciphertext);
+
+        let expired = entries.get(&key)?.inserted_at.elapsed() >= self.ttl;
+        if expired {
+            entries.pop(&key);
+            return None;
+        }
+        return entries.get(&key).map(|entry| entry.data_key.clone());
+    }
+
+    pub fn insert(&mut self, kms_ciphertext: &[u8], data_key: &SecretVec) {
+        if let Some(entries) = self.entries.as_mut() {
+            entries.put(
+                cache_key(kms_ciphertext),
+                CachedDataKey {
+                    data_key: data_key.clone(),
+                    inserted_at: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Evicts the entry for `kms_ciphertext`, or everything when `None`.
+    /// Returns the number of evicted entries.
+    pub fn evict(&mut self, kms_ciphertext: Option<&[u8]>) -> usize {
+        let Some(entries) = self.entries.as_mut() else {
+            return 0;
+        };
+        match kms_ciphertext {
+            Some(kms_ciphertext) => {
+                return entries.pop(&cache_key(kms_ciphertext)).map_or(0, |_| 1);
+            }
+            None => {
+                let evicted = entries.len();
+                entries.clear();
+                return evicted;
+            }
+        }
+    }
+}
+
+fn cache_key(kms_ciphertext: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(kms_ciphertext);
+    let mut key = [0u8; 32];
+    hasher.result(&mut key);
+    return key;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_cache_stores_nothing() {
+        let mut cache = DataKeyCache::new(0, Duration::from_secs(60));
+        cache.insert(b"ciphertext", &SecretVec::from_slice(&[1u8; 32]));
+        assert!(cache.get(b"ciphertext").is_none());
+        assert_eq!(cache.evict(None), 0);
+    }
+
+    #[test]
+    fn test_get_insert_and_lru_eviction() {
+        let mut cache = DataKeyCache::new(2, Duration::from_secs(60));
+        cache.insert(b"a", &SecretVec::from_slice(&[1u8; 32]));
+        cache.insert(b"b", &SecretVec::from_slice(&[2u8; 32]));
+        assert_eq!(cache.get(b"a").unwrap().expose(), &[1u8; 32]);
+
+        // "b" is now least recently used
+        cache.insert(b"c", &SecretVec::from_slice(&[3u8; 32]));
+        assert!(cache.get(b"b").is_none());
+        assert!(cache.get(b"a").is_some());
+        assert!(cache.get(b"c").is_some());
+    }
+
+    #[test]
+    fn test_expired_entries_are_dropped() {
+        let mut cache = DataKeyCache::new(2, Duration::ZERO);
+        cache.insert(b"a", &SecretVec::from_slice(&[1u8; 32]));
+        assert!(cache.get(b"a").is_none());
+        assert_eq!(cache.evict(None), 0);
+    }
+
+    #[test]
+    fn test_explicit_eviction() {
+        let mut cache = DataKeyCache::new(4, Duration::from_secs(60));
+        cache.insert(b"a", &SecretVec::from_slice(&[1u8; 32]));
+        cache.insert(b"b", &SecretVec::from_slice(&[2u8; 32]));
+        cache.insert(b"c", &SecretVec::from_slice(&[3u8; 32]));
+
+        assert_eq!(cache.evict(Some(b"a")), 1);
+        assert_eq!(cache.evict(Some(b"a")), 0);
+        assert!(cache.get(b"a").is_none());
+        assert_eq!(cache.evict(None), 2);
+        assert!(cache.get(b"b").is_none());
+    }
+}
@@ -1,6 +1,11 @@
 use base64::prelude::*;
 use shared::error::KmsToolError;
+use shared::transport::REDACTED;
+use std::time::Instant;
 use tokio::process::Command;
+use zeroize::Zeroize;
+
+use crate::stats;
 
 pub struct KmsGenkeyOutput {
     pub ciphertext: Vec<u8>,
@@ -15,7 +20,8 @@ pub async fn genrandom(
     kms_proxy_port: &str,
     byte_length: &str,
 ) -> Result<[Vec<u8>; 1], KmsToolError> {
-    let result = Command::new("kmstool_enclave_cli")
+    let mut command = Command::new("kmstool_enclave_cli");
+    command
         .arg("genrandom")
         .arg("--region")
         .arg(aws_region)
@@ -28,11 +34,11 @@ pub async fn genrandom(
         .arg("--proxy-port")
         .arg(kms_proxy_port)
         .arg("--length")
-        .arg(byte_length)
-        .output()
-        .await?;
-    let parsed = parse_output(["PLAINTEXT: "], &result)?;
-    return Ok(parsed);
+        .arg(byte_length);
+    let mut result = run(&mut command).await?;
+    let parsed = parse_output(["PLAINTEXT: "], &result);
+    result.stdout.zeroize();
+    return parsed;
 }
 
 pub async fn genkey(
@@ -44,7 +50,8 @@ pub async fn genkey(
     key_id: &str,
     key_spec: &str,
 ) -> Result<[Vec<u8>; 2], KmsToolError> {
-    let result = Command::new("kmstool_enclave_cli")
+    let mut command = Command::new("kmstool_enclave_cli");
+    command
         .arg("genkey")
         .arg("--region")
         .arg(region)
@@ -59,11 +66,11 @@ pub async fn genkey(
         .arg("--key-id")
         .arg(key_id)
         .arg("--key-spec")
-        .arg(key_spec)
-        .output()
-        .await?;
-    let parsed = parse_output(["CIPHERTEXT: ", "PLAINTEXT: "], &result)?;
-    return Ok(parsed);
+        .arg(key_spec);
+    let mut result = run(&mut command).await?;
+    let parsed = parse_output(["CIPHERTEXT: ", "PLAINTEXT: "], &result);
+    result.stdout.zeroize();
+    return parsed;
 }
 
 pub async fn decrypt(
@@ -74,8 +81,9 @@ pub async fn decrypt(
     proxy_port: &str,
     ciphertext_base64: &str,
 ) -> Result<[Vec<u8>; 1], KmsToolError> {
-    let result = Command::new("kmstool_enclave_cli")
-        .arg("genkey")
+    let mut command = Command::new("kmstool_enclave_cli");
+    command
+        .arg("decrypt")
         .arg("--region")
         .arg(region)
         .arg("--aws-access-key-id")
@@ -87,11 +95,66 @@ pub async fn decrypt(
         .arg("--proxy-port")
         .arg(proxy_port)
         .arg("--ciphertext")
-        .arg(ciphertext_base64)
-        .output()
-        .await?;
-    let parsed = parse_output(["PLAINTEXT: "], &result)?;
-    return Ok(parsed);
+        .arg(ciphertext_base64);
+    let mut result = run(&mut command).await?;
+    let parsed = parse_output(["PLAINTEXT: "], &result);
+    result.stdout.zeroize();
+    return parsed;
+}
+
+/// Decrypts `ciphertext_base64`, encrypted with `RSAES_OAEP_SHA_256` to the
+/// public key of the asymmetric KMS key `key_id`.
+pub async fn decrypt_rsa_oaep(
+    region: &str,
+    access_key_id: &str,
+    secret_access_key: &str,
+    session_token: &str,
+    proxy_port: &str,
+    key_id: &str,
+    ciphertext_base64: &str,
+) -> Result<[Vec<u8>; 1], KmsToolError> {
+    let mut command = Command::new("kmstool_enclave_cli");
+    command
+        .arg("decrypt")
+        .arg("--region")
+        .arg(region)
+        .arg("--aws-access-key-id")
+        .arg(access_key_id)
+        .arg("--aws-secret-access-key")
+        .arg(secret_access_key)
+        .arg("--aws-session-token")
+        .arg(session_token)
+        .arg("--proxy-port")
+        .arg(proxy_port)
+        .arg("--key-id")
+        .arg(key_id)
+        .arg("--encryption-algorithm")
+        .arg("RSAES_OAEP_SHA_256")
+        .arg("--ciphertext")
+        .arg(ciphertext_base64);
+    let mut result = run(&mut command).await?;
+    let parsed = parse_output(["PLAINTEXT: "], &result);
+    result.stdout.zeroize();
+    return parsed;
+}
+
+/// Runs kmstool, recording the call's latency in the request's stats.
+async fn run(command: &mut Command) -> Result<std::process::Output, KmsToolError> {
+    let name = command
+        .as_std()
+        .get_args()
+        .next()
+        .and_then(|arg| arg.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let started = Instant::now();
+    let output = command.output().await;
+    stats::record_kms_call(
+        name,
+        started,
+        output.as_ref().is_ok_and(|output| output.status.success()),
+    );
+    return Ok(output?);
 }
 
 fn parse_output<const N: usize>(
@@ -104,7 +167,7 @@ fn parse_output<const N: usize>(
             .lines()
             .find_map(|line| line.trim().strip_prefix(prefix))
             .ok_or_else(|| KmsToolError::StdoutParse {
-                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stdout: redact_plaintext(&stdout),
                 status: output.status.to_string(),
                 stderr: String::from_utf8_lossy(&output.stderr).to_string(),
             })
@@ -116,12 +179,25 @@ fn parse_output<const N: usize>(
         .collect::<Result<Vec<_>, _>>()?
         .try_into()
         .map_err(|_| KmsToolError::StdoutParse {
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+            stdout: redact_plaintext(&stdout),
             status: output.status.to_string(),
             stderr: String::from_utf8_lossy(&output.stderr).to_string(),
         });
 }
 
+/// The stdout for errors, which reach the host and the logs, without the
+/// plaintext of any data key or secret kmstool printed.
+fn redact_plaintext(stdout: &str) -> String {
+    return stdout
+        .lines()
+        .map(|line| match line.trim().strip_prefix("PLAINTEXT:") {
+            Some(_) => format!("PLAINTEXT: {}", REDACTED),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -208,4 +284,19 @@ mod tests {
         assert_eq!(result[0], b"ciphertext");
         assert_eq!(result[1], b"secretdata!");
     }
+
+    #[test]
+    fn test_parse_error_does_not_include_plaintext() {
+        let stdout = format!("PLAINTEXT: {}\n", BASE64_STANDARD.encode(b"secretdata!"));
+        let output = Output {
+            status: ExitStatus::from_raw(256),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        };
+
+        let err = parse_output(["CIPHERTEXT: ", "PLAINTEXT: "], &output).unwrap_err();
+        let message = err.to_string();
+        assert!(!message.contains(&BASE64_STANDARD.encode(b"secretdata!")));
+        assert!(message.contains(REDACTED));
+    }
 }
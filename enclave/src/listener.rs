@@ -0,0 +1,44 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener};
+
+use crate::cli::Args;
+
+/// A host connection, over vsock or TCP.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// Where host requests arrive: vsock inside a Nitro enclave, or TCP so that
+/// several enclave processes can run side by side on one machine, e.g. to test
+/// threshold signing.
+pub enum Listener {
+    Vsock(VsockListener),
+    Tcp(TcpListener),
+}
+
+impl Listener {
+    pub async fn bind(args: &Args) -> std::io::Result<Self> {
+        if let Some(tcp_listen) = args.tcp_listen {
+            return Ok(Listener::Tcp(TcpListener::bind(tcp_listen).await?));
+        }
+
+        // clap requires the vsock port when no tcp address is given
+        let vsock_port = args.vsock_port.unwrap_or_default();
+        let vsock_addr = VsockAddr::new(VMADDR_CID_ANY, vsock_port);
+        return Ok(Listener::Vsock(VsockListener::bind(vsock_addr)?));
+    }
+
+    pub async fn accept(&self) -> std::io::Result<(Box<dyn Connection>, String)> {
+        return match self {
+            Listener::Vsock(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), addr.to_string()))
+            }
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), addr.to_string()))
+            }
+        };
+    }
+}
@@ -0,0 +1,89 @@
+use std::io;
+
+use shared::error::ErrorCode;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio_vsock::{VsockAddr, VsockStream};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// The parent instance is always CID 3 to its enclaves.
+const PARENT_CID: u32 = 3;
+
+/// Log lines buffered for the host before new ones are dropped.
+const LOG_CHANNEL_CAPACITY: usize = 1024;
+
+/// Logs JSON lines to stderr and, with `log_vsock_port`, forwards them to the
+/// parent instance on that vsock port, since an enclave's console is only
+/// readable in debug mode. The level comes from `RUST_LOG`, `info` by default.
+///
+/// Must be called from within the tokio runtime.
+pub fn init(log_vsock_port: Option<u32>) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let stderr = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(io::stderr);
+    let vsock = log_vsock_port.map(|port| {
+        let (sender, receiver) = mpsc::channel(LOG_CHANNEL_CAPACITY);
+        tokio::spawn(forward(port, receiver));
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(VsockLogWriter { sender })
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stderr)
+        .with(vsock)
+        .init();
+}
+
+/// The error code of a failed request, for the request's log line.
+pub fn error_code<T, E: ErrorCode>(result: &Result<T, E>) -> Option<&'static str> {
+    return result.as_ref().err().map(ErrorCode::code);
+}
+
+/// Hands each formatted line to [`forward`]. Logging must never block or fail
+/// a request, so lines are dropped while the channel is full.
+#[derive(Clone)]
+struct VsockLogWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+}
+
+impl io::Write for VsockLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _ = self.sender.try_send(buf.to_vec());
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+impl<'a> MakeWriter<'a> for VsockLogWriter {
+    type Writer = VsockLogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        return self.clone();
+    }
+}
+
+/// Writes log lines to the host, reconnecting for the next line after a
+/// failure. Lines logged while the host is not listening are lost.
+async fn forward(port: u32, mut receiver: mpsc::Receiver<Vec<u8>>) {
+    let addr = VsockAddr::new(PARENT_CID, port);
+    let mut stream: Option<VsockStream> = None;
+    while let Some(line) = receiver.recv().await {
+        if stream.is_none() {
+            stream = VsockStream::connect(addr).await.ok();
+        }
+        if let Some(connected) = &mut stream
+            && connected.write_all(&line).await.is_err()
+        {
+            stream = None;
+        }
+    }
+}
@@ -1,159 +1,1377 @@
-use base64::{Engine, prelude::BASE64_STANDARD};
 use clap::Parser;
+use shared::attestation::AttestationVerifier;
+use shared::audit::SignedAuditEvent;
+use shared::error::{BackupError, PolicyError};
+use shared::policy::{Chain, SignatureSchemeKind};
 use shared::transport::{
-    SignatureScheme, VsockEnclaveCreateWalletData, VsockEnclaveCreateWalletResponse,
-    VsockEnclaveSignData, VsockEnclaveSignResponse, VsockHostRequest, VsockTransport,
+    RequestStats, VsockEnclaveAttestationData, VsockEnclaveAttestationResponse,
+    VsockEnclaveAuditKeyResponse, VsockEnclaveCreateWalletData, VsockEnclaveCreateWalletResponse,
+    VsockEnclaveEvictCacheData, VsockEnclaveEvictCacheResponse, VsockEnclaveExportBackupResponse,
+    VsockEnclaveFrostCommitResponse, VsockEnclaveFrostDkgPart1Response,
+    VsockEnclaveFrostDkgPart2Response, VsockEnclaveFrostDkgPart3Response,
+    VsockEnclaveFrostSignResponse, VsockEnclaveHealthResponse, VsockEnclaveImportBackupResponse,
+    VsockEnclaveImportKeyData, VsockEnclaveImportKeyResponse, VsockEnclavePingResponse,
+    VsockEnclaveResponseTrailer, VsockEnclaveRewrapWalletResponse, VsockEnclaveSealedResponse,
+    VsockEnclaveSealingKeyResponse, VsockEnclaveSetWalletPolicyResponse, VsockEnclaveShutdownData,
+    VsockEnclaveShutdownResponse, VsockEnclaveSignBatchResponse,
+    VsockEnclaveSignCardanoMessageResponse, VsockEnclaveSignCardanoTxResponse,
+    VsockEnclaveSignEthereumMessageResponse, VsockEnclaveSignEthereumTxResponse,
+    VsockEnclaveSignPsbtResponse, VsockEnclaveSignResponse, VsockEnclaveSignSolanaTxResponse,
+    VsockHostRequest, VsockTransport, WalletOrigin,
 };
-use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing::{Instrument, error, field, info, info_span, warn};
 
-use crate::aes256gcm::{decrypt_private_key_aes256gcm, encrypt_private_key_aes256gcm};
+use crate::audit::{AuditLog, SharedAuditLog};
+use crate::channel::{OperatorChannel, SharedOperatorChannel};
+use crate::frost::{FrostSessions, PeerAttestation, SharedFrostSessions};
+use crate::key_cache::{DataKeyCache, SharedDataKeyCache};
+use crate::listener::Listener;
+use crate::policy::{PolicyEngine, SharedPolicyEngine, SigningIntent};
+use crate::sealing::{SealingKey, SharedSealingKey};
+use crate::shutdown::{SharedShutdown, Shutdown};
 
 pub mod aes256gcm;
+pub mod approval;
+pub mod audit;
+pub mod backup;
+pub mod cardano;
+pub mod channel;
 pub mod cli;
+pub mod eip712;
+pub mod ethereum;
+pub mod frost;
+pub mod health;
+pub mod import;
+pub mod key_cache;
 pub mod kmstool;
+pub mod listener;
+pub mod logging;
+pub mod nsm;
+pub mod policy;
+pub mod psbt;
+pub mod schnorr;
+pub mod sealing;
+pub mod secret;
+pub mod shamir;
+pub mod shutdown;
+pub mod signer;
+pub mod slip39;
+pub mod solana;
+pub mod stats;
+pub mod wallet;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let booted = Instant::now();
     let args = cli::Args::parse();
-    let vsock_addr = VsockAddr::new(VMADDR_CID_ANY, args.vsock_port);
-    let listener = VsockListener::bind(vsock_addr)
-        .expect(&format!("failed to bind vsock on port {}", args.vsock_port));
+    logging::init(args.log_vsock_port);
+    let listener = Listener::bind(&args)
+        .await
+        .unwrap_or_else(|e| panic!("failed to bind listener: {}", e));
+    let key_cache: SharedDataKeyCache = Arc::new(Mutex::new(DataKeyCache::new(
+        args.data_key_cache_size,
+        Duration::from_secs(args.data_key_cache_ttl_secs),
+    )));
+    let policy_engine: SharedPolicyEngine = Arc::new(PolicyEngine::new(
+        args.policy_admin_public_key.map(|key| key.0),
+    ));
+    let operator_channel: SharedOperatorChannel = Arc::new(OperatorChannel::new(
+        args.operator_public_key.iter().map(|key| key.0).collect(),
+    )?);
+    let sealing_key: SharedSealingKey = Arc::new(SealingKey::generate()?);
+    let audit_log: SharedAuditLog = Arc::new(Mutex::new(AuditLog::generate()?));
+    let frost_sessions: SharedFrostSessions = Arc::new(Mutex::new(FrostSessions::new()));
+    let peer_attestation: Arc<Option<PeerAttestation>> =
+        Arc::new(if args.insecure_skip_peer_attestation {
+            warn!("peer attestation is disabled, never use this in production");
+            Some(PeerAttestation::Insecure)
+        } else {
+            (!args.peer_pcr.is_empty()).then(|| {
+                PeerAttestation::Verify(AttestationVerifier::new(
+                    args.peer_pcr
+                        .iter()
+                        .map(|pcr| (pcr.0, pcr.1.clone()))
+                        .collect(),
+                ))
+            })
+        });
+
+    let shutdown: SharedShutdown =
+        Arc::new(Shutdown::new(Duration::from_secs(args.shutdown_grace_secs)));
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
 
+    let mut next_request_id: u64 = 0;
     loop {
-        let (stream, addr) = match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = terminate.recv() => {
+                info!(signal = "SIGTERM", "shutting down");
+                break;
+            }
+            _ = interrupt.recv() => {
+                info!(signal = "SIGINT", "shutting down");
+                break;
+            }
+            _ = shutdown.requested() => {
+                info!("shutting down on request");
+                break;
+            }
+        };
+        let (stream, addr) = match accepted {
             Ok(connection) => connection,
             Err(e) => {
-                #[cfg(debug_assertions)]
-                eprintln!("failed to accept connection with error: {}", e);
+                error!(error = %e, "failed to accept connection");
                 continue;
             }
         };
 
-        #[cfg(debug_assertions)]
-        println!("received vsock connection {} ", addr);
+        let request_id = next_request_id;
+        next_request_id = next_request_id.wrapping_add(1);
+        let span = info_span!(
+            "request",
+            request_id,
+            peer = %addr,
+            operation = field::Empty,
+            sealed = field::Empty,
+        );
 
-        tokio::spawn(async move {
+        let key_cache = key_cache.clone();
+        let policy_engine = policy_engine.clone();
+        let operator_channel = operator_channel.clone();
+        let sealing_key = sealing_key.clone();
+        let audit_log = audit_log.clone();
+        let frost_sessions = frost_sessions.clone();
+        let peer_attestation = peer_attestation.clone();
+        let vsock_port = args.vsock_port;
+        let shutdown = shutdown.clone();
+        let in_flight = shutdown.start_request();
+        let task = async move {
+            let _in_flight = in_flight;
+            let started = Instant::now();
             let mut transport = VsockTransport::new(stream);
 
-            let request = match transport.receive::<VsockHostRequest>().await {
+            let mut request = match transport.receive::<VsockHostRequest>().await {
                 Ok(request) => request,
                 Err(e) => {
-                    // TODO: figure out how best to handle vsock errors instead of silently failing
-                    #[cfg(debug_assertions)]
-                    eprintln!("failed to receive request: {}", e);
+                    warn!(error = %e, "failed to receive request");
                     return;
                 }
             };
 
+            let mut authenticated = !operator_channel.requires_authentication();
+            if let VsockHostRequest::Handshake { noise_message } = &request {
+                match operator_channel.accept(&mut transport, noise_message).await {
+                    Ok(is_operator) => authenticated |= is_operator,
+                    Err(e) => {
+                        warn!(error = %e, "failed to complete handshake");
+                        return;
+                    }
+                }
+
+                request = match transport.receive::<VsockHostRequest>().await {
+                    Ok(request) => request,
+                    Err(e) => {
+                        warn!(error = %e, "failed to receive request");
+                        return;
+                    }
+                };
+            }
+
+            if !authenticated {
+                warn!(
+                    operation = request.operation(),
+                    "rejected unauthenticated request"
+                );
+                let send_result = channel::reject_unauthenticated(&mut transport, &request).await;
+
+                if let Err(e) = send_result {
+                    warn!(error = %e, "failed to send response");
+                }
+                return;
+            }
+
+            let mut arrived_sealed = false;
+            if let VsockHostRequest::Sealed { sealed_request } = &request {
+                match sealing_key.open(sealed_request) {
+                    Ok((sealed, response_key)) => {
+                        request = sealed;
+                        arrived_sealed = true;
+                        transport.seal_next_reply(response_key);
+                    }
+                    Err(e) => {
+                        warn!(error = %e, "failed to open sealed request");
+                        let result: VsockEnclaveSealedResponse = Err(e.into());
+                        let send_result =
+                            transport.send::<VsockEnclaveSealedResponse>(&result).await;
+
+                        if let Err(e) = send_result {
+                            warn!(error = %e, "failed to send response");
+                        }
+                        return;
+                    }
+                }
+            }
+
+            let span = tracing::Span::current();
+            span.record("operation", request.operation());
+            span.record("sealed", arrived_sealed);
+
+            let request_hash = approval::request_hash(&request);
+            let audit_context = audit::context(&request);
+            let mut audit_event: Option<SignedAuditEvent> = None;
+            let error_code: Option<&'static str>;
+
             match request {
+                VsockHostRequest::Handshake { .. } => {
+                    // a connection only gets one handshake
+                    warn!("received a handshake on an established channel");
+                    return;
+                }
+                VsockHostRequest::Sealed { .. } => {
+                    warn!("received a sealed request inside a sealed request");
+                    return;
+                }
+                VsockHostRequest::GetAttestation {
+                    nonce,
+                    user_data,
+                    public_key,
+                } => {
+                    let result: VsockEnclaveAttestationResponse = nsm::attestation(
+                        user_data.as_deref(),
+                        nonce.as_deref(),
+                        public_key.as_deref(),
+                    )
+                    .map(|document| VsockEnclaveAttestationData { document })
+                    .map_err(Into::into);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveAttestationResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::GetSealingKey { nonce } => {
+                    let result: VsockEnclaveSealingKeyResponse =
+                        sealing_key.attest(nonce.as_deref()).map_err(Into::into);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSealingKeyResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::GetAuditKey { nonce } => {
+                    let result: VsockEnclaveAuditKeyResponse = audit_log
+                        .lock()
+                        .unwrap()
+                        .attest(nonce.as_deref())
+                        .map_err(Into::into);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveAuditKeyResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::Ping => {
+                    let result: VsockEnclavePingResponse = Ok(());
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport.send::<VsockEnclavePingResponse>(&result).await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::HealthCheck { credentials } => {
+                    let health = health::check(&credentials, booted.elapsed(), vsock_port).await;
+                    if !health.is_healthy() {
+                        warn!(degraded = ?health.degraded, "enclave is degraded");
+                    }
+                    let result: VsockEnclaveHealthResponse = Ok(health);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport.send::<VsockEnclaveHealthResponse>(&result).await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::Shutdown => {
+                    shutdown.request();
+                    let drained = shutdown.drain_others().await;
+                    let result: VsockEnclaveShutdownResponse =
+                        Ok(VsockEnclaveShutdownData { drained });
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveShutdownResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
                 VsockHostRequest::CreateWallet {
-                    aws_access_key_id,
-                    aws_region,
-                    aws_secret_access_key,
-                    aws_session_token,
-                    kms_proxy_port,
+                    credentials,
                     kms_key_id,
                     aes_gcm_nonce,
+                    policy,
+                    quorum,
                 } => {
                     let result = (async || -> VsockEnclaveCreateWalletResponse {
-                        let genrandom_output = kmstool::genrandom(
-                            aws_region.as_str(),
-                            aws_access_key_id.as_str(),
-                            aws_secret_access_key.as_str(),
-                            aws_session_token.as_str(),
-                            kms_proxy_port.as_str(),
+                        if let Some(policy) = &policy {
+                            // the wallet does not exist yet, so cannot be named
+                            if policy_engine.verify(policy)?.wallet.is_some() {
+                                return Err(PolicyError::WrongWallet.into());
+                            }
+                        }
+                        if let Some(quorum) = &quorum {
+                            approval::validate_quorum(quorum)?;
+                        }
+
+                        let [random_bytes] = kmstool::genrandom(
+                            credentials.aws_region.as_str(),
+                            credentials.aws_access_key_id.as_str(),
+                            credentials.aws_secret_access_key.as_str(),
+                            credentials.aws_session_token.as_str(),
+                            credentials.kms_proxy_port.as_str(),
                             "64",
                         )
                         .await?;
 
-                        let private_key = &genrandom_output[0];
-                        #[cfg(debug_assertions)]
-                        eprintln!("kmstool::genrandom() stdout: {:?}", private_key);
-
                         let [encryption_key_ciphertext, encryption_key_plaintext] =
                             kmstool::genkey(
-                                aws_region.as_str(),
-                                aws_access_key_id.as_str(),
-                                aws_secret_access_key.as_str(),
-                                aws_session_token.as_str(),
-                                kms_proxy_port.as_str(),
+                                credentials.aws_region.as_str(),
+                                credentials.aws_access_key_id.as_str(),
+                                credentials.aws_secret_access_key.as_str(),
+                                credentials.aws_session_token.as_str(),
+                                credentials.kms_proxy_port.as_str(),
                                 kms_key_id.as_str(),
                                 "AES-256",
                             )
                             .await?;
+                        let wallet_id = wallet::wallet_id(&random_bytes);
 
-                        let private_key_ciphertext = encrypt_private_key_aes256gcm(
-                            private_key.as_slice().try_into().unwrap(), // TODO: remove .unwrap()
-                            &encryption_key_plaintext,
+                        let private_key_ciphertext = wallet::seal_secret_key(
+                            random_bytes,
+                            encryption_key_plaintext,
                             &aes_gcm_nonce,
+                            policy.as_ref(),
+                            quorum.as_ref(),
                         )?;
 
                         return Ok(VsockEnclaveCreateWalletData {
-                            aes_gcm_nonce: aes_gcm_nonce,
+                            aes_gcm_nonce,
                             encrypted_secret_key: private_key_ciphertext,
                             kms_ciphertext: encryption_key_ciphertext,
-                            kms_key_id: kms_key_id,
+                            kms_key_id,
+                            policy,
+                            quorum,
+                            origin: WalletOrigin::Generated,
+                            wallet_id,
                         });
                     })()
                     .await;
 
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|wallet| wallet.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
                     let send_result = transport
                         .send::<VsockEnclaveCreateWalletResponse>(&result)
                         .await;
 
                     if let Err(e) = send_result {
-                        // TODO: figure out how best to handle vsock errors instead of silently failing
-                        #[cfg(debug_assertions)]
-                        eprintln!("failed to send send result: {}", e);
+                        warn!(error = %e, "failed to send response");
                         return;
                     }
                 }
-                VsockHostRequest::Sign {
-                    aws_region,
-                    aws_access_key_id,
-                    aws_secret_access_key,
-                    aws_session_token,
-                    kms_proxy_port,
+                VsockHostRequest::SetWalletPolicy {
+                    credentials,
+                    wallet,
+                    policy,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSetWalletPolicyResponse {
+                        let data_key =
+                            wallet::unwrap_data_key(&credentials, &wallet, &key_cache).await?;
+                        let private_key = wallet::open_secret_key(&wallet, &data_key)?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        let wallet_id = wallet::wallet_id(private_key.expose());
+
+                        // an unbound policy could be replayed onto any wallet
+                        let new_policy = policy_engine.verify(&policy)?;
+                        if new_policy.wallet != Some(wallet_id) {
+                            return Err(PolicyError::WrongWallet.into());
+                        }
+                        let new_version = new_policy.version;
+                        if let Some(current) = &wallet.policy {
+                            let current_version = policy_engine.verify(current)?.version;
+                            if new_version <= current_version {
+                                return Err(PolicyError::VersionNotIncreasing {
+                                    current: current_version,
+                                    new: new_version,
+                                }
+                                .into());
+                            }
+                        }
+                        policy_engine.check_version(&wallet_id, new_version)?;
+
+                        return wallet::reseal_secret_key(&wallet, &data_key, &private_key, policy);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|wallet| wallet.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSetWalletPolicyResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::RewrapWallet {
+                    credentials,
+                    wallet,
+                    new_credentials,
+                    new_kms_key_id,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveRewrapWalletResponse {
+                        // a forged quorum fails to decrypt below, as it is
+                        // bound to the envelope
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        let private_key =
+                            wallet::decrypt_secret_key(&credentials, &wallet, &key_cache).await?;
+
+                        let [encryption_key_ciphertext, encryption_key_plaintext] =
+                            kmstool::genkey(
+                                new_credentials.aws_region.as_str(),
+                                new_credentials.aws_access_key_id.as_str(),
+                                new_credentials.aws_secret_access_key.as_str(),
+                                new_credentials.aws_session_token.as_str(),
+                                new_credentials.kms_proxy_port.as_str(),
+                                new_kms_key_id.as_str(),
+                                "AES-256",
+                            )
+                            .await?;
+
+                        return wallet::rewrap_secret_key(
+                            &wallet,
+                            &private_key,
+                            encryption_key_plaintext,
+                            encryption_key_ciphertext,
+                            new_kms_key_id,
+                        );
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|wallet| wallet.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveRewrapWalletResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::ExportBackup {
+                    credentials,
+                    wallet,
+                    recovery_keys,
+                    threshold,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveExportBackupResponse {
+                        let private_key =
+                            wallet::decrypt_secret_key(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+
+                        return Ok(backup::export(
+                            &wallet,
+                            &private_key,
+                            &recovery_keys,
+                            threshold,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveExportBackupResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::ImportBackup {
+                    credentials,
+                    kms_key_id,
+                    shares,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveImportBackupResponse {
+                        if !arrived_sealed {
+                            return Err(BackupError::NotSealed.into());
+                        }
+                        let private_key = backup::restore(&shares)?;
+                        // restore checked that every share agrees on these
+                        let policy = shares[0].policy.clone();
+                        let quorum = shares[0].quorum.clone();
+                        let origin = shares[0].origin;
+                        if let Some(policy) = &policy {
+                            policy_engine
+                                .verify_for(policy, &wallet::wallet_id(private_key.expose()))?;
+                        }
+                        if let Some(quorum) = &quorum {
+                            approval::validate_quorum(quorum)?;
+                        }
+                        approval::verify(quorum.as_ref(), &request_hash, &approvals)?;
+
+                        let [encryption_key_ciphertext, encryption_key_plaintext] =
+                            kmstool::genkey(
+                                credentials.aws_region.as_str(),
+                                credentials.aws_access_key_id.as_str(),
+                                credentials.aws_secret_access_key.as_str(),
+                                credentials.aws_session_token.as_str(),
+                                credentials.kms_proxy_port.as_str(),
+                                kms_key_id.as_str(),
+                                "AES-256",
+                            )
+                            .await?;
+
+                        return wallet::restore_secret_key(
+                            &private_key,
+                            encryption_key_plaintext,
+                            encryption_key_ciphertext,
+                            kms_key_id,
+                            policy,
+                            quorum,
+                            origin,
+                        );
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|wallet| wallet.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveImportBackupResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::ImportKey {
+                    credentials,
                     kms_key_id,
-                    aes_gcm_nonce,
-                    encrypted_secret_key,
-                    kms_ciphertext,
                     signature_scheme,
+                    key,
+                    policy,
+                    quorum,
                 } => {
-                    let result = (async || -> VsockEnclaveSignResponse {
-                        let kms_ciphertext_base64 = BASE64_STANDARD.encode(kms_ciphertext);
-                        let [decrypted_encryption_key] = kmstool::decrypt(
-                            aws_region.as_str(),
-                            aws_access_key_id.as_str(),
-                            aws_secret_access_key.as_str(),
-                            aws_session_token.as_str(),
-                            kms_proxy_port.as_str(),
-                            kms_ciphertext_base64.as_str(),
-                        )
-                        .await?;
+                    let result = (async || -> VsockEnclaveImportKeyResponse {
+                        if let Some(quorum) = &quorum {
+                            approval::validate_quorum(quorum)?;
+                        }
+                        let key = import::unwrap_key(&credentials, &key, arrived_sealed).await?;
+                        let (private_key, public_key) =
+                            import::validate(signature_scheme, key.expose())?;
+                        if let Some(policy) = &policy {
+                            policy_engine
+                                .verify_for(policy, &wallet::wallet_id(private_key.expose()))?;
+                        }
 
-                        let private_key = decrypt_private_key_aes256gcm(
-                            &encrypted_secret_key,
-                            &decrypted_encryption_key,
-                            &aes_gcm_nonce,
+                        let [encryption_key_ciphertext, encryption_key_plaintext] =
+                            kmstool::genkey(
+                                credentials.aws_region.as_str(),
+                                credentials.aws_access_key_id.as_str(),
+                                credentials.aws_secret_access_key.as_str(),
+                                credentials.aws_session_token.as_str(),
+                                credentials.kms_proxy_port.as_str(),
+                                kms_key_id.as_str(),
+                                "AES-256",
+                            )
+                            .await?;
+
+                        let wallet = wallet::restore_secret_key(
+                            &private_key,
+                            encryption_key_plaintext,
+                            encryption_key_ciphertext,
+                            kms_key_id,
+                            policy,
+                            quorum,
+                            WalletOrigin::Imported { signature_scheme },
                         )?;
+                        return Ok(VsockEnclaveImportKeyData { wallet, public_key });
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|data| data.wallet.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveImportKeyResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::FrostDkgPart1 {
+                    session_id,
+                    identifier,
+                    threshold,
+                    participants,
+                } => {
+                    let result: VsockEnclaveFrostDkgPart1Response = frost_sessions
+                        .lock()
+                        .unwrap()
+                        .dkg_part1(
+                            &sealing_key,
+                            session_id,
+                            identifier,
+                            threshold,
+                            participants,
+                        )
+                        .map_err(Into::into);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveFrostDkgPart1Response>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::FrostDkgPart2 {
+                    session_id,
+                    round1_packages,
+                } => {
+                    let result: VsockEnclaveFrostDkgPart2Response = frost_sessions
+                        .lock()
+                        .unwrap()
+                        .dkg_part2(
+                            session_id,
+                            round1_packages,
+                            peer_attestation.as_ref().as_ref(),
+                        )
+                        .map_err(Into::into);
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveFrostDkgPart2Response>(&result)
+                        .await;
 
-                        match signature_scheme {
-                            SignatureScheme::Ed25519 => {
-                                // TODO: implement Ed25519 signing
-                                todo!();
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::FrostDkgPart3 {
+                    session_id,
+                    credentials,
+                    kms_key_id,
+                    round2_packages,
+                    policy,
+                    quorum,
+                } => {
+                    let result = (async || -> VsockEnclaveFrostDkgPart3Response {
+                        if let Some(policy) = &policy {
+                            // the group key does not exist yet, so cannot be named
+                            if policy_engine.verify(policy)?.wallet.is_some() {
+                                return Err(PolicyError::WrongWallet.into());
                             }
-                            SignatureScheme::Secp256k1 => {}
                         }
+                        if let Some(quorum) = &quorum {
+                            approval::validate_quorum(quorum)?;
+                        }
+                        let output = frost_sessions.lock().unwrap().dkg_part3(
+                            &sealing_key,
+                            session_id,
+                            &round2_packages,
+                        )?;
+
+                        let [encryption_key_ciphertext, encryption_key_plaintext] =
+                            kmstool::genkey(
+                                credentials.aws_region.as_str(),
+                                credentials.aws_access_key_id.as_str(),
+                                credentials.aws_secret_access_key.as_str(),
+                                credentials.aws_session_token.as_str(),
+                                credentials.kms_proxy_port.as_str(),
+                                kms_key_id.as_str(),
+                                "AES-256",
+                            )
+                            .await?;
+
+                        return frost::seal_key_share(
+                            output,
+                            encryption_key_plaintext,
+                            encryption_key_ciphertext,
+                            kms_key_id,
+                            policy,
+                            quorum,
+                        );
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        result
+                            .as_ref()
+                            .ok()
+                            .map(|key_share| key_share.kms_ciphertext.as_slice()),
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveFrostDkgPart3Response>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::FrostCommit {
+                    credentials,
+                    key_share,
+                } => {
+                    let result = (async || -> VsockEnclaveFrostCommitResponse {
+                        let data_key = wallet::unwrap_kms_ciphertext(
+                            &credentials,
+                            &key_share.kms_ciphertext,
+                            &key_cache,
+                        )
+                        .await?;
+                        let signing_share = frost::open_key_share(&key_share, &data_key)?;
+
+                        return Ok(frost_sessions
+                            .lock()
+                            .unwrap()
+                            .commit(&key_share, &signing_share)?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveFrostCommitResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::FrostSign {
+                    credentials,
+                    key_share,
+                    message,
+                    commitments,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveFrostSignResponse {
+                        let data_key = wallet::unwrap_kms_ciphertext(
+                            &credentials,
+                            &key_share.kms_ciphertext,
+                            &key_cache,
+                        )
+                        .await?;
+                        let signing_share = frost::open_key_share(&key_share, &data_key)?;
+                        approval::verify(key_share.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            key_share.policy.as_ref(),
+                            &frost::key_id(&key_share),
+                            &SigningIntent::opaque(vec![SignatureSchemeKind::Ed25519]),
+                        )?;
+
+                        return Ok(frost_sessions.lock().unwrap().sign(
+                            &key_share,
+                            &signing_share,
+                            &message,
+                            &commitments,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
 
-                        return Ok(VsockEnclaveSignData {});
+                    let send_result = transport
+                        .send::<VsockEnclaveFrostSignResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::Sign {
+                    credentials,
+                    wallet,
+                    signature_scheme,
+                    derivation_path,
+                    payload,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignResponse {
+                        let private_key =
+                            wallet::decrypt_secret_key(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &SigningIntent::raw(vec![(&signature_scheme).into()]),
+                        )?;
+
+                        return Ok(signer::sign(
+                            private_key.expose(),
+                            wallet.origin,
+                            &derivation_path,
+                            &signature_scheme,
+                            &payload,
+                        )?);
                     })()
                     .await;
 
-                    #[cfg(debug_assertions)]
-                    eprintln!("sign is not yet implemented");
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport.send::<VsockEnclaveSignResponse>(&result).await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignBatch {
+                    credentials,
+                    wallet,
+                    items,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignBatchResponse {
+                        let private_key =
+                            wallet::decrypt_secret_key(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &SigningIntent::raw(
+                                items
+                                    .iter()
+                                    .map(|item| (&item.signature_scheme).into())
+                                    .collect(),
+                            ),
+                        )?;
+
+                        return Ok(signer::sign_batch(
+                            private_key.expose(),
+                            wallet.origin,
+                            &items,
+                        ));
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignBatchResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::EvictCache { kms_ciphertext } => {
+                    let evicted = key_cache.lock().unwrap().evict(kms_ciphertext.as_deref());
+                    let result: VsockEnclaveEvictCacheResponse = Ok(VsockEnclaveEvictCacheData {
+                        evicted: evicted as u32,
+                    });
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveEvictCacheResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignCardanoTx {
+                    credentials,
+                    wallet,
+                    tx_cbor,
+                    derivation_paths,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignCardanoTxResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &cardano::signing_intent(
+                                private_key.expose(),
+                                &tx_cbor,
+                                &derivation_paths,
+                            )?,
+                        )?;
+
+                        return Ok(cardano::sign_transaction(
+                            private_key.expose(),
+                            &tx_cbor,
+                            &derivation_paths,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignCardanoTxResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignCardanoMessage {
+                    credentials,
+                    wallet,
+                    derivation_path,
+                    address,
+                    payload,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignCardanoMessageResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &SigningIntent::opaque(vec![SignatureSchemeKind::Ed25519]),
+                        )?;
+
+                        return Ok(cardano::sign_data(
+                            private_key.expose(),
+                            &derivation_path,
+                            &address,
+                            &payload,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignCardanoMessageResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignEthereumTx {
+                    credentials,
+                    wallet,
+                    derivation_path,
+                    transaction,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignEthereumTxResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &ethereum::signing_intent(&transaction),
+                        )?;
+
+                        return Ok(ethereum::sign_transaction(
+                            private_key.expose(),
+                            &derivation_path,
+                            &transaction,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignEthereumTxResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignEthereumTypedData {
+                    credentials,
+                    wallet,
+                    derivation_path,
+                    typed_data_json,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignEthereumMessageResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &SigningIntent::opaque(vec![SignatureSchemeKind::Secp256k1]),
+                        )?;
+
+                        return Ok(ethereum::sign_message_hash(
+                            private_key.expose(),
+                            &derivation_path,
+                            eip712::hash_typed_data(&typed_data_json)?,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignEthereumMessageResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignEthereumMessage {
+                    credentials,
+                    wallet,
+                    derivation_path,
+                    message,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignEthereumMessageResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &SigningIntent::opaque(vec![SignatureSchemeKind::Secp256k1]),
+                        )?;
+
+                        return Ok(ethereum::sign_message_hash(
+                            private_key.expose(),
+                            &derivation_path,
+                            ethereum::hash_personal_message(&message),
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignEthereumMessageResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignPsbt {
+                    credentials,
+                    wallet,
+                    psbt,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignPsbtResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &psbt::signing_intent(private_key.expose(), &psbt)?,
+                        )?;
+
+                        return Ok(psbt::sign_psbt(private_key.expose(), &psbt)?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignPsbtResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
+                }
+                VsockHostRequest::SignSolanaTx {
+                    credentials,
+                    wallet,
+                    derivation_path,
+                    message,
+                    approvals,
+                } => {
+                    let result = (async || -> VsockEnclaveSignSolanaTxResponse {
+                        let private_key =
+                            wallet::decrypt_seed(&credentials, &wallet, &key_cache).await?;
+                        approval::verify(wallet.quorum.as_ref(), &request_hash, &approvals)?;
+                        let parsed = solana::parse_message(&message)?;
+                        let intent = match solana::system_transfers(&parsed) {
+                            Some(transfers) => SigningIntent::transaction(
+                                vec![SignatureSchemeKind::Ed25519],
+                                Chain::Solana,
+                                transfers,
+                            ),
+                            None => SigningIntent::opaque(vec![SignatureSchemeKind::Ed25519]),
+                        };
+                        policy_engine.authorize(
+                            wallet.policy.as_ref(),
+                            &wallet::wallet_id(private_key.expose()),
+                            &intent,
+                        )?;
+
+                        return Ok(solana::sign_transaction(
+                            private_key.expose(),
+                            &derivation_path,
+                            &message,
+                        )?);
+                    })()
+                    .await;
+
+                    audit_event = audit_log.lock().unwrap().record(
+                        audit_context.as_ref(),
+                        &request_hash,
+                        &result,
+                        None,
+                    );
+
+                    error_code = logging::error_code(&result);
+
+                    let send_result = transport
+                        .send::<VsockEnclaveSignSolanaTxResponse>(&result)
+                        .await;
+
+                    if let Err(e) = send_result {
+                        warn!(error = %e, "failed to send response");
+                        return;
+                    }
                 }
             };
-        });
+
+            let duration = started.elapsed();
+            let latency_ms = duration.as_millis() as u64;
+            match error_code {
+                Some(error_code) => warn!(latency_ms, error_code, "request failed"),
+                None => info!(latency_ms, "request completed"),
+            }
+
+            let trailer = VsockEnclaveResponseTrailer {
+                audit_event,
+                stats: RequestStats {
+                    duration_us: duration.as_micros() as u64,
+                    kms_calls: stats::take_kms_calls(),
+                },
+            };
+            let send_result = transport
+                .send::<VsockEnclaveResponseTrailer>(&trailer)
+                .await;
+
+            if let Err(e) = send_result {
+                warn!(error = %e, "failed to send response trailer");
+            }
+        };
+        tokio::spawn(stats::scope(task).instrument(span));
+    }
+
+    // refuse new connections while draining
+    drop(listener);
+    if !shutdown.drain().await {
+        warn!(
+            in_flight = shutdown.in_flight(),
+            "dropping requests still in progress"
+        );
     }
+    key_cache.lock().unwrap().evict(None);
+    frost_sessions.lock().unwrap().clear();
+    info!("shutdown complete");
+    return Ok(());
 }
@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use serde_bytes::{ByteBuf, Bytes};
+use shared::error::NsmError;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+const NSM_DEVICE: &str = "/dev/nsm";
+/// `_IOWR(0x0A, 0, struct nsm_message)` from the Nitro Secure Module driver.
+const NSM_IOCTL_REQUEST: u64 = 0xC020_0A00;
+const NSM_REQUEST_MAX_SIZE: usize = 0x1000;
+const NSM_RESPONSE_MAX_SIZE: usize = 0x3000;
+
+#[repr(C)]
+struct NsmMessage {
+    request: libc::iovec,
+    response: libc::iovec,
+}
+
+#[derive(Serialize)]
+enum NsmRequest<'a> {
+    Attestation {
+        user_data: Option<&'a Bytes>,
+        nonce: Option<&'a Bytes>,
+        public_key: Option<&'a Bytes>,
+    },
+    DescribePCR {
+        index: u16,
+    },
+}
+
+#[derive(Deserialize)]
+enum NsmResponse {
+    Attestation { document: ByteBuf },
+    DescribePCR { data: ByteBuf },
+    Error(String),
+}
+
+/// Asks the Nitro Secure Module for a COSE_Sign1 attestation document over the
+/// enclave's PCRs and the given fields. Only works inside a Nitro enclave.
+pub fn attestation(
+    user_data: Option<&[u8]>,
+    nonce: Option<&[u8]>,
+    public_key: Option<&[u8]>,
+) -> Result<Vec<u8>, NsmError> {
+    let request = serde_cbor::to_vec(&NsmRequest::Attestation {
+        user_data: user_data.map(Bytes::new),
+        nonce: nonce.map(Bytes::new),
+        public_key: public_key.map(Bytes::new),
+    })?;
+    if request.len() > NSM_REQUEST_MAX_SIZE {
+        return Err(NsmError::Nsm("request too large".to_string()));
+    }
+
+    let response = process_request(&request)?;
+    match serde_cbor::from_slice::<NsmResponse>(&response)? {
+        NsmResponse::Attestation { document } => return Ok(document.into_vec()),
+        NsmResponse::Error(code) => return Err(NsmError::Nsm(code)),
+        _ => return Err(NsmError::Nsm("unexpected response".to_string())),
+    }
+}
+
+/// Reads PCR `index`, e.g. 0 for the hash of the enclave image. Only works
+/// inside a Nitro enclave.
+pub fn describe_pcr(index: u16) -> Result<Vec<u8>, NsmError> {
+    let request = serde_cbor::to_vec(&NsmRequest::DescribePCR { index })?;
+    let response = process_request(&request)?;
+    match serde_cbor::from_slice::<NsmResponse>(&response)? {
+        NsmResponse::DescribePCR { data } => return Ok(data.into_vec()),
+        NsmResponse::Error(code) => return Err(NsmError::Nsm(code)),
+        _ => return Err(NsmError::Nsm("unexpected response".to_string())),
+    }
+}
+
+fn process_request(request: &[u8]) -> Result<Vec<u8>, NsmError> {
+    let device = File::open(NSM_DEVICE)?;
+    let mut response = vec![0u8; NSM_RESPONSE_MAX_SIZE];
+    let mut message = NsmMessage {
+        request: libc::iovec {
+            iov_base: request.as_ptr() as *mut libc::c_void,
+            iov_len: request.len(),
+        },
+        response: libc::iovec {
+            iov_base: response.as_mut_ptr().cast(),
+            iov_len: response.len(),
+        },
+    };
+
+    // SAFETY: both iovecs point to live buffers of the given lengths, and the
+    // driver only writes to the response buffer, updating its length.
+    let result = unsafe {
+        libc::ioctl(
+            device.as_raw_fd(),
+            NSM_IOCTL_REQUEST as _,
+            &mut message as *mut NsmMessage,
+        )
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    response.truncate(message.response.iov_len);
+    return Ok(response);
+}
@@ -0,0 +1,699 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use pallas_crypto::key::ed25519::{PublicKey, Signature};
+use shared::error::PolicyError;
+use shared::policy::{Chain, Policy, SignatureSchemeKind, SignedPolicy};
+
+pub type SharedPolicyEngine = Arc<PolicyEngine>;
+
+const MINUTES_PER_DAY: u64 = 24 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transfer {
+    /// `None` for fees, which count towards the value limits but have no
+    /// destination to allowlist.
+    pub destination: Option<Vec<u8>>,
+    pub value: u128,
+}
+
+/// What a signing request would do, as far as the enclave can tell.
+#[derive(Debug)]
+pub struct SigningIntent {
+    pub schemes: Vec<SignatureSchemeKind>,
+    /// `None` when the payload cannot be decoded into transfers.
+    pub transaction: Option<(Chain, Vec<Transfer>)>,
+    /// Whether the payload is signed as is, by `Sign` or `SignBatch`.
+    pub raw: bool,
+}
+
+impl SigningIntent {
+    pub fn opaque(schemes: Vec<SignatureSchemeKind>) -> Self {
+        return Self {
+            schemes,
+            transaction: None,
+            raw: false,
+        };
+    }
+
+    /// A payload signed as is. A secp256k1 ECDSA digest could be the hash of
+    /// any transaction, e.g. an EVM one, so those are only signed under a
+    /// policy allowing opaque requests.
+    pub fn raw(schemes: Vec<SignatureSchemeKind>) -> Self {
+        return Self {
+            schemes,
+            transaction: None,
+            raw: true,
+        };
+    }
+
+    pub fn transaction(
+        schemes: Vec<SignatureSchemeKind>,
+        chain: Chain,
+        transfers: Vec<Transfer>,
+    ) -> Self {
+        return Self {
+            schemes,
+            transaction: Some((chain, transfers)),
+            raw: false,
+        };
+    }
+}
+
+struct Spend {
+    at: Instant,
+    chain: Chain,
+    value: u128,
+}
+
+/// Verifies admin-signed policies and evaluates them against signing requests.
+/// With an admin key, wallets without a policy cannot sign.
+///
+/// A replaced policy still decrypts under the wallet's data key, so the newest
+/// version seen for each wallet is remembered and older ones are refused.
+/// Both those versions and rolling window spends are keyed on the wallet's
+/// stable identity, so a rewrap or a backup round-trip does not reset them.
+///
+/// Known limitation: they are only held in memory, so a restart of the enclave
+/// forgets them, which resets rolling limits and lets an older envelope sign
+/// again until its wallet's newest policy is seen.
+pub struct PolicyEngine {
+    admin_key: Option<PublicKey>,
+    spends: Mutex<HashMap<[u8; 32], Vec<Spend>>>,
+    newest_versions: Mutex<HashMap<[u8; 32], u64>>,
+}
+
+impl PolicyEngine {
+    pub fn new(admin_key: Option<[u8; 32]>) -> Self {
+        return Self {
+            admin_key: admin_key.map(PublicKey::from),
+            spends: Mutex::new(HashMap::new()),
+            newest_versions: Mutex::new(HashMap::new()),
+        };
+    }
+
+    pub fn verify(&self, signed_policy: &SignedPolicy) -> Result<Policy, PolicyError> {
+        let admin_key = self.admin_key.as_ref().ok_or(PolicyError::NoAdminKey)?;
+        let signature: [u8; 64] = signed_policy
+            .signature
+            .as_slice()
+            .try_into()
+            .map_err(|_| PolicyError::InvalidSignature)?;
+        if !admin_key.verify(&signed_policy.policy, &Signature::from(signature)) {
+            return Err(PolicyError::InvalidSignature);
+        }
+
+        return serde_cbor::from_slice(&signed_policy.policy)
+            .map_err(|e| PolicyError::InvalidPolicy(e.to_string()));
+    }
+
+    /// [`PolicyEngine::verify`], also checking that the policy is for the
+    /// wallet with `wallet_id`, or for no wallet in particular.
+    pub fn verify_for(
+        &self,
+        signed_policy: &SignedPolicy,
+        wallet_id: &[u8; 32],
+    ) -> Result<Policy, PolicyError> {
+        let policy = self.verify(signed_policy)?;
+        if policy.wallet.is_some_and(|wallet| wallet != *wallet_id) {
+            return Err(PolicyError::WrongWallet);
+        }
+        return Ok(policy);
+    }
+
+    /// Refuses `version` if a newer policy was seen for the wallet, otherwise
+    /// remembers it as the newest.
+    pub fn check_version(&self, wallet_id: &[u8; 32], version: u64) -> Result<(), PolicyError> {
+        let mut newest_versions = self.newest_versions.lock().unwrap();
+        let newest = newest_versions.entry(*wallet_id).or_insert(version);
+        if version < *newest {
+            return Err(PolicyError::Superseded {
+                version,
+                newest: *newest,
+            });
+        }
+        *newest = version;
+        return Ok(());
+    }
+
+    /// Checks `intent` against a wallet's policy and records its value towards
+    /// rolling limits. Must only be called once the wallet secret has been
+    /// decrypted, which proves `policy` is the one the wallet was sealed with,
+    /// and `wallet_id` derived from it. FROST key shares are wallets here too.
+    pub fn authorize(
+        &self,
+        policy: Option<&SignedPolicy>,
+        wallet_id: &[u8; 32],
+        intent: &SigningIntent,
+    ) -> Result<(), PolicyError> {
+        return self.authorize_at(policy, wallet_id, intent, SystemTime::now(), Instant::now());
+    }
+
+    fn authorize_at(
+        &self,
+        policy: Option<&SignedPolicy>,
+        wallet_id: &[u8; 32],
+        intent: &SigningIntent,
+        wall_clock: SystemTime,
+        now: Instant,
+    ) -> Result<(), PolicyError> {
+        let Some(signed_policy) = policy else {
+            if self.admin_key.is_some() {
+                return Err(PolicyError::PolicyRequired);
+            }
+            if intent.raw && intent.schemes.contains(&SignatureSchemeKind::Secp256k1) {
+                return Err(PolicyError::DigestNeedsPolicy);
+            }
+            return Ok(());
+        };
+        let policy = self.verify_for(signed_policy, wallet_id)?;
+        self.check_version(wallet_id, policy.version)?;
+
+        if !policy.time_windows.is_empty() {
+            let minute = wall_clock
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() / 60 % MINUTES_PER_DAY)
+                as u16;
+            let in_window = policy.time_windows.iter().any(|window| {
+                if window.start_minute <= window.end_minute {
+                    return window.start_minute <= minute && minute < window.end_minute;
+                }
+                return minute >= window.start_minute || minute < window.end_minute;
+            });
+            if !in_window {
+                return Err(PolicyError::OutsideTimeWindow);
+            }
+        }
+
+        let schemes_allowed = policy
+            .allowed_schemes
+            .as_ref()
+            .is_none_or(|allowed| intent.schemes.iter().all(|scheme| allowed.contains(scheme)));
+        if !schemes_allowed {
+            return Err(PolicyError::SchemeNotAllowed);
+        }
+
+        let Some((chain, transfers)) = &intent.transaction else {
+            if !policy.allow_opaque {
+                return Err(PolicyError::OpaqueNotAllowed);
+            }
+            return Ok(());
+        };
+        let chain_policy = policy
+            .chains
+            .iter()
+            .find(|chain_policy| chain_policy.chain == *chain)
+            .ok_or(PolicyError::ChainNotAllowed)?;
+
+        if let Some(allowlist) = &chain_policy.destination_allowlist {
+            let allowed = transfers
+                .iter()
+                .filter_map(|transfer| transfer.destination.as_deref())
+                .all(|transfer| {
+                    allowlist
+                        .iter()
+                        .any(|destination| destination.as_slice() == transfer)
+                });
+            if !allowed {
+                return Err(PolicyError::DestinationNotAllowed);
+            }
+        }
+
+        let value = transfers.iter().fold(0u128, |total, transfer| {
+            total.saturating_add(transfer.value)
+        });
+        if chain_policy
+            .max_value_per_tx
+            .is_some_and(|max_value| value > max_value.0)
+        {
+            return Err(PolicyError::ValueLimitExceeded);
+        }
+
+        let mut spends = self.spends.lock().unwrap();
+        let wallet_spends = spends.entry(*wallet_id).or_default();
+        if let Some(rolling_limit) = &chain_policy.rolling_limit {
+            let window = Duration::from_secs(rolling_limit.window_secs);
+            wallet_spends
+                .retain(|spend| spend.chain != *chain || now.duration_since(spend.at) < window);
+            let spent = wallet_spends
+                .iter()
+                .filter(|spend| spend.chain == *chain)
+                .fold(0u128, |total, spend| total.saturating_add(spend.value));
+            if spent.saturating_add(value) > rolling_limit.max_value.0 {
+                return Err(PolicyError::RollingLimitExceeded);
+            }
+            wallet_spends.push(Spend {
+                at: now,
+                chain: *chain,
+                value,
+            });
+        }
+
+        return Ok(());
+    }
+}
+
+/// AES-GCM associated data binding a wallet secret to its policy. Wallets
+/// without a policy use no associated data.
+pub fn envelope_aad(policy: Option<&SignedPolicy>) -> Vec<u8> {
+    let Some(policy) = policy else {
+        return Vec::new();
+    };
+    let mut hasher = Sha256::new();
+    hasher.input(&policy.policy);
+    let mut aad = vec![0u8; 32];
+    hasher.result(&mut aad);
+    return aad;
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use pallas_crypto::key::ed25519::SecretKey;
+    use shared::policy::{Amount, ChainPolicy, RollingLimit, TimeWindow};
+    use shared::transport::{VsockEnclaveCreateWalletData, WalletOrigin};
+
+    const ADMIN_SECRET: [u8; 32] = [8u8; 32];
+    const ETHEREUM: Chain = Chain::Ethereum { chain_id: 1 };
+    const WALLET_ID: [u8; 32] = [5u8; 32];
+
+    pub(crate) fn sign_policy(policy: &Policy) -> SignedPolicy {
+        let policy = serde_cbor::to_vec(policy).unwrap();
+        let signature = SecretKey::from(ADMIN_SECRET).sign(&policy);
+        return SignedPolicy {
+            policy,
+            signature: signature.as_ref().to_vec(),
+        };
+    }
+
+    fn engine() -> PolicyEngine {
+        return PolicyEngine::new(Some(SecretKey::from(ADMIN_SECRET).public_key().into()));
+    }
+
+    fn wallet(policy: &Policy) -> VsockEnclaveCreateWalletData {
+        return VsockEnclaveCreateWalletData {
+            encrypted_secret_key: vec![],
+            aes_gcm_nonce: [0u8; 12],
+            kms_ciphertext: vec![1, 2, 3],
+            kms_key_id: "key".to_string(),
+            policy: Some(sign_policy(policy)),
+            quorum: None,
+            origin: WalletOrigin::Generated,
+            wallet_id: WALLET_ID,
+        };
+    }
+
+    fn base_policy() -> Policy {
+        return Policy {
+            version: 1,
+            wallet: Some(WALLET_ID),
+            allowed_schemes: Some(vec![SignatureSchemeKind::Secp256k1]),
+            allow_opaque: false,
+            chains: vec![ChainPolicy {
+                chain: ETHEREUM,
+                destination_allowlist: Some(vec![serde_bytes::ByteBuf::from(vec![0xaa; 20])]),
+                max_value_per_tx: Some(Amount(100)),
+                rolling_limit: Some(RollingLimit {
+                    window_secs: 3600,
+                    max_value: Amount(150),
+                }),
+            }],
+            time_windows: vec![],
+        };
+    }
+
+    fn transfer(destination: u8, value: u128) -> SigningIntent {
+        return SigningIntent::transaction(
+            vec![SignatureSchemeKind::Secp256k1],
+            ETHEREUM,
+            vec![Transfer {
+                destination: Some(vec![destination; 20]),
+                value,
+            }],
+        );
+    }
+
+    #[test]
+    fn test_wallet_without_policy_needs_no_admin_key() {
+        let mut wallet = wallet(&base_policy());
+        wallet.policy = None;
+        let intent = SigningIntent::opaque(vec![SignatureSchemeKind::Ed25519]);
+        assert!(
+            PolicyEngine::new(None)
+                .authorize(wallet.policy.as_ref(), &WALLET_ID, &intent)
+                .is_ok()
+        );
+        assert!(matches!(
+            engine().authorize(wallet.policy.as_ref(), &WALLET_ID, &intent),
+            Err(PolicyError::PolicyRequired)
+        ));
+    }
+
+    #[test]
+    fn test_raw_secp256k1_digests_need_a_policy_allowing_opaque() {
+        let digest = SigningIntent::raw(vec![SignatureSchemeKind::Secp256k1]);
+        assert!(matches!(
+            PolicyEngine::new(None).authorize(None, &WALLET_ID, &digest),
+            Err(PolicyError::DigestNeedsPolicy)
+        ));
+        let payload = SigningIntent::raw(vec![SignatureSchemeKind::Ed25519]);
+        assert!(
+            PolicyEngine::new(None)
+                .authorize(None, &WALLET_ID, &payload)
+                .is_ok()
+        );
+
+        let mut policy = base_policy();
+        let strict = wallet(&policy);
+        assert!(matches!(
+            engine().authorize(strict.policy.as_ref(), &WALLET_ID, &digest),
+            Err(PolicyError::OpaqueNotAllowed)
+        ));
+        policy.allow_opaque = true;
+        let lenient = wallet(&policy);
+        assert!(
+            engine()
+                .authorize(lenient.policy.as_ref(), &WALLET_ID, &digest)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_policy_is_bound_to_its_wallet() {
+        let engine = engine();
+        let wallet = wallet(&base_policy());
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &[6u8; 32], &transfer(0xaa, 1)),
+            Err(PolicyError::WrongWallet)
+        ));
+
+        let mut unbound = base_policy();
+        unbound.wallet = None;
+        assert!(
+            engine
+                .verify_for(&sign_policy(&unbound), &[6u8; 32])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_replaced_policy_is_refused() {
+        let engine = engine();
+        let mut newer = base_policy();
+        newer.version = 2;
+        engine
+            .authorize(
+                wallet(&newer).policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 1),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            engine.authorize(
+                wallet(&base_policy()).policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 1)
+            ),
+            Err(PolicyError::Superseded {
+                version: 1,
+                newest: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_policy_not_signed_by_admin() {
+        let mut wallet = wallet(&base_policy());
+        wallet.policy.as_mut().unwrap().signature[0] ^= 1;
+        assert!(matches!(
+            engine().authorize(wallet.policy.as_ref(), &WALLET_ID, &transfer(0xaa, 1)),
+            Err(PolicyError::InvalidSignature)
+        ));
+        assert!(matches!(
+            PolicyEngine::new(None).verify(wallet.policy.as_ref().unwrap()),
+            Err(PolicyError::NoAdminKey)
+        ));
+    }
+
+    #[test]
+    fn test_schemes_chains_and_opaque_requests() {
+        let engine = engine();
+        let wallet = wallet(&base_policy());
+
+        let intent = SigningIntent::opaque(vec![SignatureSchemeKind::Secp256k1]);
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &WALLET_ID, &intent),
+            Err(PolicyError::OpaqueNotAllowed)
+        ));
+        let intent = SigningIntent::opaque(vec![SignatureSchemeKind::Ed25519]);
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &WALLET_ID, &intent),
+            Err(PolicyError::SchemeNotAllowed)
+        ));
+        let intent = SigningIntent::transaction(
+            vec![SignatureSchemeKind::Secp256k1],
+            Chain::Ethereum { chain_id: 5 },
+            vec![],
+        );
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &WALLET_ID, &intent),
+            Err(PolicyError::ChainNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_destination_and_value_limits() {
+        let engine = engine();
+        let wallet = wallet(&base_policy());
+        let start = Instant::now();
+        let clock = SystemTime::now();
+
+        assert!(matches!(
+            engine.authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xbb, 1),
+                clock,
+                start
+            ),
+            Err(PolicyError::DestinationNotAllowed)
+        ));
+        assert!(matches!(
+            engine.authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 101),
+                clock,
+                start
+            ),
+            Err(PolicyError::ValueLimitExceeded)
+        ));
+
+        engine
+            .authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 100),
+                clock,
+                start,
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 60),
+                clock,
+                start
+            ),
+            Err(PolicyError::RollingLimitExceeded)
+        ));
+        engine
+            .authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 50),
+                clock,
+                start,
+            )
+            .unwrap();
+
+        let later = start + Duration::from_secs(3600);
+        engine
+            .authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 100),
+                clock,
+                later,
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_fees_count_towards_limits() {
+        let engine = engine();
+        let wallet = wallet(&base_policy());
+        let with_fee = |value: u128, fee: u128| {
+            SigningIntent::transaction(
+                vec![SignatureSchemeKind::Secp256k1],
+                ETHEREUM,
+                vec![
+                    Transfer {
+                        destination: Some(vec![0xaa; 20]),
+                        value,
+                    },
+                    Transfer {
+                        destination: None,
+                        value: fee,
+                    },
+                ],
+            )
+        };
+
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &WALLET_ID, &with_fee(1, 100)),
+            Err(PolicyError::ValueLimitExceeded)
+        ));
+        engine
+            .authorize(wallet.policy.as_ref(), &WALLET_ID, &with_fee(1, 99))
+            .unwrap();
+        assert!(matches!(
+            engine.authorize(wallet.policy.as_ref(), &WALLET_ID, &with_fee(1, 50)),
+            Err(PolicyError::RollingLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_token_transfers_are_opaque() {
+        use shared::transport::EthereumTransaction;
+
+        // ERC-20 transfer(0xbb..bb, 1000) on the allowlisted contract 0xaa..aa
+        let mut data = vec![0xa9, 0x05, 0x9c, 0xbb];
+        data.extend_from_slice(&[0u8; 12]);
+        data.extend_from_slice(&[0xbb; 20]);
+        data.extend_from_slice(&[0u8; 30]);
+        data.extend_from_slice(&1000u16.to_be_bytes());
+        let token_transfer = EthereumTransaction::Eip1559 {
+            chain_id: 1,
+            nonce: 0,
+            max_priority_fee_per_gas: 0,
+            max_fee_per_gas: 0,
+            gas_limit: 60_000,
+            to: Some([0xaa; 20]),
+            value: [0u8; 32],
+            data,
+            access_list: vec![],
+        };
+        let mut native_transfer = token_transfer.clone();
+        if let EthereumTransaction::Eip1559 { data, .. } = &mut native_transfer {
+            data.clear();
+        }
+        let mut creation = native_transfer.clone();
+        if let EthereumTransaction::Eip1559 { to, .. } = &mut creation {
+            *to = None;
+        }
+
+        let engine = engine();
+        let policy = base_policy();
+        let wallet = wallet(&policy);
+        let authorize = |transaction: &EthereumTransaction| {
+            engine.authorize(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &crate::ethereum::signing_intent(transaction),
+            )
+        };
+        assert!(authorize(&native_transfer).is_ok());
+        assert!(matches!(
+            authorize(&token_transfer),
+            Err(PolicyError::OpaqueNotAllowed)
+        ));
+        assert!(matches!(
+            authorize(&creation),
+            Err(PolicyError::OpaqueNotAllowed)
+        ));
+    }
+
+    #[test]
+    fn test_rolling_limit_survives_rewrap() {
+        let engine = engine();
+        let wallet = wallet(&base_policy());
+        let now = Instant::now();
+        let clock = SystemTime::now();
+        engine
+            .authorize_at(
+                wallet.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 100),
+                clock,
+                now,
+            )
+            .unwrap();
+
+        // same secret under a fresh data key
+        let mut rewrapped = wallet.clone();
+        rewrapped.kms_ciphertext = vec![4, 5, 6];
+        assert!(matches!(
+            engine.authorize_at(
+                rewrapped.policy.as_ref(),
+                &WALLET_ID,
+                &transfer(0xaa, 100),
+                clock,
+                now
+            ),
+            Err(PolicyError::RollingLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn test_time_windows() {
+        let engine = engine();
+        let mut policy = base_policy();
+        // 22:00 - 02:00 UTC
+        policy.time_windows = vec![TimeWindow {
+            start_minute: 22 * 60,
+            end_minute: 2 * 60,
+        }];
+        let wallet = wallet(&policy);
+        let at = |hour: u64| UNIX_EPOCH + Duration::from_secs(hour * 3600);
+
+        for hour in [22, 23, 24, 25] {
+            engine
+                .authorize_at(
+                    wallet.policy.as_ref(),
+                    &WALLET_ID,
+                    &transfer(0xaa, 1),
+                    at(hour),
+                    Instant::now(),
+                )
+                .unwrap();
+        }
+        for hour in [2, 12, 21] {
+            assert!(matches!(
+                engine.authorize_at(
+                    wallet.policy.as_ref(),
+                    &WALLET_ID,
+                    &transfer(0xaa, 1),
+                    at(hour),
+                    Instant::now()
+                ),
+                Err(PolicyError::OutsideTimeWindow)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_envelope_aad_depends_on_policy() {
+        let mut other = base_policy();
+        other.version = 2;
+        assert!(envelope_aad(None).is_empty());
+        assert_ne!(
+            envelope_aad(Some(&sign_policy(&base_policy()))),
+            envelope_aad(Some(&sign_policy(&other)))
+        );
+    }
+}
@@ -0,0 +1,651 @@
+use bitcoin::bip32::{DerivationPath, Fingerprint, Xpriv};
+use bitcoin::hashes::Hash;
+use bitcoin::key::TapTweak;
+use bitcoin::psbt::Psbt;
+use bitcoin::secp256k1::{All, Message, Secp256k1};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::{NetworkKind, ScriptBuf, TxOut, ecdsa, taproot};
+use shared::error::PsbtError;
+use shared::policy::{Chain, SignatureSchemeKind};
+use shared::transport::{PsbtSignedInput, SignatureScheme, TaprootTweak, VsockEnclaveSignPsbtData};
+
+use crate::policy::{SigningIntent, Transfer};
+use crate::secret::Erased;
+
+/// Signs every P2WPKH, P2SH-P2WPKH and P2TR key path input whose BIP32 derivation
+/// matches the master fingerprint of the wallet secret (used as the BIP32 seed).
+/// Inputs of other types, or derived from other keys, are left untouched. Only
+/// `SIGHASH_ALL`, or `SIGHASH_DEFAULT` for P2TR, is signed: any other sighash
+/// type would let the rest of the transaction change after approval.
+///
+/// Every input of this wallet needs its UTXO. Segwit v0 sighashes only commit
+/// to the amount of the input being signed, so P2WPKH and P2SH-P2WPKH inputs
+/// also need the full previous transaction, checked against the outpoint.
+pub fn sign_psbt(
+    secret_key: &[u8; 64],
+    psbt: &[u8],
+) -> Result<VsockEnclaveSignPsbtData, PsbtError> {
+    let secp = Secp256k1::new();
+    let master = Erased(
+        Xpriv::new_master(NetworkKind::Main, secret_key)
+            .map_err(|e| PsbtError::Bip32(e.to_string()))?,
+    );
+    let mut psbt = Psbt::deserialize(psbt).map_err(|e| PsbtError::InvalidPsbt(e.to_string()))?;
+
+    let signed_inputs = sign_inputs(&secp, &master, &mut psbt)?;
+
+    return Ok(VsockEnclaveSignPsbtData {
+        psbt: psbt.serialize(),
+        signed_inputs,
+    });
+}
+
+/// Describes the PSBT for policy checks: every output that is not provably
+/// change back to this wallet, the fee, and the schemes its inputs are signed
+/// with. The fee needs the UTXO of every input.
+pub fn signing_intent(secret_key: &[u8; 64], psbt: &[u8]) -> Result<SigningIntent, PsbtError> {
+    let secp = Secp256k1::new();
+    let master = Erased(
+        Xpriv::new_master(NetworkKind::Main, secret_key)
+            .map_err(|e| PsbtError::Bip32(e.to_string()))?,
+    );
+    let psbt = Psbt::deserialize(psbt).map_err(|e| PsbtError::InvalidPsbt(e.to_string()))?;
+
+    let mut schemes = Vec::new();
+    for input in &psbt.inputs {
+        let scheme = match input.tap_internal_key {
+            Some(_) => SignatureSchemeKind::Secp256k1Schnorr,
+            None => SignatureSchemeKind::Secp256k1,
+        };
+        if !schemes.contains(&scheme) {
+            schemes.push(scheme);
+        }
+    }
+
+    let spent = spend_utxos(&psbt)?.iter().fold(0u64, |total, utxo| {
+        total.saturating_add(utxo.value.to_sat())
+    });
+    let paid = psbt.unsigned_tx.output.iter().fold(0u64, |total, output| {
+        total.saturating_add(output.value.to_sat())
+    });
+    let fee = spent
+        .checked_sub(paid)
+        .ok_or_else(|| PsbtError::InvalidPsbt("outputs exceed inputs".to_string()))?;
+
+    let mut transfers = vec![Transfer {
+        destination: None,
+        value: fee as u128,
+    }];
+    for (index, output) in psbt.unsigned_tx.output.iter().enumerate() {
+        if !is_change(&secp, &master, &psbt.outputs[index], &output.script_pubkey)? {
+            transfers.push(Transfer {
+                destination: Some(output.script_pubkey.to_bytes()),
+                value: output.value.to_sat() as u128,
+            });
+        }
+    }
+
+    return Ok(SigningIntent::transaction(
+        schemes,
+        Chain::Bitcoin,
+        transfers,
+    ));
+}
+
+/// An output is change when its script pays a key this wallet derives, as
+/// claimed by the PSBT's BIP32 derivation fields and checked here.
+fn is_change(
+    secp: &Secp256k1<All>,
+    master: &Xpriv,
+    output: &bitcoin::psbt::Output,
+    script_pubkey: &ScriptBuf,
+) -> Result<bool, PsbtError> {
+    let fingerprint = master.fingerprint(secp);
+
+    for (public_key, (key_fingerprint, path)) in &output.bip32_derivation {
+        if *key_fingerprint != fingerprint {
+            continue;
+        }
+        let derived = derive(secp, master, path)?.private_key.public_key(secp);
+        let p2wpkh = ScriptBuf::new_p2wpkh(&bitcoin::CompressedPublicKey(derived).wpubkey_hash());
+        if derived == *public_key
+            && (*script_pubkey == p2wpkh
+                || *script_pubkey == ScriptBuf::new_p2sh(&p2wpkh.script_hash()))
+        {
+            return Ok(true);
+        }
+    }
+
+    for (x_only, (leaf_hashes, (key_fingerprint, path))) in &output.tap_key_origins {
+        if !leaf_hashes.is_empty() || *key_fingerprint != fingerprint {
+            continue;
+        }
+        let keypair = Erased(derive(secp, master, path)?.to_keypair(secp));
+        let derived = keypair.x_only_public_key().0;
+        if derived == *x_only && *script_pubkey == ScriptBuf::new_p2tr(secp, derived, None) {
+            return Ok(true);
+        }
+    }
+
+    return Ok(false);
+}
+
+fn sign_inputs(
+    secp: &Secp256k1<All>,
+    master: &Xpriv,
+    psbt: &mut Psbt,
+) -> Result<Vec<PsbtSignedInput>, PsbtError> {
+    let fingerprint = master.fingerprint(secp);
+    let unsigned_tx = psbt.unsigned_tx.clone();
+    let mut cache = SighashCache::new(&unsigned_tx);
+    let mut signed_inputs = Vec::new();
+
+    for index in 0..psbt.inputs.len() {
+        let Ok(utxo) = psbt.spend_utxo(index) else {
+            if is_own_input(&psbt.inputs[index], fingerprint) {
+                return Err(PsbtError::MissingUtxo(index));
+            }
+            continue;
+        };
+        let script_pubkey = utxo.script_pubkey.clone();
+        let input = &psbt.inputs[index];
+        let is_p2sh_p2wpkh = script_pubkey.is_p2sh()
+            && input
+                .redeem_script
+                .as_ref()
+                .is_some_and(|script| script.is_p2wpkh());
+
+        if script_pubkey.is_p2wpkh() || is_p2sh_p2wpkh {
+            let key_sources: Vec<_> = input
+                .bip32_derivation
+                .iter()
+                .filter(|(_, (key_fingerprint, _))| *key_fingerprint == fingerprint)
+                .map(|(public_key, key_source)| (*public_key, key_source.clone()))
+                .collect();
+            if key_sources.is_empty() {
+                continue;
+            }
+            check_non_witness_utxo(psbt, index)?;
+
+            let (message, sighash_type) = psbt
+                .sighash_ecdsa(index, &mut cache)
+                .map_err(|e| PsbtError::Sighash(index, e.to_string()))?;
+            if sighash_type != EcdsaSighashType::All {
+                return Err(PsbtError::Sighash(
+                    index,
+                    format!("sighash type {sighash_type} is not allowed"),
+                ));
+            }
+            for (public_key, (_, path)) in key_sources {
+                let child = derive(secp, master, &path)?;
+                if child.private_key.public_key(secp) != public_key {
+                    return Err(PsbtError::KeyMismatch(index));
+                }
+                let signature = ecdsa::Signature {
+                    signature: secp.sign_ecdsa(&message, &child.private_key),
+                    sighash_type,
+                };
+                psbt.inputs[index]
+                    .partial_sigs
+                    .insert(bitcoin::PublicKey::new(public_key), signature);
+            }
+            signed_inputs.push(PsbtSignedInput {
+                input_index: index as u32,
+                signature_scheme: SignatureScheme::Secp256k1,
+            });
+        } else if script_pubkey.is_p2tr() {
+            // key path spend: BIP 371 marks the internal key with an empty leaf hash list
+            let Some(internal_key) = input.tap_internal_key else {
+                continue;
+            };
+            let Some((leaf_hashes, (key_fingerprint, path))) =
+                input.tap_key_origins.get(&internal_key)
+            else {
+                continue;
+            };
+            if !leaf_hashes.is_empty() || *key_fingerprint != fingerprint {
+                continue;
+            }
+            let (path, merkle_root) = (path.clone(), input.tap_merkle_root);
+            let sighash_type = input
+                .taproot_hash_ty()
+                .map_err(|e| PsbtError::Sighash(index, e.to_string()))?;
+            if sighash_type != TapSighashType::Default {
+                return Err(PsbtError::Sighash(
+                    index,
+                    format!("sighash type {sighash_type} is not allowed"),
+                ));
+            }
+
+            let keypair = Erased(derive(secp, master, &path)?.to_keypair(secp));
+            if keypair.x_only_public_key().0 != internal_key {
+                return Err(PsbtError::KeyMismatch(index));
+            }
+            let prevouts = spend_utxos(psbt)?;
+            let sighash = cache
+                .taproot_key_spend_signature_hash(index, &Prevouts::All(&prevouts), sighash_type)
+                .map_err(|e| PsbtError::Sighash(index, e.to_string()))?;
+            let tweaked = Erased(keypair.tap_tweak(secp, merkle_root).to_keypair());
+            let message = Message::from_digest(sighash.to_raw_hash().to_byte_array());
+
+            psbt.inputs[index].tap_key_sig = Some(taproot::Signature {
+                signature: secp.sign_schnorr_no_aux_rand(&message, &tweaked),
+                sighash_type,
+            });
+            signed_inputs.push(PsbtSignedInput {
+                input_index: index as u32,
+                signature_scheme: SignatureScheme::Secp256k1Schnorr {
+                    taproot: Some(TaprootTweak {
+                        merkle_root: merkle_root.map(|root| root.to_byte_array()),
+                    }),
+                },
+            });
+        }
+    }
+
+    return Ok(signed_inputs);
+}
+
+/// Whether any key of the input claims to derive from this wallet.
+fn is_own_input(input: &bitcoin::psbt::Input, fingerprint: Fingerprint) -> bool {
+    return input
+        .bip32_derivation
+        .values()
+        .any(|(key_fingerprint, _)| *key_fingerprint == fingerprint)
+        || input
+            .tap_key_origins
+            .values()
+            .any(|(_, (key_fingerprint, _))| *key_fingerprint == fingerprint);
+}
+
+/// Checks the previous transaction of a segwit v0 input against its outpoint,
+/// and the witness UTXO against the spent output, so the amount that was
+/// signed for cannot be understated.
+fn check_non_witness_utxo(psbt: &Psbt, index: usize) -> Result<(), PsbtError> {
+    let input = &psbt.inputs[index];
+    let outpoint = psbt.unsigned_tx.input[index].previous_output;
+    let previous = input
+        .non_witness_utxo
+        .as_ref()
+        .ok_or(PsbtError::MissingUtxo(index))?;
+    if previous.compute_txid() != outpoint.txid {
+        return Err(PsbtError::UtxoMismatch(index));
+    }
+    let spent = previous
+        .output
+        .get(outpoint.vout as usize)
+        .ok_or(PsbtError::UtxoMismatch(index))?;
+    if input
+        .witness_utxo
+        .as_ref()
+        .is_some_and(|utxo| utxo != spent)
+    {
+        return Err(PsbtError::UtxoMismatch(index));
+    }
+
+    return Ok(());
+}
+
+fn derive(
+    secp: &Secp256k1<All>,
+    master: &Xpriv,
+    path: &DerivationPath,
+) -> Result<Erased<Xpriv>, PsbtError> {
+    return master
+        .derive_priv(secp, path)
+        .map(Erased)
+        .map_err(|e| PsbtError::Bip32(e.to_string()));
+}
+
+/// Taproot sighashes commit to every spent output, so all of them must be known.
+fn spend_utxos(psbt: &Psbt) -> Result<Vec<TxOut>, PsbtError> {
+    return (0..psbt.inputs.len())
+        .map(|index| {
+            psbt.spend_utxo(index)
+                .cloned()
+                .map_err(|_| PsbtError::MissingUtxo(index))
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::psbt::Input;
+    use bitcoin::{
+        Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, Txid, Witness,
+        absolute::LockTime, transaction::Version,
+    };
+    use std::collections::BTreeMap;
+    use std::str::FromStr;
+
+    use crate::secret::tests::erased_keys;
+
+    const SECRET_KEY: [u8; 64] = [11u8; 64];
+
+    fn input(txid_byte: u8) -> TxIn {
+        return TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_byte_array([txid_byte; 32]),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        };
+    }
+
+    /// Builds a PSBT spending a P2WPKH, a P2SH-P2WPKH, a P2TR and a foreign P2WPKH output.
+    fn build_psbt(secp: &Secp256k1<All>, master: &Xpriv) -> Psbt {
+        let fingerprint = master.fingerprint(secp);
+        let foreign = Xpriv::new_master(NetworkKind::Main, &[12u8; 64]).unwrap();
+
+        let wpkh_path = DerivationPath::from_str("m/84'/0'/0'/0/0").unwrap();
+        let sh_wpkh_path = DerivationPath::from_str("m/49'/0'/0'/0/0").unwrap();
+        let tr_path = DerivationPath::from_str("m/86'/0'/0'/0/0").unwrap();
+
+        let wpkh_key = derive(secp, master, &wpkh_path).unwrap().to_priv();
+        let sh_wpkh_key = derive(secp, master, &sh_wpkh_path).unwrap().to_priv();
+        let tr_key = derive(secp, master, &tr_path).unwrap().to_keypair(secp);
+        let foreign_key = derive(secp, &foreign, &wpkh_path).unwrap().to_priv();
+
+        let wpkh_script = ScriptBuf::new_p2wpkh(&wpkh_key.public_key(secp).wpubkey_hash().unwrap());
+        let redeem_script =
+            ScriptBuf::new_p2wpkh(&sh_wpkh_key.public_key(secp).wpubkey_hash().unwrap());
+        let sh_script = ScriptBuf::new_p2sh(&redeem_script.script_hash());
+        let (internal_key, _) = tr_key.x_only_public_key();
+        let tr_script = ScriptBuf::new_p2tr(secp, internal_key, None);
+        let foreign_script =
+            ScriptBuf::new_p2wpkh(&foreign_key.public_key(secp).wpubkey_hash().unwrap());
+
+        let utxo = |script_pubkey: &ScriptBuf| TxOut {
+            value: Amount::from_sat(25_000),
+            script_pubkey: script_pubkey.clone(),
+        };
+        let previous: Vec<Transaction> = [&wpkh_script, &sh_script, &tr_script, &foreign_script]
+            .into_iter()
+            .enumerate()
+            .map(|(index, script_pubkey)| Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![input(index as u8 + 1)],
+                output: vec![utxo(script_pubkey)],
+            })
+            .collect();
+
+        let unsigned_tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: previous
+                .iter()
+                .map(|transaction| TxIn {
+                    previous_output: OutPoint::new(transaction.compute_txid(), 0),
+                    ..input(0)
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: wpkh_script.clone(),
+            }],
+        };
+        let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).unwrap();
+
+        psbt.inputs[0] = Input {
+            witness_utxo: Some(utxo(&wpkh_script)),
+            non_witness_utxo: Some(previous[0].clone()),
+            bip32_derivation: BTreeMap::from([(
+                wpkh_key.public_key(secp).inner,
+                (fingerprint, wpkh_path.clone()),
+            )]),
+            ..Default::default()
+        };
+        psbt.inputs[1] = Input {
+            witness_utxo: Some(utxo(&sh_script)),
+            non_witness_utxo: Some(previous[1].clone()),
+            redeem_script: Some(redeem_script),
+            bip32_derivation: BTreeMap::from([(
+                sh_wpkh_key.public_key(secp).inner,
+                (fingerprint, sh_wpkh_path),
+            )]),
+            ..Default::default()
+        };
+        psbt.inputs[2] = Input {
+            witness_utxo: Some(utxo(&tr_script)),
+            tap_internal_key: Some(internal_key),
+            tap_key_origins: BTreeMap::from([(internal_key, (vec![], (fingerprint, tr_path)))]),
+            ..Default::default()
+        };
+        psbt.inputs[3] = Input {
+            witness_utxo: Some(utxo(&foreign_script)),
+            non_witness_utxo: Some(previous[3].clone()),
+            bip32_derivation: BTreeMap::from([(
+                foreign_key.public_key(secp).inner,
+                (foreign.fingerprint(secp), wpkh_path),
+            )]),
+            ..Default::default()
+        };
+        return psbt;
+    }
+
+    #[test]
+    fn test_sign_psbt_signs_matching_inputs() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+        let psbt = build_psbt(&secp, &master);
+
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize()).unwrap();
+        let signed = Psbt::deserialize(&result.psbt).unwrap();
+
+        let signed_indices: Vec<u32> = result.signed_inputs.iter().map(|i| i.input_index).collect();
+        assert_eq!(signed_indices, vec![0, 1, 2]);
+        assert!(matches!(
+            result.signed_inputs[2].signature_scheme,
+            SignatureScheme::Secp256k1Schnorr {
+                taproot: Some(TaprootTweak { merkle_root: None })
+            }
+        ));
+
+        let unsigned_tx = signed.unsigned_tx.clone();
+        let mut cache = SighashCache::new(&unsigned_tx);
+        for index in 0..2 {
+            let (message, _) = signed.sighash_ecdsa(index, &mut cache).unwrap();
+            let (public_key, signature) = signed.inputs[index].partial_sigs.iter().next().unwrap();
+            secp.verify_ecdsa(&message, &signature.signature, &public_key.inner)
+                .unwrap();
+        }
+
+        let prevouts = spend_utxos(&signed).unwrap();
+        let sighash = cache
+            .taproot_key_spend_signature_hash(
+                2,
+                &Prevouts::All(&prevouts),
+                bitcoin::TapSighashType::Default,
+            )
+            .unwrap();
+        let output_key = signed.inputs[2]
+            .tap_internal_key
+            .unwrap()
+            .tap_tweak(&secp, None)
+            .0
+            .to_x_only_public_key();
+        secp.verify_schnorr(
+            &signed.inputs[2].tap_key_sig.unwrap().signature,
+            &Message::from_digest(sighash.to_byte_array()),
+            &output_key,
+        )
+        .unwrap();
+
+        assert!(signed.inputs[3].partial_sigs.is_empty());
+    }
+
+    #[test]
+    fn test_signing_intent_excludes_verified_change() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+        let mut psbt = build_psbt(&secp, &master);
+
+        let intent = signing_intent(&SECRET_KEY, &psbt.serialize()).unwrap();
+        assert_eq!(
+            intent.schemes,
+            vec![
+                SignatureSchemeKind::Secp256k1,
+                SignatureSchemeKind::Secp256k1Schnorr
+            ]
+        );
+        let (_, transfers) = intent.transaction.unwrap();
+        assert_eq!(
+            transfers,
+            vec![
+                Transfer {
+                    destination: None,
+                    value: 10_000,
+                },
+                Transfer {
+                    destination: Some(psbt.unsigned_tx.output[0].script_pubkey.to_bytes()),
+                    value: 90_000,
+                },
+            ]
+        );
+
+        // claiming a foreign key as ours does not make the output change
+        let wpkh_path = DerivationPath::from_str("m/84'/0'/0'/0/0").unwrap();
+        let foreign_key = derive(&secp, &master, &DerivationPath::from_str("m/1").unwrap())
+            .unwrap()
+            .private_key
+            .public_key(&secp);
+        psbt.outputs[0].bip32_derivation =
+            BTreeMap::from([(foreign_key, (master.fingerprint(&secp), wpkh_path.clone()))]);
+        let intent = signing_intent(&SECRET_KEY, &psbt.serialize()).unwrap();
+        assert_eq!(intent.transaction.unwrap().1.len(), 2);
+
+        let change_key = derive(&secp, &master, &wpkh_path)
+            .unwrap()
+            .private_key
+            .public_key(&secp);
+        psbt.outputs[0].bip32_derivation =
+            BTreeMap::from([(change_key, (master.fingerprint(&secp), wpkh_path))]);
+        let intent = signing_intent(&SECRET_KEY, &psbt.serialize()).unwrap();
+        assert_eq!(
+            intent.transaction.unwrap().1,
+            vec![Transfer {
+                destination: None,
+                value: 10_000,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_signing_intent_needs_every_utxo() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+        let mut psbt = build_psbt(&secp, &master);
+
+        // the fee is only known with every input's value
+        psbt.inputs[3].witness_utxo = None;
+        psbt.inputs[3].non_witness_utxo = None;
+        assert!(matches!(
+            signing_intent(&SECRET_KEY, &psbt.serialize()),
+            Err(PsbtError::MissingUtxo(3))
+        ));
+
+        psbt.unsigned_tx.output[0].value = Amount::from_sat(200_000);
+        psbt.inputs[3] = build_psbt(&secp, &master).inputs[3].clone();
+        assert!(matches!(
+            signing_intent(&SECRET_KEY, &psbt.serialize()),
+            Err(PsbtError::InvalidPsbt(_))
+        ));
+    }
+
+    #[test]
+    fn test_sign_psbt_requires_utxos_of_own_inputs() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[2].witness_utxo = None;
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::MissingUtxo(2))));
+
+        // segwit v0 inputs need the previous transaction, not just the spent output
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[1].non_witness_utxo = None;
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::MissingUtxo(1))));
+
+        // foreign inputs do not need the previous transaction
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[3].non_witness_utxo = None;
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize()).unwrap();
+        assert_eq!(result.signed_inputs.len(), 3);
+    }
+
+    #[test]
+    fn test_sign_psbt_rejects_mismatched_previous_transaction() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+
+        // a previous transaction that is not the one the outpoint spends
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[0].non_witness_utxo = psbt.inputs[1].non_witness_utxo.clone();
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::UtxoMismatch(0))));
+
+        // an understated amount in the witness UTXO
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value = Amount::from_sat(1_000);
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::UtxoMismatch(0))));
+
+        // an outpoint past the outputs of the previous transaction
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.unsigned_tx.input[1].previous_output.vout = 1;
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::UtxoMismatch(1))));
+    }
+
+    #[test]
+    fn test_sign_psbt_rejects_sighash_none() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[0].sighash_type = Some(EcdsaSighashType::None.into());
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::Sighash(0, _))));
+
+        let mut psbt = build_psbt(&secp, &master);
+        psbt.inputs[2].sighash_type = Some(TapSighashType::None.into());
+        let result = sign_psbt(&SECRET_KEY, &psbt.serialize());
+        assert!(matches!(result, Err(PsbtError::Sighash(2, _))));
+    }
+
+    #[test]
+    fn test_sign_psbt_rejects_garbage() {
+        let result = sign_psbt(&SECRET_KEY, b"not a psbt");
+        assert!(matches!(result, Err(PsbtError::InvalidPsbt(_))));
+    }
+
+    #[test]
+    fn test_derived_keys_are_erased() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(NetworkKind::Main, &SECRET_KEY).unwrap();
+        let psbt = build_psbt(&secp, &master).serialize();
+
+        let erased = erased_keys(|| {
+            sign_psbt(&SECRET_KEY, &psbt).unwrap();
+        });
+        // both segwit v0 children, the taproot child, its key pair and tweaked
+        // key pair, then the master
+        assert_eq!(
+            erased,
+            vec![
+                ("Xpriv", true),
+                ("Xpriv", true),
+                ("Xpriv", true),
+                ("Keypair", true),
+                ("Keypair", true),
+                ("Xpriv", true),
+            ]
+        );
+    }
+}
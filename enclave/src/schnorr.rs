@@ -0,0 +1,159 @@
+use bitcoin::TapNodeHash;
+use bitcoin::hashes::Hash;
+use bitcoin::key::{Keypair, TapTweak, XOnlyPublicKey};
+use bitcoin::secp256k1::{All, Message, Secp256k1, schnorr};
+use shared::transport::TaprootTweak;
+
+use crate::secret::Erased;
+
+/// BIP341 output key pair: the internal key tweaked with `merkle_root`
+/// (`None` for key path only outputs, as in BIP86).
+pub fn tweak_keypair(secp: &Secp256k1<All>, keypair: &Keypair, taproot: &TaprootTweak) -> Keypair {
+    let merkle_root = taproot.merkle_root.map(TapNodeHash::from_byte_array);
+    return keypair.tap_tweak(secp, merkle_root).to_keypair();
+}
+
+/// BIP340 signature over a 32 byte message, tweaking the key first when
+/// `taproot` is set. Returns the signature together with the (possibly tweaked)
+/// x-only public key it verifies against.
+pub fn sign(
+    secp: &Secp256k1<All>,
+    keypair: &Keypair,
+    message: &[u8; 32],
+    taproot: Option<&TaprootTweak>,
+    aux_rand: &[u8; 32],
+) -> (schnorr::Signature, XOnlyPublicKey) {
+    let keypair = Erased(match taproot {
+        Some(taproot) => tweak_keypair(secp, keypair, taproot),
+        None => *keypair,
+    });
+    let signature =
+        secp.sign_schnorr_with_aux_rand(&Message::from_digest(*message), &keypair, aux_rand);
+    return (signature, keypair.x_only_public_key().0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::NetworkKind;
+    use bitcoin::bip32::{DerivationPath, Xpriv};
+    use std::str::FromStr;
+
+    fn hex<const N: usize>(s: &str) -> [u8; N] {
+        let bytes: Vec<u8> = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+        return bytes.try_into().unwrap();
+    }
+
+    // BIP340 test vectors 0-3: (secret key, public key, aux_rand, message, signature)
+    const BIP340_SIGNING_VECTORS: [(&str, &str, &str, &str, &str); 4] = [
+        (
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0",
+        ),
+        (
+            "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A",
+        ),
+        (
+            "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C9",
+            "DD308AFEC5777E13121FA72B9CC1B7CC0139715309B086C960E18FD969774EB8",
+            "C87AA53824B4D7AE2EB035A2B5BBBCCC080E76CDC6D1692C4B0B62D798E6D906",
+            "7E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "5831AAEED7B44BB74E5EAB94BA9D4294C49BCF2A60728D8B4C200F50DD313C1BAB745879A5AD954A72C45A91C3A51D3C7ADEA98D82F8481E0E1E03674A6F3FB7",
+        ),
+        (
+            "0B432B2677937381AEF05BB02A66ECD012773062CF3FA2549E44F58ED2401710",
+            "25D1DFF95105F5253C4022F628A996AD3A0D95FBF21D468A1B33F8C160D8F517",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+            "7EB0509757E246F19449885651611CB965ECC1A187DD51B64FDA1EDC9637D5EC97582B9CB13DB3933705B32BA982AF5AF25FD78881EBB32771FC5922EFC66EA3",
+        ),
+    ];
+
+    #[test]
+    fn test_bip340_signing_vectors() {
+        let secp = Secp256k1::new();
+        for (secret_key, public_key, aux_rand, message, signature) in BIP340_SIGNING_VECTORS {
+            let keypair = Keypair::from_seckey_slice(&secp, &hex::<32>(secret_key)).unwrap();
+            let (actual, x_only) = sign(&secp, &keypair, &hex(message), None, &hex(aux_rand));
+            assert_eq!(x_only.serialize(), hex::<32>(public_key));
+            assert_eq!(actual.serialize(), hex::<64>(signature));
+        }
+    }
+
+    #[test]
+    fn test_bip340_rejects_odd_r() {
+        // BIP340 test vector 6: has_even_y(R) is false
+        let secp = Secp256k1::new();
+        let public_key = XOnlyPublicKey::from_slice(&hex::<32>(
+            "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+        ))
+        .unwrap();
+        let signature = schnorr::Signature::from_slice(&hex::<64>(
+            "FFF97BD5755EEEA420453A14355235D382F6472F8568A18B2F057A14602975563CC27944640AC607CD107AE10923D9EF7A73C643E166BE5EBEAFA34B1AC553E2",
+        ))
+        .unwrap();
+        let message = Message::from_digest(hex(
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+        ));
+        assert!(
+            secp.verify_schnorr(&signature, &message, &public_key)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_bip86_key_path_tweak() {
+        // BIP86 test vector: "abandon" x11 "about", first receiving address
+        let secp = Secp256k1::new();
+        let seed = hex::<64>(
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        );
+        let keypair = Xpriv::new_master(NetworkKind::Main, &seed)
+            .unwrap()
+            .derive_priv(&secp, &DerivationPath::from_str("m/86'/0'/0'/0/0").unwrap())
+            .unwrap()
+            .to_keypair(&secp);
+        assert_eq!(
+            keypair.x_only_public_key().0.serialize(),
+            hex::<32>("cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+        );
+
+        let message = [0x42u8; 32];
+        let taproot = TaprootTweak { merkle_root: None };
+        let (signature, output_key) = sign(&secp, &keypair, &message, Some(&taproot), &[0u8; 32]);
+        assert_eq!(
+            output_key.serialize(),
+            hex::<32>("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c")
+        );
+        secp.verify_schnorr(&signature, &Message::from_digest(message), &output_key)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_merkle_root_changes_output_key() {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[9u8; 32]).unwrap();
+        let key_only = tweak_keypair(&secp, &keypair, &TaprootTweak { merkle_root: None });
+        let with_scripts = tweak_keypair(
+            &secp,
+            &keypair,
+            &TaprootTweak {
+                merkle_root: Some([1u8; 32]),
+            },
+        );
+        assert_ne!(
+            key_only.x_only_public_key().0,
+            with_scripts.x_only_public_key().0
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use shared::error::{NsmError, SealedBoxError};
+use shared::sealed::{self, ResponseKey, SealedBox, SealedRequest, open_request};
+use shared::transport::{VsockEnclaveSealingKeyData, VsockHostRequest};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::nsm;
+
+pub type SharedSealingKey = Arc<SealingKey>;
+
+/// X25519 key clients seal requests to, so that sensitive payloads such as
+/// imported keys pass through the host only as ciphertext. It is generated at
+/// boot and dies with the enclave; clients learn it from an attestation document.
+pub struct SealingKey {
+    secret: StaticSecret,
+    public_key: [u8; 32],
+}
+
+impl SealingKey {
+    pub fn generate() -> Result<Self, SealedBoxError> {
+        let mut secret = Zeroizing::new([0u8; 32]);
+        getrandom::getrandom(secret.as_mut())
+            .map_err(|e| SealedBoxError::Randomness(e.to_string()))?;
+        return Ok(Self::from_secret(StaticSecret::from(*secret)));
+    }
+
+    fn from_secret(secret: StaticSecret) -> Self {
+        let public_key = PublicKey::from(&secret).to_bytes();
+        return Self { secret, public_key };
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        return self.public_key;
+    }
+
+    pub fn attest(&self, nonce: Option<&[u8]>) -> Result<VsockEnclaveSealingKeyData, NsmError> {
+        return Ok(VsockEnclaveSealingKeyData {
+            public_key: self.public_key,
+            attestation: nsm::attestation(None, nonce, Some(&self.public_key))?,
+        });
+    }
+
+    /// Decrypts and decodes a sealed request, returning the key its response
+    /// must be sealed with.
+    pub fn open(
+        &self,
+        sealed_request: &SealedRequest,
+    ) -> Result<(VsockHostRequest, ResponseKey), SealedBoxError> {
+        let (plaintext, response_key) = open_request(&self.secret, sealed_request)?;
+        let request = serde_cbor::from_slice(&plaintext)
+            .map_err(|e| SealedBoxError::InvalidRequest(e.to_string()))?;
+        return Ok((request, response_key));
+    }
+
+    /// Opens a box sealed to this key for `info`, e.g. a DKG share sent by
+    /// another enclave.
+    pub fn open_box(
+        &self,
+        info: &[u8],
+        sealed_box: &SealedBox,
+    ) -> Result<Zeroizing<Vec<u8>>, SealedBoxError> {
+        return sealed::open(&self.secret, info, sealed_box);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::sealed::seal_request;
+
+    #[test]
+    fn test_open_decodes_sealed_requests() {
+        let sealing_key = SealingKey::from_secret(StaticSecret::from([3u8; 32]));
+        let request = VsockHostRequest::EvictCache {
+            kms_ciphertext: Some(vec![1, 2, 3]),
+        };
+        let (sealed_request, _) = seal_request(
+            &sealing_key.public_key,
+            &serde_cbor::to_vec(&request).unwrap(),
+        )
+        .unwrap();
+        let (opened, _) = sealing_key.open(&sealed_request).unwrap();
+        assert!(matches!(
+            opened,
+            VsockHostRequest::EvictCache { kms_ciphertext: Some(bytes) } if bytes == [1, 2, 3]
+        ));
+
+        let (sealed_garbage, _) = seal_request(&sealing_key.public_key, b"garbage").unwrap();
+        assert!(matches!(
+            sealing_key.open(&sealed_garbage),
+            Err(SealedBoxError::InvalidRequest(_))
+        ));
+    }
+}
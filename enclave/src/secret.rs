@@ -0,0 +1,223 @@
+use std::ops::Deref;
+
+use bitcoin::bip32::{ChainCode, Xpriv};
+use bitcoin::secp256k1::{Keypair, SecretKey};
+use zeroize::Zeroize;
+
+/// Heap allocated key material. The buffer is `mlock`ed where the platform
+/// allows it, deliberately has no `Debug` impl, and is zeroized on drop.
+pub struct Secret<T: ?Sized + Zeroize + AsRef<[u8]>> {
+    bytes: Box<T>,
+    locked: bool,
+}
+
+pub type SecretVec = Secret<[u8]>;
+
+impl<T: ?Sized + Zeroize + AsRef<[u8]>> Secret<T> {
+    fn new(bytes: Box<T>) -> Self {
+        let locked = lock((*bytes).as_ref());
+        return Self { bytes, locked };
+    }
+
+    pub fn expose(&self) -> &T {
+        return &self.bytes;
+    }
+
+    /// For filling a buffer in place, so the secret never exists unlocked.
+    pub fn expose_mut(&mut self) -> &mut T {
+        return &mut self.bytes;
+    }
+}
+
+impl<const N: usize> Secret<[u8; N]> {
+    /// Copies `bytes` into a new secret; `None` if the length is not `N`.
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != N {
+            return None;
+        }
+        let mut secret = Self::new(Box::new([0u8; N]));
+        secret.bytes.copy_from_slice(bytes);
+        return Some(secret);
+    }
+}
+
+impl SecretVec {
+    /// Moves `bytes` into a locked buffer and wipes the original allocation
+    /// (including any spare capacity).
+    pub fn from_vec(mut bytes: Vec<u8>) -> Self {
+        let secret = Self::from_slice(&bytes);
+        bytes.zeroize();
+        return secret;
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        return Self::new(bytes.to_vec().into_boxed_slice());
+    }
+}
+
+impl Clone for SecretVec {
+    fn clone(&self) -> Self {
+        return Self::from_slice(self.expose());
+    }
+}
+
+impl<T: ?Sized + Zeroize + AsRef<[u8]>> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        #[cfg(test)]
+        tests::record_drop((*self.bytes).as_ref());
+        if self.locked {
+            unlock((*self.bytes).as_ref());
+        }
+    }
+}
+
+/// Key material erased when dropped. The `bitcoin` key types are `Copy` and
+/// never clear themselves, so every one holding a private key is kept in this.
+pub struct Erased<T: Erase>(pub T);
+
+pub trait Erase {
+    fn erase(&mut self);
+
+    /// The private key, to check that `erase` cleared it.
+    #[cfg(test)]
+    fn secret_bytes(&self) -> [u8; 32];
+}
+
+impl Erase for Xpriv {
+    fn erase(&mut self) {
+        self.private_key.non_secure_erase();
+        self.chain_code = ChainCode::from([0u8; 32]);
+    }
+
+    #[cfg(test)]
+    fn secret_bytes(&self) -> [u8; 32] {
+        return self.private_key.secret_bytes();
+    }
+}
+
+impl Erase for SecretKey {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+
+    #[cfg(test)]
+    fn secret_bytes(&self) -> [u8; 32] {
+        return SecretKey::secret_bytes(self);
+    }
+}
+
+impl Erase for Keypair {
+    fn erase(&mut self) {
+        self.non_secure_erase();
+    }
+
+    #[cfg(test)]
+    fn secret_bytes(&self) -> [u8; 32] {
+        return Keypair::secret_bytes(self);
+    }
+}
+
+impl<T: Erase> Deref for Erased<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        return &self.0;
+    }
+}
+
+impl<T: Erase> Drop for Erased<T> {
+    fn drop(&mut self) {
+        #[cfg(test)]
+        let before = self.0.secret_bytes();
+        self.0.erase();
+        #[cfg(test)]
+        tests::record_erase(std::any::type_name::<T>(), before, self.0.secret_bytes());
+    }
+}
+
+#[cfg(unix)]
+fn lock(bytes: &[u8]) -> bool {
+    if bytes.is_empty() {
+        return false;
+    }
+    // SAFETY: the range is a live allocation owned by the `Secret` for as long
+    // as it stays locked.
+    return unsafe { libc::mlock(bytes.as_ptr().cast(), bytes.len()) } == 0;
+}
+
+#[cfg(unix)]
+fn unlock(bytes: &[u8]) {
+    // SAFETY: see `lock`.
+    unsafe {
+        libc::munlock(bytes.as_ptr().cast(), bytes.len());
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &[u8]) -> bool {
+    return false;
+}
+
+#[cfg(not(unix))]
+fn unlock(_bytes: &[u8]) {}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static DROPPED: RefCell<Vec<(usize, bool)>> = const { RefCell::new(Vec::new()) };
+        static ERASED: RefCell<Vec<(&'static str, bool)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// `non_secure_erase` overwrites keys with a fixed byte, so an erased key is
+    /// one that changed to a single repeated byte.
+    pub(crate) fn record_erase(type_name: &'static str, before: [u8; 32], after: [u8; 32]) {
+        let erased = after != before && after.iter().all(|b| *b == after[0]);
+        let type_name = type_name.rsplit("::").next().unwrap();
+        ERASED.with(|erased_keys| erased_keys.borrow_mut().push((type_name, erased)));
+    }
+
+    /// Runs `f` and returns `(type, erased)` for every [`Erased`] key dropped
+    /// meanwhile on this thread.
+    pub(crate) fn erased_keys(f: impl FnOnce()) -> Vec<(&'static str, bool)> {
+        ERASED.with(|erased_keys| erased_keys.borrow_mut().clear());
+        f();
+        return ERASED.with(|erased_keys| erased_keys.borrow_mut().drain(..).collect());
+    }
+
+    pub(crate) fn record_drop(bytes: &[u8]) {
+        DROPPED.with(|dropped| {
+            dropped
+                .borrow_mut()
+                .push((bytes.len(), bytes.iter().all(|b| *b == 0)))
+        });
+    }
+
+    /// Runs `f` and returns `(length, wiped)` for every secret dropped meanwhile
+    /// on this thread.
+    pub(crate) fn dropped_secrets(f: impl FnOnce()) -> Vec<(usize, bool)> {
+        DROPPED.with(|dropped| dropped.borrow_mut().clear());
+        f();
+        return DROPPED.with(|dropped| dropped.borrow_mut().drain(..).collect());
+    }
+
+    #[test]
+    fn test_from_slice_checks_length() {
+        assert!(Secret::<[u8; 4]>::from_slice(&[1, 2, 3]).is_none());
+        let secret = Secret::<[u8; 4]>::from_slice(&[1, 2, 3, 4]).unwrap();
+        assert_eq!(secret.expose(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_secrets_are_wiped_on_drop() {
+        let dropped = dropped_secrets(|| {
+            let secret = SecretVec::from_vec(vec![7u8; 48]);
+            let copy = secret.clone();
+            assert_eq!(copy.expose(), secret.expose());
+        });
+        assert_eq!(dropped, vec![(48, true), (48, true)]);
+    }
+}
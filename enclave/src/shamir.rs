@@ -0,0 +1,104 @@
+use shared::error::BackupError;
+
+use crate::secret::SecretVec;
+
+/// Evaluates at `x` the polynomial through `shares`, byte by byte over
+/// GF(2^8) with the AES polynomial, as SLIP-39 does.
+pub fn interpolate(shares: &[(u8, &[u8])], x: u8) -> Result<SecretVec, BackupError> {
+    let Some((_, first)) = shares.first() else {
+        return Err(BackupError::InvalidShare("no shares".to_string()));
+    };
+    for (i, (xi, yi)) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|(other, _)| other == xi) {
+            return Err(BackupError::InvalidShare(format!(
+                "duplicate share index {}",
+                xi
+            )));
+        }
+        if yi.len() != first.len() {
+            return Err(BackupError::InvalidShare(
+                "shares differ in length".to_string(),
+            ));
+        }
+    }
+
+    let mut value = SecretVec::from_vec(vec![0u8; first.len()]);
+    if let Some((_, y)) = shares.iter().find(|(xi, _)| *xi == x) {
+        value.expose_mut().copy_from_slice(y);
+        return Ok(value);
+    }
+    for (i, (xi, yi)) in shares.iter().enumerate() {
+        // Lagrange basis polynomial for share i, evaluated at x
+        let mut numerator = 1u8;
+        let mut denominator = 1u8;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                numerator = mul(numerator, x ^ xj);
+                denominator = mul(denominator, xi ^ xj);
+            }
+        }
+        let basis = mul(numerator, inverse(denominator));
+        for (byte, y) in value.expose_mut().iter_mut().zip(yi.iter()) {
+            *byte ^= mul(*y, basis);
+        }
+    }
+    return Ok(value);
+}
+
+/// Multiplication modulo the AES polynomial, without secret dependent branches.
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = a >> 7;
+        a <<= 1;
+        a ^= 0x1b & carry.wrapping_neg();
+        b >>= 1;
+    }
+    return product;
+}
+
+/// a^254, which is a^-1 for every non-zero a.
+fn inverse(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul(result, base);
+        }
+        base = mul(base, base);
+        exponent >>= 1;
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolation_recovers_the_polynomial() {
+        // f(x) = 7 + 3x + 5x^2 for every byte
+        let f = |x: u8| mul(5, mul(x, x)) ^ mul(3, x) ^ 7;
+        let points: Vec<(u8, Vec<u8>)> =
+            [1u8, 2, 200].iter().map(|x| (*x, vec![f(*x); 4])).collect();
+        let shares: Vec<(u8, &[u8])> = points.iter().map(|(x, y)| (*x, y.as_slice())).collect();
+
+        for x in [0u8, 9, 254, 255] {
+            assert_eq!(interpolate(&shares, x).unwrap().expose(), &[f(x); 4]);
+        }
+        assert_eq!(interpolate(&shares, 2).unwrap().expose(), &[f(2); 4]);
+        assert_ne!(interpolate(&shares[..2], 0).unwrap().expose(), &[f(0); 4]);
+    }
+
+    #[test]
+    fn test_invalid_shares_are_rejected() {
+        assert!(interpolate(&[], 0).is_err());
+        assert!(interpolate(&[(1, &[1u8; 4]), (1, &[2u8; 4])], 0).is_err());
+        assert!(interpolate(&[(1, &[1u8; 4]), (2, &[2u8; 2])], 0).is_err());
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inverse(a)), 1);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{Notify, watch};
+
+pub type SharedShutdown = Arc<Shutdown>;
+
+/// Extra time the process waits after the grace period, so that a `Shutdown`
+/// request that timed out still gets its answer sent.
+const ANSWER_MARGIN: Duration = Duration::from_secs(1);
+
+/// Coordinates a graceful shutdown, requested by SIGTERM, SIGINT or a
+/// [`shared::transport::VsockHostRequest::Shutdown`]: the accept loop stops,
+/// requests in progress get `grace` to finish, then caches are cleared and the
+/// process exits, dropping whatever is still running.
+pub struct Shutdown {
+    requested: Notify,
+    in_flight: watch::Sender<usize>,
+    grace: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        return Self {
+            requested: Notify::new(),
+            in_flight: watch::Sender::new(0),
+            grace,
+        };
+    }
+
+    pub fn request(&self) {
+        self.requested.notify_one();
+    }
+
+    /// Resolves once [`Shutdown::request`] was called, even before this.
+    pub async fn requested(&self) {
+        self.requested.notified().await;
+    }
+
+    /// Counts a request as in progress until the returned guard is dropped.
+    /// Taken before the request's task is spawned, so that a shutdown cannot
+    /// miss it.
+    pub fn start_request(self: &Arc<Self>) -> InFlightRequest {
+        self.in_flight.send_modify(|in_flight| *in_flight += 1);
+        return InFlightRequest {
+            shutdown: self.clone(),
+        };
+    }
+
+    pub fn in_flight(&self) -> usize {
+        return *self.in_flight.borrow();
+    }
+
+    /// Waits up to the grace period for every request but the calling one to
+    /// finish. Returns whether they did.
+    pub async fn drain_others(&self) -> bool {
+        return self.wait_for_requests(1, self.grace).await;
+    }
+
+    /// Waits for every request to finish, giving up shortly after the grace
+    /// period. Returns whether they did.
+    pub async fn drain(&self) -> bool {
+        return self.wait_for_requests(0, self.grace + ANSWER_MARGIN).await;
+    }
+
+    async fn wait_for_requests(&self, remaining: usize, timeout: Duration) -> bool {
+        let mut in_flight = self.in_flight.subscribe();
+        let drained = in_flight.wait_for(|in_flight| *in_flight <= remaining);
+        return matches!(tokio::time::timeout(timeout, drained).await, Ok(Ok(_)));
+    }
+}
+
+pub struct InFlightRequest {
+    shutdown: SharedShutdown,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        self.shutdown
+            .in_flight
+            .send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_are_drained_until_the_grace_period_ends() {
+        let shutdown = Arc::new(Shutdown::new(Duration::from_millis(200)));
+        let quick = shutdown.start_request();
+        let slow = shutdown.start_request();
+        assert_eq!(shutdown.in_flight(), 2);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(quick);
+        });
+        assert!(shutdown.drain_others().await);
+        assert_eq!(shutdown.in_flight(), 1);
+
+        assert!(!shutdown.drain().await);
+        drop(slow);
+        assert!(shutdown.drain().await);
+    }
+
+    #[tokio::test]
+    async fn test_request_before_waiting_is_not_lost() {
+        let shutdown = Shutdown::new(Duration::from_secs(30));
+        shutdown.request();
+        shutdown.requested().await;
+    }
+}
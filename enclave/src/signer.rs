@@ -0,0 +1,300 @@
+use bitcoin::NetworkKind;
+use bitcoin::bip32::{DerivationPath, Xpriv};
+use bitcoin::secp256k1::{Keypair, Message, Secp256k1, SecretKey};
+use pallas_crypto::key::ed25519;
+use shared::error::SignerError;
+use shared::policy::SignatureSchemeKind;
+use shared::transport::{
+    SignBatchItem, SignatureScheme, VsockEnclaveSignBatchData, VsockEnclaveSignData, WalletOrigin,
+};
+use zeroize::Zeroizing;
+
+use crate::secret::Erased;
+use crate::{schnorr, solana};
+
+/// Signs a raw payload for the generic `Sign` request. Generated wallets
+/// derive the key at `derivation_path`; imported keys sign as is.
+pub fn sign(
+    secret_key: &[u8; 64],
+    origin: WalletOrigin,
+    derivation_path: &str,
+    signature_scheme: &SignatureScheme,
+    payload: &[u8],
+) -> Result<VsockEnclaveSignData, SignerError> {
+    match signature_scheme {
+        SignatureScheme::Secp256k1 => {
+            let secp = Secp256k1::new();
+            let private_key = secp256k1_key(secret_key, origin, derivation_path, signature_scheme)?;
+            let signature = secp.sign_ecdsa(&Message::from_digest(digest(payload)?), &private_key);
+
+            return Ok(VsockEnclaveSignData {
+                public_key: private_key.public_key(&secp).serialize().to_vec(),
+                signature: signature.serialize_compact().to_vec(),
+            });
+        }
+        SignatureScheme::Secp256k1Schnorr { taproot } => {
+            let secp = Secp256k1::new();
+            let keypair = Erased(Keypair::from_secret_key(
+                &secp,
+                &*secp256k1_key(secret_key, origin, derivation_path, signature_scheme)?,
+            ));
+            let mut aux_rand = [0u8; 32];
+            getrandom::getrandom(&mut aux_rand)
+                .map_err(|e| SignerError::Randomness(e.to_string()))?;
+            let (signature, public_key) = schnorr::sign(
+                &secp,
+                &keypair,
+                &digest(payload)?,
+                taproot.as_ref(),
+                &aux_rand,
+            );
+
+            return Ok(VsockEnclaveSignData {
+                public_key: public_key.serialize().to_vec(),
+                signature: signature.serialize().to_vec(),
+            });
+        }
+        SignatureScheme::Ed25519 => {
+            let signing_key = ed25519_key(secret_key, origin, derivation_path, signature_scheme)?;
+            let public_key: [u8; 32] = signing_key.public_key().into();
+
+            return Ok(VsockEnclaveSignData {
+                public_key: public_key.to_vec(),
+                signature: signing_key.sign(payload).as_ref().to_vec(),
+            });
+        }
+    }
+}
+
+/// Signs every item with the same unwrapped wallet secret, keeping per-item errors.
+pub fn sign_batch(
+    secret_key: &[u8; 64],
+    origin: WalletOrigin,
+    items: &[SignBatchItem],
+) -> VsockEnclaveSignBatchData {
+    let results = items
+        .iter()
+        .map(|item| {
+            return Ok(sign(
+                secret_key,
+                origin,
+                &item.derivation_path,
+                &item.signature_scheme,
+                &item.payload,
+            )?);
+        })
+        .collect();
+    return VsockEnclaveSignBatchData { results };
+}
+
+fn secp256k1_key(
+    secret_key: &[u8; 64],
+    origin: WalletOrigin,
+    derivation_path: &str,
+    signature_scheme: &SignatureScheme,
+) -> Result<Erased<SecretKey>, SignerError> {
+    let WalletOrigin::Imported {
+        signature_scheme: imported,
+    } = origin
+    else {
+        return Ok(Erased(
+            derive_secp256k1(secret_key, derivation_path)?.private_key,
+        ));
+    };
+    check_imported(imported, derivation_path, signature_scheme)?;
+    return SecretKey::from_slice(&secret_key[..32])
+        .map(Erased)
+        .map_err(|_| SignerError::InvalidImportedKey);
+}
+
+fn ed25519_key(
+    secret_key: &[u8; 64],
+    origin: WalletOrigin,
+    derivation_path: &str,
+    signature_scheme: &SignatureScheme,
+) -> Result<ed25519::SecretKey, SignerError> {
+    let WalletOrigin::Imported {
+        signature_scheme: imported,
+    } = origin
+    else {
+        return Ok(solana::Slip10Key::from_seed(secret_key)
+            .derive_path(derivation_path)
+            .map_err(|_| SignerError::InvalidDerivationPath(derivation_path.to_string()))?
+            .signing_key());
+    };
+    check_imported(imported, derivation_path, signature_scheme)?;
+    let seed: Zeroizing<[u8; 32]> = Zeroizing::new(secret_key[..32].try_into().unwrap());
+    return Ok(ed25519::SecretKey::from(*seed));
+}
+
+/// Imported keys sign only with the scheme they were imported for, and have no
+/// children to derive.
+fn check_imported(
+    imported: SignatureSchemeKind,
+    derivation_path: &str,
+    signature_scheme: &SignatureScheme,
+) -> Result<(), SignerError> {
+    let requested = SignatureSchemeKind::from(signature_scheme);
+    if requested != imported {
+        return Err(SignerError::ImportedKeyScheme {
+            imported,
+            requested,
+        });
+    }
+    if derivation_path != "m" {
+        return Err(SignerError::ImportedKeyDerivation(
+            derivation_path.to_string(),
+        ));
+    }
+    return Ok(());
+}
+
+fn derive_secp256k1(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+) -> Result<Erased<Xpriv>, SignerError> {
+    let invalid_path = || SignerError::InvalidDerivationPath(derivation_path.to_string());
+    let path: DerivationPath = derivation_path.parse().map_err(|_| invalid_path())?;
+    let master =
+        Erased(Xpriv::new_master(NetworkKind::Main, secret_key).map_err(|_| invalid_path())?);
+    return master
+        .derive_priv(&Secp256k1::new(), &path)
+        .map(Erased)
+        .map_err(|_| invalid_path());
+}
+
+fn digest(payload: &[u8]) -> Result<[u8; 32], SignerError> {
+    return payload
+        .try_into()
+        .map_err(|_| SignerError::InvalidDigestLength(payload.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::key::XOnlyPublicKey;
+    use bitcoin::secp256k1::{PublicKey, ecdsa, schnorr::Signature};
+    use shared::error::VsockEnclaveSignError;
+    use shared::transport::TaprootTweak;
+
+    const SECRET_KEY: [u8; 64] = [3u8; 64];
+    const DIGEST: [u8; 32] = [0xabu8; 32];
+
+    #[test]
+    fn test_sign_ecdsa_verifies() {
+        let result = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/44'/0'/0'/0/0",
+            &SignatureScheme::Secp256k1,
+            &DIGEST,
+        )
+        .unwrap();
+
+        let public_key = PublicKey::from_slice(&result.public_key).unwrap();
+        let signature = ecdsa::Signature::from_compact(&result.signature).unwrap();
+        Secp256k1::new()
+            .verify_ecdsa(&Message::from_digest(DIGEST), &signature, &public_key)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sign_schnorr_with_and_without_tweak() {
+        let secp = Secp256k1::new();
+        let untweaked = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/86'/0'/0'/0/0",
+            &SignatureScheme::Secp256k1Schnorr { taproot: None },
+            &DIGEST,
+        )
+        .unwrap();
+        let tweaked = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/86'/0'/0'/0/0",
+            &SignatureScheme::Secp256k1Schnorr {
+                taproot: Some(TaprootTweak { merkle_root: None }),
+            },
+            &DIGEST,
+        )
+        .unwrap();
+        assert_ne!(untweaked.public_key, tweaked.public_key);
+
+        for result in [untweaked, tweaked] {
+            let public_key = XOnlyPublicKey::from_slice(&result.public_key).unwrap();
+            let signature = Signature::from_slice(&result.signature).unwrap();
+            secp.verify_schnorr(&signature, &Message::from_digest(DIGEST), &public_key)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sign_ed25519_matches_solana_key() {
+        let result = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/44'/501'/0'/0'",
+            &SignatureScheme::Ed25519,
+            b"arbitrary length payload",
+        )
+        .unwrap();
+        assert_eq!(result.public_key.len(), 32);
+        assert_eq!(result.signature.len(), 64);
+    }
+
+    #[test]
+    fn test_sign_rejects_non_digest_payload() {
+        let result = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/0",
+            &SignatureScheme::Secp256k1,
+            b"too short",
+        );
+        assert!(matches!(result, Err(SignerError::InvalidDigestLength(9))));
+    }
+
+    #[test]
+    fn test_sign_batch_keeps_per_item_errors() {
+        let items = vec![
+            SignBatchItem {
+                signature_scheme: SignatureScheme::Secp256k1,
+                derivation_path: "m/44'/0'/0'/0/0".to_string(),
+                payload: DIGEST.to_vec(),
+            },
+            SignBatchItem {
+                signature_scheme: SignatureScheme::Secp256k1,
+                derivation_path: "not a path".to_string(),
+                payload: DIGEST.to_vec(),
+            },
+            SignBatchItem {
+                signature_scheme: SignatureScheme::Ed25519,
+                derivation_path: "m/44'/501'/1'/0'".to_string(),
+                payload: b"payload".to_vec(),
+            },
+        ];
+
+        let batch = sign_batch(&SECRET_KEY, WalletOrigin::Generated, &items);
+        assert_eq!(batch.results.len(), 3);
+        assert!(batch.results[0].is_ok());
+        assert!(matches!(
+            batch.results[1],
+            Err(VsockEnclaveSignError::SignerError(_))
+        ));
+        assert!(batch.results[2].is_ok());
+
+        let single = sign(
+            &SECRET_KEY,
+            WalletOrigin::Generated,
+            "m/44'/0'/0'/0/0",
+            &SignatureScheme::Secp256k1,
+            &DIGEST,
+        )
+        .unwrap();
+        assert_eq!(
+            batch.results[0].as_ref().unwrap().signature,
+            single.signature
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use cryptoxide::{hmac::Hmac, mac::Mac, pbkdf2::pbkdf2, sha2::Sha256};
+use shared::error::BackupError;
+use shared::slip39::Slip39Share;
+use zeroize::Zeroizing;
+
+use crate::secret::SecretVec;
+use crate::shamir;
+
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+const DIGEST_LENGTH: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const MAX_SHARE_COUNT: usize = 16;
+/// The shares themselves are sealed to custodian keys, so the passphrase is
+/// empty and the key stretching kept at its minimum.
+const ITERATION_EXPONENT: u8 = 0;
+
+/// Splits `secret` into `count` SLIP-39 shares of a single group, any
+/// `threshold` of which recover it.
+pub fn split(secret: &[u8], threshold: u8, count: usize) -> Result<Vec<Slip39Share>, BackupError> {
+    if threshold == 0 || usize::from(threshold) > count || count > MAX_SHARE_COUNT {
+        return Err(BackupError::InvalidThreshold {
+            threshold,
+            shares: count,
+        });
+    }
+
+    let mut identifier = [0u8; 2];
+    getrandom::getrandom(&mut identifier).map_err(|e| BackupError::Randomness(e.to_string()))?;
+    let identifier = u16::from_be_bytes(identifier) & 0x7fff;
+
+    let encrypted_secret = crypt(secret, b"", identifier, true, ITERATION_EXPONENT, false);
+    return Ok(split_secret(encrypted_secret.expose(), threshold, count)?
+        .into_iter()
+        .map(|(member_index, value)| Slip39Share {
+            identifier,
+            extendable: true,
+            iteration_exponent: ITERATION_EXPONENT,
+            group_index: 0,
+            group_threshold: 1,
+            group_count: 1,
+            member_index,
+            member_threshold: threshold,
+            value: value.expose().to_vec(),
+        })
+        .collect());
+}
+
+/// Recovers the secret from shares of a single group split with an empty
+/// passphrase, checking the SLIP-39 digest.
+pub fn combine(shares: &[Slip39Share]) -> Result<SecretVec, BackupError> {
+    return combine_with_passphrase(shares, b"");
+}
+
+fn combine_with_passphrase(
+    shares: &[Slip39Share],
+    passphrase: &[u8],
+) -> Result<SecretVec, BackupError> {
+    let Some(first) = shares.first() else {
+        return Err(BackupError::NotEnoughShares {
+            threshold: 1,
+            shares: 0,
+        });
+    };
+    let consistent = shares.iter().all(|share| {
+        share.identifier == first.identifier
+            && share.extendable == first.extendable
+            && share.iteration_exponent == first.iteration_exponent
+            && share.group_index == first.group_index
+            && share.group_threshold == first.group_threshold
+            && share.group_count == first.group_count
+            && share.member_threshold == first.member_threshold
+    });
+    if !consistent {
+        return Err(BackupError::MismatchedShares);
+    }
+    if first.group_threshold != 1 {
+        return Err(BackupError::InvalidShare(
+            "only single group shares are supported".to_string(),
+        ));
+    }
+    if shares.len() < usize::from(first.member_threshold) {
+        return Err(BackupError::NotEnoughShares {
+            threshold: first.member_threshold,
+            shares: shares.len(),
+        });
+    }
+
+    let points: Vec<(u8, &[u8])> = shares
+        .iter()
+        .map(|share| (share.member_index, share.value.as_slice()))
+        .collect();
+    let encrypted_secret = recover_secret(first.member_threshold, &points)?;
+    return Ok(crypt(
+        encrypted_secret.expose(),
+        passphrase,
+        first.identifier,
+        first.extendable,
+        first.iteration_exponent,
+        true,
+    ));
+}
+
+fn split_secret(
+    secret: &[u8],
+    threshold: u8,
+    count: usize,
+) -> Result<Vec<(u8, SecretVec)>, BackupError> {
+    if threshold == 1 {
+        return Ok((0..count as u8)
+            .map(|index| (index, SecretVec::from_slice(secret)))
+            .collect());
+    }
+
+    // threshold - 2 random shares, plus the digest and the secret, fix the polynomial
+    let random_count = usize::from(threshold - 2);
+    let mut base = Vec::with_capacity(random_count + 2);
+    for index in 0..random_count as u8 {
+        let mut value = SecretVec::from_vec(vec![0u8; secret.len()]);
+        getrandom::getrandom(value.expose_mut())
+            .map_err(|e| BackupError::Randomness(e.to_string()))?;
+        base.push((index, value));
+    }
+    let mut digest_share = SecretVec::from_vec(vec![0u8; secret.len()]);
+    getrandom::getrandom(&mut digest_share.expose_mut()[DIGEST_LENGTH..])
+        .map_err(|e| BackupError::Randomness(e.to_string()))?;
+    let digest = digest(&digest_share.expose()[DIGEST_LENGTH..], secret);
+    digest_share.expose_mut()[..DIGEST_LENGTH].copy_from_slice(&digest);
+    base.push((DIGEST_INDEX, digest_share));
+    base.push((SECRET_INDEX, SecretVec::from_slice(secret)));
+
+    let points: Vec<(u8, &[u8])> = base.iter().map(|(x, y)| (*x, y.expose())).collect();
+    let mut shares = Vec::with_capacity(count);
+    for index in 0..count as u8 {
+        let value = match base.iter().take(random_count).find(|(x, _)| *x == index) {
+            Some((_, value)) => value.clone(),
+            None => shamir::interpolate(&points, index)?,
+        };
+        shares.push((index, value));
+    }
+    return Ok(shares);
+}
+
+fn recover_secret(threshold: u8, shares: &[(u8, &[u8])]) -> Result<SecretVec, BackupError> {
+    if threshold == 1 {
+        return Ok(SecretVec::from_slice(shares[0].1));
+    }
+
+    let secret = shamir::interpolate(shares, SECRET_INDEX)?;
+    let digest_share = shamir::interpolate(shares, DIGEST_INDEX)?;
+    let (expected, random_part) = digest_share.expose().split_at(DIGEST_LENGTH);
+    if digest(random_part, secret.expose()) != expected {
+        return Err(BackupError::InvalidShare(
+            "shares do not match their digest".to_string(),
+        ));
+    }
+    return Ok(secret);
+}
+
+fn digest(random_part: &[u8], secret: &[u8]) -> [u8; DIGEST_LENGTH] {
+    let mut mac = Hmac::new(Sha256::new(), random_part);
+    mac.input(secret);
+    let mut output = [0u8; 32];
+    mac.raw_result(&mut output);
+    return output[..DIGEST_LENGTH].try_into().unwrap();
+}
+
+/// The SLIP-39 Feistel network.
+fn crypt(
+    input: &[u8],
+    passphrase: &[u8],
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    decrypt: bool,
+) -> SecretVec {
+    let salt = match extendable {
+        true => Vec::new(),
+        false => [b"shamir".as_slice(), &identifier.to_be_bytes()].concat(),
+    };
+    let half = input.len() / 2;
+    let mut left = SecretVec::from_slice(&input[..half]);
+    let mut right = SecretVec::from_slice(&input[half..]);
+    let mut round_key = SecretVec::from_vec(vec![0u8; half]);
+
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / u32::from(ROUND_COUNT);
+    for round in 0..ROUND_COUNT {
+        let round = match decrypt {
+            true => ROUND_COUNT - 1 - round,
+            false => round,
+        };
+        let password = Zeroizing::new([[round].as_slice(), passphrase].concat());
+        let mut mac = Hmac::new(Sha256::new(), &password);
+        let round_salt = Zeroizing::new([salt.as_slice(), right.expose()].concat());
+        pbkdf2(&mut mac, &round_salt, iterations, round_key.expose_mut());
+        for (byte, key) in left.expose_mut().iter_mut().zip(round_key.expose()) {
+            *byte ^= key;
+        }
+        std::mem::swap(&mut left, &mut right);
+    }
+
+    let mut output = SecretVec::from_vec(vec![0u8; input.len()]);
+    output.expose_mut()[..half].copy_from_slice(right.expose());
+    output.expose_mut()[half..].copy_from_slice(left.expose());
+    return output;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_any_threshold_shares_recover_the_secret() {
+        let secret: Vec<u8> = (0..64).collect();
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.value.len() == 64));
+
+        for a in 0..5 {
+            for b in a + 1..5 {
+                for c in b + 1..5 {
+                    let subset = [shares[a].clone(), shares[b].clone(), shares[c].clone()];
+                    assert_eq!(combine(&subset).unwrap().expose(), &secret);
+                }
+            }
+        }
+        assert!(matches!(
+            combine(&shares[..2]),
+            Err(BackupError::NotEnoughShares {
+                threshold: 3,
+                shares: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn test_shares_survive_index_encoding() {
+        let secret = [9u8; 64];
+        let shares = split(&secret, 2, 3).unwrap();
+        let decoded: Vec<Slip39Share> = shares[1..]
+            .iter()
+            .map(|share| Slip39Share::from_indices(&share.to_indices()).unwrap())
+            .collect();
+        assert_eq!(combine(&decoded).unwrap().expose(), &secret);
+
+        // a single share holds the encrypted secret, not the secret itself
+        let single = split(&secret, 1, 2).unwrap();
+        assert_ne!(single[0].value, secret);
+        assert_eq!(combine(&single[1..]).unwrap().expose(), &secret);
+    }
+
+    #[test]
+    fn test_tampered_or_mismatched_shares_are_rejected() {
+        let secret = [9u8; 64];
+        let mut shares = split(&secret, 2, 3).unwrap();
+        assert!(split(&secret, 2, 17).is_err());
+
+        shares[0].value[0] ^= 1;
+        assert!(combine(&shares[..2]).is_err());
+
+        let other = split(&secret, 2, 3).unwrap();
+        let mixed = [shares[1].clone(), other[2].clone()];
+        assert!(combine(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_feistel_round_trip() {
+        let secret: Vec<u8> = (0..16).collect();
+        for extendable in [true, false] {
+            let encrypted = crypt(&secret, b"", 7, extendable, 1, false);
+            assert_ne!(encrypted.expose(), secret.as_slice());
+            let decrypted = crypt(encrypted.expose(), b"", 7, extendable, 1, true);
+            assert_eq!(decrypted.expose(), secret.as_slice());
+        }
+    }
+
+    /// A subset of the official SLIP-39 test vectors, in their format, all of a
+    /// single group. Their passphrase is "TREZOR".
+    #[test]
+    fn test_official_vectors() {
+        let wordlist: Vec<&str> = include_str!("../testdata/slip39/wordlist.txt")
+            .lines()
+            .collect();
+        let vectors: Vec<(String, Vec<String>, String)> =
+            serde_json::from_str(include_str!("../testdata/slip39/vectors.json")).unwrap();
+
+        for (description, mnemonics, master_secret) in vectors {
+            let shares: Result<Vec<Slip39Share>, BackupError> = mnemonics
+                .iter()
+                .map(|mnemonic| {
+                    let indices: Vec<u16> = mnemonic
+                        .split(' ')
+                        .map(|word| wordlist.iter().position(|w| *w == word).unwrap() as u16)
+                        .collect();
+                    return Slip39Share::from_indices(&indices);
+                })
+                .collect();
+            let result = shares.and_then(|shares| combine_with_passphrase(&shares, b"TREZOR"));
+            if master_secret.is_empty() {
+                assert!(result.is_err(), "{}", description);
+            } else {
+                assert_eq!(
+                    hex::encode(result.unwrap().expose()),
+                    master_secret,
+                    "{}",
+                    description
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use bip32::DerivationPath;
+use cryptoxide::{hmac::Hmac, mac::Mac, sha2::Sha512};
+use pallas_crypto::key::ed25519::SecretKey;
+use shared::error::SolanaError;
+use shared::transport::VsockEnclaveSignSolanaTxData;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::policy::Transfer;
+
+const SLIP10_ED25519_SEED: &[u8] = b"ed25519 seed";
+const VERSION_PREFIX_MASK: u8 = 0x80;
+const SYSTEM_PROGRAM_ID: [u8; 32] = [0u8; 32];
+const SYSTEM_TRANSFER_INSTRUCTION: u32 = 2;
+
+/// SLIP-10 Ed25519 key, as used by Solana wallets (e.g. `m/44'/501'/0'/0'`).
+/// Ed25519 only supports hardened derivation under SLIP-10.
+pub struct Slip10Key {
+    secret: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+impl Slip10Key {
+    pub fn from_seed(seed: &[u8]) -> Self {
+        return Self::from_hmac(SLIP10_ED25519_SEED, &[seed]);
+    }
+
+    pub fn derive_path(&self, path: &str) -> Result<Self, SolanaError> {
+        let path: DerivationPath = path
+            .parse()
+            .map_err(|_| SolanaError::InvalidDerivationPath(path.to_string()))?;
+
+        let mut key = Self {
+            secret: self.secret,
+            chain_code: self.chain_code,
+        };
+        for child_number in path.iter() {
+            if !child_number.is_hardened() {
+                return Err(SolanaError::NonHardenedDerivation);
+            }
+            key = key.derive_hardened(child_number.into());
+        }
+        return Ok(key);
+    }
+
+    fn derive_hardened(&self, index: u32) -> Self {
+        return Self::from_hmac(
+            &self.chain_code,
+            &[&[0u8], &self.secret, &index.to_be_bytes()],
+        );
+    }
+
+    fn from_hmac(key: &[u8], data: &[&[u8]]) -> Self {
+        let mut mac = Hmac::new(Sha512::new(), key);
+        for chunk in data {
+            mac.input(chunk);
+        }
+        let mut output = Zeroizing::new([0u8; 64]);
+        mac.raw_result(&mut *output);
+
+        let mut secret = [0u8; 32];
+        let mut chain_code = [0u8; 32];
+        secret.copy_from_slice(&output[..32]);
+        chain_code.copy_from_slice(&output[32..]);
+        return Self { secret, chain_code };
+    }
+
+    /// The returned key scrubs itself when dropped.
+    pub fn signing_key(&self) -> SecretKey {
+        let secret = Zeroizing::new(self.secret);
+        return SecretKey::from(*secret);
+    }
+}
+
+impl Drop for Slip10Key {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+        self.chain_code.zeroize();
+    }
+}
+
+/// The parts of a legacy or v0 message needed to place a signature.
+#[derive(Debug)]
+pub struct SolanaMessage {
+    /// `None` for legacy messages, `Some(0)` for v0.
+    pub version: Option<u8>,
+    pub num_required_signatures: u8,
+    pub account_keys: Vec<[u8; 32]>,
+    pub instructions: Vec<CompiledInstruction>,
+}
+
+#[derive(Debug)]
+pub struct CompiledInstruction {
+    pub program_id_index: u8,
+    pub accounts: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SolanaError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| SolanaError::InvalidMessage("unexpected end of message".to_string()))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        return Ok(slice);
+    }
+
+    fn u8(&mut self) -> Result<u8, SolanaError> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    /// Solana's "compact-u16" (shortvec) length encoding.
+    fn compact_u16(&mut self) -> Result<usize, SolanaError> {
+        let mut value: usize = 0;
+        for i in 0..3 {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << (i * 7);
+            if byte & 0x80 == 0 {
+                if value > u16::MAX as usize || (i > 0 && byte == 0) {
+                    return Err(SolanaError::InvalidMessage(
+                        "invalid compact-u16".to_string(),
+                    ));
+                }
+                return Ok(value);
+            }
+        }
+        return Err(SolanaError::InvalidMessage(
+            "invalid compact-u16".to_string(),
+        ));
+    }
+
+    fn compact_bytes(&mut self) -> Result<&'a [u8], SolanaError> {
+        let len = self.compact_u16()?;
+        return self.take(len);
+    }
+}
+
+fn encode_compact_u16(mut value: u16, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Parses a serialized legacy or v0 message, rejecting trailing bytes and
+/// out of range account indices.
+pub fn parse_message(message: &[u8]) -> Result<SolanaMessage, SolanaError> {
+    let mut reader = Reader {
+        bytes: message,
+        position: 0,
+    };
+
+    let mut version = None;
+    let mut num_required_signatures = reader.u8()?;
+    if num_required_signatures & VERSION_PREFIX_MASK != 0 {
+        let message_version = num_required_signatures & !VERSION_PREFIX_MASK;
+        if message_version != 0 {
+            return Err(SolanaError::InvalidMessage(format!(
+                "unsupported message version {}",
+                message_version
+            )));
+        }
+        version = Some(message_version);
+        num_required_signatures = reader.u8()?;
+    }
+    let num_readonly_signed = reader.u8()?;
+    let num_readonly_unsigned = reader.u8()?;
+
+    let num_account_keys = reader.compact_u16()?;
+    let mut account_keys = Vec::with_capacity(num_account_keys);
+    for _ in 0..num_account_keys {
+        account_keys.push(reader.take(32)?.try_into().unwrap());
+    }
+    if num_required_signatures == 0
+        || num_readonly_signed >= num_required_signatures
+        || num_required_signatures as usize + num_readonly_unsigned as usize > account_keys.len()
+    {
+        return Err(SolanaError::InvalidMessage(
+            "invalid message header".to_string(),
+        ));
+    }
+
+    // recent blockhash
+    reader.take(32)?;
+
+    let num_instructions = reader.compact_u16()?;
+    let mut instructions = Vec::with_capacity(num_instructions);
+    for _ in 0..num_instructions {
+        instructions.push(CompiledInstruction {
+            program_id_index: reader.u8()?,
+            accounts: reader.compact_bytes()?.to_vec(),
+            data: reader.compact_bytes()?.to_vec(),
+        });
+    }
+
+    let mut num_loaded_addresses = 0;
+    if version.is_some() {
+        let num_lookups = reader.compact_u16()?;
+        for _ in 0..num_lookups {
+            reader.take(32)?;
+            num_loaded_addresses += reader.compact_bytes()?.len();
+            num_loaded_addresses += reader.compact_bytes()?.len();
+        }
+    }
+
+    if reader.position != message.len() {
+        return Err(SolanaError::InvalidMessage(
+            "trailing bytes after message".to_string(),
+        ));
+    }
+    let num_accounts = account_keys.len() + num_loaded_addresses;
+    if instructions.iter().any(|instruction| {
+        std::iter::once(&instruction.program_id_index)
+            .chain(&instruction.accounts)
+            .any(|index| *index as usize >= num_accounts)
+    }) {
+        return Err(SolanaError::InvalidMessage(
+            "instruction references an unknown account".to_string(),
+        ));
+    }
+
+    return Ok(SolanaMessage {
+        version,
+        num_required_signatures,
+        account_keys,
+        instructions,
+    });
+}
+
+/// The lamports moved by a message made only of System Program `Transfer`
+/// instructions, for policy checks. `None` if it does anything else.
+pub fn system_transfers(message: &SolanaMessage) -> Option<Vec<Transfer>> {
+    let mut transfers = Vec::with_capacity(message.instructions.len());
+    for instruction in &message.instructions {
+        let program_id = message
+            .account_keys
+            .get(instruction.program_id_index as usize)?;
+        let destination = message
+            .account_keys
+            .get(*instruction.accounts.get(1)? as usize)?;
+        if *program_id != SYSTEM_PROGRAM_ID
+            || instruction.data.len() != 12
+            || instruction.data[..4] != SYSTEM_TRANSFER_INSTRUCTION.to_le_bytes()
+        {
+            return None;
+        }
+        transfers.push(Transfer {
+            destination: Some(destination.to_vec()),
+            value: u64::from_le_bytes(instruction.data[4..].try_into().unwrap()) as u128,
+        });
+    }
+    return Some(transfers);
+}
+
+/// Signs a serialized message with the key at `derivation_path`. The key must be
+/// one of the message's required signers; the signatures of any other signers
+/// are left zeroed in the returned transaction for the host to fill in.
+pub fn sign_transaction(
+    secret_key: &[u8; 64],
+    derivation_path: &str,
+    message: &[u8],
+) -> Result<VsockEnclaveSignSolanaTxData, SolanaError> {
+    let parsed = parse_message(message)?;
+    let signing_key = Slip10Key::from_seed(secret_key)
+        .derive_path(derivation_path)?
+        .signing_key();
+    let public_key: [u8; 32] = signing_key.public_key().into();
+
+    let signer_index = parsed.account_keys[..parsed.num_required_signatures as usize]
+        .iter()
+        .position(|key| *key == public_key)
+        .ok_or(SolanaError::NotRequiredSigner)?;
+
+    let signature = signing_key.sign(message);
+
+    let mut signed_transaction = Vec::new();
+    encode_compact_u16(
+        parsed.num_required_signatures as u16,
+        &mut signed_transaction,
+    );
+    for index in 0..parsed.num_required_signatures as usize {
+        if index == signer_index {
+            signed_transaction.extend_from_slice(signature.as_ref());
+        } else {
+            signed_transaction.extend_from_slice(&[0u8; 64]);
+        }
+    }
+    signed_transaction.extend_from_slice(message);
+
+    return Ok(VsockEnclaveSignSolanaTxData {
+        public_key,
+        signer_index: signer_index as u8,
+        signature: signature.as_ref().to_vec(),
+        signed_transaction,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pallas_crypto::key::ed25519::{PublicKey, Signature};
+
+    const SECRET_KEY: [u8; 64] = [5u8; 64];
+    const PATH: &str = "m/44'/501'/0'/0'";
+
+    fn hex(s: &str) -> Vec<u8> {
+        return (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect();
+    }
+
+    /// A transfer from `payer` (fee payer, signer) to a second account via the
+    /// system program.
+    fn transfer_message(payer: [u8; 32], version: Option<u8>) -> Vec<u8> {
+        let mut message = Vec::new();
+        if let Some(version) = version {
+            message.push(VERSION_PREFIX_MASK | version);
+        }
+        message.extend_from_slice(&[1, 0, 1]);
+        message.push(3);
+        message.extend_from_slice(&payer);
+        message.extend_from_slice(&[9u8; 32]);
+        message.extend_from_slice(&[0u8; 32]);
+        message.extend_from_slice(&[7u8; 32]);
+        message.push(1);
+        message.extend_from_slice(&[2, 2, 0, 1, 12]);
+        message.extend_from_slice(&[2, 0, 0, 0, 0xe8, 0x03, 0, 0, 0, 0, 0, 0]);
+        if version.is_some() {
+            message.push(0);
+        }
+        return message;
+    }
+
+    fn wallet_public_key() -> [u8; 32] {
+        return Slip10Key::from_seed(&SECRET_KEY)
+            .derive_path(PATH)
+            .unwrap()
+            .signing_key()
+            .public_key()
+            .into();
+    }
+
+    #[test]
+    fn test_slip10_ed25519_vector() {
+        // SLIP-10 test vector 1 for ed25519, chain m/0H
+        let key = Slip10Key::from_seed(&hex("000102030405060708090a0b0c0d0e0f"));
+        assert_eq!(
+            key.chain_code.to_vec(),
+            hex("90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb")
+        );
+        let child = key.derive_path("m/0'").unwrap();
+        assert_eq!(
+            child.secret.to_vec(),
+            hex("68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3")
+        );
+        let public_key: [u8; 32] = child.signing_key().public_key().into();
+        assert_eq!(
+            public_key.to_vec(),
+            hex("8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c")
+        );
+    }
+
+    #[test]
+    fn test_derive_path_rejects_soft_derivation() {
+        let result = Slip10Key::from_seed(&SECRET_KEY).derive_path("m/44'/501'/0");
+        assert!(matches!(result, Err(SolanaError::NonHardenedDerivation)));
+    }
+
+    #[test]
+    fn test_sign_legacy_and_v0_messages() {
+        for version in [None, Some(0)] {
+            let message = transfer_message(wallet_public_key(), version);
+            assert_eq!(parse_message(&message).unwrap().version, version);
+
+            let result = sign_transaction(&SECRET_KEY, PATH, &message).unwrap();
+            assert_eq!(result.signer_index, 0);
+            assert_eq!(result.signed_transaction[0], 1);
+            assert_eq!(
+                &result.signed_transaction[1..65],
+                result.signature.as_slice()
+            );
+            assert_eq!(&result.signed_transaction[65..], message.as_slice());
+
+            let signature: [u8; 64] = result.signature.try_into().unwrap();
+            assert!(
+                PublicKey::from(result.public_key).verify(&message, &Signature::from(signature))
+            );
+        }
+    }
+
+    #[test]
+    fn test_system_transfers() {
+        let message = parse_message(&transfer_message([3u8; 32], None)).unwrap();
+        assert_eq!(
+            system_transfers(&message),
+            Some(vec![Transfer {
+                destination: Some(vec![9u8; 32]),
+                value: 1000
+            }])
+        );
+
+        let mut message = message;
+        message.instructions[0].data[0] = 3;
+        assert_eq!(system_transfers(&message), None);
+    }
+
+    #[test]
+    fn test_sign_rejects_non_signer() {
+        let message = transfer_message([3u8; 32], None);
+        let result = sign_transaction(&SECRET_KEY, PATH, &message);
+        assert!(matches!(result, Err(SolanaError::NotRequiredSigner)));
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_messages() {
+        let message = transfer_message([3u8; 32], None);
+        let mut trailing = message.clone();
+        trailing.push(0);
+        assert!(matches!(
+            parse_message(&trailing),
+            Err(SolanaError::InvalidMessage(_))
+        ));
+        assert!(matches!(
+            parse_message(&message[..message.len() - 1]),
+            Err(SolanaError::InvalidMessage(_))
+        ));
+        assert!(matches!(
+            parse_message(&[0x81, 1, 0, 0]),
+            Err(SolanaError::InvalidMessage(_))
+        ));
+    }
+}
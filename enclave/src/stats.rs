@@ -0,0 +1,51 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Instant;
+
+use shared::transport::KmsCallStats;
+
+tokio::task_local! {
+    static KMS_CALLS: RefCell<Vec<KmsCallStats>>;
+}
+
+/// Runs a request's task, collecting the KMS calls made on it for
+/// [`take_kms_calls`].
+pub fn scope<F: Future>(task: F) -> impl Future<Output = F::Output> {
+    return KMS_CALLS.scope(RefCell::new(Vec::new()), task);
+}
+
+/// Does nothing outside [`scope`], e.g. in tests.
+pub fn record_kms_call(command: String, started: Instant, success: bool) {
+    let _ = KMS_CALLS.try_with(|calls| {
+        calls.borrow_mut().push(KmsCallStats {
+            command,
+            duration_us: started.elapsed().as_micros() as u64,
+            success,
+        });
+    });
+}
+
+pub fn take_kms_calls() -> Vec<KmsCallStats> {
+    return KMS_CALLS.try_with(|calls| calls.take()).unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_kms_calls_are_collected_per_scope() {
+        let calls = scope(async {
+            record_kms_call("decrypt".to_string(), Instant::now(), true);
+            record_kms_call("genkey".to_string(), Instant::now(), false);
+            return take_kms_calls();
+        })
+        .await;
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].command, "decrypt");
+        assert!(!calls[1].success);
+
+        record_kms_call("decrypt".to_string(), Instant::now(), true);
+        assert!(take_kms_calls().is_empty());
+    }
+}
@@ -0,0 +1,558 @@
+use base64::{Engine, prelude::BASE64_STANDARD};
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use shared::approval::Quorum;
+use shared::error::{
+    ImportKeyError, SignerError, VsockEnclaveCreateWalletError, VsockEnclaveSignError,
+};
+use shared::policy::SignedPolicy;
+use shared::transport::{KmsCredentials, VsockEnclaveCreateWalletData, WalletOrigin};
+
+use crate::aes256gcm::{decrypt_private_key_aes256gcm, encrypt_private_key_aes256gcm};
+use crate::approval;
+use crate::key_cache::SharedDataKeyCache;
+use crate::kmstool;
+use crate::policy;
+use crate::secret::{Secret, SecretVec};
+
+/// AES-GCM associated data binding a wallet's policy, quorum and origin to its
+/// secret.
+fn envelope_aad(
+    policy: Option<&SignedPolicy>,
+    quorum: Option<&Quorum>,
+    origin: WalletOrigin,
+) -> Vec<u8> {
+    return [
+        policy::envelope_aad(policy),
+        approval::envelope_aad(quorum),
+        origin_aad(origin),
+    ]
+    .concat();
+}
+
+/// Empty for generated wallets, so their associated data is unchanged.
+fn origin_aad(origin: WalletOrigin) -> Vec<u8> {
+    if origin == WalletOrigin::Generated {
+        return Vec::new();
+    }
+    let origin_cbor = serde_cbor::to_vec(&origin).expect("origin is serializable");
+    let mut hasher = Sha256::new();
+    hasher.input(b"origin");
+    hasher.input(&origin_cbor);
+    let mut aad = vec![0u8; 32];
+    hasher.result(&mut aad);
+    return aad;
+}
+
+/// Stable identity of a wallet, which policies are bound to. Derived from the
+/// secret, so it survives rewraps, backups and restores and cannot be chosen
+/// by the host, without revealing anything about the secret.
+pub fn wallet_id(secret_key: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.input(b"trustvault wallet id");
+    hasher.input(secret_key);
+    let mut id = [0u8; 32];
+    hasher.result(&mut id);
+    return id;
+}
+
+/// Encrypts freshly generated wallet randomness under the plaintext data key,
+/// bound to `policy` and `quorum`. Both inputs come straight from kmstool and
+/// are wiped before returning.
+pub fn seal_secret_key(
+    random_bytes: Vec<u8>,
+    data_key: Vec<u8>,
+    nonce: &[u8; 12],
+    policy: Option<&SignedPolicy>,
+    quorum: Option<&Quorum>,
+) -> Result<Vec<u8>, VsockEnclaveCreateWalletError> {
+    let random_bytes = SecretVec::from_vec(random_bytes);
+    let data_key = SecretVec::from_vec(data_key);
+    let secret_key = Secret::<[u8; 64]>::from_slice(random_bytes.expose())
+        .ok_or(VsockEnclaveCreateWalletError::InvalidSecretKey)?;
+
+    return Ok(encrypt_private_key_aes256gcm(
+        secret_key.expose(),
+        data_key.expose(),
+        nonce,
+        &envelope_aad(policy, quorum, WalletOrigin::Generated),
+    )?);
+}
+
+/// Re-encrypts an already opened wallet secret under a new policy, with a fresh
+/// nonce since the data key stays the same.
+pub fn reseal_secret_key(
+    wallet: &VsockEnclaveCreateWalletData,
+    data_key: &SecretVec,
+    secret_key: &Secret<[u8; 64]>,
+    policy: SignedPolicy,
+) -> Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError> {
+    let (encrypted_secret_key, aes_gcm_nonce) = seal_with_fresh_nonce(
+        secret_key,
+        data_key,
+        Some(&policy),
+        wallet.quorum.as_ref(),
+        wallet.origin,
+    )?;
+
+    return Ok(VsockEnclaveCreateWalletData {
+        encrypted_secret_key,
+        aes_gcm_nonce,
+        kms_ciphertext: wallet.kms_ciphertext.clone(),
+        kms_key_id: wallet.kms_key_id.clone(),
+        policy: Some(policy),
+        quorum: wallet.quorum.clone(),
+        origin: wallet.origin,
+        wallet_id: wallet_id(secret_key.expose()),
+    });
+}
+
+/// Re-encrypts an already opened wallet secret under a fresh data key from
+/// another KMS key, keeping its policy and quorum. `data_key` comes straight
+/// from kmstool and is wiped before returning.
+pub fn rewrap_secret_key(
+    wallet: &VsockEnclaveCreateWalletData,
+    secret_key: &Secret<[u8; 64]>,
+    data_key: Vec<u8>,
+    kms_ciphertext: Vec<u8>,
+    kms_key_id: String,
+) -> Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError> {
+    let data_key = SecretVec::from_vec(data_key);
+    let (encrypted_secret_key, aes_gcm_nonce) = seal_with_fresh_nonce(
+        secret_key,
+        &data_key,
+        wallet.policy.as_ref(),
+        wallet.quorum.as_ref(),
+        wallet.origin,
+    )?;
+
+    return Ok(VsockEnclaveCreateWalletData {
+        encrypted_secret_key,
+        aes_gcm_nonce,
+        kms_ciphertext,
+        kms_key_id,
+        policy: wallet.policy.clone(),
+        quorum: wallet.quorum.clone(),
+        origin: wallet.origin,
+        wallet_id: wallet_id(secret_key.expose()),
+    });
+}
+
+/// Seals a wallet secret that did not come from kmstool, one recovered from a
+/// backup or an imported key, under a fresh data key. `data_key` comes straight
+/// from kmstool and is wiped before returning.
+pub fn restore_secret_key(
+    secret_key: &Secret<[u8; 64]>,
+    data_key: Vec<u8>,
+    kms_ciphertext: Vec<u8>,
+    kms_key_id: String,
+    policy: Option<SignedPolicy>,
+    quorum: Option<Quorum>,
+    origin: WalletOrigin,
+) -> Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError> {
+    let data_key = SecretVec::from_vec(data_key);
+    let (encrypted_secret_key, aes_gcm_nonce) = seal_with_fresh_nonce(
+        secret_key,
+        &data_key,
+        policy.as_ref(),
+        quorum.as_ref(),
+        origin,
+    )?;
+
+    return Ok(VsockEnclaveCreateWalletData {
+        encrypted_secret_key,
+        aes_gcm_nonce,
+        kms_ciphertext,
+        kms_key_id,
+        policy,
+        quorum,
+        origin,
+        wallet_id: wallet_id(secret_key.expose()),
+    });
+}
+
+fn seal_with_fresh_nonce(
+    secret_key: &Secret<[u8; 64]>,
+    data_key: &SecretVec,
+    policy: Option<&SignedPolicy>,
+    quorum: Option<&Quorum>,
+    origin: WalletOrigin,
+) -> Result<(Vec<u8>, [u8; 12]), VsockEnclaveSignError> {
+    let mut aes_gcm_nonce = [0u8; 12];
+    getrandom::getrandom(&mut aes_gcm_nonce).map_err(|e| SignerError::Randomness(e.to_string()))?;
+    let encrypted_secret_key = encrypt_private_key_aes256gcm(
+        secret_key.expose(),
+        data_key.expose(),
+        &aes_gcm_nonce,
+        &envelope_aad(policy, quorum, origin),
+    )?;
+    return Ok((encrypted_secret_key, aes_gcm_nonce));
+}
+
+/// Decrypts the wallet's 64 byte secret with an already unwrapped data key.
+pub fn open_secret_key(
+    wallet: &VsockEnclaveCreateWalletData,
+    data_key: &SecretVec,
+) -> Result<Secret<[u8; 64]>, VsockEnclaveSignError> {
+    let private_key = decrypt_private_key_aes256gcm(
+        &wallet.encrypted_secret_key,
+        data_key.expose(),
+        &wallet.aes_gcm_nonce,
+        &envelope_aad(
+            wallet.policy.as_ref(),
+            wallet.quorum.as_ref(),
+            wallet.origin,
+        ),
+    )?;
+
+    return Secret::<[u8; 64]>::from_slice(private_key.expose())
+        .ok_or(VsockEnclaveSignError::InvalidSecretKey);
+}
+
+/// Unwraps the wallet's data key, from `key_cache` if present, otherwise with KMS.
+pub async fn unwrap_data_key(
+    credentials: &KmsCredentials,
+    wallet: &VsockEnclaveCreateWalletData,
+    key_cache: &SharedDataKeyCache,
+) -> Result<SecretVec, VsockEnclaveSignError> {
+    return unwrap_kms_ciphertext(credentials, &wallet.kms_ciphertext, key_cache).await;
+}
+
+/// Unwraps a KMS encrypted data key, from `key_cache` if present.
+pub async fn unwrap_kms_ciphertext(
+    credentials: &KmsCredentials,
+    kms_ciphertext: &[u8],
+    key_cache: &SharedDataKeyCache,
+) -> Result<SecretVec, VsockEnclaveSignError> {
+    let cached_encryption_key = key_cache.lock().unwrap().get(kms_ciphertext);
+    let decrypted_encryption_key = match cached_encryption_key {
+        Some(encryption_key) => encryption_key,
+        None => {
+            let kms_ciphertext_base64 = BASE64_STANDARD.encode(kms_ciphertext);
+            let [decrypted_encryption_key] = kmstool::decrypt(
+                credentials.aws_region.as_str(),
+                credentials.aws_access_key_id.as_str(),
+                credentials.aws_secret_access_key.as_str(),
+                credentials.aws_session_token.as_str(),
+                credentials.kms_proxy_port.as_str(),
+                kms_ciphertext_base64.as_str(),
+            )
+            .await?;
+            let decrypted_encryption_key = SecretVec::from_vec(decrypted_encryption_key);
+
+            key_cache
+                .lock()
+                .unwrap()
+                .insert(kms_ciphertext, &decrypted_encryption_key);
+            decrypted_encryption_key
+        }
+    };
+
+    return Ok(decrypted_encryption_key);
+}
+
+/// Unwraps the data key and decrypts the wallet's 64 byte secret. This also
+/// proves `wallet.policy` and `wallet.quorum` are the ones it was sealed with.
+pub async fn decrypt_secret_key(
+    credentials: &KmsCredentials,
+    wallet: &VsockEnclaveCreateWalletData,
+    key_cache: &SharedDataKeyCache,
+) -> Result<Secret<[u8; 64]>, VsockEnclaveSignError> {
+    let data_key = unwrap_data_key(credentials, wallet, key_cache).await?;
+    return open_secret_key(wallet, &data_key);
+}
+
+/// [`decrypt_secret_key`] for requests that derive keys from the wallet seed,
+/// which imported keys do not have.
+pub async fn decrypt_seed(
+    credentials: &KmsCredentials,
+    wallet: &VsockEnclaveCreateWalletData,
+    key_cache: &SharedDataKeyCache,
+) -> Result<Secret<[u8; 64]>, VsockEnclaveSignError> {
+    if wallet.origin != WalletOrigin::Generated {
+        return Err(ImportKeyError::SeedRequired.into());
+    }
+    return decrypt_secret_key(credentials, wallet, key_cache).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::tests::sign_policy;
+    use crate::secret::tests::{dropped_secrets, erased_keys};
+    use crate::signer;
+    use shared::policy::{Policy, SignatureSchemeKind};
+    use shared::transport::{SignatureScheme, TaprootTweak};
+
+    #[test]
+    fn test_create_wallet_and_sign_wipe_key_buffers() {
+        let mut erased = Vec::new();
+        let dropped = dropped_secrets(|| {
+            let nonce = [4u8; 12];
+            let encrypted_secret_key =
+                seal_secret_key(vec![9u8; 64], vec![1u8; 32], &nonce, None, None).unwrap();
+            let wallet = VsockEnclaveCreateWalletData {
+                aes_gcm_nonce: nonce,
+                encrypted_secret_key,
+                kms_ciphertext: vec![2u8; 16],
+                kms_key_id: "key".to_string(),
+                policy: None,
+                quorum: None,
+                origin: WalletOrigin::Generated,
+                wallet_id: [0u8; 32],
+            };
+
+            let data_key = SecretVec::from_vec(vec![1u8; 32]);
+            let private_key = open_secret_key(&wallet, &data_key).unwrap();
+            assert_eq!(private_key.expose(), &[9u8; 64]);
+            signer::sign(
+                private_key.expose(),
+                WalletOrigin::Generated,
+                "m/44'/501'/0'/0'",
+                &SignatureScheme::Ed25519,
+                b"payload",
+            )
+            .unwrap();
+
+            for signature_scheme in [
+                SignatureScheme::Secp256k1,
+                SignatureScheme::Secp256k1Schnorr {
+                    taproot: Some(TaprootTweak { merkle_root: None }),
+                },
+            ] {
+                erased.push(erased_keys(|| {
+                    signer::sign(
+                        private_key.expose(),
+                        WalletOrigin::Generated,
+                        "m/86'/0'/0'/0/0",
+                        &signature_scheme,
+                        &[7u8; 32],
+                    )
+                    .unwrap();
+                }));
+            }
+        });
+
+        // create: secret key, data key, random bytes
+        // sign: decrypted plaintext, then secret key and data key
+        assert_eq!(
+            dropped,
+            vec![
+                (64, true),
+                (32, true),
+                (64, true),
+                (64, true),
+                (64, true),
+                (32, true)
+            ]
+        );
+        // the master, the child and its secret key, and for Schnorr the key pair
+        // and the tweaked key pair
+        assert_eq!(
+            erased,
+            vec![
+                vec![("Xpriv", true), ("Xpriv", true), ("SecretKey", true)],
+                vec![
+                    ("Xpriv", true),
+                    ("Xpriv", true),
+                    ("SecretKey", true),
+                    ("Keypair", true),
+                    ("Keypair", true)
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_seal_rejects_short_randomness() {
+        let result = seal_secret_key(vec![9u8; 32], vec![1u8; 32], &[0u8; 12], None, None);
+        assert!(matches!(
+            result,
+            Err(VsockEnclaveCreateWalletError::InvalidSecretKey)
+        ));
+    }
+
+    #[test]
+    fn test_policy_is_bound_to_envelope() {
+        let policy = sign_policy(&Policy {
+            version: 1,
+            wallet: None,
+            allowed_schemes: None,
+            allow_opaque: false,
+            chains: vec![],
+            time_windows: vec![],
+        });
+        let data_key = SecretVec::from_slice(&[1u8; 32]);
+        let mut wallet = VsockEnclaveCreateWalletData {
+            aes_gcm_nonce: [4u8; 12],
+            encrypted_secret_key: seal_secret_key(
+                vec![9u8; 64],
+                vec![1u8; 32],
+                &[4u8; 12],
+                Some(&policy),
+                None,
+            )
+            .unwrap(),
+            kms_ciphertext: vec![2u8; 16],
+            kms_key_id: "key".to_string(),
+            policy: Some(policy.clone()),
+            quorum: None,
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+        assert!(open_secret_key(&wallet, &data_key).is_ok());
+
+        wallet.policy = None;
+        assert!(matches!(
+            open_secret_key(&wallet, &data_key),
+            Err(VsockEnclaveSignError::Aes256GcmError(_))
+        ));
+
+        wallet.policy = Some(policy);
+        let secret_key = open_secret_key(&wallet, &data_key).unwrap();
+        let mut other = Policy {
+            version: 2,
+            wallet: None,
+            allowed_schemes: None,
+            allow_opaque: true,
+            chains: vec![],
+            time_windows: vec![],
+        };
+        let resealed =
+            reseal_secret_key(&wallet, &data_key, &secret_key, sign_policy(&other)).unwrap();
+        assert_ne!(resealed.aes_gcm_nonce, wallet.aes_gcm_nonce);
+        assert_eq!(
+            open_secret_key(&resealed, &data_key).unwrap().expose(),
+            &[9u8; 64]
+        );
+
+        other.allow_opaque = false;
+        let mut swapped = resealed;
+        swapped.policy = Some(sign_policy(&other));
+        assert!(open_secret_key(&swapped, &data_key).is_err());
+    }
+
+    #[test]
+    fn test_origin_is_bound_to_envelope() {
+        let origin = WalletOrigin::Imported {
+            signature_scheme: SignatureSchemeKind::Ed25519,
+        };
+        let secret_key = Secret::<[u8; 64]>::from_slice(&[9u8; 64]).unwrap();
+        let mut wallet = restore_secret_key(
+            &secret_key,
+            vec![1u8; 32],
+            vec![2u8; 16],
+            "key".to_string(),
+            None,
+            None,
+            origin,
+        )
+        .unwrap();
+        let data_key = SecretVec::from_slice(&[1u8; 32]);
+        assert!(open_secret_key(&wallet, &data_key).is_ok());
+
+        // an imported key cannot pass for a seed, nor for another scheme
+        wallet.origin = WalletOrigin::Generated;
+        assert!(open_secret_key(&wallet, &data_key).is_err());
+        wallet.origin = WalletOrigin::Imported {
+            signature_scheme: SignatureSchemeKind::Secp256k1,
+        };
+        assert!(open_secret_key(&wallet, &data_key).is_err());
+    }
+
+    #[test]
+    fn test_quorum_is_bound_to_envelope() {
+        let quorum = approval::tests::quorum(2);
+        let data_key = SecretVec::from_slice(&[1u8; 32]);
+        let mut wallet = VsockEnclaveCreateWalletData {
+            aes_gcm_nonce: [4u8; 12],
+            encrypted_secret_key: seal_secret_key(
+                vec![9u8; 64],
+                vec![1u8; 32],
+                &[4u8; 12],
+                None,
+                Some(&quorum),
+            )
+            .unwrap(),
+            kms_ciphertext: vec![2u8; 16],
+            kms_key_id: "key".to_string(),
+            policy: None,
+            quorum: Some(quorum.clone()),
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+        let secret_key = open_secret_key(&wallet, &data_key).unwrap();
+
+        // the quorum survives a policy change
+        let policy = sign_policy(&Policy {
+            version: 1,
+            wallet: None,
+            allowed_schemes: None,
+            allow_opaque: true,
+            chains: vec![],
+            time_windows: vec![],
+        });
+        let resealed = reseal_secret_key(&wallet, &data_key, &secret_key, policy).unwrap();
+        assert_eq!(resealed.quorum, Some(quorum.clone()));
+        assert!(open_secret_key(&resealed, &data_key).is_ok());
+
+        wallet.quorum = None;
+        assert!(open_secret_key(&wallet, &data_key).is_err());
+
+        let mut lowered = quorum;
+        lowered.threshold = 1;
+        wallet.quorum = Some(lowered);
+        assert!(open_secret_key(&wallet, &data_key).is_err());
+    }
+
+    #[test]
+    fn test_rewrap_keeps_secret_policy_and_quorum() {
+        let policy = sign_policy(&Policy {
+            version: 1,
+            wallet: None,
+            allowed_schemes: None,
+            allow_opaque: true,
+            chains: vec![],
+            time_windows: vec![],
+        });
+        let quorum = approval::tests::quorum(1);
+        let wallet = VsockEnclaveCreateWalletData {
+            aes_gcm_nonce: [4u8; 12],
+            encrypted_secret_key: seal_secret_key(
+                vec![9u8; 64],
+                vec![1u8; 32],
+                &[4u8; 12],
+                Some(&policy),
+                Some(&quorum),
+            )
+            .unwrap(),
+            kms_ciphertext: vec![2u8; 16],
+            kms_key_id: "old".to_string(),
+            policy: Some(policy),
+            quorum: Some(quorum),
+            origin: WalletOrigin::Generated,
+            wallet_id: [0u8; 32],
+        };
+        let secret_key = open_secret_key(&wallet, &SecretVec::from_slice(&[1u8; 32])).unwrap();
+
+        let rewrapped = rewrap_secret_key(
+            &wallet,
+            &secret_key,
+            vec![7u8; 32],
+            vec![3u8; 16],
+            "new".to_string(),
+        )
+        .unwrap();
+        assert_eq!(rewrapped.kms_key_id, "new");
+        assert_eq!(rewrapped.kms_ciphertext, vec![3u8; 16]);
+        assert_eq!(rewrapped.policy, wallet.policy);
+        assert_eq!(rewrapped.quorum, wallet.quorum);
+        assert_ne!(rewrapped.encrypted_secret_key, wallet.encrypted_secret_key);
+
+        let new_data_key = SecretVec::from_slice(&[7u8; 32]);
+        assert_eq!(
+            open_secret_key(&rewrapped, &new_data_key).unwrap().expose(),
+            &[9u8; 64]
+        );
+        assert!(open_secret_key(&rewrapped, &SecretVec::from_slice(&[1u8; 32])).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Runs a 2-of-3 FROST key generation and signature across three enclave
+//! processes listening on localhost TCP, with a stand-in for kmstool whose
+//! "ciphertext" is the data key itself.
+
+use std::cell::RefCell;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use pallas_crypto::key::ed25519::{PublicKey, Signature};
+use shared::error::VsockReceiveError;
+use shared::transport::{
+    KmsCredentials, VsockEnclaveResponseTrailer, VsockHostRequest, VsockTransport,
+};
+use tokio::net::TcpStream;
+
+const FAKE_KMSTOOL: &str = r#"#!/bin/sh
+command=$1
+while [ $# -gt 0 ]; do
+    if [ "$1" = "--ciphertext" ]; then
+        ciphertext=$2
+    fi
+    shift
+done
+case $command in
+    genkey)
+        key=$(head -c 32 /dev/urandom | base64)
+        echo "CIPHERTEXT: $key"
+        echo "PLAINTEXT: $key"
+        ;;
+    decrypt)
+        echo "PLAINTEXT: $ciphertext"
+        ;;
+    *)
+        exit 1
+        ;;
+esac
+"#;
+
+/// Enclave processes, killed when the test ends however it ends.
+struct Enclaves {
+    children: Vec<Child>,
+    addrs: Vec<SocketAddr>,
+    kmstool_dir: PathBuf,
+}
+
+impl Drop for Enclaves {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        let _ = std::fs::remove_dir_all(&self.kmstool_dir);
+    }
+}
+
+fn spawn_enclaves(count: usize) -> Enclaves {
+    let kmstool_dir = std::env::temp_dir().join(format!("frost-kmstool-{}", std::process::id()));
+    std::fs::create_dir_all(&kmstool_dir).unwrap();
+    let kmstool = kmstool_dir.join("kmstool_enclave_cli");
+    std::fs::write(&kmstool, FAKE_KMSTOOL).unwrap();
+    std::fs::set_permissions(&kmstool, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        kmstool_dir.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let mut enclaves = Enclaves {
+        children: Vec::new(),
+        addrs: Vec::new(),
+        kmstool_dir,
+    };
+    for _ in 0..count {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_enclave"))
+            .arg("--tcp-listen")
+            .arg(addr.to_string())
+            .arg("--insecure-skip-peer-attestation")
+            .env("PATH", &path)
+            .spawn()
+            .unwrap();
+        enclaves.children.push(child);
+        enclaves.addrs.push(addr);
+    }
+    return enclaves;
+}
+
+async fn connect(addr: SocketAddr) -> VsockTransport<TcpStream> {
+    for _ in 0..100 {
+        if let Ok(stream) = TcpStream::connect(addr).await {
+            return VsockTransport::new(stream);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("enclave on {} did not start", addr);
+}
+
+fn credentials() -> KmsCredentials {
+    return KmsCredentials {
+        aws_region: "us-east-1".to_string(),
+        aws_access_key_id: "id".to_string(),
+        aws_secret_access_key: "secret".to_string(),
+        aws_session_token: "token".to_string(),
+        kms_proxy_port: "8000".to_string(),
+    };
+}
+
+#[tokio::test]
+async fn test_threshold_key_signs_across_enclave_processes() {
+    let enclaves = spawn_enclaves(3);
+    let addrs = enclaves.addrs.clone();
+    let connect_to = async |identifier: u16| connect(addrs[usize::from(identifier) - 1]).await;
+    let kms_key_ids = vec!["key".to_string(); 3];
+    let audited = RefCell::new(Vec::new());
+    let on_trailer =
+        |request: &VsockHostRequest,
+         trailer: Result<VsockEnclaveResponseTrailer, VsockReceiveError>| {
+            if trailer.unwrap().audit_event.is_some() {
+                audited.borrow_mut().push(request.operation());
+            }
+        };
+
+    let key_shares = shared::frost::generate_key(
+        connect_to,
+        &on_trailer,
+        2,
+        &credentials(),
+        &kms_key_ids,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
+    assert_eq!(key_shares.len(), 3);
+    assert_eq!(*audited.borrow(), ["FrostDkgPart3"; 3]);
+    let public_key = PublicKey::from(key_shares[0].group_public_key);
+
+    let message = b"signed by two of three enclaves";
+    for signers in [[0usize, 1], [1, 2], [0, 2]] {
+        let signer_shares: Vec<_> = signers.iter().map(|i| key_shares[*i].clone()).collect();
+        let signature = shared::frost::sign(
+            connect_to,
+            &on_trailer,
+            &signer_shares,
+            &credentials(),
+            message,
+            &[],
+        )
+        .await
+        .unwrap();
+        let signature: [u8; 64] = signature.try_into().unwrap();
+        assert!(public_key.verify(message, &Signature::from(signature)));
+    }
+    let audited = audited.take();
+    assert_eq!(audited.len(), 3 + 3 * 4);
+    assert_eq!(
+        audited[3..7],
+        ["FrostCommit", "FrostCommit", "FrostSign", "FrostSign"]
+    );
+
+    // a single enclave is below the threshold
+    assert!(
+        shared::frost::sign(
+            connect_to,
+            &on_trailer,
+            &key_shares[..1],
+            &credentials(),
+            message,
+            &[]
+        )
+        .await
+        .is_err()
+    );
+}
@@ -0,0 +1,58 @@
+use clap::Parser;
+use shared::audit::{SignedAuditEvent, verify_chain};
+use std::path::PathBuf;
+
+/// Checks an audit log written by `host --audit-log` for reordered or altered
+/// events, exiting with 1 if it finds any, and lists the events it is missing,
+/// exiting with 2 if there are some.
+#[derive(Parser)]
+pub struct Args {
+    pub audit_log: PathBuf,
+    /// Hex encoded Ed25519 key, as returned by `GetAuditKey`, the events must
+    /// be signed with; may be repeated. Without it, any key is accepted.
+    #[arg(long, value_parser = parse_key)]
+    pub enclave_key: Vec<[u8; 32]>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let log = std::fs::read_to_string(&args.audit_log).expect("failed to read audit log");
+    let mut events = Vec::new();
+    for (line_number, line) in log.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: SignedAuditEvent = serde_json::from_str(line)
+            .unwrap_or_else(|e| panic!("failed to parse event on line {}: {}", line_number + 1, e));
+        events.push(event);
+    }
+
+    let gaps = match verify_chain(&events, &args.enclave_key) {
+        Ok(gaps) => gaps,
+        Err(e) => {
+            eprintln!("audit log is invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("{} events verified", events.len());
+    if !gaps.is_empty() {
+        for gap in &gaps {
+            eprintln!(
+                "missing events {} to {} of {} before event {}",
+                gap.expected,
+                gap.found - 1,
+                hex::encode(events[gap.index].event.enclave_key),
+                gap.index
+            );
+        }
+        std::process::exit(2);
+    }
+}
+
+fn parse_key(s: &str) -> Result<[u8; 32], String> {
+    let key = hex::decode(s).map_err(|e| format!("invalid key: {}", e))?;
+    return key
+        .try_into()
+        .map_err(|_| "key must be 32 bytes".to_string());
+}
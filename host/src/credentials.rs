@@ -0,0 +1,113 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_sts::Client as StsClient;
+use shared::transport::KmsCredentials;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::metrics::METRICS;
+
+/// How long before they expire credentials are replaced.
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const SESSION_DURATION_SECS: i32 = 3600;
+
+/// KMS credentials from assuming the role the host runs as, refreshed shortly
+/// before they expire so that long runs, e.g. bulk rewraps or `--serve`, keep
+/// working.
+pub struct CredentialProvider {
+    sts_client: StsClient,
+    role_arn: String,
+    aws_region: String,
+    kms_proxy_port: String,
+    current: Mutex<Option<(KmsCredentials, SystemTime)>>,
+}
+
+impl CredentialProvider {
+    pub async fn new(aws_region: &str, kms_proxy_port: &str) -> Self {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .region(Region::new(aws_region.to_string()))
+            .load()
+            .await;
+        let sts_client = StsClient::new(&config);
+
+        let identity = sts_client.get_caller_identity().send().await.unwrap();
+        let arn = identity.arn().unwrap().to_string();
+
+        return Self {
+            sts_client,
+            role_arn: convert_to_role_arn(&arn),
+            aws_region: aws_region.to_string(),
+            kms_proxy_port: kms_proxy_port.to_string(),
+            current: Mutex::new(None),
+        };
+    }
+
+    /// Credentials valid for at least [`REFRESH_MARGIN`].
+    pub async fn get(&self) -> KmsCredentials {
+        return self.try_get().await.unwrap_or_else(|e| panic!("{}", e));
+    }
+
+    /// [`CredentialProvider::get`], failing instead of panicking when the role
+    /// cannot be assumed.
+    pub async fn try_get(&self) -> Result<KmsCredentials, String> {
+        // held across the refresh, so that concurrent callers assume the role once
+        let mut current = self.current.lock().await;
+        if let Some((credentials, expiration)) = &*current
+            && SystemTime::now() + REFRESH_MARGIN < *expiration
+        {
+            return Ok(credentials.clone());
+        }
+
+        let response = self
+            .sts_client
+            .assume_role()
+            .role_arn(&self.role_arn)
+            .role_session_name(format!(
+                "trustvault-{}",
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("failed to get system time")
+                    .as_secs()
+            ))
+            .duration_seconds(SESSION_DURATION_SECS)
+            .send()
+            .await;
+        METRICS.record_credential_refresh(response.is_ok());
+        let response = response.map_err(|e| {
+            warn!(role_arn = %self.role_arn, error = %e, "failed to assume role");
+            format!("failed to obtain sts assume role: {}", e)
+        })?;
+        let assumed = response.credentials().unwrap();
+        info!(role_arn = %self.role_arn, expiration = %assumed.expiration, "assumed role");
+
+        let credentials = KmsCredentials {
+            aws_region: self.aws_region.clone(),
+            aws_access_key_id: assumed.access_key_id.clone(),
+            aws_secret_access_key: assumed.secret_access_key.clone(),
+            aws_session_token: assumed.session_token.clone(),
+            kms_proxy_port: self.kms_proxy_port.clone(),
+        };
+        let expiration = UNIX_EPOCH + Duration::from_secs(assumed.expiration.secs().max(0) as u64);
+        *current = Some((credentials.clone(), expiration));
+        return Ok(credentials);
+    }
+}
+
+fn convert_to_role_arn(assumed_role_arn: &str) -> String {
+    let parts: Vec<&str> = assumed_role_arn.split(':').collect();
+    if parts.len() < 6 {
+        panic!("Above 6");
+    }
+
+    let account_id = parts[4];
+    let resource_parts: Vec<&str> = parts[5].split('/').collect();
+    if resource_parts.len() < 2 {
+        panic!("less than 2");
+    }
+
+    let role_name = resource_parts[1];
+    let role_arn = format!("arn:aws:iam::{}:role/{}", account_id, role_name);
+
+    return role_arn;
+}
@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use sd_notify::NotifyState;
+use shared::error::VsockEnclaveSignError;
+use shared::transport::{
+    VsockEnclaveHealthData, VsockEnclaveHealthResponse, VsockEnclavePingResponse, VsockHostRequest,
+};
+use tracing::warn;
+
+use crate::credentials::CredentialProvider;
+use crate::{Args, try_call, try_connect_to};
+
+/// Asks the enclave for its health over a new connection. Fails when the
+/// enclave cannot be reached, in which case it is not healthy either.
+pub async fn check(
+    args: &Args,
+    credentials: &CredentialProvider,
+) -> Result<VsockEnclaveHealthData, String> {
+    let request = VsockHostRequest::HealthCheck {
+        credentials: credentials.try_get().await?,
+    };
+    let mut transport = try_connect_to(args, args.enclave_cid).await?;
+    let response: VsockEnclaveHealthResponse = try_call(args, &mut transport, &request).await?;
+    return response.map_err(|e| e.to_string());
+}
+
+/// Checks that the enclave accepts requests, without touching KMS.
+pub async fn ping(args: &Args) -> Result<(), String> {
+    let mut transport = try_connect_to(args, args.enclave_cid).await?;
+    let response: VsockEnclavePingResponse =
+        try_call::<(), VsockEnclaveSignError>(args, &mut transport, &VsockHostRequest::Ping)
+            .await?;
+    return response.map_err(|e| e.to_string());
+}
+
+/// Notifies systemd's watchdog, at half its interval, for as long as the
+/// enclave is healthy, so that systemd restarts the host when it is not.
+/// Returns straight away when no watchdog is configured.
+pub async fn watchdog(args: Arc<Args>, credentials: Arc<CredentialProvider>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_micros(usec / 2));
+    loop {
+        interval.tick().await;
+        match check(&args, &credentials).await {
+            Ok(health) if health.is_healthy() => {
+                if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                    warn!(error = %e, "failed to notify watchdog");
+                }
+            }
+            Ok(health) => warn!(degraded = ?health.degraded, "enclave is degraded"),
+            Err(e) => warn!(error = %e, "enclave health check failed"),
+        }
+    }
+}
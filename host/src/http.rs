@@ -0,0 +1,87 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use sd_notify::NotifyState;
+use tracing::{error, info};
+
+use crate::credentials::CredentialProvider;
+use crate::metrics::METRICS;
+use crate::{Args, health};
+
+#[derive(Clone)]
+struct HttpState {
+    args: Arc<Args>,
+    credentials: Arc<CredentialProvider>,
+    draining: Arc<AtomicBool>,
+}
+
+/// Serves `/metrics`, `/health` and `/health/live` on `addr` for as long as
+/// the host runs, telling systemd the host is ready once listening. `/health`
+/// fails once `draining` is set.
+pub async fn serve(
+    addr: SocketAddr,
+    args: Arc<Args>,
+    credentials: Arc<CredentialProvider>,
+    draining: Arc<AtomicBool>,
+) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(%addr, error = %e, "failed to listen for http");
+            return;
+        }
+    };
+    info!(%addr, "listening for http");
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+
+    let router = Router::new()
+        .route("/metrics", get(metrics))
+        .route("/health", get(health_check))
+        .route("/health/live", get(ping))
+        .with_state(HttpState {
+            args,
+            credentials,
+            draining,
+        });
+    if let Err(e) = axum::serve(listener, router).await {
+        error!(error = %e, "http server failed");
+    }
+}
+
+async fn metrics() -> impl IntoResponse {
+    return (
+        [(
+            CONTENT_TYPE,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        )],
+        METRICS.encode(),
+    );
+}
+
+/// Readiness: 200 with the enclave's health when it can serve requests, 503
+/// when it is degraded or unreachable, or the host is draining.
+async fn health_check(State(state): State<HttpState>) -> Response {
+    if state.draining.load(Ordering::Relaxed) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "draining").into_response();
+    }
+    return match health::check(&state.args, &state.credentials).await {
+        Ok(health) if health.is_healthy() => (StatusCode::OK, Json(health)).into_response(),
+        Ok(health) => (StatusCode::SERVICE_UNAVAILABLE, Json(health)).into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+    };
+}
+
+/// Liveness: 200 when the enclave accepts requests.
+async fn ping(State(state): State<HttpState>) -> Response {
+    return match health::ping(&state.args).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, e).into_response(),
+    };
+}
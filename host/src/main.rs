@@ -1,8 +1,35 @@
-use aws_config::{BehaviorVersion, Region};
-use aws_sdk_sts::Client as StsClient;
 use clap::Parser;
-use shared::transport::{VsockEnclaveCreateWalletResponse, VsockHostRequest, VsockTransport};
-use tokio_vsock::{VsockAddr, VsockStream};
+use sd_notify::NotifyState;
+use shared::attestation::AttestationVerifier;
+use shared::audit::SignedAuditEvent;
+use shared::error::{ErrorCode, VsockReceiveError};
+use shared::transport::{
+    KmsCredentials, VsockEnclaveCreateWalletData, VsockEnclaveCreateWalletResponse,
+    VsockEnclaveResponseTrailer, VsockEnclaveRewrapWalletResponse, VsockEnclaveShutdownResponse,
+    VsockHostRequest, VsockTransport,
+};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener, VsockStream};
+use tracing::{Instrument, error, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::credentials::CredentialProvider;
+use crate::metrics::METRICS;
+
+mod credentials;
+mod health;
+mod http;
+mod metrics;
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Parser)]
 pub struct Args {
@@ -14,88 +41,524 @@ pub struct Args {
     pub enclave_cid: u32,
     #[arg(long)]
     pub kms_proxy_port: String,
+    /// Key new wallets are created under, or that wallets are re-wrapped to.
     #[arg(long)]
     pub kms_key_id: String,
+    /// Re-wrap the wallets in this file, one JSON encoded wallet per line,
+    /// instead of creating a new wallet.
+    #[arg(long, requires = "rewrap_output")]
+    pub rewrap_wallets: Option<PathBuf>,
+    #[arg(long)]
+    pub rewrap_output: Option<PathBuf>,
+    /// Region of the wallets' current KMS key, when moving regions. Defaults to
+    /// `--aws-region`.
+    #[arg(long)]
+    pub source_aws_region: Option<String>,
+    /// Hex encoded X25519 operator key. When given, requests go over a Noise
+    /// channel that the enclave authenticates the operator on.
+    #[arg(long)]
+    pub operator_private_key: Option<String>,
+    /// Hex encoded Noise static key the enclave must present, as found in its
+    /// attestation document. Requires `--operator-private-key`.
+    #[arg(long, requires = "operator_private_key")]
+    pub enclave_public_key: Option<String>,
+    /// `INDEX=HEX` PCR value the enclave's attestation document must contain;
+    /// may be repeated. When given, the document is verified against the AWS
+    /// Nitro root before any request is sent. The document is bound to the
+    /// Noise channel, so this requires `--operator-private-key`.
+    #[arg(long, value_parser = parse_pcr, requires = "operator_private_key")]
+    pub expected_pcr: Vec<(usize, Vec<u8>)>,
+    /// Vsock CID of an enclave taking part in a threshold key generation, in
+    /// identifier order; may be repeated. When given, a FROST key is generated
+    /// across these enclaves, each sealing its share under `--kms-key-id`,
+    /// instead of creating a wallet.
+    #[arg(long, requires = "frost_threshold")]
+    pub frost_enclave_cid: Vec<u32>,
+    /// Number of the `--frost-enclave-cid` enclaves needed to sign.
+    #[arg(long)]
+    pub frost_threshold: Option<u8>,
+    /// Append the enclave's signed audit events to this file, one JSON encoded
+    /// event per line. Check it with the `verify_audit_log` command.
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+    /// Vsock port to receive the enclave's logs on while the host runs, see the
+    /// enclave's `--log-vsock-port`. They are written to stderr as received.
+    #[arg(long)]
+    pub enclave_log_port: Option<u32>,
+    /// Serve Prometheus metrics on `/metrics`, and the enclave's health on
+    /// `/health` and `/health/live`, at this address while the host runs.
+    #[arg(long)]
+    pub http_listen: Option<SocketAddr>,
+    /// Keep running and serving `--http-listen` instead of creating a wallet,
+    /// e.g. behind a load balancer. Under systemd with `WatchdogSec`, the
+    /// watchdog is notified while the enclave is healthy.
+    #[arg(long, requires = "http_listen")]
+    pub serve: bool,
+    /// How long `/health` fails after SIGTERM in `--serve` mode before the host
+    /// exits, so that load balancers stop routing to it first.
+    #[arg(long, default_value_t = 30)]
+    pub drain_secs: u64,
+    /// Ask the enclave to shut down, e.g. before replacing its EIF, and wait
+    /// for its requests in progress to finish, instead of creating a wallet.
+    /// Exits with an error when some did not.
+    #[arg(long, conflicts_with = "serve")]
+    pub drain: bool,
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
+    let args = Arc::new(Args::parse());
+    tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
 
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .region(Region::new(args.aws_region.clone()))
-        .load()
-        .await;
+    if let Some(port) = args.enclave_log_port {
+        tokio::spawn(receive_enclave_logs(port));
+    }
 
-    let sts_client = StsClient::new(&config);
+    if args.drain {
+        return drain_enclave(&args).await;
+    }
 
-    let identity = sts_client.get_caller_identity().send().await.unwrap();
+    let credentials =
+        Arc::new(CredentialProvider::new(&args.aws_region, &args.kms_proxy_port).await);
 
-    let arn = identity.arn().unwrap().to_string();
+    if let Some(addr) = args.http_listen {
+        let draining = Arc::new(AtomicBool::new(false));
+        let server = http::serve(addr, args.clone(), credentials.clone(), draining.clone());
+        if args.serve {
+            tokio::spawn(health::watchdog(args.clone(), credentials.clone()));
+            tokio::select! {
+                _ = server => {}
+                _ = drain_on_signal(&args, &draining) => {}
+            }
+            return;
+        }
+        tokio::spawn(server);
+    }
 
-    let role_arn = convert_to_role_arn(&arn);
+    if !args.frost_enclave_cid.is_empty() {
+        return generate_frost_key(&args, &credentials).await;
+    }
 
-    let response = sts_client
-        .assume_role()
-        .role_arn(&role_arn)
-        .role_session_name(format!(
-            "trustvault-{}",
-            std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .expect("failed to get system time")
-                .as_secs()
-        ))
-        .duration_seconds(3600)
-        .send()
-        .await
-        .expect("failed to obtain sts assume role");
+    match &args.rewrap_wallets {
+        Some(input) => rewrap_wallets(&args, input, &credentials).await,
+        None => create_wallet(&args, &credentials).await,
+    }
+}
+
+async fn create_wallet(args: &Args, credentials: &CredentialProvider) {
+    let mut transport = connect(args).await;
+
+    let request = VsockHostRequest::CreateWallet {
+        credentials: credentials.get().await,
+        kms_key_id: args.kms_key_id.clone(),
+        aes_gcm_nonce: [0u8; 12],
+        policy: None,
+        quorum: None,
+    };
+
+    let response: VsockEnclaveCreateWalletResponse = call(args, &mut transport, &request).await;
+    println!("response: {:?}", response);
+}
+
+/// Re-wraps every wallet in `input`, one JSON encoded wallet per line, under
+/// `--kms-key-id` and writes the results to `--rewrap-output` in the same
+/// format. Wallets that fail are reported and left out of the output.
+async fn rewrap_wallets(args: &Args, input: &Path, credentials: &CredentialProvider) {
+    let output_path = args
+        .rewrap_output
+        .as_ref()
+        .expect("--rewrap-output is required with --rewrap-wallets");
+    let input = std::fs::read_to_string(input).expect("failed to read wallets");
+    let mut output = std::fs::File::create(output_path).expect("failed to create output file");
 
-    println!("{:?}", response.credentials());
+    let mut failed = 0;
+    for (line_number, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let wallet: VsockEnclaveCreateWalletData =
+            serde_json::from_str(line).expect("failed to parse wallet");
 
-    let addr = VsockAddr::new(args.enclave_cid, args.vsock_port);
+        let credentials = credentials.get().await;
+        let source_credentials = KmsCredentials {
+            aws_region: args
+                .source_aws_region
+                .clone()
+                .unwrap_or_else(|| credentials.aws_region.clone()),
+            ..credentials.clone()
+        };
+        let mut transport = connect(args).await;
+        let request = VsockHostRequest::RewrapWallet {
+            credentials: source_credentials,
+            wallet,
+            new_credentials: credentials,
+            new_kms_key_id: args.kms_key_id.clone(),
+            approvals: Vec::new(),
+        };
+        let response: VsockEnclaveRewrapWalletResponse = call(args, &mut transport, &request).await;
 
-    let stream = VsockStream::connect(addr)
+        match response {
+            Ok(wallet) => {
+                let wallet = serde_json::to_string(&wallet).expect("failed to encode wallet");
+                writeln!(output, "{}", wallet).expect("failed to write wallet");
+            }
+            Err(e) => {
+                error!(line = line_number + 1, error = %e, "failed to rewrap wallet");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        error!(failed, "wallets were not rewrapped");
+        std::process::exit(1);
+    }
+}
+
+/// Runs a FROST key generation across `--frost-enclave-cid` and prints one
+/// JSON encoded key share per line, in identifier order.
+async fn generate_frost_key(args: &Args, credentials: &CredentialProvider) {
+    let threshold = args
+        .frost_threshold
+        .expect("--frost-threshold is required with --frost-enclave-cid");
+    let kms_key_ids = vec![args.kms_key_id.clone(); args.frost_enclave_cid.len()];
+
+    let key_shares = shared::frost::generate_key(
+        async |identifier: u16| {
+            let cid = args.frost_enclave_cid[usize::from(identifier) - 1];
+            return connect_to(args, cid).await;
+        },
+        |request: &VsockHostRequest, trailer| handle_trailer(args, request.operation(), trailer),
+        threshold,
+        &credentials.get().await,
+        &kms_key_ids,
+        None,
+        None,
+    )
+    .await
+    .expect("failed to generate threshold key");
+
+    println!(
+        "group public key: {}",
+        hex::encode(key_shares[0].group_public_key)
+    );
+    for key_share in &key_shares {
+        let key_share = serde_json::to_string(key_share).expect("failed to encode key share");
+        println!("{}", key_share);
+    }
+}
+
+/// Asks the enclave to shut down and waits for it to drain, so that its EIF
+/// can be replaced without dropping requests.
+async fn drain_enclave(args: &Args) {
+    let mut transport = connect(args).await;
+    let response: VsockEnclaveShutdownResponse =
+        call(args, &mut transport, &VsockHostRequest::Shutdown).await;
+    match response {
+        Ok(data) if data.drained => info!("enclave drained"),
+        Ok(_) => {
+            error!("enclave dropped requests still in progress");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            error!(error = %e, "enclave failed to shut down");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Waits for SIGTERM or SIGINT, then fails `/health` for `--drain-secs`.
+async fn drain_on_signal(args: &Args, draining: &AtomicBool) {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = tokio::signal::ctrl_c() => {}
+    }
+    info!(drain_secs = args.drain_secs, "draining");
+    draining.store(true, Ordering::Relaxed);
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+    tokio::time::sleep(Duration::from_secs(args.drain_secs)).await;
+}
+
+/// Sends `request` and reads its response and trailer, in a span carrying a
+/// request id and the operation. Logs the latency and any error code, records
+/// metrics, and appends the audit event to `--audit-log`.
+async fn call<T, E>(
+    args: &Args,
+    transport: &mut VsockTransport,
+    request: &VsockHostRequest,
+) -> Result<T, E>
+where
+    T: for<'de> serde::Deserialize<'de>,
+    E: for<'de> serde::Deserialize<'de> + ErrorCode + std::fmt::Display,
+{
+    return try_call(args, transport, request)
         .await
-        .expect("failed to connect to 16 3000");
+        .unwrap_or_else(|e| panic!("{}", e));
+}
 
-    let mut transport = VsockTransport::new(stream);
+/// [`call`], failing instead of panicking when the enclave cannot be reached.
+async fn try_call<T, E>(
+    args: &Args,
+    transport: &mut VsockTransport,
+    request: &VsockHostRequest,
+) -> Result<Result<T, E>, String>
+where
+    T: for<'de> serde::Deserialize<'de>,
+    E: for<'de> serde::Deserialize<'de> + ErrorCode + std::fmt::Display,
+{
+    let operation = request.operation();
+    let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let span = info_span!("request", request_id, operation);
+    return async {
+        let _in_flight = METRICS.start_request(operation);
+        let started = Instant::now();
+        transport
+            .send::<VsockHostRequest>(request)
+            .await
+            .map_err(|e| {
+                METRICS.record_vsock_error("send");
+                format!("failed to send request: {}", e)
+            })?;
+        let response = transport.receive::<Result<T, E>>().await.map_err(|e| {
+            METRICS.record_vsock_error("receive");
+            format!("failed to receive response: {}", e)
+        })?;
 
-    let request = VsockHostRequest::CreateWallet {
-        aws_region: args.aws_region,
-        aws_access_key_id: response.credentials().unwrap().access_key_id.clone(),
-        aws_secret_access_key: response.credentials().unwrap().secret_access_key.clone(),
-        aws_session_token: response.credentials().unwrap().session_token.clone(),
-        kms_proxy_port: args.kms_proxy_port,
-        kms_key_id: args.kms_key_id,
-        aes_gcm_nonce: [0u8; 12],
+        let duration = started.elapsed();
+        let latency_ms = duration.as_millis() as u64;
+        let error_code = response.as_ref().err().map(ErrorCode::code);
+        match &response {
+            Ok(_) => info!(latency_ms, "request completed"),
+            Err(e) => warn!(latency_ms, error_code, error = %e, "request failed"),
+        }
+        METRICS.record_request(operation, error_code, duration);
+
+        let trailer = transport.receive::<VsockEnclaveResponseTrailer>().await;
+        handle_trailer(args, operation, trailer);
+        return Ok(response);
+    }
+    .instrument(span)
+    .await;
+}
+
+/// Records the enclave's own timings and appends its audit event, if any.
+fn handle_trailer(
+    args: &Args,
+    operation: &'static str,
+    trailer: Result<VsockEnclaveResponseTrailer, VsockReceiveError>,
+) {
+    match trailer {
+        Ok(trailer) => {
+            METRICS.record_enclave_stats(operation, &trailer.stats);
+            if let Some(audit_event) = trailer.audit_event {
+                append_audit_event(args, &audit_event);
+            }
+        }
+        Err(e) => {
+            METRICS.record_vsock_error("receive");
+            warn!(error = %e, "failed to receive response trailer");
+        }
+    }
+}
+
+/// Copies the log lines enclaves forward on `port` to stderr.
+async fn receive_enclave_logs(port: u32) {
+    let listener = match VsockListener::bind(VsockAddr::new(VMADDR_CID_ANY, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(port, error = %e, "failed to listen for enclave logs");
+            return;
+        }
     };
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!(error = %e, "failed to accept enclave log connection");
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stream).lines();
+            let mut stderr = tokio::io::stderr();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        let _ = stderr.write_all(format!("{}\n", line).as_bytes()).await;
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!(peer = %addr, error = %e, "enclave log connection failed");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
 
-    transport
-        .send::<VsockHostRequest>(&request)
-        .await
-        .expect("failed to send transport layer");
+/// Appends `audit_event` to `--audit-log`, if given. A failure is logged and
+/// counted rather than failing the request, which the enclave has already
+/// carried out; `verify_audit_log` reports the missing event.
+fn append_audit_event(args: &Args, audit_event: &SignedAuditEvent) {
+    let Some(path) = &args.audit_log else {
+        return;
+    };
+    let event = serde_json::to_string(audit_event).expect("failed to encode audit event");
+    let appended = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut log| writeln!(log, "{}", event));
+    if let Err(e) = appended {
+        METRICS.record_audit_log_error();
+        error!(
+            path = %path.display(),
+            sequence = audit_event.event.sequence,
+            error = %e,
+            "failed to append audit event"
+        );
+    }
+}
 
-    let response = transport
-        .receive::<VsockEnclaveCreateWalletResponse>()
+async fn connect(args: &Args) -> VsockTransport {
+    return connect_to(args, args.enclave_cid).await;
+}
+
+async fn connect_to(args: &Args, cid: u32) -> VsockTransport {
+    return try_connect_to(args, cid)
         .await
-        .expect("failed to recieve response");
-    println!("response: {:?}", response);
+        .unwrap_or_else(|e| panic!("{}", e));
 }
 
-fn convert_to_role_arn(assumed_role_arn: &str) -> String {
-    let parts: Vec<&str> = assumed_role_arn.split(':').collect();
-    if parts.len() < 6 {
-        panic!("Above 6");
+/// [`connect_to`], failing instead of panicking when the enclave cannot be
+/// reached or does not authenticate.
+async fn try_connect_to(args: &Args, cid: u32) -> Result<VsockTransport, String> {
+    let addr = VsockAddr::new(cid, args.vsock_port);
+
+    let stream = VsockStream::connect(addr).await.map_err(|e| {
+        METRICS.record_vsock_error("connect");
+        format!("failed to connect to {} {}: {}", cid, args.vsock_port, e)
+    })?;
+
+    let mut transport = VsockTransport::new(stream);
+
+    if let Some(operator_private_key) = &args.operator_private_key {
+        let operator_private_key =
+            hex::decode(operator_private_key).expect("operator key must be hex");
+        let (enclave_public_key, attestation) = transport
+            .initiate_handshake(&operator_private_key)
+            .await
+            .map_err(|e| format!("failed to complete handshake: {}", e))?;
+        if let Some(expected) = &args.enclave_public_key {
+            let expected = hex::decode(expected).expect("enclave key must be hex");
+            if expected != enclave_public_key {
+                return Err("enclave presented an unexpected channel key".to_string());
+            }
+        }
+        if !args.expected_pcr.is_empty() {
+            let verifier = AttestationVerifier::new(args.expected_pcr.iter().cloned().collect());
+            let document = verifier
+                .verify(&attestation)
+                .map_err(|e| format!("failed to verify enclave attestation: {}", e))?;
+            if document.public_key.as_deref().map(|key| key.as_slice())
+                != Some(enclave_public_key.as_slice())
+            {
+                return Err("attestation document is not for the enclave's channel key".to_string());
+            }
+        }
     }
 
-    let account_id = parts[4];
-    let resource_parts: Vec<&str> = parts[5].split('/').collect();
-    if resource_parts.len() < 2 {
-        panic!("less than 2");
+    return Ok(transport);
+}
+
+fn parse_pcr(s: &str) -> Result<(usize, Vec<u8>), String> {
+    let (index, value) = s.split_once('=').ok_or("expected INDEX=HEX")?;
+    let index = index
+        .parse()
+        .map_err(|e| format!("invalid pcr index: {}", e))?;
+    let value = hex::decode(value).map_err(|e| format!("invalid pcr value: {}", e))?;
+    return Ok((index, value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::audit::{AuditDecision, AuditEvent};
+
+    const REQUIRED: [&str; 11] = [
+        "host",
+        "--aws-region",
+        "us-east-1",
+        "--vsock-port",
+        "5000",
+        "--enclave-cid",
+        "16",
+        "--kms-proxy-port",
+        "8000",
+        "--kms-key-id",
+        "key",
+    ];
+
+    #[test]
+    fn test_expected_pcr_requires_operator_key() {
+        let pcr = format!("0={}", "00".repeat(48));
+        let args = REQUIRED.iter().copied().chain(["--expected-pcr", &pcr]);
+        assert!(Args::try_parse_from(args).is_err());
+
+        let operator_key = "11".repeat(32);
+        let args = REQUIRED.iter().copied().chain([
+            "--expected-pcr",
+            &pcr,
+            "--operator-private-key",
+            &operator_key,
+        ]);
+        assert_eq!(Args::try_parse_from(args).unwrap().expected_pcr.len(), 1);
+
+        let args = REQUIRED
+            .iter()
+            .copied()
+            .chain(["--enclave-public-key", &operator_key]);
+        assert!(Args::try_parse_from(args).is_err());
     }
 
-    let role_name = resource_parts[1];
-    let role_arn = format!("arn:aws:iam::{}:role/{}", account_id, role_name);
+    #[test]
+    fn test_append_audit_event_survives_io_errors() {
+        // a directory cannot be opened for appending
+        let audit_log = std::env::temp_dir();
+        let args = REQUIRED
+            .iter()
+            .copied()
+            .chain(["--audit-log", audit_log.to_str().unwrap()]);
+        let args = Args::try_parse_from(args).unwrap();
+        let audit_event = SignedAuditEvent {
+            event: AuditEvent {
+                sequence: 0,
+                timestamp_ms: 0,
+                operation: "Sign".to_string(),
+                wallet_id: None,
+                created_wallet_id: None,
+                request_hash: [0u8; 32],
+                decision: AuditDecision::Allowed,
+                error: None,
+                previous_hash: [0u8; 32],
+                enclave_key: [0u8; 32],
+            },
+            signature: vec![],
+        };
 
-    return role_arn;
+        append_audit_event(&args, &audit_event);
+        assert!(
+            METRICS
+                .encode()
+                .contains("trustvault_host_audit_log_errors_total 1")
+        );
+    }
 }
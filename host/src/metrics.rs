@@ -0,0 +1,248 @@
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+use prometheus_client::registry::Registry;
+use shared::transport::RequestStats;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct OperationLabels {
+    pub operation: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RequestLabels {
+    pub operation: String,
+    /// `ok`, or the error code of the enclave's error.
+    pub result: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct KmsCallLabels {
+    pub command: String,
+    /// `ok` or `error`.
+    pub result: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct VsockErrorLabels {
+    /// `connect`, `send` or `receive`.
+    pub stage: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ResultLabels {
+    /// `ok` or `error`.
+    pub result: String,
+}
+
+type HistogramFamily<L> = Family<L, Histogram, fn() -> Histogram>;
+
+/// Everything the host exposes on `/metrics`. Durations are in seconds.
+pub struct Metrics {
+    registry: Registry,
+    requests: Family<RequestLabels, Counter>,
+    requests_in_flight: Family<OperationLabels, Gauge>,
+    request_duration: HistogramFamily<OperationLabels>,
+    enclave_duration: HistogramFamily<OperationLabels>,
+    kms_call_duration: HistogramFamily<KmsCallLabels>,
+    vsock_errors: Family<VsockErrorLabels, Counter>,
+    credential_refreshes: Family<ResultLabels, Counter>,
+    audit_log_errors: Counter,
+}
+
+fn latency_histogram() -> Histogram {
+    // 1ms to about 33s
+    return Histogram::new(exponential_buckets(0.001, 2.0, 16));
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let mut metrics = Self {
+            registry: Registry::with_prefix("trustvault_host"),
+            requests: Family::default(),
+            requests_in_flight: Family::default(),
+            request_duration: Family::new_with_constructor(latency_histogram),
+            enclave_duration: Family::new_with_constructor(latency_histogram),
+            kms_call_duration: Family::new_with_constructor(latency_histogram),
+            vsock_errors: Family::default(),
+            credential_refreshes: Family::default(),
+            audit_log_errors: Counter::default(),
+        };
+        let registry = &mut metrics.registry;
+        registry.register(
+            "requests",
+            "Enclave requests by operation and result",
+            metrics.requests.clone(),
+        );
+        registry.register(
+            "requests_in_flight",
+            "Enclave requests sent and not yet answered",
+            metrics.requests_in_flight.clone(),
+        );
+        registry.register(
+            "request_duration_seconds",
+            "Enclave request latency as seen by the host, connection excluded",
+            metrics.request_duration.clone(),
+        );
+        registry.register(
+            "enclave_duration_seconds",
+            "Time the enclave reported spending on a request",
+            metrics.enclave_duration.clone(),
+        );
+        registry.register(
+            "kms_call_duration_seconds",
+            "Latency of the kmstool calls the enclave reported",
+            metrics.kms_call_duration.clone(),
+        );
+        registry.register(
+            "vsock_errors",
+            "Failures to connect to, send to or receive from the enclave",
+            metrics.vsock_errors.clone(),
+        );
+        registry.register(
+            "credential_refreshes",
+            "Attempts to assume the role KMS credentials come from",
+            metrics.credential_refreshes.clone(),
+        );
+        registry.register(
+            "audit_log_errors",
+            "Audit events that could not be appended to the audit log",
+            metrics.audit_log_errors.clone(),
+        );
+        return metrics;
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn start_request(&self, operation: &str) -> InFlight {
+        let gauge = self
+            .requests_in_flight
+            .get_or_create(&OperationLabels {
+                operation: operation.to_string(),
+            })
+            .clone();
+        gauge.inc();
+        return InFlight { gauge };
+    }
+
+    pub fn record_request(&self, operation: &str, error_code: Option<&str>, duration: Duration) {
+        self.requests
+            .get_or_create(&RequestLabels {
+                operation: operation.to_string(),
+                result: error_code.unwrap_or("ok").to_string(),
+            })
+            .inc();
+        self.request_duration
+            .get_or_create(&OperationLabels {
+                operation: operation.to_string(),
+            })
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn record_enclave_stats(&self, operation: &str, stats: &RequestStats) {
+        self.enclave_duration
+            .get_or_create(&OperationLabels {
+                operation: operation.to_string(),
+            })
+            .observe(micros_to_secs(stats.duration_us));
+        for call in &stats.kms_calls {
+            self.kms_call_duration
+                .get_or_create(&KmsCallLabels {
+                    command: call.command.clone(),
+                    result: result_label(call.success),
+                })
+                .observe(micros_to_secs(call.duration_us));
+        }
+    }
+
+    pub fn record_vsock_error(&self, stage: &str) {
+        self.vsock_errors
+            .get_or_create(&VsockErrorLabels {
+                stage: stage.to_string(),
+            })
+            .inc();
+    }
+
+    pub fn record_credential_refresh(&self, success: bool) {
+        self.credential_refreshes
+            .get_or_create(&ResultLabels {
+                result: result_label(success),
+            })
+            .inc();
+    }
+
+    pub fn record_audit_log_error(&self) {
+        self.audit_log_errors.inc();
+    }
+
+    pub fn encode(&self) -> String {
+        let mut body = String::new();
+        encode(&mut body, &self.registry).expect("writing to a string cannot fail");
+        return body;
+    }
+}
+
+pub struct InFlight {
+    gauge: Gauge,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+fn micros_to_secs(micros: u64) -> f64 {
+    return micros as f64 / 1_000_000.0;
+}
+
+fn result_label(success: bool) -> String {
+    return if success { "ok" } else { "error" }.to_string();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::transport::KmsCallStats;
+
+    #[test]
+    fn test_metrics_are_encoded_per_operation() {
+        let metrics = Metrics::new();
+        {
+            let _in_flight = metrics.start_request("Sign");
+            assert!(
+                metrics
+                    .encode()
+                    .contains("trustvault_host_requests_in_flight{operation=\"Sign\"} 1")
+            );
+        }
+        metrics.record_request("Sign", Some("policy"), Duration::from_millis(5));
+        metrics.record_enclave_stats(
+            "Sign",
+            &RequestStats {
+                duration_us: 4_000,
+                kms_calls: vec![KmsCallStats {
+                    command: "decrypt".to_string(),
+                    duration_us: 3_000,
+                    success: true,
+                }],
+            },
+        );
+
+        let body = metrics.encode();
+        assert!(body.contains("trustvault_host_requests_in_flight{operation=\"Sign\"} 0"));
+        assert!(
+            body.contains("trustvault_host_requests_total{operation=\"Sign\",result=\"policy\"} 1")
+        );
+        assert!(body.contains(
+            "trustvault_host_kms_call_duration_seconds_count{command=\"decrypt\",result=\"ok\"} 1"
+        ));
+    }
+}
@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+/// M-of-N approver set sealed into a wallet envelope alongside its policy.
+/// Once set, every signing request for the wallet must carry `threshold`
+/// valid approvals from distinct `approvers`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Quorum {
+    pub threshold: u8,
+    /// Ed25519 public keys of the registered approvers.
+    pub approvers: Vec<[u8; 32]>,
+}
+
+/// An approver's Ed25519 signature over the request hash: the SHA-256 of the
+/// CBOR encoded request after resetting its credentials to their defaults and
+/// removing all approvals.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Approval {
+    pub approver: [u8; 32],
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
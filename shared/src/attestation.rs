@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use coset::{CborSerializable, CoseSign1, TaggedCborSerializable, iana};
+use p384::ecdsa::signature::Verifier;
+use p384::ecdsa::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+use x509_cert::ext::pkix::BasicConstraints;
+
+use crate::error::AttestationError;
+
+/// DER encoded AWS Nitro Enclaves root certificate
+/// (`AWS_NitroEnclaves_Root-G1`), as published by AWS. Every genuine document
+/// carries that certificate as the first entry of its `cabundle`.
+pub const AWS_NITRO_ROOT_G1: &[u8] = include_bytes!("../certs/AWS_NitroEnclaves_Root-G1.der");
+
+/// SHA-256 fingerprint of [`AWS_NITRO_ROOT_G1`].
+pub const AWS_NITRO_ROOT_G1_SHA256: [u8; 32] = [
+    0x64, 0x1a, 0x03, 0x21, 0xa3, 0xe2, 0x44, 0xef, 0xe4, 0x56, 0x46, 0x31, 0x95, 0xd6, 0x06, 0x31,
+    0x7e, 0xd7, 0xcd, 0xcc, 0x3c, 0x17, 0x56, 0xe0, 0x98, 0x93, 0xf3, 0xc6, 0x8f, 0x79, 0xbb, 0x5b,
+];
+
+const ECDSA_WITH_SHA_384: x509_cert::der::oid::ObjectIdentifier =
+    x509_cert::der::oid::db::rfc5912::ECDSA_WITH_SHA_384;
+
+/// Payload of an NSM attestation document.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttestationDocument {
+    pub module_id: String,
+    pub digest: String,
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: u64,
+    pub pcrs: BTreeMap<usize, ByteBuf>,
+    /// DER encoded certificate whose key signed the document.
+    pub certificate: ByteBuf,
+    /// DER encoded chain from the root down to `certificate`'s issuer.
+    pub cabundle: Vec<ByteBuf>,
+    pub public_key: Option<ByteBuf>,
+    pub user_data: Option<ByteBuf>,
+    pub nonce: Option<ByteBuf>,
+}
+
+/// Verifies attestation documents against a pinned root and expected PCRs.
+///
+/// Certificates are checked for validity at the document's own timestamp, so
+/// callers that need freshness should send a nonce and compare it.
+pub struct AttestationVerifier {
+    root_fingerprint: [u8; 32],
+    expected_pcrs: BTreeMap<usize, Vec<u8>>,
+}
+
+impl AttestationVerifier {
+    /// Trusts the AWS Nitro root. PCRs not in `expected_pcrs` are not checked.
+    pub fn new(expected_pcrs: BTreeMap<usize, Vec<u8>>) -> Self {
+        return Self::with_root_fingerprint(AWS_NITRO_ROOT_G1_SHA256, expected_pcrs);
+    }
+
+    pub fn with_root_fingerprint(
+        root_fingerprint: [u8; 32],
+        expected_pcrs: BTreeMap<usize, Vec<u8>>,
+    ) -> Self {
+        return Self {
+            root_fingerprint,
+            expected_pcrs,
+        };
+    }
+
+    pub fn verify(&self, document: &[u8]) -> Result<AttestationDocument, AttestationError> {
+        let sign1 = CoseSign1::from_tagged_slice(document)
+            .or_else(|_| CoseSign1::from_slice(document))
+            .map_err(|e| AttestationError::InvalidCose(e.to_string()))?;
+        let es384 = coset::RegisteredLabelWithPrivate::Assigned(iana::Algorithm::ES384);
+        if sign1.protected.header.alg != Some(es384) {
+            return Err(AttestationError::UnsupportedAlgorithm);
+        }
+        let payload = sign1
+            .payload
+            .as_ref()
+            .ok_or_else(|| AttestationError::InvalidCose("missing payload".to_string()))?;
+        let attestation: AttestationDocument = serde_cbor::from_slice(payload)
+            .map_err(|e| AttestationError::InvalidDocument(e.to_string()))?;
+        if attestation.digest != "SHA384" {
+            return Err(AttestationError::InvalidDocument(format!(
+                "unsupported digest {}",
+                attestation.digest
+            )));
+        }
+
+        let leaf = self.verify_chain(&attestation)?;
+        sign1.verify_signature(b"", |signature, data| {
+            let signature =
+                Signature::from_slice(signature).map_err(|_| AttestationError::InvalidSignature)?;
+            return leaf
+                .verify(data, &signature)
+                .map_err(|_| AttestationError::InvalidSignature);
+        })?;
+
+        for (index, expected) in &self.expected_pcrs {
+            match attestation.pcrs.get(index) {
+                Some(actual) if actual.as_slice() == expected.as_slice() => {}
+                _ => return Err(AttestationError::PcrMismatch(*index)),
+            }
+        }
+        return Ok(attestation);
+    }
+
+    /// Walks `cabundle` from the pinned root down to `certificate` and returns
+    /// the key of the latter.
+    fn verify_chain(
+        &self,
+        attestation: &AttestationDocument,
+    ) -> Result<VerifyingKey, AttestationError> {
+        let root = attestation
+            .cabundle
+            .first()
+            .ok_or(AttestationError::UntrustedRoot)?;
+        let fingerprint: [u8; 32] = Sha256::digest(root).into();
+        if fingerprint != self.root_fingerprint {
+            return Err(AttestationError::UntrustedRoot);
+        }
+
+        let chain = attestation
+            .cabundle
+            .iter()
+            .chain(std::iter::once(&attestation.certificate))
+            .map(|der| Certificate::from_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| AttestationError::InvalidCertificate(e.to_string()))?;
+        let at = Duration::from_millis(attestation.timestamp);
+
+        let mut issuer = &chain[0];
+        for (depth, certificate) in chain.iter().enumerate() {
+            if !is_valid_at(certificate, at) {
+                return Err(AttestationError::CertificateExpired(depth));
+            }
+            if depth > 0 && !is_ca(issuer) {
+                return Err(AttestationError::InvalidChain(depth));
+            }
+            if certificate.tbs_certificate.issuer != issuer.tbs_certificate.subject
+                || !is_signed_by(certificate, &public_key(issuer)?)
+            {
+                return Err(AttestationError::InvalidChain(depth));
+            }
+            issuer = certificate;
+        }
+        return public_key(issuer);
+    }
+}
+
+fn public_key(certificate: &Certificate) -> Result<VerifyingKey, AttestationError> {
+    let spki = &certificate.tbs_certificate.subject_public_key_info;
+    return VerifyingKey::from_sec1_bytes(spki.subject_public_key.raw_bytes())
+        .map_err(|e| AttestationError::InvalidCertificate(e.to_string()));
+}
+
+fn is_signed_by(certificate: &Certificate, issuer_key: &VerifyingKey) -> bool {
+    if certificate.signature_algorithm.oid != ECDSA_WITH_SHA_384 {
+        return false;
+    }
+    let Ok(tbs) = certificate.tbs_certificate.to_der() else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_der(certificate.signature.raw_bytes()) else {
+        return false;
+    };
+    return issuer_key.verify(&tbs, &signature).is_ok();
+}
+
+fn is_valid_at(certificate: &Certificate, at: Duration) -> bool {
+    let validity = &certificate.tbs_certificate.validity;
+    return validity.not_before.to_unix_duration() <= at
+        && at <= validity.not_after.to_unix_duration();
+}
+
+fn is_ca(certificate: &Certificate) -> bool {
+    let Some(extensions) = &certificate.tbs_certificate.extensions else {
+        return false;
+    };
+    return extensions
+        .iter()
+        .filter(|extension| {
+            extension.extn_id == x509_cert::der::oid::db::rfc5280::ID_CE_BASIC_CONSTRAINTS
+        })
+        .filter_map(|extension| BasicConstraints::from_der(extension.extn_value.as_bytes()).ok())
+        .any(|constraints| constraints.ca);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use coset::{CoseSign1Builder, HeaderBuilder};
+    use p384::ecdsa::SigningKey;
+    use p384::ecdsa::signature::Signer;
+    use p384::pkcs8::EncodePublicKey;
+    use std::str::FromStr;
+    use x509_cert::TbsCertificate;
+    use x509_cert::certificate::Version;
+    use x509_cert::der::asn1::{BitString, OctetString};
+    use x509_cert::ext::Extension;
+    use x509_cert::name::Name;
+    use x509_cert::serial_number::SerialNumber;
+    use x509_cert::spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+    use x509_cert::time::{Time, Validity};
+
+    /// 2024-01-01T00:00:00Z
+    const TIMESTAMP_MS: u64 = 1_704_067_200_000;
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn signing_key(seed: u8) -> SigningKey {
+        return SigningKey::from_slice(&[seed; 48]).unwrap();
+    }
+
+    fn certificate(
+        subject: &str,
+        key: &SigningKey,
+        issuer: &str,
+        issuer_key: &SigningKey,
+        ca: bool,
+        not_after: Duration,
+    ) -> Vec<u8> {
+        let not_before = Duration::from_millis(TIMESTAMP_MS) - DAY;
+        let extensions = ca.then(|| {
+            vec![Extension {
+                extn_id: x509_cert::der::oid::db::rfc5280::ID_CE_BASIC_CONSTRAINTS,
+                critical: true,
+                extn_value: OctetString::new(
+                    BasicConstraints {
+                        ca: true,
+                        path_len_constraint: None,
+                    }
+                    .to_der()
+                    .unwrap(),
+                )
+                .unwrap(),
+            }]
+        });
+        let tbs_certificate = TbsCertificate {
+            version: Version::V3,
+            serial_number: SerialNumber::new(&[1]).unwrap(),
+            signature: AlgorithmIdentifierOwned {
+                oid: ECDSA_WITH_SHA_384,
+                parameters: None,
+            },
+            issuer: Name::from_str(issuer).unwrap(),
+            validity: Validity {
+                not_before: Time::try_from(std::time::UNIX_EPOCH + not_before).unwrap(),
+                not_after: Time::try_from(std::time::UNIX_EPOCH + not_after).unwrap(),
+            },
+            subject: Name::from_str(subject).unwrap(),
+            subject_public_key_info: SubjectPublicKeyInfoOwned::from_der(
+                key.verifying_key().to_public_key_der().unwrap().as_bytes(),
+            )
+            .unwrap(),
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions,
+        };
+        let signature: Signature = issuer_key.sign(&tbs_certificate.to_der().unwrap());
+        return Certificate {
+            tbs_certificate,
+            signature_algorithm: AlgorithmIdentifierOwned {
+                oid: ECDSA_WITH_SHA_384,
+                parameters: None,
+            },
+            signature: BitString::from_bytes(signature.to_der().as_bytes()).unwrap(),
+        }
+        .to_der()
+        .unwrap();
+    }
+
+    /// Builds documents the way the NSM does, but chained to a synthetic root
+    /// instead of AWS's.
+    struct Fixture {
+        root_fingerprint: [u8; 32],
+        root: Vec<u8>,
+        intermediate: Vec<u8>,
+        leaf: Vec<u8>,
+        leaf_key: SigningKey,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            return Self::with_leaf_expiry(Duration::from_millis(TIMESTAMP_MS) + DAY);
+        }
+
+        fn with_leaf_expiry(not_after: Duration) -> Self {
+            let (root_key, intermediate_key, leaf_key) =
+                (signing_key(1), signing_key(2), signing_key(3));
+            let ten_years = Duration::from_millis(TIMESTAMP_MS) + 3650 * DAY;
+            let root = certificate("CN=root", &root_key, "CN=root", &root_key, true, ten_years);
+            let intermediate = certificate(
+                "CN=intermediate",
+                &intermediate_key,
+                "CN=root",
+                &root_key,
+                true,
+                ten_years,
+            );
+            let leaf = certificate(
+                "CN=enclave",
+                &leaf_key,
+                "CN=intermediate",
+                &intermediate_key,
+                false,
+                not_after,
+            );
+            return Self {
+                root_fingerprint: Sha256::digest(&root).into(),
+                root,
+                intermediate,
+                leaf,
+                leaf_key,
+            };
+        }
+
+        fn pcrs() -> BTreeMap<usize, Vec<u8>> {
+            return (0..3).map(|i| (i, vec![i as u8; 48])).collect();
+        }
+
+        fn document(&self, nonce: Option<&[u8]>) -> Vec<u8> {
+            let attestation = AttestationDocument {
+                module_id: "i-0123456789abcdef0-enc0123456789abcdef".to_string(),
+                digest: "SHA384".to_string(),
+                timestamp: TIMESTAMP_MS,
+                pcrs: Self::pcrs()
+                    .into_iter()
+                    .map(|(i, pcr)| (i, ByteBuf::from(pcr)))
+                    .collect(),
+                certificate: ByteBuf::from(self.leaf.clone()),
+                cabundle: vec![
+                    ByteBuf::from(self.root.clone()),
+                    ByteBuf::from(self.intermediate.clone()),
+                ],
+                public_key: Some(ByteBuf::from(vec![4u8; 32])),
+                user_data: None,
+                nonce: nonce.map(|nonce| ByteBuf::from(nonce.to_vec())),
+            };
+            return CoseSign1Builder::new()
+                .protected(
+                    HeaderBuilder::new()
+                        .algorithm(iana::Algorithm::ES384)
+                        .build(),
+                )
+                .payload(serde_cbor::to_vec(&attestation).unwrap())
+                .create_signature(b"", |data| {
+                    let signature: Signature = self.leaf_key.sign(data);
+                    signature.to_vec()
+                })
+                .build()
+                .to_vec()
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_verify_fixture_document() {
+        let fixture = Fixture::new();
+        let verifier =
+            AttestationVerifier::with_root_fingerprint(fixture.root_fingerprint, Fixture::pcrs());
+        let attestation = verifier.verify(&fixture.document(Some(b"nonce"))).unwrap();
+        assert_eq!(attestation.nonce.unwrap().as_slice(), b"nonce");
+        assert_eq!(attestation.public_key.unwrap().as_slice(), &[4u8; 32]);
+    }
+
+    #[test]
+    fn test_bundled_aws_root() {
+        let fingerprint: [u8; 32] = Sha256::digest(AWS_NITRO_ROOT_G1).into();
+        assert_eq!(fingerprint, AWS_NITRO_ROOT_G1_SHA256);
+
+        let root = Certificate::from_der(AWS_NITRO_ROOT_G1).unwrap();
+        assert_eq!(
+            root.tbs_certificate.subject.to_string(),
+            "CN=aws.nitro-enclaves,OU=AWS,O=Amazon,C=US"
+        );
+        assert!(is_ca(&root));
+        assert!(is_signed_by(&root, &public_key(&root).unwrap()));
+        assert!(is_valid_at(&root, Duration::from_millis(TIMESTAMP_MS)));
+    }
+
+    #[test]
+    fn test_verify_rejects_intermediate_not_signed_by_aws_root() {
+        let fixture = Fixture::new();
+        let mut sign1 = CoseSign1::from_slice(&fixture.document(None)).unwrap();
+        let mut attestation: AttestationDocument =
+            serde_cbor::from_slice(sign1.payload.as_ref().unwrap()).unwrap();
+        attestation.cabundle[0] = ByteBuf::from(AWS_NITRO_ROOT_G1.to_vec());
+        sign1.payload = Some(serde_cbor::to_vec(&attestation).unwrap());
+        assert!(matches!(
+            AttestationVerifier::new(BTreeMap::new()).verify(&sign1.to_vec().unwrap()),
+            Err(AttestationError::InvalidChain(1))
+        ));
+    }
+
+    #[test]
+    fn test_verify_rejects_untrusted_root() {
+        let fixture = Fixture::new();
+        let verifier = AttestationVerifier::new(BTreeMap::new());
+        assert!(matches!(
+            verifier.verify(&fixture.document(None)),
+            Err(AttestationError::UntrustedRoot)
+        ));
+    }
+
+    #[test]
+    fn test_verify_rejects_pcr_mismatch() {
+        let fixture = Fixture::new();
+        let mut pcrs = Fixture::pcrs();
+        pcrs.insert(0, vec![9u8; 48]);
+        let verifier = AttestationVerifier::with_root_fingerprint(fixture.root_fingerprint, pcrs);
+        assert!(matches!(
+            verifier.verify(&fixture.document(None)),
+            Err(AttestationError::PcrMismatch(0))
+        ));
+
+        let mut pcrs = Fixture::pcrs();
+        pcrs.insert(8, vec![0u8; 48]);
+        let verifier = AttestationVerifier::with_root_fingerprint(fixture.root_fingerprint, pcrs);
+        assert!(matches!(
+            verifier.verify(&fixture.document(None)),
+            Err(AttestationError::PcrMismatch(8))
+        ));
+    }
+
+    #[test]
+    fn test_verify_rejects_tampered_payload_and_expired_leaf() {
+        let fixture = Fixture::new();
+        let verifier =
+            AttestationVerifier::with_root_fingerprint(fixture.root_fingerprint, BTreeMap::new());
+
+        let mut sign1 = CoseSign1::from_slice(&fixture.document(Some(b"nonce"))).unwrap();
+        let mut attestation: AttestationDocument =
+            serde_cbor::from_slice(sign1.payload.as_ref().unwrap()).unwrap();
+        attestation.nonce = Some(ByteBuf::from(b"other".to_vec()));
+        sign1.payload = Some(serde_cbor::to_vec(&attestation).unwrap());
+        assert!(matches!(
+            verifier.verify(&sign1.to_vec().unwrap()),
+            Err(AttestationError::InvalidSignature)
+        ));
+
+        let expired = Fixture::with_leaf_expiry(Duration::from_millis(TIMESTAMP_MS) - DAY / 2);
+        assert!(matches!(
+            verifier.verify(&expired.document(None)),
+            Err(AttestationError::CertificateExpired(2))
+        ));
+    }
+}
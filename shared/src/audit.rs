@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use pallas_crypto::key::ed25519::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::AuditError;
+
+/// One operation on a wallet, as recorded by the enclave. Each event is signed
+/// with a key the enclave generates at boot and commits to the previous event
+/// signed with that key, so [`verify_chain`] fails on a log that reorders or
+/// alters events and reports the ones it is missing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    /// Starts at 0 when the enclave boots, with a new `enclave_key`.
+    pub sequence: u64,
+    /// Milliseconds since the UNIX epoch by the enclave's clock, which is
+    /// only as trustworthy as the host's.
+    pub timestamp_ms: u64,
+    /// Name of the request, e.g. `Sign`.
+    pub operation: String,
+    /// SHA-256 of the `kms_ciphertext` of the wallet or FROST key share the
+    /// request used.
+    pub wallet_id: Option<[u8; 32]>,
+    /// Same for the wallet the request returned, e.g. a created or rewrapped one.
+    pub created_wallet_id: Option<[u8; 32]>,
+    /// The hash approvers sign, so events can be matched to approvals.
+    pub request_hash: [u8; 32],
+    pub decision: AuditDecision,
+    /// The error returned, if the request failed.
+    pub error: Option<String>,
+    /// [`AuditEvent::hash`] of the previous event, zero for the first after boot.
+    pub previous_hash: [u8; 32],
+    /// Ed25519 key the enclave signs events with, see `GetAuditKey`.
+    pub enclave_key: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AuditDecision {
+    /// The request succeeded, past the wallet's policy and quorum if it has them.
+    Allowed,
+    /// The wallet's policy or quorum refused the request.
+    Denied,
+    /// The request failed for another reason, e.g. KMS or an invalid payload.
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedAuditEvent {
+    pub event: AuditEvent,
+    /// Ed25519 signature over [`AuditEvent::hash`] by `event.enclave_key`.
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+impl AuditEvent {
+    /// SHA-256 of the CBOR encoded event.
+    pub fn hash(&self) -> [u8; 32] {
+        let encoded = serde_cbor::to_vec(self).expect("audit events always encode");
+        return Sha256::digest(encoded).into();
+    }
+}
+
+impl SignedAuditEvent {
+    pub fn verify_signature(&self) -> bool {
+        let Ok(signature) = <[u8; 64]>::try_from(self.signature.as_slice()) else {
+            return false;
+        };
+        return PublicKey::from(self.event.enclave_key)
+            .verify(self.event.hash(), &Signature::from(signature));
+    }
+}
+
+/// Events of one enclave key missing from a log, found by [`verify_chain`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditGap {
+    /// Index of the first event after the missing ones.
+    pub index: usize,
+    /// Sequence of the first missing event.
+    pub expected: u64,
+    /// Sequence of the event at `index`.
+    pub found: u64,
+}
+
+/// Checks a log of events in the order they were appended. Events of several
+/// enclaves, or of one enclave across reboots, may be interleaved. Each key's
+/// chain starts at the first of its events in the log, as the log may have
+/// been started after the enclave booted; from there its events must follow on
+/// from one another. Events the log is missing, e.g. because the host failed
+/// to receive or write them, are returned as gaps rather than errors: they are
+/// not evidence of tampering, but still worth looking into. When
+/// `trusted_keys` is not empty, only events signed by one of them are accepted.
+pub fn verify_chain(
+    events: &[SignedAuditEvent],
+    trusted_keys: &[[u8; 32]],
+) -> Result<Vec<AuditGap>, AuditError> {
+    let mut last_events: HashMap<[u8; 32], &AuditEvent> = HashMap::new();
+    let mut gaps = Vec::new();
+    for (index, signed) in events.iter().enumerate() {
+        let event = &signed.event;
+        if !trusted_keys.is_empty() && !trusted_keys.contains(&event.enclave_key) {
+            return Err(AuditError::UntrustedKey { index });
+        }
+        if !signed.verify_signature() {
+            return Err(AuditError::InvalidSignature { index });
+        }
+
+        if let Some(last) = last_events.get(&event.enclave_key) {
+            if event.sequence <= last.sequence {
+                return Err(AuditError::OutOfOrder {
+                    index,
+                    previous: last.sequence,
+                    found: event.sequence,
+                });
+            }
+            if event.sequence > last.sequence + 1 {
+                gaps.push(AuditGap {
+                    index,
+                    expected: last.sequence + 1,
+                    found: event.sequence,
+                });
+            } else if event.previous_hash != last.hash() {
+                return Err(AuditError::BrokenChain { index });
+            }
+        } else if event.sequence == 0 && event.previous_hash != [0u8; 32] {
+            return Err(AuditError::BrokenChain { index });
+        }
+        last_events.insert(event.enclave_key, event);
+    }
+    return Ok(gaps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pallas_crypto::key::ed25519::SecretKey;
+
+    fn chain(secret_key: &SecretKey, count: u64) -> Vec<SignedAuditEvent> {
+        let mut events = Vec::new();
+        let mut previous_hash = [0u8; 32];
+        for sequence in 0..count {
+            let event = AuditEvent {
+                sequence,
+                timestamp_ms: 1_700_000_000_000 + sequence,
+                operation: "Sign".to_string(),
+                wallet_id: Some([1u8; 32]),
+                created_wallet_id: None,
+                request_hash: [sequence as u8; 32],
+                decision: AuditDecision::Allowed,
+                error: None,
+                previous_hash,
+                enclave_key: secret_key.public_key().into(),
+            };
+            previous_hash = event.hash();
+            let signature = secret_key.sign(event.hash()).as_ref().to_vec();
+            events.push(SignedAuditEvent { event, signature });
+        }
+        return events;
+    }
+
+    fn resign(secret_key: &SecretKey, signed: &mut SignedAuditEvent) {
+        signed.signature = secret_key.sign(signed.event.hash()).as_ref().to_vec();
+    }
+
+    #[test]
+    fn test_interleaved_chains_verify() {
+        let first = SecretKey::from([1u8; 32]);
+        let second = SecretKey::from([2u8; 32]);
+        let mut events = chain(&first, 3);
+        events.extend(chain(&second, 2));
+        events.swap(2, 3);
+        assert_eq!(verify_chain(&events, &[]), Ok(vec![]));
+
+        let trusted: [u8; 32] = first.public_key().into();
+        assert_eq!(
+            verify_chain(&events, &[trusted]),
+            Err(AuditError::UntrustedKey { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_altered_event_is_detected() {
+        let secret_key = SecretKey::from([1u8; 32]);
+        let mut events = chain(&secret_key, 3);
+        events[1].event.decision = AuditDecision::Denied;
+        assert_eq!(
+            verify_chain(&events, &[]),
+            Err(AuditError::InvalidSignature { index: 1 })
+        );
+
+        // even re-signed, the next event still commits to the original
+        resign(&secret_key, &mut events[1]);
+        assert_eq!(
+            verify_chain(&events, &[]),
+            Err(AuditError::BrokenChain { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_missing_events_are_reported_as_gaps() {
+        let secret_key = SecretKey::from([1u8; 32]);
+        let mut events = chain(&secret_key, 5);
+        events.remove(1);
+        events.remove(1);
+        assert_eq!(
+            verify_chain(&events, &[]),
+            Ok(vec![AuditGap {
+                index: 1,
+                expected: 1,
+                found: 3
+            }])
+        );
+
+        // a log started after the enclave booted
+        let events = chain(&secret_key, 4).split_off(2);
+        assert_eq!(verify_chain(&events, &[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_reordered_events_are_detected() {
+        let secret_key = SecretKey::from([1u8; 32]);
+        let mut events = chain(&secret_key, 4);
+        events.swap(1, 2);
+        assert_eq!(
+            verify_chain(&events, &[]),
+            Err(AuditError::OutOfOrder {
+                index: 2,
+                previous: 2,
+                found: 1
+            })
+        );
+
+        let mut events = chain(&secret_key, 4);
+        events.push(events[1].clone());
+        assert_eq!(
+            verify_chain(&events, &[]),
+            Err(AuditError::OutOfOrder {
+                index: 4,
+                previous: 3,
+                found: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroize;
+
+use crate::approval::Quorum;
+use crate::error::BackupError;
+use crate::policy::SignedPolicy;
+use crate::sealed::{self, SealedBox};
+use crate::slip39::Slip39Share;
+use crate::transport::{REDACTED, WalletOrigin};
+
+/// HKDF info for shares sealed to recovery keys, so a share can never be
+/// replayed as a sealed request or response.
+pub const BACKUP_SHARE_INFO: &[u8] = b"wallet backup share";
+
+/// What a recovery key holder finds inside their share. The wallet's policy,
+/// quorum and origin travel with the secret, so they cannot be stripped on restore.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupSecret {
+    /// Random per export; shares of different exports never combine.
+    pub backup_id: [u8; 16],
+    pub part: BackupPart,
+    pub policy: Option<SignedPolicy>,
+    pub quorum: Option<Quorum>,
+    #[serde(default)]
+    pub origin: WalletOrigin,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum BackupPart {
+    /// The whole 64 byte wallet secret.
+    Secret(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// A single group SLIP-39 share of the wallet secret, with an empty
+    /// passphrase, so any SLIP-39 implementation can recover the wallet.
+    Slip39(Slip39Share),
+}
+
+impl std::fmt::Debug for BackupPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            BackupPart::Secret(_) => f.debug_tuple("Secret").field(&REDACTED).finish(),
+            BackupPart::Slip39(share) => f.debug_tuple("Slip39").field(share).finish(),
+        };
+    }
+}
+
+impl Drop for BackupPart {
+    fn drop(&mut self) {
+        if let BackupPart::Secret(secret) = self {
+            secret.zeroize();
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupShare {
+    /// X25519 recovery public key the share is sealed to.
+    pub recipient: [u8; 32],
+    /// CBOR encoded [`BackupSecret`], sealed with [`BACKUP_SHARE_INFO`].
+    pub sealed_secret: SealedBox,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WalletBackup {
+    pub backup_id: [u8; 16],
+    pub threshold: Option<u8>,
+    /// One share per recovery key, in the order the keys were given.
+    pub shares: Vec<BackupShare>,
+}
+
+/// Recovery side: opens the share sealed to `recovery_key`.
+pub fn open_share(
+    recovery_key: &StaticSecret,
+    share: &BackupShare,
+) -> Result<BackupSecret, BackupError> {
+    let plaintext = sealed::open(recovery_key, BACKUP_SHARE_INFO, &share.sealed_secret)?;
+    return serde_cbor::from_slice(&plaintext)
+        .map_err(|e| BackupError::InvalidShare(e.to_string()));
+}
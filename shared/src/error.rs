@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::policy::SignatureSchemeKind;
+
 #[derive(Debug, thiserror::Error)]
 pub enum KmsToolError {
     #[error("failed to Command::new().output()")]
@@ -30,6 +32,14 @@ pub enum VsockEnclaveCreateWalletError {
     KmsToolError(String),
     #[error("{0}")]
     Aes256GcmError(String),
+    #[error("generated secret key has an invalid length")]
+    InvalidSecretKey,
+    #[error("{0}")]
+    PolicyError(String),
+    #[error("{0}")]
+    ApprovalError(ApprovalError),
+    #[error("request is not from an authenticated operator")]
+    Unauthenticated,
 }
 
 impl From<KmsToolError> for VsockEnclaveCreateWalletError {
@@ -44,12 +54,322 @@ impl From<Aes256GcmError> for VsockEnclaveCreateWalletError {
     }
 }
 
+impl From<PolicyError> for VsockEnclaveCreateWalletError {
+    fn from(e: PolicyError) -> Self {
+        VsockEnclaveCreateWalletError::PolicyError(e.to_string())
+    }
+}
+
+impl From<ApprovalError> for VsockEnclaveCreateWalletError {
+    fn from(e: ApprovalError) -> Self {
+        VsockEnclaveCreateWalletError::ApprovalError(e)
+    }
+}
+
+/// Sent back to the host as is, so approval tooling can tell which approvals
+/// were not counted and why.
+#[derive(Debug, Clone, PartialEq, thiserror::Error, Serialize, Deserialize)]
+pub enum ApprovalError {
+    #[error("invalid quorum: {0}")]
+    InvalidQuorum(String),
+    #[error("quorum not met: {approved} of {threshold} required approvals")]
+    QuorumNotMet {
+        threshold: u8,
+        approved: u8,
+        rejected: Vec<RejectedApproval>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RejectedApproval {
+    pub approver: [u8; 32],
+    pub reason: ApprovalRejection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ApprovalRejection {
+    UnknownApprover,
+    DuplicateApprover,
+    InvalidSignature,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("invalid policy: {0}")]
+    InvalidPolicy(String),
+    #[error("no policy admin key is configured")]
+    NoAdminKey,
+    #[error("policy signature is invalid")]
+    InvalidSignature,
+    #[error("new policy version {new} must be greater than {current}")]
+    VersionNotIncreasing { current: u64, new: u64 },
+    #[error("policy version {version} was replaced by version {newest}")]
+    Superseded { version: u64, newest: u64 },
+    #[error("policy is for another wallet")]
+    WrongWallet,
+    #[error("wallets must have a policy to sign")]
+    PolicyRequired,
+    #[error("raw secp256k1 digests are only signed under a policy allowing opaque requests")]
+    DigestNeedsPolicy,
+    #[error("signing is not allowed at this time of day")]
+    OutsideTimeWindow,
+    #[error("signature scheme is not allowed")]
+    SchemeNotAllowed,
+    #[error("requests that cannot be decoded are not allowed")]
+    OpaqueNotAllowed,
+    #[error("chain is not allowed")]
+    ChainNotAllowed,
+    #[error("destination is not allowlisted")]
+    DestinationNotAllowed,
+    #[error("transaction value exceeds the per-transaction limit")]
+    ValueLimitExceeded,
+    #[error("transaction value exceeds the rolling window limit")]
+    RollingLimitExceeded,
+    #[error("{0}")]
+    Decode(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CardanoError {
+    #[error("invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    #[error("invalid transaction cbor: {0}")]
+    InvalidTransaction(String),
+    #[error("derived key is not a valid extended ed25519 key")]
+    InvalidExtendedKey,
+    #[error("derived key is not a credential of the given address")]
+    AddressMismatch,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EthereumError {
+    #[error("invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    #[error("legacy transactions must set an EIP-155 chain id")]
+    InvalidChainId,
+    #[error("secp256k1 signing failed")]
+    SigningFailed,
+    #[error("invalid typed data: {0}")]
+    InvalidTypedData(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PsbtError {
+    #[error("invalid psbt: {0}")]
+    InvalidPsbt(String),
+    #[error("bip32 derivation failed: {0}")]
+    Bip32(String),
+    #[error("failed to compute sighash for input {0}: {1}")]
+    Sighash(usize, String),
+    #[error("derived key does not match the public key of input {0}")]
+    KeyMismatch(usize),
+    #[error("input {0} has no utxo")]
+    MissingUtxo(usize),
+    #[error("utxo of input {0} does not match its outpoint")]
+    UtxoMismatch(usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SolanaError {
+    #[error("invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    #[error("slip-10 ed25519 only supports hardened derivation")]
+    NonHardenedDerivation,
+    #[error("invalid message: {0}")]
+    InvalidMessage(String),
+    #[error("wallet key is not a required signer of the message")]
+    NotRequiredSigner,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SignerError {
+    #[error("invalid derivation path: {0}")]
+    InvalidDerivationPath(String),
+    #[error("expected a 32 byte digest, got {0} bytes")]
+    InvalidDigestLength(usize),
+    #[error("failed to get randomness: {0}")]
+    Randomness(String),
+    #[error("imported key signs with {imported:?}, not {requested:?}")]
+    ImportedKeyScheme {
+        imported: SignatureSchemeKind,
+        requested: SignatureSchemeKind,
+    },
+    #[error("imported keys are used as is, the derivation path must be \"m\", got {0}")]
+    ImportedKeyDerivation(String),
+    #[error("imported key is invalid")]
+    InvalidImportedKey,
+}
+
+#[derive(Debug, thiserror::Error, Serialize, Deserialize)]
+pub enum VsockEnclaveSignError {
+    #[error("{0}")]
+    KmsToolError(String),
+    #[error("{0}")]
+    Aes256GcmError(String),
+    #[error("decrypted secret key has an invalid length")]
+    InvalidSecretKey,
+    #[error("{0}")]
+    CardanoError(String),
+    #[error("{0}")]
+    EthereumError(String),
+    #[error("{0}")]
+    PsbtError(String),
+    #[error("{0}")]
+    SolanaError(String),
+    #[error("{0}")]
+    SignerError(String),
+    #[error("{0}")]
+    PolicyError(String),
+    #[error("{0}")]
+    ApprovalError(ApprovalError),
+    #[error("request is not from an authenticated operator")]
+    Unauthenticated,
+    #[error("{0}")]
+    SealedBoxError(String),
+    #[error("{0}")]
+    NsmError(String),
+    #[error("{0}")]
+    BackupError(String),
+    #[error("{0}")]
+    FrostError(String),
+    #[error("{0}")]
+    ImportKeyError(String),
+}
+
+/// Short, stable name of an error for logs and metrics, free of the error's
+/// details.
+pub trait ErrorCode {
+    fn code(&self) -> &'static str;
+}
+
+impl ErrorCode for VsockEnclaveSignError {
+    fn code(&self) -> &'static str {
+        return match self {
+            VsockEnclaveSignError::KmsToolError(_) => "kms_tool",
+            VsockEnclaveSignError::Aes256GcmError(_) => "aes_256_gcm",
+            VsockEnclaveSignError::InvalidSecretKey => "invalid_secret_key",
+            VsockEnclaveSignError::CardanoError(_) => "cardano",
+            VsockEnclaveSignError::EthereumError(_) => "ethereum",
+            VsockEnclaveSignError::PsbtError(_) => "psbt",
+            VsockEnclaveSignError::SolanaError(_) => "solana",
+            VsockEnclaveSignError::SignerError(_) => "signer",
+            VsockEnclaveSignError::PolicyError(_) => "policy",
+            VsockEnclaveSignError::ApprovalError(_) => "approval",
+            VsockEnclaveSignError::Unauthenticated => "unauthenticated",
+            VsockEnclaveSignError::SealedBoxError(_) => "sealed_box",
+            VsockEnclaveSignError::NsmError(_) => "nsm",
+            VsockEnclaveSignError::BackupError(_) => "backup",
+            VsockEnclaveSignError::FrostError(_) => "frost",
+            VsockEnclaveSignError::ImportKeyError(_) => "import_key",
+        };
+    }
+}
+
+impl ErrorCode for VsockEnclaveCreateWalletError {
+    fn code(&self) -> &'static str {
+        return match self {
+            VsockEnclaveCreateWalletError::KmsToolError(_) => "kms_tool",
+            VsockEnclaveCreateWalletError::Aes256GcmError(_) => "aes_256_gcm",
+            VsockEnclaveCreateWalletError::InvalidSecretKey => "invalid_secret_key",
+            VsockEnclaveCreateWalletError::PolicyError(_) => "policy",
+            VsockEnclaveCreateWalletError::ApprovalError(_) => "approval",
+            VsockEnclaveCreateWalletError::Unauthenticated => "unauthenticated",
+        };
+    }
+}
+
+impl From<KmsToolError> for VsockEnclaveSignError {
+    fn from(e: KmsToolError) -> Self {
+        VsockEnclaveSignError::KmsToolError(e.to_string())
+    }
+}
+
+impl From<Aes256GcmError> for VsockEnclaveSignError {
+    fn from(e: Aes256GcmError) -> Self {
+        VsockEnclaveSignError::Aes256GcmError(e.to_string())
+    }
+}
+
+impl From<CardanoError> for VsockEnclaveSignError {
+    fn from(e: CardanoError) -> Self {
+        VsockEnclaveSignError::CardanoError(e.to_string())
+    }
+}
+
+impl From<EthereumError> for VsockEnclaveSignError {
+    fn from(e: EthereumError) -> Self {
+        VsockEnclaveSignError::EthereumError(e.to_string())
+    }
+}
+
+impl From<PsbtError> for VsockEnclaveSignError {
+    fn from(e: PsbtError) -> Self {
+        VsockEnclaveSignError::PsbtError(e.to_string())
+    }
+}
+
+impl From<SolanaError> for VsockEnclaveSignError {
+    fn from(e: SolanaError) -> Self {
+        VsockEnclaveSignError::SolanaError(e.to_string())
+    }
+}
+
+impl From<SignerError> for VsockEnclaveSignError {
+    fn from(e: SignerError) -> Self {
+        VsockEnclaveSignError::SignerError(e.to_string())
+    }
+}
+
+impl From<PolicyError> for VsockEnclaveSignError {
+    fn from(e: PolicyError) -> Self {
+        VsockEnclaveSignError::PolicyError(e.to_string())
+    }
+}
+
+impl From<ApprovalError> for VsockEnclaveSignError {
+    fn from(e: ApprovalError) -> Self {
+        VsockEnclaveSignError::ApprovalError(e)
+    }
+}
+
+impl From<SealedBoxError> for VsockEnclaveSignError {
+    fn from(e: SealedBoxError) -> Self {
+        VsockEnclaveSignError::SealedBoxError(e.to_string())
+    }
+}
+
+impl From<NsmError> for VsockEnclaveSignError {
+    fn from(e: NsmError) -> Self {
+        VsockEnclaveSignError::NsmError(e.to_string())
+    }
+}
+
+impl From<BackupError> for VsockEnclaveSignError {
+    fn from(e: BackupError) -> Self {
+        VsockEnclaveSignError::BackupError(e.to_string())
+    }
+}
+
+impl From<ImportKeyError> for VsockEnclaveSignError {
+    fn from(e: ImportKeyError) -> Self {
+        VsockEnclaveSignError::ImportKeyError(e.to_string())
+    }
+}
+
+impl From<FrostError> for VsockEnclaveSignError {
+    fn from(e: FrostError) -> Self {
+        VsockEnclaveSignError::FrostError(e.to_string())
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum VsockReceiveError {
     #[error("failed to stream.read_exact()")]
     Io(#[from] std::io::Error),
     #[error("failed to deserialize cbor")]
     Deserialization(#[from] serde_cbor::Error),
+    #[error("failed to decrypt frame")]
+    Decryption(#[from] snow::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -58,4 +378,151 @@ pub enum VsockSendError {
     Io(#[from] std::io::Error),
     #[error("failed to serialize cbor")]
     Serialization(#[from] serde_cbor::Error),
+    #[error("failed to encrypt frame")]
+    Encryption(#[from] snow::Error),
+    #[error("failed to seal response")]
+    Sealing(#[from] SealedBoxError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum VsockChannelError {
+    #[error("noise handshake failed: {0}")]
+    Noise(#[from] snow::Error),
+    #[error(transparent)]
+    Receive(#[from] VsockReceiveError),
+    #[error(transparent)]
+    Send(#[from] VsockSendError),
+    #[error("enclave rejected the handshake: {0}")]
+    Rejected(#[from] VsockEnclaveSignError),
+    #[error("peer did not send a static key")]
+    MissingRemoteStatic,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttestationError {
+    #[error("invalid COSE_Sign1: {0}")]
+    InvalidCose(String),
+    #[error("attestation document is not signed with ES384")]
+    UnsupportedAlgorithm,
+    #[error("invalid attestation document: {0}")]
+    InvalidDocument(String),
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("certificate chain does not start at the trusted root")]
+    UntrustedRoot,
+    #[error("certificate chain is broken at depth {0}")]
+    InvalidChain(usize),
+    #[error("certificate at depth {0} is not valid at the document's timestamp")]
+    CertificateExpired(usize),
+    #[error("attestation document signature is invalid")]
+    InvalidSignature,
+    #[error("PCR{0} does not match the expected value")]
+    PcrMismatch(usize),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SealedBoxError {
+    #[error("public key is not a valid x25519 key")]
+    InvalidPublicKey,
+    #[error("failed to get randomness: {0}")]
+    Randomness(String),
+    #[error("encryption operation failed")]
+    EncryptionFailed,
+    #[error("decryption operation failed")]
+    DecryptionFailed,
+    #[error("sealed request is not a valid request: {0}")]
+    InvalidRequest(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum NsmError {
+    #[error("failed to open /dev/nsm")]
+    Io(#[from] std::io::Error),
+    #[error("invalid nsm message: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("nsm returned an error: {0}")]
+    Nsm(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("a backup needs at least one recovery key")]
+    NoRecipients,
+    #[error("threshold {threshold} is not between 1 and {shares} shares")]
+    InvalidThreshold { threshold: u8, shares: usize },
+    #[error("only wallets with a quorum can be backed up or restored")]
+    QuorumRequired,
+    #[error("backup shares must be sent in a sealed request")]
+    NotSealed,
+    #[error("invalid backup share: {0}")]
+    InvalidShare(String),
+    #[error("backup shares are not from the same backup")]
+    MismatchedShares,
+    #[error("{shares} of {threshold} required shares")]
+    NotEnoughShares { threshold: u8, shares: usize },
+    #[error("failed to get randomness: {0}")]
+    Randomness(String),
+    #[error(transparent)]
+    Sealing(#[from] SealedBoxError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportKeyError {
+    #[error("raw keys must be sent in a sealed request")]
+    NotSealed,
+    #[error("invalid {scheme:?} key: {reason}")]
+    InvalidKey {
+        scheme: SignatureSchemeKind,
+        reason: String,
+    },
+    #[error("imported keys can only sign with Sign and SignBatch")]
+    SeedRequired,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FrostError {
+    #[error("invalid parameters: {0}")]
+    InvalidParameters(String),
+    #[error("no dkg session with this id")]
+    UnknownSession,
+    #[error("a dkg session with this id already exists")]
+    SessionExists,
+    #[error("invalid package from participant {0}: {1}")]
+    InvalidPackage(u16, String),
+    #[error("peer attestation is not configured, see --peer-pcr")]
+    PeerAttestationRequired,
+    #[error("invalid commitment: {0}")]
+    InvalidCommitment(String),
+    #[error("no nonces for this commitment, they are single use")]
+    UnknownCommitment,
+    #[error("invalid signature share from participant {0}")]
+    InvalidSignatureShare(u16),
+    #[error("signing share does not match the key share")]
+    InvalidKeyShare,
+    #[error("failed to get randomness: {0}")]
+    Randomness(String),
+    #[error(transparent)]
+    Sealing(#[from] SealedBoxError),
+    #[error(transparent)]
+    Send(#[from] VsockSendError),
+    #[error(transparent)]
+    Receive(#[from] VsockReceiveError),
+    #[error("enclave {0} failed: {1}")]
+    Enclave(u16, VsockEnclaveSignError),
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum AuditError {
+    #[error("event {index} is not signed by a trusted enclave key")]
+    UntrustedKey { index: usize },
+    #[error("event {index} has an invalid signature")]
+    InvalidSignature { index: usize },
+    #[error("event {index} has sequence {found}, not after {previous}")]
+    OutOfOrder {
+        index: usize,
+        previous: u64,
+        found: u64,
+    },
+    #[error("event {index} does not follow on from the previous event")]
+    BrokenChain { index: usize },
 }
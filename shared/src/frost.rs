@@ -0,0 +1,470 @@
+//! FROST(Ed25519, SHA-512) threshold signing as in RFC 9591, with keys from a
+//! Pedersen DKG run between enclaves. Aggregated signatures are plain Ed25519
+//! signatures under the group public key.
+//!
+//! The enclaves hold the secret halves of both protocols; this module has the
+//! shared arithmetic, the wire types and the coordinator the host runs.
+
+use curve25519_dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use curve25519_dalek::scalar::Scalar;
+use curve25519_dalek::traits::{Identity, IsIdentity};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha512};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::approval::{Approval, Quorum};
+use crate::error::{FrostError, VsockEnclaveSignError, VsockReceiveError};
+use crate::policy::SignedPolicy;
+use crate::sealed::SealedBox;
+use crate::transport::{
+    KmsCredentials, VsockEnclaveResponseTrailer, VsockHostRequest, VsockTransport,
+};
+
+const CONTEXT_STRING: &[u8] = b"FROST-ED25519-SHA512-v1";
+
+/// HKDF info for DKG shares sealed from one enclave to another.
+pub const FROST_SHARE_INFO: &[u8] = b"frost dkg share";
+
+/// A participant's public DKG commitment, broadcast to all others.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrostRound1Package {
+    pub identifier: u16,
+    /// Commitments to the participant's secret polynomial, constant term first.
+    pub commitment: Vec<[u8; 32]>,
+    /// Schnorr proof of knowledge of the constant term, `R` then `z`.
+    pub proof_r: [u8; 32],
+    pub proof_z: [u8; 32],
+    /// Sealing key the other participants encrypt their shares for it to.
+    pub sealing_key: [u8; 32],
+    /// NSM attestation document over `sealing_key`, empty outside an enclave.
+    #[serde(with = "serde_bytes")]
+    pub attestation: Vec<u8>,
+}
+
+/// A secret share of `sender`'s polynomial, sealed to `recipient`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrostRound2Package {
+    pub sender: u16,
+    pub recipient: u16,
+    pub sealed_share: SealedBox,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrostVerifyingShare {
+    pub identifier: u16,
+    pub public_key: [u8; 32],
+}
+
+/// One participant's signing share, encrypted like a wallet secret. The
+/// public fields are bound to the ciphertext as associated data.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrostKeyShare {
+    pub identifier: u16,
+    pub threshold: u8,
+    /// Ed25519 public key signatures verify under.
+    pub group_public_key: [u8; 32],
+    pub verifying_shares: Vec<FrostVerifyingShare>,
+    pub encrypted_signing_share: Vec<u8>,
+    pub aes_gcm_nonce: [u8; 12],
+    pub kms_ciphertext: Vec<u8>,
+    pub kms_key_id: String,
+    /// Checked before every signature share, like a wallet's.
+    #[serde(default)]
+    pub policy: Option<SignedPolicy>,
+    /// Approvers whose signatures every signature share needs.
+    #[serde(default)]
+    pub quorum: Option<Quorum>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FrostSigningCommitment {
+    pub identifier: u16,
+    pub hiding: [u8; 32],
+    pub binding: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FrostSignatureShare {
+    pub identifier: u16,
+    pub share: [u8; 32],
+}
+
+fn hash_to_scalar(parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    return Scalar::from_bytes_mod_order_wide(&hasher.finalize().into());
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    return hasher.finalize().into();
+}
+
+pub fn identifier_scalar(identifier: u16) -> Result<Scalar, FrostError> {
+    if identifier == 0 {
+        return Err(FrostError::InvalidParameters(
+            "identifiers start at 1".to_string(),
+        ));
+    }
+    return Ok(Scalar::from(identifier));
+}
+
+/// RFC 9591 `DeserializeElement`: rejects the identity and points outside the
+/// prime order subgroup.
+pub fn decompress(point: &[u8; 32]) -> Result<EdwardsPoint, FrostError> {
+    return CompressedEdwardsY(*point)
+        .decompress()
+        .filter(|point| !point.is_identity() && point.is_torsion_free())
+        .ok_or_else(|| FrostError::InvalidCommitment("not a valid group element".to_string()));
+}
+
+pub fn scalar(bytes: &[u8; 32]) -> Result<Scalar, FrostError> {
+    return Option::from(Scalar::from_canonical_bytes(*bytes))
+        .ok_or_else(|| FrostError::InvalidCommitment("not a canonical scalar".to_string()));
+}
+
+/// RFC 9591 `nonce_generate`, hedging the randomness with the signing share.
+pub fn nonce_generate(random_bytes: &[u8; 32], signing_share: &Scalar) -> Scalar {
+    return hash_to_scalar(&[
+        CONTEXT_STRING,
+        b"nonce",
+        random_bytes,
+        signing_share.as_bytes(),
+    ]);
+}
+
+/// Challenge of the proof of knowledge in DKG round one.
+pub fn dkg_challenge(identifier: u16, verifying_key: &[u8; 32], r: &[u8; 32]) -> Scalar {
+    return hash_to_scalar(&[
+        CONTEXT_STRING,
+        b"dkg",
+        Scalar::from(identifier).as_bytes(),
+        verifying_key,
+        r,
+    ]);
+}
+
+/// Evaluates a committed polynomial at `identifier`, in the exponent.
+pub fn evaluate_commitment(
+    commitment: &[EdwardsPoint],
+    identifier: u16,
+) -> Result<EdwardsPoint, FrostError> {
+    let x = identifier_scalar(identifier)?;
+    let mut result = EdwardsPoint::identity();
+    for coefficient in commitment.iter().rev() {
+        result = result * x + coefficient;
+    }
+    return Ok(result);
+}
+
+pub fn lagrange_coefficient(identifier: u16, signers: &[u16]) -> Result<Scalar, FrostError> {
+    let x = identifier_scalar(identifier)?;
+    let mut numerator = Scalar::ONE;
+    let mut denominator = Scalar::ONE;
+    for signer in signers.iter().filter(|signer| **signer != identifier) {
+        let other = identifier_scalar(*signer)?;
+        numerator *= other;
+        denominator *= other - x;
+    }
+    return Ok(numerator * denominator.invert());
+}
+
+/// Per-signer binding factors, in the order of `commitments`, which must be
+/// sorted by identifier without duplicates.
+pub fn binding_factors(
+    group_public_key: &[u8; 32],
+    commitments: &[FrostSigningCommitment],
+    message: &[u8],
+) -> Result<Vec<Scalar>, FrostError> {
+    if commitments
+        .windows(2)
+        .any(|pair| pair[0].identifier >= pair[1].identifier)
+    {
+        return Err(FrostError::InvalidCommitment(
+            "commitments must be sorted by identifier without duplicates".to_string(),
+        ));
+    }
+
+    let mut encoded_commitments = Vec::with_capacity(commitments.len() * 96);
+    for commitment in commitments {
+        encoded_commitments.extend_from_slice(identifier_scalar(commitment.identifier)?.as_bytes());
+        encoded_commitments.extend_from_slice(&commitment.hiding);
+        encoded_commitments.extend_from_slice(&commitment.binding);
+    }
+    let message_hash = hash(&[CONTEXT_STRING, b"msg", message]);
+    let commitments_hash = hash(&[CONTEXT_STRING, b"com", &encoded_commitments]);
+
+    return Ok(commitments
+        .iter()
+        .map(|commitment| {
+            hash_to_scalar(&[
+                CONTEXT_STRING,
+                b"rho",
+                group_public_key,
+                &message_hash,
+                &commitments_hash,
+                Scalar::from(commitment.identifier).as_bytes(),
+            ])
+        })
+        .collect());
+}
+
+pub fn group_commitment(
+    commitments: &[FrostSigningCommitment],
+    binding_factors: &[Scalar],
+) -> Result<EdwardsPoint, FrostError> {
+    let mut group_commitment = EdwardsPoint::identity();
+    for (commitment, binding_factor) in commitments.iter().zip(binding_factors) {
+        group_commitment +=
+            decompress(&commitment.hiding)? + decompress(&commitment.binding)? * binding_factor;
+    }
+    return Ok(group_commitment);
+}
+
+/// The Ed25519 challenge, so aggregated signatures verify as Ed25519.
+pub fn challenge(
+    group_commitment: &EdwardsPoint,
+    group_public_key: &[u8; 32],
+    message: &[u8],
+) -> Scalar {
+    return hash_to_scalar(&[
+        group_commitment.compress().as_bytes(),
+        group_public_key,
+        message,
+    ]);
+}
+
+/// Sums signature shares into a 64 byte Ed25519 signature, checking every
+/// share against its verifying share so a misbehaving signer is identified.
+pub fn aggregate(
+    key_share: &FrostKeyShare,
+    message: &[u8],
+    commitments: &[FrostSigningCommitment],
+    signature_shares: &[FrostSignatureShare],
+) -> Result<Vec<u8>, FrostError> {
+    let binding_factors = binding_factors(&key_share.group_public_key, commitments, message)?;
+    let group_commitment = group_commitment(commitments, &binding_factors)?;
+    let challenge = challenge(&group_commitment, &key_share.group_public_key, message);
+    let signers: Vec<u16> = commitments
+        .iter()
+        .map(|commitment| commitment.identifier)
+        .collect();
+
+    let mut z = Scalar::ZERO;
+    for (commitment, binding_factor) in commitments.iter().zip(&binding_factors) {
+        let identifier = commitment.identifier;
+        let share = signature_shares
+            .iter()
+            .find(|share| share.identifier == identifier)
+            .ok_or(FrostError::InvalidSignatureShare(identifier))?;
+        let share =
+            scalar(&share.share).map_err(|_| FrostError::InvalidSignatureShare(identifier))?;
+        let verifying_share = key_share
+            .verifying_shares
+            .iter()
+            .find(|verifying_share| verifying_share.identifier == identifier)
+            .ok_or(FrostError::InvalidSignatureShare(identifier))?;
+
+        let commitment_share =
+            decompress(&commitment.hiding)? + decompress(&commitment.binding)? * binding_factor;
+        let expected = commitment_share
+            + decompress(&verifying_share.public_key)?
+                * (challenge * lagrange_coefficient(identifier, &signers)?);
+        if EdwardsPoint::mul_base(&share) != expected {
+            return Err(FrostError::InvalidSignatureShare(identifier));
+        }
+        z += share;
+    }
+
+    return Ok([group_commitment.compress().to_bytes(), z.to_bytes()].concat());
+}
+
+/// Sends `request` and reads both the response and the trailer that follows
+/// it, which is handed to `on_trailer` whether or not the request succeeded.
+async fn call<S, T, R>(
+    transport: &mut VsockTransport<S>,
+    identifier: u16,
+    request: &VsockHostRequest,
+    on_trailer: &mut R,
+) -> Result<T, FrostError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: for<'de> Deserialize<'de>,
+    R: FnMut(&VsockHostRequest, Result<VsockEnclaveResponseTrailer, VsockReceiveError>),
+{
+    transport.send(request).await?;
+    let response = transport
+        .receive::<Result<T, VsockEnclaveSignError>>()
+        .await?;
+    on_trailer(
+        request,
+        transport.receive::<VsockEnclaveResponseTrailer>().await,
+    );
+    return response.map_err(|e| FrostError::Enclave(identifier, e));
+}
+
+/// Runs the DKG between the enclaves `connect` reaches for identifiers 1 to
+/// `kms_key_ids.len()`. Each enclave seals its share under its own KMS key,
+/// bound to `policy` and `quorum`. The trailer of every response is passed to
+/// `on_trailer`.
+pub async fn generate_key<S, C, R>(
+    mut connect: C,
+    mut on_trailer: R,
+    threshold: u8,
+    credentials: &KmsCredentials,
+    kms_key_ids: &[String],
+    policy: Option<&SignedPolicy>,
+    quorum: Option<&Quorum>,
+) -> Result<Vec<FrostKeyShare>, FrostError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: AsyncFnMut(u16) -> VsockTransport<S>,
+    R: FnMut(&VsockHostRequest, Result<VsockEnclaveResponseTrailer, VsockReceiveError>),
+{
+    let participants = u8::try_from(kms_key_ids.len())
+        .map_err(|_| FrostError::InvalidParameters("too many participants".to_string()))?;
+    let mut session_id = [0u8; 16];
+    getrandom::getrandom(&mut session_id).map_err(|e| FrostError::Randomness(e.to_string()))?;
+    let identifiers: Vec<u16> = (1..=u16::from(participants)).collect();
+
+    let mut round1_packages = Vec::with_capacity(identifiers.len());
+    for identifier in &identifiers {
+        let request = VsockHostRequest::FrostDkgPart1 {
+            session_id,
+            identifier: *identifier,
+            threshold,
+            participants,
+        };
+        let package: FrostRound1Package = call(
+            &mut connect(*identifier).await,
+            *identifier,
+            &request,
+            &mut on_trailer,
+        )
+        .await?;
+        round1_packages.push(package);
+    }
+
+    let mut round2_packages = Vec::new();
+    for identifier in &identifiers {
+        let request = VsockHostRequest::FrostDkgPart2 {
+            session_id,
+            round1_packages: round1_packages.clone(),
+        };
+        let packages: Vec<FrostRound2Package> = call(
+            &mut connect(*identifier).await,
+            *identifier,
+            &request,
+            &mut on_trailer,
+        )
+        .await?;
+        round2_packages.extend(packages);
+    }
+
+    let mut key_shares = Vec::with_capacity(identifiers.len());
+    for (identifier, kms_key_id) in identifiers.iter().zip(kms_key_ids) {
+        let request = VsockHostRequest::FrostDkgPart3 {
+            session_id,
+            credentials: credentials.clone(),
+            kms_key_id: kms_key_id.clone(),
+            round2_packages: round2_packages
+                .iter()
+                .filter(|package| package.recipient == *identifier)
+                .cloned()
+                .collect(),
+            policy: policy.cloned(),
+            quorum: quorum.cloned(),
+        };
+        let key_share: FrostKeyShare = call(
+            &mut connect(*identifier).await,
+            *identifier,
+            &request,
+            &mut on_trailer,
+        )
+        .await?;
+        key_shares.push(key_share);
+    }
+
+    if key_shares
+        .windows(2)
+        .any(|pair| pair[0].group_public_key != pair[1].group_public_key)
+    {
+        return Err(FrostError::InvalidParameters(
+            "participants derived different group keys".to_string(),
+        ));
+    }
+    return Ok(key_shares);
+}
+
+/// Signs `message` with the enclaves holding `key_shares`, at least the
+/// threshold of them, and returns the aggregated Ed25519 signature.
+/// `approvals` must meet the key's quorum, if it has one. The trailer of every
+/// response is passed to `on_trailer`.
+pub async fn sign<S, C, R>(
+    mut connect: C,
+    mut on_trailer: R,
+    key_shares: &[FrostKeyShare],
+    credentials: &KmsCredentials,
+    message: &[u8],
+    approvals: &[Approval],
+) -> Result<Vec<u8>, FrostError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    C: AsyncFnMut(u16) -> VsockTransport<S>,
+    R: FnMut(&VsockHostRequest, Result<VsockEnclaveResponseTrailer, VsockReceiveError>),
+{
+    let mut key_shares = key_shares.to_vec();
+    key_shares.sort_by_key(|key_share| key_share.identifier);
+    let Some(first) = key_shares.first() else {
+        return Err(FrostError::InvalidParameters("no signers".to_string()));
+    };
+    if key_shares.len() < usize::from(first.threshold) {
+        return Err(FrostError::InvalidParameters(format!(
+            "{} signers are fewer than the threshold of {}",
+            key_shares.len(),
+            first.threshold
+        )));
+    }
+
+    let mut commitments = Vec::with_capacity(key_shares.len());
+    for key_share in &key_shares {
+        let request = VsockHostRequest::FrostCommit {
+            credentials: credentials.clone(),
+            key_share: key_share.clone(),
+        };
+        let commitment: FrostSigningCommitment = call(
+            &mut connect(key_share.identifier).await,
+            key_share.identifier,
+            &request,
+            &mut on_trailer,
+        )
+        .await?;
+        commitments.push(commitment);
+    }
+
+    let mut signature_shares = Vec::with_capacity(key_shares.len());
+    for key_share in &key_shares {
+        let request = VsockHostRequest::FrostSign {
+            credentials: credentials.clone(),
+            key_share: key_share.clone(),
+            message: message.to_vec(),
+            commitments: commitments.clone(),
+            approvals: approvals.to_vec(),
+        };
+        let share: FrostSignatureShare = call(
+            &mut connect(key_share.identifier).await,
+            key_share.identifier,
+            &request,
+            &mut on_trailer,
+        )
+        .await?;
+        signature_shares.push(share);
+    }
+
+    return aggregate(first, message, &commitments, &signature_shares);
+}
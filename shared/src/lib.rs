@@ -1,2 +1,10 @@
+pub mod approval;
+pub mod attestation;
+pub mod audit;
+pub mod backup;
 pub mod error;
+pub mod frost;
+pub mod policy;
+pub mod sealed;
+pub mod slip39;
 pub mod transport;
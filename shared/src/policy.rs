@@ -0,0 +1,111 @@
+use crate::transport::SignatureScheme;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A [`Policy`] signed by the enclave's policy admin key. The SHA-256 of
+/// `policy` is used as AES-GCM associated data for the wallet's encrypted
+/// secret, so the host can neither strip nor swap it without breaking decryption.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SignedPolicy {
+    /// CBOR encoded [`Policy`], signed exactly as stored.
+    #[serde(with = "serde_bytes")]
+    pub policy: Vec<u8>,
+    /// Ed25519 signature over `policy`.
+    #[serde(with = "serde_bytes")]
+    pub signature: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Policy {
+    /// Must increase every time a wallet's policy is replaced.
+    pub version: u64,
+    /// The [`crate::transport::VsockEnclaveCreateWalletData::wallet_id`] this
+    /// policy is for. Required to replace a wallet's policy; only a policy
+    /// given when a wallet is created or imported may leave it unset.
+    #[serde(default)]
+    pub wallet: Option<[u8; 32]>,
+    /// `None` allows every scheme.
+    pub allowed_schemes: Option<Vec<SignatureSchemeKind>>,
+    /// Allows requests whose effect the enclave cannot decode: raw `Sign` and
+    /// `SignBatch` digests, messages, EVM contract calls and creations, Cardano
+    /// transactions that move more than lovelace, and Solana transactions that
+    /// are not plain system transfers.
+    pub allow_opaque: bool,
+    /// Chains that transactions may be signed for; anything else is denied.
+    pub chains: Vec<ChainPolicy>,
+    /// UTC windows in which signing is allowed. Empty means at any time.
+    pub time_windows: Vec<TimeWindow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SignatureSchemeKind {
+    Secp256k1,
+    Secp256k1Schnorr,
+    Ed25519,
+}
+
+impl From<&SignatureScheme> for SignatureSchemeKind {
+    fn from(scheme: &SignatureScheme) -> Self {
+        match scheme {
+            SignatureScheme::Secp256k1 => return SignatureSchemeKind::Secp256k1,
+            SignatureScheme::Secp256k1Schnorr { .. } => {
+                return SignatureSchemeKind::Secp256k1Schnorr;
+            }
+            SignatureScheme::Ed25519 => return SignatureSchemeKind::Ed25519,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Chain {
+    Bitcoin,
+    Ethereum { chain_id: u64 },
+    Cardano,
+    Solana,
+}
+
+/// Values are in the chain's base unit (satoshi, wei, lovelace, lamport).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ChainPolicy {
+    pub chain: Chain,
+    /// Allowed destinations: output scripts for Bitcoin, 20 byte addresses for
+    /// Ethereum, raw address bytes for Cardano and 32 byte keys for Solana.
+    /// `None` allows any destination.
+    pub destination_allowlist: Option<Vec<serde_bytes::ByteBuf>>,
+    pub max_value_per_tx: Option<Amount>,
+    pub rolling_limit: Option<RollingLimit>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RollingLimit {
+    pub window_secs: u64,
+    pub max_value: Amount,
+}
+
+/// Minutes after UTC midnight, `end_minute` exclusive. A window with
+/// `start_minute > end_minute` wraps around midnight.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    pub start_minute: u16,
+    pub end_minute: u16,
+}
+
+/// A `u128` encoded as 16 big endian bytes, since CBOR integers stop at 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Amount(pub u128);
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return serializer.serialize_bytes(&self.0.to_be_bytes());
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = serde_bytes::ByteBuf::deserialize(deserializer)?;
+        let bytes: [u8; 16] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| serde::de::Error::invalid_length(bytes.len(), &"16 bytes"))?;
+        return Ok(Amount(u128::from_be_bytes(bytes)));
+    }
+}
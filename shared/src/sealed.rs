@@ -0,0 +1,211 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::error::SealedBoxError;
+
+const REQUEST_INFO: &[u8] = b"sealed request";
+const RESPONSE_INFO: &[u8] = b"sealed response";
+
+/// A message encrypted to an X25519 public key, readable only by its holder.
+///
+/// The key comes from X25519 between a fresh ephemeral key and the recipient
+/// key, run through HKDF-SHA256 with a purpose specific `info`. Each key
+/// encrypts exactly one message with ChaCha20-Poly1305, so the nonce is always zero.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SealedBox {
+    pub ephemeral_public_key: [u8; 32],
+    #[serde(with = "serde_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// A request sealed by a client directly to the enclave's attested sealing
+/// key, so the host relaying it only ever sees ciphertext.
+pub type SealedRequest = SealedBox;
+
+/// Key for the single response to a [`SealedRequest`]. Only the client that
+/// sealed the request and the enclave can derive it.
+pub struct ResponseKey(Zeroizing<[u8; 32]>);
+
+impl ResponseKey {
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, SealedBoxError> {
+        return encrypt(&self.0, plaintext);
+    }
+
+    pub fn open(&self, ciphertext: &[u8]) -> Result<Vec<u8>, SealedBoxError> {
+        return decrypt(&self.0, ciphertext);
+    }
+}
+
+pub fn seal(
+    recipient_public_key: &[u8; 32],
+    info: &[u8],
+    plaintext: &[u8],
+) -> Result<SealedBox, SealedBoxError> {
+    let (ephemeral_secret, ephemeral_public_key) = ephemeral_key()?;
+    let key = derive_key(
+        &ephemeral_secret,
+        &PublicKey::from(*recipient_public_key),
+        &ephemeral_public_key,
+        recipient_public_key,
+        info,
+    )?;
+    return Ok(SealedBox {
+        ephemeral_public_key,
+        ciphertext: encrypt(&key, plaintext)?,
+    });
+}
+
+pub fn open(
+    secret: &StaticSecret,
+    info: &[u8],
+    sealed_box: &SealedBox,
+) -> Result<Zeroizing<Vec<u8>>, SealedBoxError> {
+    let key = derive_key(
+        secret,
+        &PublicKey::from(sealed_box.ephemeral_public_key),
+        &sealed_box.ephemeral_public_key,
+        &PublicKey::from(secret).to_bytes(),
+        info,
+    )?;
+    return Ok(Zeroizing::new(decrypt(&key, &sealed_box.ciphertext)?));
+}
+
+/// Client side: encrypts `plaintext` to `enclave_public_key`.
+pub fn seal_request(
+    enclave_public_key: &[u8; 32],
+    plaintext: &[u8],
+) -> Result<(SealedRequest, ResponseKey), SealedBoxError> {
+    let (ephemeral_secret, ephemeral_public_key) = ephemeral_key()?;
+    let enclave_key = PublicKey::from(*enclave_public_key);
+    let request_key = derive_key(
+        &ephemeral_secret,
+        &enclave_key,
+        &ephemeral_public_key,
+        enclave_public_key,
+        REQUEST_INFO,
+    )?;
+    let response_key = derive_key(
+        &ephemeral_secret,
+        &enclave_key,
+        &ephemeral_public_key,
+        enclave_public_key,
+        RESPONSE_INFO,
+    )?;
+
+    let sealed_request = SealedRequest {
+        ephemeral_public_key,
+        ciphertext: encrypt(&request_key, plaintext)?,
+    };
+    return Ok((sealed_request, ResponseKey(response_key)));
+}
+
+/// Enclave side: decrypts a request sealed to `secret`.
+pub fn open_request(
+    secret: &StaticSecret,
+    sealed_request: &SealedRequest,
+) -> Result<(Zeroizing<Vec<u8>>, ResponseKey), SealedBoxError> {
+    let plaintext = open(secret, REQUEST_INFO, sealed_request)?;
+    let response_key = derive_key(
+        secret,
+        &PublicKey::from(sealed_request.ephemeral_public_key),
+        &sealed_request.ephemeral_public_key,
+        &PublicKey::from(secret).to_bytes(),
+        RESPONSE_INFO,
+    )?;
+    return Ok((plaintext, ResponseKey(response_key)));
+}
+
+fn ephemeral_key() -> Result<(StaticSecret, [u8; 32]), SealedBoxError> {
+    let mut ephemeral_secret = Zeroizing::new([0u8; 32]);
+    getrandom::getrandom(ephemeral_secret.as_mut())
+        .map_err(|e| SealedBoxError::Randomness(e.to_string()))?;
+    let ephemeral_secret = StaticSecret::from(*ephemeral_secret);
+    let ephemeral_public_key = PublicKey::from(&ephemeral_secret).to_bytes();
+    return Ok((ephemeral_secret, ephemeral_public_key));
+}
+
+fn derive_key(
+    secret: &StaticSecret,
+    their_public_key: &PublicKey,
+    ephemeral_public_key: &[u8; 32],
+    recipient_public_key: &[u8; 32],
+    info: &[u8],
+) -> Result<Zeroizing<[u8; 32]>, SealedBoxError> {
+    let shared_secret = secret.diffie_hellman(their_public_key);
+    if !shared_secret.was_contributory() {
+        return Err(SealedBoxError::InvalidPublicKey);
+    }
+
+    let salt = [ephemeral_public_key.as_slice(), recipient_public_key].concat();
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes())
+        .expand(info, key.as_mut())
+        .expect("32 bytes is a valid hkdf output length");
+    return Ok(key);
+}
+
+fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>, SealedBoxError> {
+    return ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt(&Nonce::default(), plaintext)
+        .map_err(|_| SealedBoxError::EncryptionFailed);
+}
+
+fn decrypt(key: &[u8; 32], ciphertext: &[u8]) -> Result<Vec<u8>, SealedBoxError> {
+    return ChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(&Nonce::default(), ciphertext)
+        .map_err(|_| SealedBoxError::DecryptionFailed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sealed_round_trip() {
+        let enclave_secret = StaticSecret::from([5u8; 32]);
+        let enclave_public_key = PublicKey::from(&enclave_secret).to_bytes();
+
+        let (sealed_request, client_response_key) =
+            seal_request(&enclave_public_key, b"request").unwrap();
+        let (plaintext, enclave_response_key) =
+            open_request(&enclave_secret, &sealed_request).unwrap();
+        assert_eq!(plaintext.as_slice(), b"request");
+
+        let response = enclave_response_key.seal(b"response").unwrap();
+        assert_eq!(client_response_key.open(&response).unwrap(), b"response");
+        // the request key cannot be used to read the response and vice versa
+        assert!(
+            client_response_key
+                .open(&sealed_request.ciphertext)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_tampering_and_low_order_keys() {
+        let enclave_secret = StaticSecret::from([5u8; 32]);
+        let enclave_public_key = PublicKey::from(&enclave_secret).to_bytes();
+        let (mut sealed_request, _) = seal_request(&enclave_public_key, b"request").unwrap();
+
+        sealed_request.ciphertext[0] ^= 1;
+        assert!(matches!(
+            open_request(&enclave_secret, &sealed_request),
+            Err(SealedBoxError::DecryptionFailed)
+        ));
+
+        sealed_request.ephemeral_public_key = [0u8; 32];
+        assert!(matches!(
+            open_request(&enclave_secret, &sealed_request),
+            Err(SealedBoxError::InvalidPublicKey)
+        ));
+        assert!(matches!(
+            seal_request(&[0u8; 32], b"request"),
+            Err(SealedBoxError::InvalidPublicKey)
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+//! SLIP-39 share encoding. Shares are kept as indices into the 1024 word
+//! SLIP-39 wordlist, which custodian tooling turns into mnemonics and back.
+
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::error::BackupError;
+use crate::transport::REDACTED;
+
+const RADIX_BITS: usize = 10;
+const METADATA_WORDS: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+const MIN_SHARE_WORDS: usize = METADATA_WORDS + CHECKSUM_WORDS + 13;
+
+const CUSTOMIZATION_STRING: &[u8] = b"shamir";
+const CUSTOMIZATION_STRING_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+/// One share of a SLIP-39 encrypted master secret.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Slip39Share {
+    /// 15 bit identifier common to all shares of a secret.
+    pub identifier: u16,
+    pub extendable: bool,
+    pub iteration_exponent: u8,
+    pub group_index: u8,
+    pub group_threshold: u8,
+    pub group_count: u8,
+    pub member_index: u8,
+    pub member_threshold: u8,
+    #[serde(with = "serde_bytes")]
+    pub value: Vec<u8>,
+}
+
+impl std::fmt::Debug for Slip39Share {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("Slip39Share")
+            .field("identifier", &self.identifier)
+            .field("extendable", &self.extendable)
+            .field("iteration_exponent", &self.iteration_exponent)
+            .field("group_index", &self.group_index)
+            .field("group_threshold", &self.group_threshold)
+            .field("group_count", &self.group_count)
+            .field("member_index", &self.member_index)
+            .field("member_threshold", &self.member_threshold)
+            .field("value", &REDACTED)
+            .finish();
+    }
+}
+
+impl Drop for Slip39Share {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl Slip39Share {
+    /// Word indices of the share's mnemonic, checksum included.
+    pub fn to_indices(&self) -> Vec<u16> {
+        let id_exp = (u32::from(self.identifier) << 5)
+            | (u32::from(self.extendable) << 4)
+            | u32::from(self.iteration_exponent);
+        let params = (u32::from(self.group_index) << 16)
+            | (u32::from(self.group_threshold - 1) << 12)
+            | (u32::from(self.group_count - 1) << 8)
+            | (u32::from(self.member_index) << 4)
+            | u32::from(self.member_threshold - 1);
+
+        let mut indices = vec![
+            (id_exp >> 10) as u16,
+            (id_exp & 1023) as u16,
+            (params >> 10) as u16,
+            (params & 1023) as u16,
+        ];
+        indices.extend(bytes_to_indices(&self.value));
+        indices.extend(create_checksum(&indices, self.extendable));
+        return indices;
+    }
+
+    pub fn from_indices(indices: &[u16]) -> Result<Self, BackupError> {
+        if indices.len() < MIN_SHARE_WORDS {
+            return Err(BackupError::InvalidShare("share is too short".to_string()));
+        }
+        if indices.iter().any(|index| *index >= 1 << RADIX_BITS) {
+            return Err(BackupError::InvalidShare("invalid word index".to_string()));
+        }
+
+        let id_exp = (u32::from(indices[0]) << 10) | u32::from(indices[1]);
+        let extendable = (id_exp >> 4) & 1 == 1;
+        if polymod(customization_string(extendable), indices) != 1 {
+            return Err(BackupError::InvalidShare("invalid checksum".to_string()));
+        }
+
+        let params = (u32::from(indices[2]) << 10) | u32::from(indices[3]);
+        let share = Slip39Share {
+            identifier: (id_exp >> 5) as u16,
+            extendable,
+            iteration_exponent: (id_exp & 15) as u8,
+            group_index: (params >> 16) as u8,
+            group_threshold: ((params >> 12) & 15) as u8 + 1,
+            group_count: ((params >> 8) & 15) as u8 + 1,
+            member_index: ((params >> 4) & 15) as u8,
+            member_threshold: (params & 15) as u8 + 1,
+            value: indices_to_bytes(&indices[METADATA_WORDS..indices.len() - CHECKSUM_WORDS])?,
+        };
+        if share.group_threshold > share.group_count {
+            return Err(BackupError::InvalidShare(
+                "group threshold exceeds group count".to_string(),
+            ));
+        }
+        return Ok(share);
+    }
+}
+
+/// Packs `bytes` big endian into 10 bit words, zero padded at the front.
+fn bytes_to_indices(bytes: &[u8]) -> Vec<u16> {
+    let word_count = (bytes.len() * 8).div_ceil(RADIX_BITS);
+    let mut indices = Vec::with_capacity(word_count);
+    let mut accumulator = 0u32;
+    let mut bits = word_count * RADIX_BITS - bytes.len() * 8;
+    for byte in bytes {
+        accumulator = (accumulator << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= RADIX_BITS {
+            bits -= RADIX_BITS;
+            indices.push(((accumulator >> bits) & 1023) as u16);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    return indices;
+}
+
+fn indices_to_bytes(indices: &[u16]) -> Result<Vec<u8>, BackupError> {
+    let padding = (indices.len() * RADIX_BITS) % 16;
+    if padding > 8 {
+        return Err(BackupError::InvalidShare(
+            "invalid share length".to_string(),
+        ));
+    }
+
+    let mut bytes = Vec::with_capacity((indices.len() * RADIX_BITS - padding) / 8);
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    let mut skip = padding;
+    for index in indices {
+        accumulator = (accumulator << RADIX_BITS) | u32::from(*index);
+        bits += RADIX_BITS;
+        if skip > 0 {
+            bits -= skip;
+            skip = 0;
+            if accumulator >> bits != 0 {
+                return Err(BackupError::InvalidShare("invalid padding".to_string()));
+            }
+        }
+        while bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+            accumulator &= (1 << bits) - 1;
+        }
+    }
+    return Ok(bytes);
+}
+
+fn customization_string(extendable: bool) -> &'static [u8] {
+    return match extendable {
+        true => CUSTOMIZATION_STRING_EXTENDABLE,
+        false => CUSTOMIZATION_STRING,
+    };
+}
+
+/// RS1024 over GF(1024), as in SLIP-39.
+fn polymod(customization: &[u8], values: &[u16]) -> u32 {
+    const GENERATOR: [u32; 10] = [
+        0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009, 0x1C0C2412, 0x38086C24, 0x3090FC48,
+        0x21B1F890, 0x3F3F120,
+    ];
+    let mut checksum = 1u32;
+    let values = customization
+        .iter()
+        .map(|byte| u32::from(*byte))
+        .chain(values.iter().map(|value| u32::from(*value)));
+    for value in values {
+        let top = checksum >> 20;
+        checksum = ((checksum & 0xFFFFF) << 10) ^ value;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    return checksum;
+}
+
+fn create_checksum(data: &[u16], extendable: bool) -> Vec<u16> {
+    let values = [data, &[0u16; CHECKSUM_WORDS]].concat();
+    let checksum = polymod(customization_string(extendable), &values) ^ 1;
+    return (0..CHECKSUM_WORDS)
+        .rev()
+        .map(|i| ((checksum >> (RADIX_BITS * i)) & 1023) as u16)
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share() -> Slip39Share {
+        return Slip39Share {
+            identifier: 0x5a5a,
+            extendable: true,
+            iteration_exponent: 1,
+            group_index: 0,
+            group_threshold: 1,
+            group_count: 1,
+            member_index: 3,
+            member_threshold: 2,
+            value: (0..64).collect(),
+        };
+    }
+
+    #[test]
+    fn test_share_indices_round_trip() {
+        let indices = share().to_indices();
+        // 52 value words for a 512 bit secret, plus metadata and checksum
+        assert_eq!(indices.len(), 59);
+        assert_eq!(Slip39Share::from_indices(&indices).unwrap(), share());
+
+        let mut short = share();
+        short.value = vec![7u8; 16];
+        short.extendable = false;
+        assert_eq!(short.to_indices().len(), 20);
+        assert_eq!(
+            Slip39Share::from_indices(&short.to_indices()).unwrap(),
+            short
+        );
+    }
+
+    #[test]
+    fn test_corrupted_share_is_rejected() {
+        let mut indices = share().to_indices();
+        indices[10] ^= 1;
+        assert!(Slip39Share::from_indices(&indices).is_err());
+
+        // the checksum is bound to the extendable flag
+        let mut indices = share().to_indices();
+        indices[1] ^= 1 << 4;
+        assert!(Slip39Share::from_indices(&indices).is_err());
+    }
+}
@@ -1,80 +1,894 @@
-use crate::error::{VsockEnclaveCreateWalletError, VsockReceiveError, VsockSendError};
+use crate::approval::{Approval, Quorum};
+use crate::audit::SignedAuditEvent;
+use crate::backup::{BackupSecret, WalletBackup};
+use crate::error::{
+    VsockChannelError, VsockEnclaveCreateWalletError, VsockEnclaveSignError, VsockReceiveError,
+    VsockSendError,
+};
+use crate::frost::{
+    FrostKeyShare, FrostRound1Package, FrostRound2Package, FrostSignatureShare,
+    FrostSigningCommitment,
+};
+use crate::policy::{SignatureSchemeKind, SignedPolicy};
+use crate::sealed::{ResponseKey, SealedRequest};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio_vsock::VsockStream;
+use zeroize::Zeroize;
 
-pub struct VsockTransport {
-    stream: VsockStream,
+/// Noise pattern for the host to enclave channel. Both sides send their static
+/// key, so the enclave learns which operator is calling and the operator can
+/// check the enclave's key against its attestation document.
+pub const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Printed in place of secrets by `Debug` impls, so requests can be logged.
+pub const REDACTED: &str = "[REDACTED]";
+
+const NOISE_MAX_MESSAGE_LEN: usize = 65535;
+const NOISE_TAG_LEN: usize = 16;
+
+pub struct VsockTransport<S = VsockStream> {
+    stream: S,
+    /// Set once a Noise handshake completes; every later frame is encrypted.
+    channel: Option<snow::TransportState>,
+    /// Seals the next message sent, see [`VsockHostRequest::Sealed`].
+    sealed_reply: Option<ResponseKey>,
 }
 
-impl VsockTransport {
-    pub fn new(stream: VsockStream) -> Self {
-        return Self { stream };
+impl<S: AsyncRead + AsyncWrite + Unpin> VsockTransport<S> {
+    pub fn new(stream: S) -> Self {
+        return Self {
+            stream,
+            channel: None,
+            sealed_reply: None,
+        };
+    }
+
+    /// Encrypts the next message sent with `response_key` and sends it as a
+    /// [`VsockEnclaveSealedResponse`] instead.
+    pub fn seal_next_reply(&mut self, response_key: ResponseKey) {
+        self.sealed_reply = Some(response_key);
     }
 
     pub async fn receive<T: for<'de> Deserialize<'de>>(&mut self) -> Result<T, VsockReceiveError> {
+        let frame = self.receive_frame().await?;
+        let cbor_bytes = match self.channel.as_mut() {
+            Some(channel) => decrypt_frame(channel, &frame)?,
+            None => frame,
+        };
+        let message: T = serde_cbor::from_slice(&cbor_bytes)?;
+        return Ok(message);
+    }
+
+    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<(), VsockSendError> {
+        let mut cbor_bytes = serde_cbor::to_vec(message)?;
+        if let Some(response_key) = self.sealed_reply.take() {
+            let response: VsockEnclaveSealedResponse = Ok(VsockEnclaveSealedData {
+                ciphertext: response_key.seal(&cbor_bytes)?,
+            });
+            cbor_bytes = serde_cbor::to_vec(&response)?;
+        }
+        let frame = match self.channel.as_mut() {
+            Some(channel) => encrypt_frame(channel, &cbor_bytes)?,
+            None => cbor_bytes,
+        };
+        return self.send_frame(&frame).await;
+    }
+
+    /// Runs the initiator side of the Noise handshake with `local_private_key`
+    /// and returns the enclave's static key together with the handshake payload
+    /// it sent, an attestation document over that key.
+    pub async fn initiate_handshake(
+        &mut self,
+        local_private_key: &[u8],
+    ) -> Result<([u8; 32], Vec<u8>), VsockChannelError> {
+        let mut handshake = snow::Builder::new(noise_params())
+            .local_private_key(local_private_key)
+            .build_initiator()?;
+
+        let mut message = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+        let len = handshake.write_message(&[], &mut message)?;
+        self.send(&VsockHostRequest::Handshake {
+            noise_message: message[..len].to_vec(),
+        })
+        .await?;
+
+        let response = self.receive::<VsockEnclaveHandshakeResponse>().await??;
+        let mut payload = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+        let payload_len = handshake.read_message(&response.noise_message, &mut payload)?;
+        payload.truncate(payload_len);
+        let enclave_key = remote_static(&handshake)?;
+
+        let len = handshake.write_message(&[], &mut message)?;
+        self.send_frame(&message[..len]).await?;
+        self.channel = Some(handshake.into_transport_mode()?);
+        return Ok((enclave_key, payload));
+    }
+
+    /// Completes the responder side of a handshake started by
+    /// [`VsockHostRequest::Handshake`], sending `payload` along with the
+    /// enclave's static key. Returns the caller's static key.
+    pub async fn accept_handshake(
+        &mut self,
+        local_private_key: &[u8],
+        noise_message: &[u8],
+        payload: &[u8],
+    ) -> Result<[u8; 32], VsockChannelError> {
+        let mut handshake = snow::Builder::new(noise_params())
+            .local_private_key(local_private_key)
+            .build_responder()?;
+
+        let mut buffer = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+        handshake.read_message(noise_message, &mut buffer)?;
+        let len = handshake.write_message(payload, &mut buffer)?;
+        let response: VsockEnclaveHandshakeResponse = Ok(VsockEnclaveHandshakeData {
+            noise_message: buffer[..len].to_vec(),
+        });
+        self.send(&response).await?;
+
+        let message = self.receive_frame().await?;
+        handshake.read_message(&message, &mut buffer)?;
+        let operator_key = remote_static(&handshake)?;
+        self.channel = Some(handshake.into_transport_mode()?);
+        return Ok(operator_key);
+    }
+
+    async fn receive_frame(&mut self) -> Result<Vec<u8>, VsockReceiveError> {
         let mut len_bytes = [0u8; 4];
         self.stream.read_exact(&mut len_bytes).await?;
         let len = u32::from_be_bytes(len_bytes) as usize;
         let mut buf = vec![0u8; len];
         self.stream.read_exact(&mut buf).await?;
-        let message: T = serde_cbor::from_slice(&buf)?;
-        return Ok(message);
+        return Ok(buf);
     }
 
-    pub async fn send<T: Serialize>(&mut self, message: &T) -> Result<(), VsockSendError> {
-        let cbor_bytes = serde_cbor::to_vec(message)?;
-        let len = cbor_bytes.len() as u32;
+    async fn send_frame(&mut self, frame: &[u8]) -> Result<(), VsockSendError> {
+        let len = frame.len() as u32;
         self.stream.write_all(&len.to_be_bytes()).await?;
-        self.stream.write_all(&cbor_bytes).await?;
+        self.stream.write_all(frame).await?;
         self.stream.flush().await?;
         return Ok(());
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+fn noise_params() -> snow::params::NoiseParams {
+    return NOISE_PARAMS.parse().expect("noise params are valid");
+}
+
+fn remote_static(handshake: &snow::HandshakeState) -> Result<[u8; 32], VsockChannelError> {
+    return handshake
+        .get_remote_static()
+        .and_then(|key| key.try_into().ok())
+        .ok_or(VsockChannelError::MissingRemoteStatic);
+}
+
+/// Noise messages are capped at 64 KiB, so larger payloads are encrypted in
+/// chunks. Every chunk but the last is exactly `NOISE_MAX_MESSAGE_LEN` long.
+fn encrypt_frame(
+    channel: &mut snow::TransportState,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, VsockSendError> {
+    let mut frame = Vec::with_capacity(plaintext.len() + NOISE_TAG_LEN);
+    let mut chunk = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+    let mut chunks = plaintext
+        .chunks(NOISE_MAX_MESSAGE_LEN - NOISE_TAG_LEN)
+        .peekable();
+    if chunks.peek().is_none() {
+        let len = channel.write_message(&[], &mut chunk)?;
+        frame.extend_from_slice(&chunk[..len]);
+    }
+    for plaintext_chunk in chunks {
+        let len = channel.write_message(plaintext_chunk, &mut chunk)?;
+        frame.extend_from_slice(&chunk[..len]);
+    }
+    return Ok(frame);
+}
+
+fn decrypt_frame(
+    channel: &mut snow::TransportState,
+    frame: &[u8],
+) -> Result<Vec<u8>, VsockReceiveError> {
+    let mut plaintext = Vec::with_capacity(frame.len());
+    let mut chunk = vec![0u8; NOISE_MAX_MESSAGE_LEN];
+    for ciphertext_chunk in frame.chunks(NOISE_MAX_MESSAGE_LEN) {
+        let len = channel.read_message(ciphertext_chunk, &mut chunk)?;
+        plaintext.extend_from_slice(&chunk[..len]);
+    }
+    return Ok(plaintext);
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct KmsCredentials {
+    pub aws_region: String,
+    pub aws_access_key_id: String,
+    pub aws_secret_access_key: String,
+    pub aws_session_token: String,
+    pub kms_proxy_port: String,
+}
+
+impl std::fmt::Debug for KmsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f
+            .debug_struct("KmsCredentials")
+            .field("aws_region", &self.aws_region)
+            .field("aws_access_key_id", &self.aws_access_key_id)
+            .field("aws_secret_access_key", &REDACTED)
+            .field("aws_session_token", &REDACTED)
+            .field("kms_proxy_port", &self.kms_proxy_port)
+            .finish();
+    }
+}
+
+/// Every signing request carries `approvals`, which are only checked for
+/// wallets created with a [`Quorum`]. The response is followed by a
+/// [`VsockEnclaveResponseTrailer`] frame.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum VsockHostRequest {
+    /// First Noise handshake message. Once the handshake completes, every frame
+    /// on the connection is encrypted and the next request is read from it.
+    Handshake { noise_message: Vec<u8> },
+    /// Returns the enclave's sealing key with an attestation document over it,
+    /// optionally including a caller chosen `nonce` for freshness.
+    GetSealingKey { nonce: Option<Vec<u8>> },
+    /// Returns the key the enclave signs audit events with, attested like
+    /// [`VsockHostRequest::GetSealingKey`].
+    GetAuditKey { nonce: Option<Vec<u8>> },
+    /// Answers straight away, to check that the enclave accepts requests.
+    Ping,
+    /// Checks that the enclave can reach KMS, with a `genrandom` of a few
+    /// bytes, and reports its state. Never fails: problems are reported in
+    /// [`VsockEnclaveHealthData::degraded`].
+    HealthCheck { credentials: KmsCredentials },
+    /// Stops the enclave accepting connections and answers once every other
+    /// request in progress finished, or its shutdown grace period ended. The
+    /// enclave then clears its caches and exits.
+    Shutdown,
+    /// Returns an NSM attestation document over the enclave's PCRs with the
+    /// given fields, e.g. a hash of a wallet public key as `user_data`.
+    GetAttestation {
+        nonce: Option<Vec<u8>>,
+        user_data: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    },
+    /// Any other request, CBOR encoded and sealed by a client to the enclave's
+    /// sealing key. The response is the inner request's response, sealed with
+    /// the request's [`ResponseKey`].
+    Sealed { sealed_request: SealedRequest },
+    /// `policy`, if given, must be signed by the enclave's policy admin key and
+    /// is enforced on every signing request for the new wallet. `quorum`, if
+    /// given, is sealed into the wallet and cannot be changed afterwards.
     CreateWallet {
-        aws_region: String,
-        aws_access_key_id: String,
-        aws_secret_access_key: String,
-        aws_session_token: String,
-        kms_proxy_port: String,
+        credentials: KmsCredentials,
         kms_key_id: String,
         aes_gcm_nonce: [u8; 12],
+        #[serde(default)]
+        policy: Option<SignedPolicy>,
+        #[serde(default)]
+        quorum: Option<Quorum>,
     },
-    Sign {
-        aws_region: String,
-        aws_access_key_id: String,
-        aws_secret_access_key: String,
-        aws_session_token: String,
-        kms_proxy_port: String,
+    /// Re-seals the wallet under a new admin-signed policy with a higher version,
+    /// which must name the wallet. `approvals` must meet the wallet's quorum, if
+    /// it has one.
+    SetWalletPolicy {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        policy: SignedPolicy,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Moves a wallet to a fresh data key generated under `new_kms_key_id`, with
+    /// a fresh nonce. The wallet secret, and so every derived address, stays
+    /// the same. `credentials` must be able to decrypt the current data key and
+    /// `new_credentials` to generate one under the new key, e.g. in another region.
+    /// Whoever controls the new key can decrypt the wallet, so `approvals` must
+    /// meet the wallet's quorum, if it has one.
+    RewrapWallet {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        new_credentials: KmsCredentials,
+        new_kms_key_id: String,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Seals the wallet secret to offline X25519 `recovery_keys` for disaster
+    /// recovery: whole to each key, or with `threshold` as one SLIP-39 share
+    /// per key (at most 16), any `threshold` of which restore it, here or with
+    /// any SLIP-39 tool. Only wallets with a quorum can be exported, and
+    /// `approvals` must meet it.
+    ExportBackup {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        recovery_keys: Vec<[u8; 32]>,
+        threshold: Option<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Restores a wallet from shares opened by recovery key holders, under a
+    /// fresh data key from `kms_key_id` and with the backup's policy and quorum.
+    /// Only accepted inside [`VsockHostRequest::Sealed`], so the host never
+    /// sees the shares, and `approvals` must meet the backup's quorum.
+    ImportBackup {
+        credentials: KmsCredentials,
         kms_key_id: String,
-        aes_gcm_nonce: [u8; 12],
-        encrypted_secret_key: Vec<u8>,
-        kms_ciphertext: Vec<u8>,
+        shares: Vec<BackupSecret>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Wraps an existing private key for `signature_scheme` under a fresh data
+    /// key from `kms_key_id`, like a generated wallet but marked
+    /// [`WalletOrigin::Imported`]. The key reaches the enclave either inside
+    /// [`VsockHostRequest::Sealed`] or encrypted to an asymmetric KMS key, so
+    /// the host never sees it.
+    ImportKey {
+        credentials: KmsCredentials,
+        kms_key_id: String,
+        signature_scheme: SignatureSchemeKind,
+        key: ImportedKey,
+        #[serde(default)]
+        policy: Option<SignedPolicy>,
+        #[serde(default)]
+        quorum: Option<Quorum>,
+    },
+    /// Starts a FROST DKG session for a `threshold` of `participants` Ed25519
+    /// key, as participant `identifier` (1 based). The session, and later the
+    /// signing nonces, live in this enclave's memory, so every request for the
+    /// participant must reach the same enclave. See [`crate::frost`].
+    FrostDkgPart1 {
+        session_id: [u8; 16],
+        identifier: u16,
+        threshold: u8,
+        participants: u8,
+    },
+    /// Verifies everyone's round one package and returns this participant's
+    /// shares for the others, each sealed to the recipient's sealing key.
+    FrostDkgPart2 {
+        session_id: [u8; 16],
+        round1_packages: Vec<FrostRound1Package>,
+    },
+    /// Opens the shares sent to this participant and returns its key share,
+    /// sealed under a fresh data key from `kms_key_id` with `policy` and
+    /// `quorum`, as for [`VsockHostRequest::CreateWallet`].
+    FrostDkgPart3 {
+        session_id: [u8; 16],
+        credentials: KmsCredentials,
+        kms_key_id: String,
+        round2_packages: Vec<FrostRound2Package>,
+        #[serde(default)]
+        policy: Option<SignedPolicy>,
+        #[serde(default)]
+        quorum: Option<Quorum>,
+    },
+    /// FROST round one: returns a commitment to fresh single use nonces.
+    FrostCommit {
+        credentials: KmsCredentials,
+        key_share: FrostKeyShare,
+    },
+    /// FROST round two: returns this participant's share of the signature over
+    /// `message`, using the nonces committed to in `commitments`. `approvals`
+    /// must meet the key share's quorum, if it has one.
+    FrostSign {
+        credentials: KmsCredentials,
+        key_share: FrostKeyShare,
+        message: Vec<u8>,
+        commitments: Vec<FrostSigningCommitment>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Signs `payload` with the key at `derivation_path`. Secp256k1 schemes use
+    /// BIP32 and expect a 32 byte digest; Ed25519 uses SLIP-10 and signs the
+    /// payload as is. Secp256k1 ECDSA needs a policy allowing opaque requests,
+    /// as the digest could be that of any transaction.
+    Sign {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
         signature_scheme: SignatureScheme,
+        derivation_path: String,
+        payload: Vec<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Signs many payloads, possibly under different derivation paths, with a
+    /// single unwrap of the wallet key. Items fail independently.
+    SignBatch {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        items: Vec<SignBatchItem>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
     },
+    /// Drops the cached data key for `kms_ciphertext`, or every cached key when `None`.
+    EvictCache { kms_ciphertext: Option<Vec<u8>> },
+    /// Signs a full CBOR encoded Cardano transaction with the keys at the given
+    /// CIP-1852 derivation paths (e.g. `m/1852'/1815'/0'/0/0`). The body hash is
+    /// computed inside the enclave from the original body bytes.
+    SignCardanoTx {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        tx_cbor: Vec<u8>,
+        derivation_paths: Vec<String>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// CIP-8 / CIP-30 `signData`: signs `payload` with the key at `derivation_path`,
+    /// which must be the payment or stake credential of the raw `address` bytes.
+    SignCardanoMessage {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        derivation_path: String,
+        address: Vec<u8>,
+        payload: Vec<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// RLP encodes, hashes and signs an Ethereum transaction with the secp256k1 key
+    /// at `derivation_path` (e.g. `m/44'/60'/0'/0/0`).
+    SignEthereumTx {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        derivation_path: String,
+        transaction: EthereumTransaction,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// `eth_signTypedData_v4`: the EIP-712 digest is computed inside the enclave
+    /// from the JSON encoded typed data (`types`, `primaryType`, `domain`, `message`).
+    SignEthereumTypedData {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        derivation_path: String,
+        typed_data_json: String,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// `personal_sign`: signs the EIP-191 prefixed hash of `message`.
+    SignEthereumMessage {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        derivation_path: String,
+        message: Vec<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Signs a BIP174 PSBT. Only P2WPKH, P2SH-P2WPKH and P2TR key path inputs whose
+    /// BIP32 derivation matches the wallet's master fingerprint are signed.
+    SignPsbt {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        psbt: Vec<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+    /// Signs a serialized legacy or v0 Solana message with the SLIP-10 Ed25519 key
+    /// at `derivation_path`, which must be one of the message's required signers.
+    SignSolanaTx {
+        credentials: KmsCredentials,
+        wallet: VsockEnclaveCreateWalletData,
+        derivation_path: String,
+        message: Vec<u8>,
+        #[serde(default)]
+        approvals: Vec<Approval>,
+    },
+}
+
+impl VsockHostRequest {
+    /// The request's variant name, for logs and metrics.
+    pub fn operation(&self) -> &'static str {
+        return match self {
+            VsockHostRequest::Handshake { .. } => "Handshake",
+            VsockHostRequest::GetSealingKey { .. } => "GetSealingKey",
+            VsockHostRequest::GetAuditKey { .. } => "GetAuditKey",
+            VsockHostRequest::Ping => "Ping",
+            VsockHostRequest::HealthCheck { .. } => "HealthCheck",
+            VsockHostRequest::Shutdown => "Shutdown",
+            VsockHostRequest::GetAttestation { .. } => "GetAttestation",
+            VsockHostRequest::Sealed { .. } => "Sealed",
+            VsockHostRequest::CreateWallet { .. } => "CreateWallet",
+            VsockHostRequest::SetWalletPolicy { .. } => "SetWalletPolicy",
+            VsockHostRequest::RewrapWallet { .. } => "RewrapWallet",
+            VsockHostRequest::ExportBackup { .. } => "ExportBackup",
+            VsockHostRequest::ImportBackup { .. } => "ImportBackup",
+            VsockHostRequest::ImportKey { .. } => "ImportKey",
+            VsockHostRequest::FrostDkgPart1 { .. } => "FrostDkgPart1",
+            VsockHostRequest::FrostDkgPart2 { .. } => "FrostDkgPart2",
+            VsockHostRequest::FrostDkgPart3 { .. } => "FrostDkgPart3",
+            VsockHostRequest::FrostCommit { .. } => "FrostCommit",
+            VsockHostRequest::FrostSign { .. } => "FrostSign",
+            VsockHostRequest::Sign { .. } => "Sign",
+            VsockHostRequest::SignBatch { .. } => "SignBatch",
+            VsockHostRequest::EvictCache { .. } => "EvictCache",
+            VsockHostRequest::SignCardanoTx { .. } => "SignCardanoTx",
+            VsockHostRequest::SignCardanoMessage { .. } => "SignCardanoMessage",
+            VsockHostRequest::SignEthereumTx { .. } => "SignEthereumTx",
+            VsockHostRequest::SignEthereumTypedData { .. } => "SignEthereumTypedData",
+            VsockHostRequest::SignEthereumMessage { .. } => "SignEthereumMessage",
+            VsockHostRequest::SignPsbt { .. } => "SignPsbt",
+            VsockHostRequest::SignSolanaTx { .. } => "SignSolanaTx",
+        };
+    }
+}
+
+/// Sent after the response to every request the enclave handles, that is not
+/// rejected as unauthenticated or as an unreadable sealed request.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct VsockEnclaveResponseTrailer {
+    /// Set for requests on a wallet, see [`crate::audit`].
+    pub audit_event: Option<SignedAuditEvent>,
+    pub stats: RequestStats,
+}
+
+/// Timings taken inside the enclave, where the host cannot see them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RequestStats {
+    /// From receiving the request to having its response.
+    pub duration_us: u64,
+    pub kms_calls: Vec<KmsCallStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KmsCallStats {
+    /// kmstool command, e.g. `decrypt`.
+    pub command: String,
+    pub duration_us: u64,
+    /// Whether kmstool exited successfully.
+    pub success: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveHandshakeData {
+    pub noise_message: Vec<u8>,
+}
+
+pub type VsockEnclaveHandshakeResponse = Result<VsockEnclaveHandshakeData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSealingKeyData {
+    /// X25519 key generated when the enclave booted; it never leaves the enclave.
+    pub public_key: [u8; 32],
+    /// NSM attestation document with `public_key` as its public key.
+    pub attestation: Vec<u8>,
 }
 
+pub type VsockEnclaveSealingKeyResponse = Result<VsockEnclaveSealingKeyData, VsockEnclaveSignError>;
+
 #[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveAuditKeyData {
+    /// Ed25519 key generated when the enclave booted; it never leaves the enclave.
+    pub public_key: [u8; 32],
+    /// NSM attestation document with `public_key` as its public key.
+    pub attestation: Vec<u8>,
+}
+
+pub type VsockEnclaveAuditKeyResponse = Result<VsockEnclaveAuditKeyData, VsockEnclaveSignError>;
+
+pub type VsockEnclavePingResponse = Result<(), VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VsockEnclaveHealthData {
+    /// Version of the enclave binary.
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Hash of the enclave image, as attested; `None` when it could not be read.
+    pub pcr0: Option<Vec<u8>>,
+    /// `None` when listening on TCP.
+    pub vsock_port: Option<u32>,
+    /// Empty when the enclave is healthy.
+    pub degraded: Vec<HealthIssue>,
+}
+
+impl VsockEnclaveHealthData {
+    pub fn is_healthy(&self) -> bool {
+        return self.degraded.is_empty();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum HealthIssue {
+    /// `genrandom` through kmstool and the KMS proxy failed, so no wallet can
+    /// be created or unwrapped.
+    KmsUnreachable { error: String },
+    /// PCR0 could not be read from the Nitro Secure Module, so attestation
+    /// documents cannot be produced either.
+    NsmUnavailable { error: String },
+}
+
+pub type VsockEnclaveHealthResponse = Result<VsockEnclaveHealthData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveShutdownData {
+    /// Whether every other request finished within the grace period; those
+    /// that did not are dropped when the enclave exits.
+    pub drained: bool,
+}
+
+pub type VsockEnclaveShutdownResponse = Result<VsockEnclaveShutdownData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveAttestationData {
+    /// COSE_Sign1 attestation document, see [`crate::attestation`].
+    #[serde(with = "serde_bytes")]
+    pub document: Vec<u8>,
+}
+
+pub type VsockEnclaveAttestationResponse =
+    Result<VsockEnclaveAttestationData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSealedData {
+    #[serde(with = "serde_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// `Err` only when the sealed request itself could not be opened; errors of
+/// the inner request are sealed like any other response.
+pub type VsockEnclaveSealedResponse = Result<VsockEnclaveSealedData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VsockEnclaveCreateWalletData {
     pub encrypted_secret_key: Vec<u8>,
     pub aes_gcm_nonce: [u8; 12],
     pub kms_ciphertext: Vec<u8>,
     pub kms_key_id: String,
+    #[serde(default)]
+    pub policy: Option<SignedPolicy>,
+    #[serde(default)]
+    pub quorum: Option<Quorum>,
+    #[serde(default)]
+    pub origin: WalletOrigin,
+    /// Stable identity of the wallet secret, which policies are bound to. The
+    /// enclave derives it from the secret again on every use, so it is only
+    /// informational here.
+    #[serde(default)]
+    pub wallet_id: [u8; 32],
+}
+
+/// Where a wallet's secret came from. Bound to the secret as associated data.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum WalletOrigin {
+    /// 64 random bytes from KMS, the seed every key is derived from.
+    #[default]
+    Generated,
+    /// A private key brought in with [`VsockHostRequest::ImportKey`], in the
+    /// first 32 bytes of the secret with the rest zero. It signs as is, only
+    /// with `signature_scheme`, and only through `Sign` and `SignBatch`.
+    Imported {
+        signature_scheme: SignatureSchemeKind,
+    },
+}
+
+/// A private key on its way into the enclave: a 32 byte secp256k1 secret key,
+/// or a 32 byte Ed25519 seed, optionally followed by its public key as in
+/// Solana keypair files.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum ImportedKey {
+    /// Only accepted inside [`VsockHostRequest::Sealed`].
+    Plaintext(#[serde(with = "serde_bytes")] Vec<u8>),
+    /// `RSAES_OAEP_SHA_256` ciphertext under the public key of the asymmetric
+    /// KMS key `key_id`, as returned by KMS GetPublicKey. The enclave decrypts
+    /// it through kmstool.
+    KmsRsaOaep {
+        key_id: String,
+        #[serde(with = "serde_bytes")]
+        ciphertext: Vec<u8>,
+    },
+}
+
+impl std::fmt::Debug for ImportedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            ImportedKey::Plaintext(_) => f.debug_tuple("Plaintext").field(&REDACTED).finish(),
+            ImportedKey::KmsRsaOaep { key_id, ciphertext } => f
+                .debug_struct("KmsRsaOaep")
+                .field("key_id", key_id)
+                .field("ciphertext", ciphertext)
+                .finish(),
+        };
+    }
+}
+
+impl Drop for ImportedKey {
+    fn drop(&mut self) {
+        if let ImportedKey::Plaintext(key) = self {
+            key.zeroize();
+        }
+    }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VsockEnclaveImportKeyData {
+    pub wallet: VsockEnclaveCreateWalletData,
+    /// The imported key's public key, as `Sign` returns it for the scheme.
+    pub public_key: Vec<u8>,
+}
+
+pub type VsockEnclaveImportKeyResponse = Result<VsockEnclaveImportKeyData, VsockEnclaveSignError>;
+
 pub type VsockEnclaveCreateWalletResponse =
     Result<VsockEnclaveCreateWalletData, VsockEnclaveCreateWalletError>;
 
-#[derive(Serialize, Deserialize, Debug)]
+pub type VsockEnclaveSetWalletPolicyResponse =
+    Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError>;
+
+pub type VsockEnclaveRewrapWalletResponse =
+    Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError>;
+
+pub type VsockEnclaveExportBackupResponse = Result<WalletBackup, VsockEnclaveSignError>;
+
+pub type VsockEnclaveImportBackupResponse =
+    Result<VsockEnclaveCreateWalletData, VsockEnclaveSignError>;
+
+pub type VsockEnclaveFrostDkgPart1Response = Result<FrostRound1Package, VsockEnclaveSignError>;
+
+pub type VsockEnclaveFrostDkgPart2Response = Result<Vec<FrostRound2Package>, VsockEnclaveSignError>;
+
+pub type VsockEnclaveFrostDkgPart3Response = Result<FrostKeyShare, VsockEnclaveSignError>;
+
+pub type VsockEnclaveFrostCommitResponse = Result<FrostSigningCommitment, VsockEnclaveSignError>;
+
+pub type VsockEnclaveFrostSignResponse = Result<FrostSignatureShare, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SignatureScheme {
     Secp256k1,
+    /// BIP340 Schnorr signatures over secp256k1. Without `taproot` the untweaked
+    /// key signs (e.g. Nostr events); with it the key is tweaked per BIP341 first,
+    /// as needed for Taproot key path spends.
+    Secp256k1Schnorr {
+        taproot: Option<TaprootTweak>,
+    },
     Ed25519,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignBatchItem {
+    pub signature_scheme: SignatureScheme,
+    pub derivation_path: String,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaprootTweak {
+    /// Root of the script tree, or `None` for outputs without one (BIP86).
+    pub merkle_root: Option<[u8; 32]>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignData {
+    /// Compressed SEC1 key for ECDSA, x-only (tweaked, if requested) for
+    /// Schnorr, and the raw 32 byte key for Ed25519.
+    pub public_key: Vec<u8>,
+    /// 64 byte compact (low-s) ECDSA, BIP340 or Ed25519 signature.
+    pub signature: Vec<u8>,
+}
+
+pub type VsockEnclaveSignResponse = Result<VsockEnclaveSignData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignBatchData {
+    /// One result per request item, in order.
+    pub results: Vec<VsockEnclaveSignResponse>,
+}
+
+/// `Err` only when the wallet itself could not be unwrapped.
+pub type VsockEnclaveSignBatchResponse = Result<VsockEnclaveSignBatchData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignCardanoTxData {
+    /// blake2b-256 hash of the transaction body, i.e. the transaction id.
+    pub tx_body_hash: [u8; 32],
+    /// CBOR encoded transaction witness set containing only the vkey witnesses.
+    pub witness_set: Vec<u8>,
+}
+
+pub type VsockEnclaveSignCardanoTxResponse =
+    Result<VsockEnclaveSignCardanoTxData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignCardanoMessageData {
+    /// CBOR encoded COSE_Sign1 with the `address` in its protected headers.
+    pub cose_sign1: Vec<u8>,
+    /// CBOR encoded COSE_Key of the signing Ed25519 public key.
+    pub cose_key: Vec<u8>,
+}
+
+pub type VsockEnclaveSignCardanoMessageResponse =
+    Result<VsockEnclaveSignCardanoMessageData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EthereumAccessListItem {
+    pub address: [u8; 20],
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
+/// `value` is a big endian 256 bit integer, `to` is `None` for contract creation.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum EthereumTransaction {
+    /// Pre EIP-2718 transaction, always signed with EIP-155 replay protection.
+    Legacy {
+        chain_id: u64,
+        nonce: u64,
+        gas_price: u128,
+        gas_limit: u64,
+        to: Option<[u8; 20]>,
+        value: [u8; 32],
+        data: Vec<u8>,
+    },
+    Eip2930 {
+        chain_id: u64,
+        nonce: u64,
+        gas_price: u128,
+        gas_limit: u64,
+        to: Option<[u8; 20]>,
+        value: [u8; 32],
+        data: Vec<u8>,
+        access_list: Vec<EthereumAccessListItem>,
+    },
+    Eip1559 {
+        chain_id: u64,
+        nonce: u64,
+        max_priority_fee_per_gas: u128,
+        max_fee_per_gas: u128,
+        gas_limit: u64,
+        to: Option<[u8; 20]>,
+        value: [u8; 32],
+        data: Vec<u8>,
+        access_list: Vec<EthereumAccessListItem>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignEthereumTxData {
+    /// Signed transaction ready for `eth_sendRawTransaction`.
+    pub raw_transaction: Vec<u8>,
+    pub tx_hash: [u8; 32],
+}
+
+pub type VsockEnclaveSignEthereumTxResponse =
+    Result<VsockEnclaveSignEthereumTxData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignEthereumMessageData {
+    /// The EIP-191 or EIP-712 digest that was signed.
+    pub hash: [u8; 32],
+    /// 65 byte `r || s || v` signature with `v` in {27, 28}.
+    pub signature: Vec<u8>,
+    /// Address recovered from `signature`, for the host to sanity check.
+    pub recovered_address: [u8; 20],
+}
+
+pub type VsockEnclaveSignEthereumMessageResponse =
+    Result<VsockEnclaveSignEthereumMessageData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PsbtSignedInput {
+    pub input_index: u32,
+    pub signature_scheme: SignatureScheme,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveSignPsbtData {
+    /// The updated PSBT with `partial_sigs` / `tap_key_sig` filled in.
+    pub psbt: Vec<u8>,
+    pub signed_inputs: Vec<PsbtSignedInput>,
+}
+
+pub type VsockEnclaveSignPsbtResponse = Result<VsockEnclaveSignPsbtData, VsockEnclaveSignError>;
+
 #[derive(Serialize, Deserialize, Debug)]
-pub struct VsockEnclaveSignData {}
+pub struct VsockEnclaveSignSolanaTxData {
+    pub public_key: [u8; 32],
+    /// Position of `public_key` among the message's required signers.
+    pub signer_index: u8,
+    pub signature: Vec<u8>,
+    /// Wire format transaction; signatures of other required signers are zeroed.
+    pub signed_transaction: Vec<u8>,
+}
+
+pub type VsockEnclaveSignSolanaTxResponse =
+    Result<VsockEnclaveSignSolanaTxData, VsockEnclaveSignError>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VsockEnclaveEvictCacheData {
+    pub evicted: u32,
+}
 
-pub type VsockEnclaveSignResponse = Result<VsockEnclaveSignData, VsockEnclaveCreateWalletError>;
+pub type VsockEnclaveEvictCacheResponse = Result<VsockEnclaveEvictCacheData, VsockEnclaveSignError>;